@@ -0,0 +1,9 @@
+-- Short-lived OAuth CSRF states, consumed exactly once by the provider callback
+CREATE TABLE IF NOT EXISTS oauth_states (
+    state TEXT PRIMARY KEY,
+    provider TEXT NOT NULL,  -- OAuth provider (github, gitlab, bitbucket)
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_oauth_states_expires_at ON oauth_states(expires_at);
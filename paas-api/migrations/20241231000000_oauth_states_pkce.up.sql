@@ -0,0 +1,2 @@
+-- PKCE code verifier issued alongside the CSRF state (NULL when PKCE is disabled)
+ALTER TABLE oauth_states ADD COLUMN pkce_verifier TEXT;
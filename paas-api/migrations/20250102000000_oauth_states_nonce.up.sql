@@ -0,0 +1,2 @@
+-- OpenID Connect nonce sent with the authorization request (NULL for plain OAuth providers)
+ALTER TABLE oauth_states ADD COLUMN nonce TEXT;
@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS provider_instances_rename_in_use;
+DROP TRIGGER IF EXISTS provider_instances_delete_in_use;
+DROP TRIGGER IF EXISTS users_provider_instance_update;
+DROP TRIGGER IF EXISTS users_provider_instance_insert;
+DROP TABLE IF EXISTS provider_instances;
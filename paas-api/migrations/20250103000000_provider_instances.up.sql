@@ -0,0 +1,50 @@
+-- OAuth/OpenID Connect provider instances, so gitlab.com can be offered next
+-- to a self-managed GitLab. Columns left NULL fall back to the provider's
+-- environment variables (`<PREFIX>_BASE_URL`, `<PREFIX>_CLIENT_ID`, ...).
+CREATE TABLE IF NOT EXISTS provider_instances (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug TEXT NOT NULL UNIQUE,  -- Used in login URLs and stored in users.provider
+    provider_type TEXT NOT NULL,  -- "github", "gitlab", "bitbucket", "bitbucket-server", "oidc"
+    display_name TEXT NOT NULL,
+    base_url TEXT,
+    client_id TEXT,
+    client_secret TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- The environment-configured instances keep the slugs existing users already reference
+INSERT INTO provider_instances (slug, provider_type, display_name) VALUES
+    ('github', 'github', 'GitHub'),
+    ('gitlab', 'gitlab', 'GitLab'),
+    ('bitbucket', 'bitbucket', 'Bitbucket'),
+    ('oidc', 'oidc', 'Single Sign-On');
+
+-- users.provider holds the instance slug. SQLite cannot add a foreign key to an
+-- existing column, so the reference is enforced with triggers instead.
+CREATE TRIGGER IF NOT EXISTS users_provider_instance_insert
+BEFORE INSERT ON users
+WHEN NOT EXISTS (SELECT 1 FROM provider_instances WHERE slug = NEW.provider)
+BEGIN
+    SELECT RAISE(ABORT, 'users.provider must reference a provider instance');
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_provider_instance_update
+BEFORE UPDATE OF provider ON users
+WHEN NOT EXISTS (SELECT 1 FROM provider_instances WHERE slug = NEW.provider)
+BEGIN
+    SELECT RAISE(ABORT, 'users.provider must reference a provider instance');
+END;
+
+CREATE TRIGGER IF NOT EXISTS provider_instances_delete_in_use
+BEFORE DELETE ON provider_instances
+WHEN EXISTS (SELECT 1 FROM users WHERE provider = OLD.slug)
+BEGIN
+    SELECT RAISE(ABORT, 'provider instance is still used by users');
+END;
+
+CREATE TRIGGER IF NOT EXISTS provider_instances_rename_in_use
+BEFORE UPDATE OF slug ON provider_instances
+WHEN NEW.slug != OLD.slug AND EXISTS (SELECT 1 FROM users WHERE provider = OLD.slug)
+BEGIN
+    SELECT RAISE(ABORT, 'provider instance is still used by users');
+END;
@@ -0,0 +1,3 @@
+ALTER TABLE git_providers DROP COLUMN updated_at;
+ALTER TABLE git_providers DROP COLUMN needs_reauth;
+ALTER TABLE git_providers RENAME COLUMN provider TO provider_type;
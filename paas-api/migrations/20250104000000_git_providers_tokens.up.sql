@@ -0,0 +1,6 @@
+-- Connections belong to a provider instance, like users.provider
+ALTER TABLE git_providers RENAME COLUMN provider_type TO provider;
+
+-- Set when refreshing the access token failed and the user has to sign in again
+ALTER TABLE git_providers ADD COLUMN needs_reauth BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE git_providers ADD COLUMN updated_at TEXT;
@@ -0,0 +1,7 @@
+-- Encrypted tokens cannot be read without their key id, so users have to
+-- sign in again.
+UPDATE users SET access_token = NULL, refresh_token = NULL WHERE token_key_id IS NOT NULL;
+UPDATE git_providers SET refresh_token = NULL, needs_reauth = 1 WHERE token_key_id IS NOT NULL;
+
+ALTER TABLE git_providers DROP COLUMN token_key_id;
+ALTER TABLE users DROP COLUMN token_key_id;
@@ -0,0 +1,5 @@
+-- Key the row's tokens are encrypted with (see TOKEN_ENCRYPTION_KEYS).
+-- Tokens stored before encryption was introduced have no key id; they are
+-- encrypted by db::init_db right after this migration runs.
+ALTER TABLE users ADD COLUMN token_key_id TEXT;
+ALTER TABLE git_providers ADD COLUMN token_key_id TEXT;
@@ -0,0 +1,13 @@
+-- Server-side session state; the session cookie only carries the key
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,  -- SHA-256 of the session key, so a database copy cannot be used to hijack sessions
+    user_id INTEGER,  -- Signed-in user, if any
+    state TEXT NOT NULL,  -- JSON object of session entries
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    expires_at TEXT NOT NULL,  -- Idle expiry, extended on every request up to absolute_expires_at
+    absolute_expires_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_expires_at ON sessions(expires_at);
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS provider_instances_delete_linked;
+ALTER TABLE oauth_states DROP COLUMN user_id;
+DROP INDEX IF EXISTS idx_git_providers_identity;
+ALTER TABLE git_providers DROP COLUMN provider_user_id;
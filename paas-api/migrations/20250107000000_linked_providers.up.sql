@@ -0,0 +1,24 @@
+-- Account each connection was authorized as, so every linked provider can be
+-- used to sign in. Connections made before this migration belong to the
+-- account the user signed up with.
+ALTER TABLE git_providers ADD COLUMN provider_user_id TEXT;
+
+UPDATE git_providers
+SET provider_user_id = (
+    SELECT users.provider_user_id FROM users
+    WHERE users.id = git_providers.user_id AND users.provider = git_providers.provider
+);
+
+-- A provider account can only be linked to one user
+CREATE UNIQUE INDEX IF NOT EXISTS idx_git_providers_identity
+    ON git_providers(provider, provider_user_id);
+
+-- Set on states issued to connect a provider to the signed-in user
+ALTER TABLE oauth_states ADD COLUMN user_id INTEGER;
+
+CREATE TRIGGER IF NOT EXISTS provider_instances_delete_linked
+BEFORE DELETE ON provider_instances
+WHEN EXISTS (SELECT 1 FROM git_providers WHERE provider = OLD.slug)
+BEGIN
+    SELECT RAISE(ABORT, 'provider instance is still linked to users');
+END;
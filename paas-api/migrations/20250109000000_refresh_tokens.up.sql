@@ -0,0 +1,21 @@
+-- Refresh tokens handed out with the JWTs of /api/auth/token. Each use
+-- replaces the token with a new one in the same family; presenting a token
+-- that was already used revokes the whole family. Only SHA-256 hashes are
+-- stored.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    api_token_id INTEGER,  -- Set when exchanged for an API token, which bounds its scopes
+    family_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,  -- Space-separated, e.g. "read write"
+    expires_at TEXT NOT NULL,
+    used_at TEXT,
+    revoked_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (api_token_id) REFERENCES api_tokens(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens(family_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_expires_at ON refresh_tokens(expires_at);
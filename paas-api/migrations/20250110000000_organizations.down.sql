@@ -0,0 +1,9 @@
+DROP TRIGGER IF EXISTS organizations_delete_repositories;
+DROP INDEX IF EXISTS idx_repositories_organization_id;
+ALTER TABLE repositories DROP COLUMN organization_id;
+DROP INDEX IF EXISTS idx_invitations_email;
+DROP INDEX IF EXISTS idx_invitations_user_id;
+DROP TABLE IF EXISTS invitations;
+DROP INDEX IF EXISTS idx_memberships_user_id;
+DROP TABLE IF EXISTS memberships;
+DROP TABLE IF EXISTS organizations;
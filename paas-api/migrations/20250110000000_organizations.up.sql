@@ -0,0 +1,55 @@
+-- Organizations let several users share repositories and apps
+CREATE TABLE IF NOT EXISTS organizations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE IF NOT EXISTS memberships (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    organization_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'developer', 'viewer')),
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE(organization_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_memberships_user_id ON memberships(user_id);
+
+-- Pending invitations. Invitations by username are bound to that user when
+-- sent; invitations by email are accepted by whoever signs in with it.
+CREATE TABLE IF NOT EXISTS invitations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    organization_id INTEGER NOT NULL,
+    invitee TEXT NOT NULL,  -- The username or email address as shown
+    user_id INTEGER,
+    email TEXT COLLATE NOCASE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'developer', 'viewer')),
+    invited_by INTEGER,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE SET NULL,
+    CHECK ((user_id IS NULL) <> (email IS NULL)),
+    UNIQUE(organization_id, invitee)
+);
+
+CREATE INDEX IF NOT EXISTS idx_invitations_user_id ON invitations(user_id);
+CREATE INDEX IF NOT EXISTS idx_invitations_email ON invitations(email);
+
+-- Repositories belong to the organization when organization_id is set, and
+-- to user_id otherwise. There is no foreign key so the column can be dropped
+-- again; the trigger stands in for ON DELETE CASCADE.
+ALTER TABLE repositories ADD COLUMN organization_id INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_repositories_organization_id ON repositories(organization_id);
+
+CREATE TRIGGER IF NOT EXISTS organizations_delete_repositories
+AFTER DELETE ON organizations
+BEGIN
+    DELETE FROM repositories WHERE organization_id = OLD.id;
+END;
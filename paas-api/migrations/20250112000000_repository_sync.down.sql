@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_repositories_provider_repo;
+ALTER TABLE repositories DROP COLUMN last_synced;
+ALTER TABLE repositories DROP COLUMN is_private;
+ALTER TABLE repositories DROP COLUMN provider_repo_id;
@@ -0,0 +1,8 @@
+-- Repositories are imported by a user from the provider in provider_id and
+-- identified by the provider's id for them
+ALTER TABLE repositories ADD COLUMN provider_repo_id TEXT;
+ALTER TABLE repositories ADD COLUMN is_private BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE repositories ADD COLUMN last_synced TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_repositories_provider_repo
+    ON repositories(user_id, provider_id, provider_repo_id);
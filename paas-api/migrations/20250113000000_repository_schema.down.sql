@@ -0,0 +1,35 @@
+CREATE TABLE repositories_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    provider_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    url TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    organization_id INTEGER,
+    provider_repo_id TEXT,
+    is_private BOOLEAN NOT NULL DEFAULT 1,
+    last_synced TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (provider_id) REFERENCES git_providers(id) ON DELETE CASCADE
+);
+
+INSERT INTO repositories_old
+    (id, user_id, provider_id, name, description, url, created_at, organization_id,
+     provider_repo_id, is_private, last_synced)
+SELECT id, user_id, provider_id, name, description, url, created_at, organization_id,
+       provider_repo_id, visibility != 'public', last_synced
+FROM repositories;
+
+DROP TABLE repositories;
+ALTER TABLE repositories_old RENAME TO repositories;
+
+CREATE INDEX IF NOT EXISTS idx_repositories_organization_id ON repositories(organization_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_repositories_provider_repo
+    ON repositories(user_id, provider_id, provider_repo_id);
+
+CREATE TRIGGER IF NOT EXISTS organizations_delete_repositories
+AFTER DELETE ON organizations
+BEGIN
+    DELETE FROM repositories WHERE organization_id = OLD.id;
+END;
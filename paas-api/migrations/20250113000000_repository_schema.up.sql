@@ -0,0 +1,39 @@
+-- Rebuild repositories to match models::Repository. SQLite cannot alter
+-- constraints in place, so the table is copied. Visibility replaces
+-- is_private so GitLab's internal projects can be told apart, and
+-- organization_id gets the foreign key that the trigger stood in for.
+CREATE TABLE repositories_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    organization_id INTEGER,
+    provider_id INTEGER NOT NULL,
+    provider_repo_id TEXT,  -- The provider's id; NULL for repositories added by hand
+    name TEXT NOT NULL,  -- Including the owner, e.g. "octocat/hello-world"
+    description TEXT,
+    url TEXT NOT NULL,  -- Web page of the repository
+    clone_url TEXT NOT NULL,  -- HTTPS URL to clone from
+    default_branch TEXT,  -- NULL for empty repositories
+    visibility TEXT NOT NULL DEFAULT 'private'
+        CHECK (visibility IN ('public', 'internal', 'private')),
+    last_synced TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (provider_id) REFERENCES git_providers(id) ON DELETE CASCADE,
+    UNIQUE (user_id, provider_id, provider_repo_id)
+);
+
+INSERT INTO repositories_new
+    (id, user_id, organization_id, provider_id, provider_repo_id, name, description,
+     url, clone_url, visibility, last_synced, created_at)
+SELECT id, user_id, organization_id, provider_id, provider_repo_id, name, description,
+       url, url, CASE WHEN is_private THEN 'private' ELSE 'public' END, last_synced, created_at
+FROM repositories;
+
+DROP TRIGGER IF EXISTS organizations_delete_repositories;
+DROP TABLE repositories;
+ALTER TABLE repositories_new RENAME TO repositories;
+
+CREATE INDEX IF NOT EXISTS idx_repositories_organization_id ON repositories(organization_id);
+CREATE INDEX IF NOT EXISTS idx_repositories_provider_id ON repositories(provider_id);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_apps_repository_id;
+DROP INDEX IF EXISTS idx_apps_organization_id;
+DROP INDEX IF EXISTS idx_apps_user_id;
+DROP TABLE IF EXISTS apps;
@@ -0,0 +1,24 @@
+-- Apps are built from a repository and deployed. Like repositories they
+-- belong to the organization in organization_id when set, and to user_id
+-- otherwise. Slugs are unique across owners as they end up in hostnames.
+CREATE TABLE IF NOT EXISTS apps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    organization_id INTEGER,
+    repository_id INTEGER,
+    branch TEXT,  -- The repository's default branch when NULL
+    root_dir TEXT NOT NULL DEFAULT '',  -- Relative to the repository root
+    build_command TEXT,  -- Detected from the source when NULL
+    start_command TEXT,  -- Detected from the source when NULL
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (organization_id) REFERENCES organizations(id) ON DELETE CASCADE,
+    FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_apps_user_id ON apps(user_id);
+CREATE INDEX IF NOT EXISTS idx_apps_organization_id ON apps(organization_id);
+CREATE INDEX IF NOT EXISTS idx_apps_repository_id ON apps(repository_id);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_deployments_triggered_by;
+DROP INDEX IF EXISTS idx_deployments_app_status;
+DROP TABLE IF EXISTS deployments;
@@ -0,0 +1,22 @@
+-- A deployment builds an app at commit_sha and runs it. The *_at columns
+-- record when it entered each status; see src/deployments.rs for the
+-- transitions allowed between them.
+CREATE TABLE IF NOT EXISTS deployments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id INTEGER NOT NULL,
+    commit_sha TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued' CHECK (status IN
+        ('queued', 'building', 'releasing', 'running', 'failed', 'cancelled', 'superseded')),
+    triggered_by INTEGER,  -- NULL once the user is deleted
+    error TEXT,  -- Why a failed deployment failed
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    building_at TEXT,
+    releasing_at TEXT,
+    running_at TEXT,
+    finished_at TEXT,  -- When it failed, was cancelled or was superseded
+    FOREIGN KEY (app_id) REFERENCES apps(id) ON DELETE CASCADE,
+    FOREIGN KEY (triggered_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_deployments_app_status ON deployments(app_id, status);
+CREATE INDEX IF NOT EXISTS idx_deployments_triggered_by ON deployments(triggered_by);
@@ -0,0 +1,3 @@
+-- Site admins operate the instance itself, e.g. its background jobs. They are
+-- appointed with `paas-api admins add`.
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
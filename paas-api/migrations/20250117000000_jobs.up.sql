@@ -0,0 +1,24 @@
+-- Background jobs. Workers claim the pending job with the highest priority
+-- whose run_at has passed, and hold it until locked_until. A job whose worker
+-- dies is claimed again once that visibility timeout expires. Failed jobs are
+-- retried with exponential backoff until max_attempts, then left dead.
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,  -- Names the handler, e.g. 'repository.sync'
+    payload TEXT NOT NULL DEFAULT '{}',  -- JSON
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN
+        ('pending', 'running', 'succeeded', 'dead')),
+    priority INTEGER NOT NULL DEFAULT 0,  -- Higher runs first
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+    run_at TEXT NOT NULL DEFAULT (datetime('now')),  -- Not before
+    locked_by TEXT,  -- The worker running the job
+    locked_until TEXT,
+    last_error TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    finished_at TEXT  -- When it succeeded or died
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_ready ON jobs(status, priority DESC, run_at);
+CREATE INDEX IF NOT EXISTS idx_jobs_kind ON jobs(kind);
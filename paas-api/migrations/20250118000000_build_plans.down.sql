@@ -0,0 +1,2 @@
+ALTER TABLE apps DROP COLUMN runtime_version;
+ALTER TABLE deployments DROP COLUMN build_plan;
@@ -0,0 +1,7 @@
+-- How a deployment is built and started, as JSON detected from its source;
+-- see src/buildpacks.rs. NULL until the build has looked at the source.
+ALTER TABLE deployments ADD COLUMN build_plan TEXT;
+
+-- Language version overriding the one asked for by the source, like the
+-- build and start commands.
+ALTER TABLE apps ADD COLUMN runtime_version TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS deployment_logs;
+ALTER TABLE deployments DROP COLUMN image;
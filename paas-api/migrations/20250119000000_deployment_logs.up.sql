@@ -0,0 +1,14 @@
+-- The image a deployment was built into, e.g. paas/shop:<commit_sha>.
+-- NULL until its build succeeds.
+ALTER TABLE deployments ADD COLUMN image TEXT;
+
+-- Output of a deployment's build, one row per line in the order written.
+CREATE TABLE IF NOT EXISTS deployment_logs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    deployment_id INTEGER NOT NULL,
+    line TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (deployment_id) REFERENCES deployments(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_deployment_logs_deployment ON deployment_logs(deployment_id, id);
@@ -0,0 +1,3 @@
+-- Whether the provider the user last signed in with vouched for their email
+-- address. Only verified addresses accept invitations sent by email.
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,90 @@
+use crate::error::AppError;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+/// Prefix of every personal access token, so leaked tokens are easy to spot.
+pub const TOKEN_PREFIX: &str = "paas_";
+
+const TOKEN_LEN: usize = 40;
+/// Characters of the token kept in `api_tokens.token_prefix`.
+const DISPLAY_PREFIX_LEN: usize = TOKEN_PREFIX.len() + 6;
+
+pub const DEFAULT_EXPIRY_DAYS: i64 = 30;
+pub const MAX_EXPIRY_DAYS: i64 = 365;
+
+/// What a personal access token may do. `Write` implies `Read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read-only requests (GET and HEAD).
+    Read,
+    /// Requests that change something.
+    Write,
+}
+
+impl Scope {
+    /// Whether a token granted `self` may act with `required`.
+    pub fn grants(&self, required: Scope) -> bool {
+        *self == required || *self == Scope::Write
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Write => write!(f, "write"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            _ => Err(AppError::ValidationError(format!("Unknown scope: {}", s))),
+        }
+    }
+}
+
+/// Parses the space-separated scopes stored with a token, skipping unknown ones.
+pub fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes
+        .split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Serializes a stored scope list as an array.
+pub fn serialize_scopes<S: Serializer>(scopes: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(parse_scopes(scopes))
+}
+
+/// Generates a new token, returning it with the prefix shown when listing tokens.
+pub fn generate() -> (String, String) {
+    let token: String = TOKEN_PREFIX
+        .chars()
+        .chain((0..TOKEN_LEN).map(|_| OsRng.sample(Alphanumeric) as char))
+        .collect();
+    let display_prefix = token[..DISPLAY_PREFIX_LEN].to_string();
+    (token, display_prefix)
+}
+
+/// Only a hash of each token is stored, so a copy of the database cannot be
+/// used to call the API.
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,144 @@
+use crate::{error::AppError, models::AppSettings};
+
+/// Slugs end up as hostnames, so they must be valid DNS labels.
+const MAX_SLUG_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 100;
+const MAX_BRANCH_LEN: usize = 255;
+const MAX_COMMAND_LEN: usize = 1000;
+const MAX_RUNTIME_VERSION_LEN: usize = 50;
+
+/// Slugs that would collide with other `/apps/...` pages of the UI.
+const RESERVED_SLUGS: &[&str] = &["new"];
+
+pub fn validate_slug(slug: &str) -> Result<(), AppError> {
+    let valid = !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LEN
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(AppError::ValidationError(format!(
+            "Slug must be 1 to {} lowercase letters, digits and dashes",
+            MAX_SLUG_LEN
+        )));
+    }
+    if RESERVED_SLUGS.contains(&slug) {
+        return Err(AppError::ValidationError(format!(
+            "Slug '{}' is reserved",
+            slug
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(AppError::ValidationError(format!(
+            "App name must be between 1 and {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a branch name against the rules of `git check-ref-format`. Names
+/// starting with a dash are refused too, as git would take them for options.
+pub fn validate_branch(branch: &str) -> Result<(), AppError> {
+    let valid = !branch.is_empty()
+        && branch.len() <= MAX_BRANCH_LEN
+        && !branch.starts_with(['-', '/', '.'])
+        && !branch.ends_with(['/', '.'])
+        && !branch.ends_with(".lock")
+        && !branch.contains("..")
+        && !branch.contains("//")
+        && !branch.contains("@{")
+        && !branch.contains("/.")
+        && branch != "@"
+        && !branch
+            .chars()
+            .any(|c| c.is_ascii_control() || c.is_whitespace() || "~^:?*[\\".contains(c));
+    if !valid {
+        return Err(AppError::ValidationError(format!(
+            "Invalid branch name: {}",
+            branch
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a language version or version requirement, e.g. "20", "3.12.1"
+/// or ">=3.10, <4".
+pub fn validate_runtime_version(version: &str) -> Result<(), AppError> {
+    let valid = !version.is_empty()
+        && version.len() <= MAX_RUNTIME_VERSION_LEN
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_+<>=~^*, ".contains(c));
+    if !valid {
+        return Err(AppError::ValidationError(format!(
+            "Invalid runtime version: {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+/// Normalizes a directory inside the repository to a relative path without
+/// surrounding slashes, e.g. "./services/api/" -> "services/api". The
+/// repository root is "".
+pub fn normalize_root_dir(root_dir: &str) -> Result<String, AppError> {
+    let invalid = || AppError::ValidationError(format!("Invalid root directory: {}", root_dir));
+    if root_dir.starts_with('/') || root_dir.contains('\\') {
+        return Err(invalid());
+    }
+
+    let mut components = Vec::new();
+    for component in root_dir.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(invalid()),
+            component if component.chars().any(|c| c.is_ascii_control()) => return Err(invalid()),
+            component => components.push(component),
+        }
+    }
+    Ok(components.join("/"))
+}
+
+/// Trims the settings, turning blank optional ones into None, and validates
+/// them.
+pub fn normalize_settings(settings: AppSettings) -> Result<AppSettings, AppError> {
+    let blank_to_none = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let settings = AppSettings {
+        repository_id: settings.repository_id,
+        branch: blank_to_none(settings.branch),
+        root_dir: normalize_root_dir(settings.root_dir.trim())?,
+        build_command: blank_to_none(settings.build_command),
+        start_command: blank_to_none(settings.start_command),
+        runtime_version: blank_to_none(settings.runtime_version),
+    };
+
+    if let Some(branch) = &settings.branch {
+        validate_branch(branch)?;
+    }
+    if let Some(version) = &settings.runtime_version {
+        validate_runtime_version(version)?;
+    }
+    for command in [&settings.build_command, &settings.start_command]
+        .into_iter()
+        .flatten()
+    {
+        if command.len() > MAX_COMMAND_LEN {
+            return Err(AppError::ValidationError(format!(
+                "Commands must be at most {} characters",
+                MAX_COMMAND_LEN
+            )));
+        }
+    }
+    Ok(settings)
+}
@@ -0,0 +1,144 @@
+use crate::{
+    error::AppError,
+    models::{AuditEvent, User},
+    orgs::Owner,
+};
+use actix_web::{dev::Payload, http::header, FromRequest, HttpRequest};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::future::{ready, Ready};
+
+/// Default and maximum page sizes of `GET /api/audit`.
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Where a request came from. The IP is the peer address of the connection;
+/// `X-Forwarded-For` is ignored, as anyone can set it.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestContext {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect());
+        Self {
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+            user_agent,
+        }
+    }
+}
+
+impl FromRequest for RequestContext {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(RequestContext::from_request(req)))
+    }
+}
+
+/// An audit event about to be recorded, e.g.
+///
+/// ```ignore
+/// Event::new("org.member.remove")
+///     .actor(&caller.user)
+///     .organization(organization.id)
+///     .target("user", user_id)
+///     .record(pool, &context)
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub action: &'static str,
+    pub actor_id: Option<i64>,
+    pub actor: Option<String>,
+    pub organization_id: Option<i64>,
+    pub target_type: Option<&'static str>,
+    pub target_id: Option<String>,
+    pub metadata: Value,
+}
+
+impl Event {
+    pub fn new(action: &'static str) -> Self {
+        Self {
+            action,
+            actor_id: None,
+            actor: None,
+            organization_id: None,
+            target_type: None,
+            target_id: None,
+            metadata: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn actor(mut self, user: &User) -> Self {
+        self.actor_id = Some(user.id);
+        self.actor = Some(user.username.clone());
+        self
+    }
+
+    pub fn organization(mut self, organization_id: i64) -> Self {
+        self.organization_id = Some(organization_id);
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: impl ToString) -> Self {
+        self.target_type = Some(target_type);
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    /// Details of the event, a JSON object.
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub async fn record(self, pool: &SqlitePool, context: &RequestContext) -> Result<(), AppError> {
+        AuditEvent::create(pool, &self, context).await?;
+        Ok(())
+    }
+}
+
+/// Which events to list. Events of a user are those they took; events of an
+/// organization are those taken on it.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub scope: Owner,
+    pub actor: Option<String>,
+    /// An action, or a prefix of actions: `org.member` matches
+    /// `org.member.update` and `org.member.remove`.
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// Inclusive.
+    pub since: Option<String>,
+    /// Exclusive.
+    pub until: Option<String>,
+}
+
+/// Parses `since` and `until` bounds given as dates, RFC 3339 timestamps or
+/// timestamps as stored, into the stored UTC format.
+pub fn parse_timestamp(value: &str) -> Result<String, AppError> {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+    let value = value.trim();
+    let timestamp = if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0)
+    } else if let Ok(timestamp) = chrono::DateTime::parse_from_rfc3339(value) {
+        Some(timestamp.naive_utc())
+    } else {
+        chrono::NaiveDateTime::parse_from_str(value, FORMAT).ok()
+    };
+    timestamp
+        .map(|timestamp| timestamp.format(FORMAT).to_string())
+        .ok_or_else(|| AppError::ValidationError(format!("Invalid timestamp: {}", value)))
+}
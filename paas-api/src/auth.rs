@@ -1,23 +1,38 @@
-use crate::error::AppError;
+use crate::{
+    api_tokens::{self, Scope},
+    config::JwtConfig,
+    crypto::Keyring,
+    error::AppError,
+    jwt::{self, Claims},
+    models::{ApiToken, OAuthState, User},
+};
 use actix_session::{Session, SessionExt};
-use actix_web::{dev::ServiceRequest, Error};
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, Method},
+    middleware::Next,
+    web, Error, FromRequest, HttpMessage, HttpRequest,
+};
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{future::Future, pin::Pin};
 
 pub const USER_ID_KEY: &str = "user_id";
-pub const ACCESS_TOKEN_KEY: &str = "access_token";
-pub const REFRESH_TOKEN_KEY: &str = "refresh_token";
+pub const OAUTH_STATE_KEY: &str = "oauth_state";
+/// Families of the refresh tokens issued to the session, revoked on logout.
+pub const REFRESH_FAMILIES_KEY: &str = "refresh_families";
 
+/// The signed-in user as recorded in the session. Provider tokens stay in the
+/// database; use `TokenService::access_token` to obtain one.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SessionUser {
     pub id: i64,
     pub username: String,
     pub email: Option<String>,
     pub provider: String,
-    pub access_token: String,
-    pub refresh_token: Option<String>,
 }
 
-#[allow(dead_code)]
 pub async fn get_session_user(session: &Session) -> Result<Option<SessionUser>, AppError> {
     if let Some(user_id) = session.get::<i64>(USER_ID_KEY)? {
         Ok(Some(SessionUser {
@@ -25,43 +40,311 @@ pub async fn get_session_user(session: &Session) -> Result<Option<SessionUser>,
             username: session.get("username")?.unwrap_or_default(),
             email: session.get("email")?,
             provider: session.get("provider")?.unwrap_or_default(),
-            access_token: session.get(ACCESS_TOKEN_KEY)?.unwrap_or_default(),
-            refresh_token: session.get(REFRESH_TOKEN_KEY)?,
         }))
     } else {
         Ok(None)
     }
 }
 
+/// The signed-in user, or `AppError::AuthError` if there is none.
+pub async fn require_session_user(session: &Session) -> Result<SessionUser, AppError> {
+    get_session_user(session)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Not authenticated".to_string()))
+}
+
+/// Signs the user in. The session key is renewed so a key obtained before
+/// sign-in cannot be used afterwards.
 pub fn set_session_user(session: &Session, user: SessionUser) -> Result<(), AppError> {
+    session.renew();
     session.insert(USER_ID_KEY, user.id)?;
     session.insert("username", user.username)?;
     session.insert("provider", user.provider)?;
-    session.insert(ACCESS_TOKEN_KEY, user.access_token)?;
 
     if let Some(email) = user.email {
         session.insert("email", email)?;
     }
-    if let Some(refresh_token) = user.refresh_token {
-        session.insert(REFRESH_TOKEN_KEY, refresh_token)?;
-    }
 
     Ok(())
 }
 
-#[allow(dead_code)]
-pub async fn validate_auth(req: ServiceRequest) -> Result<ServiceRequest, Error> {
-    let session = req.get_session();
+/// Records that the refresh token family `family_id` was issued to the
+/// session, so logging out can revoke it.
+pub fn add_refresh_family(session: &Session, family_id: &str) -> Result<(), AppError> {
+    let mut families = refresh_families(session)?;
+    families.push(family_id.to_string());
+    session.insert(REFRESH_FAMILIES_KEY, families)?;
+    Ok(())
+}
+
+/// The refresh token families issued to the session.
+pub fn refresh_families(session: &Session) -> Result<Vec<String>, AppError> {
+    Ok(session
+        .get::<Vec<String>>(REFRESH_FAMILIES_KEY)?
+        .unwrap_or_default())
+}
+
+/// How a request was authenticated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    Session,
+    ApiToken {
+        id: i64,
+        scopes: Vec<Scope>,
+    },
+    /// An access token issued by `/api/auth/token`.
+    Jwt {
+        scopes: Vec<Scope>,
+    },
+}
+
+/// The id of the user a request acts for and how it proved it.
+#[derive(Debug, Clone)]
+pub struct Authenticated {
+    pub user_id: i64,
+    pub credentials: Credentials,
+}
+
+/// The user a request acts for: the bearer of the personal access token or
+/// JWT in the `Authorization: Bearer` header or, without that header, the
+/// signed-in user of the session. The user is loaded from the database, so an
+/// account deleted since the session began is no longer let in.
+///
+/// Bearer tokens only pass for read-only requests (GET and HEAD) if they have
+/// the `read` scope, and for all others if they have the `write` scope.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub credentials: Credentials,
+}
+
+impl AuthenticatedUser {
+    pub fn id(&self) -> i64 {
+        self.user.id
+    }
+
+    /// Rejects requests made with a bearer token, for actions that only a
+    /// signed-in user may take.
+    pub fn require_session(&self) -> Result<(), AppError> {
+        match self.credentials {
+            Credentials::Session => Ok(()),
+            Credentials::ApiToken { .. } | Credentials::Jwt { .. } => Err(AppError::AuthError(
+                "This action requires signing in with a browser session".to_string(),
+            )),
+        }
+    }
+
+    /// Rejects callers who are not site admins.
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if !self.user.is_admin {
+            return Err(AppError::Forbidden(
+                "This action requires a site admin".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn load(req: &HttpRequest) -> Result<Self, AppError> {
+        let required = match *req.method() {
+            Method::GET | Method::HEAD => Scope::Read,
+            _ => Scope::Write,
+        };
+        let caller = authenticate(req, Some(required)).await?;
+        let user = User::find(pool(req)?, keyring(req)?, caller.user_id)
+            .await?
+            .ok_or_else(|| AppError::AuthError("Not authenticated".to_string()))?;
+        Ok(Self {
+            user,
+            credentials: caller.credentials,
+        })
+    }
+}
+
+/// Takes the user found by `require_user`, and authenticates the request
+/// itself on routes outside the scope that middleware guards.
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let found = req.extensions().get::<AuthenticatedUser>().cloned();
+            match found {
+                Some(user) => Ok(user),
+                None => AuthenticatedUser::load(&req).await,
+            }
+        })
+    }
+}
+
+/// Middleware turning away requests without a signed-in user (or a valid
+/// bearer token) with the usual `AppError::AuthError` response. The user is
+/// left in the request extensions for the `AuthenticatedUser` extractor.
+pub async fn require_user(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse, Error> {
+    match AuthenticatedUser::load(req.request()).await {
+        Ok(user) => {
+            req.extensions_mut().insert(user);
+        }
+        Err(e) => return Ok(req.error_response(e).map_into_boxed_body()),
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// Authenticates a request like the `AuthenticatedUser` extractor, but
+/// accepts bearer tokens whatever their scopes and does not load the user.
+pub async fn identify(req: &HttpRequest) -> Result<Authenticated, AppError> {
+    authenticate(req, None).await
+}
+
+fn pool(req: &HttpRequest) -> Result<&SqlitePool, AppError> {
+    req.app_data::<web::Data<SqlitePool>>()
+        .map(|pool| pool.get_ref())
+        .ok_or_else(|| AppError::ConfigError("Database pool is not configured".to_string()))
+}
+
+fn keyring(req: &HttpRequest) -> Result<&Keyring, AppError> {
+    req.app_data::<web::Data<Keyring>>()
+        .map(|keyring| keyring.get_ref())
+        .ok_or_else(|| {
+            AppError::ConfigError("Token encryption keys are not configured".to_string())
+        })
+}
+
+async fn authenticate(
+    req: &HttpRequest,
+    required: Option<Scope>,
+) -> Result<Authenticated, AppError> {
+    let Some(authorization) = req.headers().get(header::AUTHORIZATION) else {
+        let user = require_session_user(&req.get_session()).await?;
+        return Ok(Authenticated {
+            user_id: user.id,
+            credentials: Credentials::Session,
+        });
+    };
 
-    if session.get::<i64>(USER_ID_KEY)?.is_none() {
-        return Err(AppError::AuthError("Unauthorized".to_string()).into());
+    let invalid_token = || AppError::AuthError("Invalid API token".to_string());
+    let token = authorization
+        .to_str()
+        .ok()
+        .and_then(jwt::bearer_token)
+        .ok_or_else(invalid_token)?;
+    if jwt::is_jwt(token) {
+        return authenticate_jwt(req, token, required);
     }
+    if !token.starts_with(api_tokens::TOKEN_PREFIX) {
+        return Err(invalid_token());
+    }
+
+    let pool = pool(req)?;
+    let api_token = ApiToken::find_active(pool, &api_tokens::hash(token))
+        .await?
+        .ok_or_else(invalid_token)?;
 
-    Ok(req)
+    let scopes = api_token.scopes();
+    check_scopes(&scopes, required, "API token")?;
+
+    ApiToken::touch(pool, api_token.id).await?;
+    Ok(Authenticated {
+        user_id: api_token.user_id,
+        credentials: Credentials::ApiToken {
+            id: api_token.id,
+            scopes,
+        },
+    })
+}
+
+/// Uses the claims left by `jwt::verify_bearer_jwt`, and verifies the token
+/// itself where that middleware is not installed.
+fn authenticate_jwt(
+    req: &HttpRequest,
+    token: &str,
+    required: Option<Scope>,
+) -> Result<Authenticated, AppError> {
+    let claims = req.extensions().get::<Claims>().cloned();
+    let claims = match claims {
+        Some(claims) => claims,
+        None => {
+            let config = req
+                .app_data::<web::Data<JwtConfig>>()
+                .ok_or_else(|| AppError::AuthError("Access tokens are not accepted".to_string()))?;
+            jwt::verify(config, token)?
+        }
+    };
+
+    let scopes = claims.scopes();
+    check_scopes(&scopes, required, "Access token")?;
+    Ok(Authenticated {
+        user_id: claims.user_id()?,
+        credentials: Credentials::Jwt { scopes },
+    })
+}
+
+fn check_scopes(scopes: &[Scope], required: Option<Scope>, kind: &str) -> Result<(), AppError> {
+    match required {
+        Some(required) if !scopes.iter().any(|scope| scope.grants(required)) => Err(
+            AppError::AuthError(format!("{} is missing the {} scope", kind, required)),
+        ),
+        _ => Ok(()),
+    }
 }
 
-#[allow(dead_code)]
 pub fn clear_session(session: &Session) -> Result<(), AppError> {
     session.purge();
     Ok(())
 }
+
+/// Persists a freshly generated CSRF state (with the PKCE verifier and OpenID
+/// Connect nonce, if any) for the provider instance `provider` and binds it to
+/// the caller's session. `user_id` is set when connecting the provider to a
+/// signed-in user rather than signing in.
+pub async fn store_oauth_state(
+    pool: &SqlitePool,
+    session: &Session,
+    provider: &str,
+    state: &str,
+    pkce_verifier: Option<&str>,
+    nonce: Option<&str>,
+    user_id: Option<i64>,
+) -> Result<(), AppError> {
+    OAuthState::create(pool, provider, state, pkce_verifier, nonce, user_id).await?;
+    session.insert(OAUTH_STATE_KEY, state)?;
+    Ok(())
+}
+
+/// Checks the `state` returned to a callback against the session and the stored
+/// record, returning the record so the caller can finish the PKCE exchange and
+/// check the ID token nonce.
+/// Rejections are reported as `AppError::AuthError`.
+pub async fn verify_oauth_state(
+    pool: &SqlitePool,
+    session: &Session,
+    provider: &str,
+    state: Option<&str>,
+) -> Result<OAuthState, AppError> {
+    let expected = session
+        .remove_as::<String>(OAUTH_STATE_KEY)
+        .and_then(Result::ok);
+
+    let state = state
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| AppError::AuthError("Missing OAuth state".to_string()))?;
+
+    if expected.as_deref() != Some(state) {
+        return Err(AppError::AuthError("OAuth state mismatch".to_string()));
+    }
+
+    let stored = OAuthState::consume(pool, provider, state)
+        .await?
+        .ok_or_else(|| AppError::AuthError("OAuth state already used".to_string()))?;
+
+    if stored.is_expired() {
+        return Err(AppError::AuthError("OAuth state expired".to_string()));
+    }
+
+    Ok(stored)
+}
@@ -0,0 +1,524 @@
+use crate::{
+    buildpacks::{BuildPlan, Language},
+    config::BuildConfig,
+    error::AppError,
+    models::DeploymentLogLine,
+};
+use async_trait::async_trait;
+use derive_more::Display;
+use log::{debug, warn};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sqlx::SqlitePool;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::watch,
+};
+
+pub const DEFAULT_IMAGE_PREFIX: &str = "paas";
+const DEFAULT_TIMEOUT_SECS: u64 = 1800;
+
+/// Port generated images tell their app to listen on, through `PORT`.
+pub const IMAGE_PORT: u16 = 8080;
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum BuildError {
+    #[display(fmt = "{} is not installed", _0)]
+    NotInstalled(String),
+
+    #[display(fmt = "Build timed out after {} seconds", secs)]
+    TimedOut { secs: u64 },
+
+    #[display(fmt = "Build cancelled")]
+    Cancelled,
+
+    #[display(fmt = "Build failed: {}", _0)]
+    Failed(String),
+
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(String),
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> Self {
+        BuildError::Io(err.to_string())
+    }
+}
+
+/// Where a build writes its output, line by line.
+#[async_trait]
+pub trait BuildLog: Send + Sync {
+    async fn line(&self, line: &str);
+}
+
+/// Appends build output to a deployment's log. Lines that cannot be
+/// stored are dropped rather than failing the build.
+pub struct DeploymentLog {
+    pool: SqlitePool,
+    deployment_id: i64,
+}
+
+impl DeploymentLog {
+    pub fn new(pool: SqlitePool, deployment_id: i64) -> Self {
+        Self {
+            pool,
+            deployment_id,
+        }
+    }
+}
+
+#[async_trait]
+impl BuildLog for DeploymentLog {
+    async fn line(&self, line: &str) {
+        if let Err(e) = DeploymentLogLine::append(&self.pool, self.deployment_id, line).await {
+            warn!(
+                "Dropped a log line of deployment {}: {}",
+                self.deployment_id, e
+            );
+        }
+    }
+}
+
+/// Tells a running build to stop, e.g. once its deployment is cancelled.
+/// The default signal never fires.
+#[derive(Debug, Clone)]
+pub struct CancelSignal(watch::Receiver<bool>);
+
+impl CancelSignal {
+    /// A signal, and the sender firing it with `true`.
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self(receiver))
+    }
+
+    /// Resolves once the signal fires, and never if its sender is dropped
+    /// first.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.0.clone();
+        if receiver.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Default for CancelSignal {
+    fn default() -> Self {
+        Self::channel().1
+    }
+}
+
+/// An app's source at a commit, to build into an image.
+#[derive(Debug, Clone)]
+pub struct BuildRequest<'a> {
+    pub app_slug: &'a str,
+    pub commit_sha: &'a str,
+    /// The app's directory in the checkout, sent as the build context.
+    pub context: &'a Path,
+    pub plan: &'a BuildPlan,
+    pub cancel: CancelSignal,
+}
+
+/// Builds images of apps.
+#[async_trait]
+pub trait Builder: Send + Sync {
+    /// Builds the image of `request`, writing the output to `log`, and
+    /// returns the image's name.
+    async fn build(
+        &self,
+        request: &BuildRequest<'_>,
+        log: &dyn BuildLog,
+    ) -> Result<String, BuildError>;
+}
+
+/// The name of an app's image at a commit, e.g. `paas/shop:<commit_sha>`.
+pub fn image_name(prefix: &str, app_slug: &str, commit_sha: &str) -> String {
+    format!(
+        "{}/{}:{}",
+        prefix.trim_end_matches('/'),
+        app_slug,
+        commit_sha
+    )
+}
+
+/// The Dockerfile building an app from its plan, or None for apps with a
+/// Dockerfile of their own. The image is based on the official image of the
+/// language, at the first version number of the plan's runtime version.
+pub fn dockerfile(plan: &BuildPlan) -> Option<String> {
+    let image = match plan.language {
+        Language::Docker => return None,
+        Language::Rust => "rust",
+        Language::Go => "golang",
+        Language::Ruby => "ruby",
+        Language::Python => "python",
+        Language::Node => "node",
+    };
+    let tag = plan
+        .runtime_version
+        .as_deref()
+        .and_then(version_number)
+        .unwrap_or("latest");
+
+    let mut lines = vec![
+        format!("FROM {}:{}", image, tag),
+        "WORKDIR /app".to_string(),
+        "COPY . .".to_string(),
+        format!("ENV PORT={}", IMAGE_PORT),
+    ];
+    let package_managers = ["pnpm ", "yarn "];
+    if plan.build_commands.iter().any(|command| {
+        package_managers
+            .iter()
+            .any(|manager| command.starts_with(manager))
+    }) {
+        lines.push("RUN corepack enable".to_string());
+    }
+    for command in &plan.build_commands {
+        lines.push(format!("RUN {}", shell_form(command)));
+    }
+    lines.push(format!("EXPOSE {}", IMAGE_PORT));
+    if let Some(command) = &plan.start_command {
+        lines.push(format!("CMD {}", shell_form(command)));
+    }
+    Some(lines.join("\n") + "\n")
+}
+
+/// A command run by `sh -c`, in the JSON form Dockerfiles take so it does
+/// not need escaping.
+fn shell_form(command: &str) -> String {
+    serde_json::json!(["/bin/sh", "-c", command]).to_string()
+}
+
+/// The leading version number of a requirement like `>=20` or `~3.11.4`.
+fn version_number(requirement: &str) -> Option<&str> {
+    let start = requirement.find(|c: char| c.is_ascii_digit())?;
+    let version = &requirement[start..];
+    let end = version
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(version.len());
+    Some(version[..end].trim_end_matches('.'))
+}
+
+/// The OCI tools `OciBuilder` can drive. Their `build` commands take the
+/// same arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OciTool {
+    Docker,
+    Podman,
+    Buildah,
+}
+
+impl OciTool {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OciTool::Docker => "docker",
+            OciTool::Podman => "podman",
+            OciTool::Buildah => "buildah",
+        }
+    }
+}
+
+impl fmt::Display for OciTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OciTool {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [OciTool::Docker, OciTool::Podman, OciTool::Buildah]
+            .into_iter()
+            .find(|tool| tool.as_str() == s)
+            .ok_or_else(|| {
+                AppError::ConfigError(format!(
+                    "Unknown builder {:?}; expected docker, podman or buildah",
+                    s
+                ))
+            })
+    }
+}
+
+/// Builds images with the local docker, podman or buildah, from the app's
+/// Dockerfile or one generated from its build plan.
+pub struct OciBuilder {
+    tool: OciTool,
+    program: PathBuf,
+    image_prefix: String,
+    timeout: Duration,
+}
+
+impl OciBuilder {
+    pub fn new(tool: OciTool) -> Self {
+        Self {
+            tool,
+            program: PathBuf::from(tool.as_str()),
+            image_prefix: DEFAULT_IMAGE_PREFIX.to_string(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+
+    pub fn from_config(config: &BuildConfig) -> Self {
+        let timeout =
+            Duration::try_from(config.timeout).unwrap_or(Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+        Self::new(config.tool)
+            .image_prefix(&config.image_prefix)
+            .timeout(timeout)
+    }
+
+    /// Runs another executable than the tool's own, e.g. a wrapper script.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Repository the images are tagged in, e.g. `registry.local/paas`.
+    pub fn image_prefix(mut self, prefix: &str) -> Self {
+        self.image_prefix = prefix.to_string();
+        self
+    }
+
+    /// How long a build may run before it is killed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The arguments building `request` into `image` from `dockerfile`.
+    pub fn args(&self, request: &BuildRequest<'_>, dockerfile: &Path, image: &str) -> Vec<String> {
+        let mut args = vec!["build".to_string()];
+        if self.tool == OciTool::Docker {
+            args.push("--progress=plain".to_string());
+        }
+        args.extend([
+            "--file".to_string(),
+            dockerfile.display().to_string(),
+            "--tag".to_string(),
+            image.to_string(),
+            "--label".to_string(),
+            format!("paas.app={}", request.app_slug),
+            "--label".to_string(),
+            format!("paas.commit={}", request.commit_sha),
+            request.context.display().to_string(),
+        ]);
+        args
+    }
+
+    /// Runs the tool, sending its stdout and stderr to `log` as they come.
+    /// The tool is killed once the build times out or `cancel` fires.
+    async fn run(
+        &self,
+        args: &[String],
+        cancel: &CancelSignal,
+        log: &dyn BuildLog,
+    ) -> Result<(), BuildError> {
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => {
+                    BuildError::NotInstalled(self.program.display().to_string())
+                }
+                _ => e.into(),
+            })?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let output = async {
+            tokio::join!(stream(stdout, log), stream(stderr, log));
+            child.wait().await
+        };
+        let outcome = tokio::select! {
+            status = tokio::time::timeout(self.timeout, output) => {
+                status.map_err(|_| BuildError::TimedOut {
+                    secs: self.timeout.as_secs(),
+                })
+            }
+            () = cancel.cancelled() => Err(BuildError::Cancelled),
+        };
+        let status: ExitStatus = match outcome {
+            Ok(status) => status?,
+            Err(e) => {
+                child.kill().await?;
+                return Err(e);
+            }
+        };
+        if !status.success() {
+            return Err(BuildError::Failed(format!("{} {}", self.tool, status)));
+        }
+        Ok(())
+    }
+}
+
+/// Sends the lines of `output` to `log`. Invalid UTF-8 is replaced rather
+/// than ending the stream.
+async fn stream(output: Option<impl AsyncRead + Unpin>, log: &dyn BuildLog) {
+    let Some(output) = output else { return };
+    let mut lines = BufReader::new(output).split(b'\n');
+    while let Ok(Some(line)) = lines.next_segment().await {
+        let line = String::from_utf8_lossy(&line);
+        log.line(line.trim_end_matches('\r')).await;
+    }
+}
+
+/// Removes a generated Dockerfile once the build is over.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Creates the file a Dockerfile is generated in. Its name is unpredictable
+/// and it must not exist yet, so another user of the temp directory cannot
+/// make the server write through a link they planted there, and only the
+/// server can read it.
+async fn create_generated(
+    request: &BuildRequest<'_>,
+) -> Result<(PathBuf, tokio::fs::File), BuildError> {
+    let suffix: String = OsRng
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    let path = std::env::temp_dir().join(format!(
+        "paas-{}-{}-{}.Dockerfile",
+        request.app_slug, request.commit_sha, suffix
+    ));
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .await?;
+    Ok((path, file))
+}
+
+#[async_trait]
+impl Builder for OciBuilder {
+    async fn build(
+        &self,
+        request: &BuildRequest<'_>,
+        log: &dyn BuildLog,
+    ) -> Result<String, BuildError> {
+        let image = image_name(&self.image_prefix, request.app_slug, request.commit_sha);
+
+        // Generated Dockerfiles stay out of the build context, so the app's
+        // `COPY . .` does not pick them up.
+        let _generated;
+        let dockerfile = match dockerfile(request.plan) {
+            None => request.context.join("Dockerfile"),
+            Some(contents) => {
+                let (path, mut file) = create_generated(request).await?;
+                _generated = TempFile(path.clone());
+                file.write_all(contents.as_bytes()).await?;
+                file.flush().await?;
+                path
+            }
+        };
+
+        log.line(&format!("Building {} with {}", image, self.tool))
+            .await;
+        self.run(
+            &self.args(request, &dockerfile, &image),
+            &request.cancel,
+            log,
+        )
+        .await?;
+        debug!("Built {}", image);
+        Ok(image)
+    }
+}
+
+/// A build `FakeBuilder` was asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeBuild {
+    pub app_slug: String,
+    pub commit_sha: String,
+    pub context: PathBuf,
+    pub plan: BuildPlan,
+    pub image: String,
+}
+
+/// Pretends to build images, recording the builds it is asked for, so the
+/// deployment pipeline can run without a container runtime.
+#[derive(Default)]
+pub struct FakeBuilder {
+    builds: Mutex<Vec<FakeBuild>>,
+    error: Option<String>,
+    hang: bool,
+}
+
+impl FakeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails every build with `error`.
+    pub fn failing(error: &str) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// Builds until cancelled, like a build that never finishes.
+    pub fn hanging() -> Self {
+        Self {
+            hang: true,
+            ..Self::default()
+        }
+    }
+
+    /// The builds so far, oldest first.
+    pub fn builds(&self) -> Vec<FakeBuild> {
+        self.builds.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Builder for FakeBuilder {
+    async fn build(
+        &self,
+        request: &BuildRequest<'_>,
+        log: &dyn BuildLog,
+    ) -> Result<String, BuildError> {
+        let image = image_name(DEFAULT_IMAGE_PREFIX, request.app_slug, request.commit_sha);
+        log.line(&format!("Building {}", image)).await;
+        for command in &request.plan.build_commands {
+            log.line(&format!("$ {}", command)).await;
+        }
+        self.builds.lock().unwrap().push(FakeBuild {
+            app_slug: request.app_slug.to_string(),
+            commit_sha: request.commit_sha.to_string(),
+            context: request.context.to_path_buf(),
+            plan: request.plan.clone(),
+            image: image.clone(),
+        });
+
+        if let Some(error) = &self.error {
+            log.line(error).await;
+            return Err(BuildError::Failed(error.clone()));
+        }
+        if self.hang {
+            request.cancel.cancelled().await;
+            return Err(BuildError::Cancelled);
+        }
+        Ok(image)
+    }
+}
@@ -0,0 +1,451 @@
+use crate::{error::AppError, models::AppSettings};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Files larger than this are refused rather than read.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// The languages apps are built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    /// Built from the Dockerfile, which decides everything else.
+    Docker,
+    Rust,
+    Go,
+    Ruby,
+    Python,
+    Node,
+}
+
+impl Language {
+    /// In the order they are looked for: a Dockerfile wins over everything,
+    /// and Ruby and Python apps often have a package.json for their assets.
+    pub const ALL: [Language; 6] = [
+        Language::Docker,
+        Language::Rust,
+        Language::Go,
+        Language::Ruby,
+        Language::Python,
+        Language::Node,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::Docker => "docker",
+            Language::Rust => "rust",
+            Language::Go => "go",
+            Language::Ruby => "ruby",
+            Language::Python => "python",
+            Language::Node => "node",
+        }
+    }
+
+    /// Files marking a source tree as written in the language.
+    pub fn manifests(&self) -> &'static [&'static str] {
+        match self {
+            Language::Docker => &["Dockerfile"],
+            Language::Rust => &["Cargo.toml"],
+            Language::Go => &["go.mod"],
+            Language::Ruby => &["Gemfile"],
+            Language::Python => &["requirements.txt", "pyproject.toml"],
+            Language::Node => &["package.json"],
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How to build and start an app.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildPlan {
+    pub language: Language,
+    /// Version of the language to build with, as asked for by the source
+    /// (e.g. "20" or ">=3.10") or set on the app.
+    pub runtime_version: Option<String>,
+    /// Run in order in the app's directory.
+    pub build_commands: Vec<String>,
+    /// Starts the app listening on `$PORT`. None for Docker builds, whose
+    /// image says how it starts.
+    pub start_command: Option<String>,
+}
+
+impl BuildPlan {
+    fn new(language: Language) -> Self {
+        Self {
+            language,
+            runtime_version: None,
+            build_commands: Vec::new(),
+            start_command: None,
+        }
+    }
+
+    /// Replaces what was detected with what is set on the app.
+    pub fn with_settings(mut self, settings: &AppSettings) -> Self {
+        if let Some(build_command) = &settings.build_command {
+            self.build_commands = vec![build_command.clone()];
+        }
+        if let Some(start_command) = &settings.start_command {
+            self.start_command = Some(start_command.clone());
+        }
+        if let Some(runtime_version) = &settings.runtime_version {
+            self.runtime_version = Some(runtime_version.clone());
+        }
+        self
+    }
+}
+
+/// The plan of the app in `settings.root_dir` of a checkout, with the app's
+/// settings applied. Fails unless the plan says how to start the app.
+pub fn plan(checkout: &Path, settings: &AppSettings) -> Result<BuildPlan, AppError> {
+    let plan = detect(&app_dir(checkout, settings)?)?.with_settings(settings);
+    if plan.start_command.is_none() && plan.language != Language::Docker {
+        return Err(AppError::ValidationError(format!(
+            "Could not detect how to start this {} app; set a start command or add a Procfile",
+            plan.language
+        )));
+    }
+    Ok(plan)
+}
+
+/// The directory of the app in a checkout, `settings.root_dir` resolved.
+pub fn app_dir(checkout: &Path, settings: &AppSettings) -> Result<PathBuf, AppError> {
+    let not_found = || {
+        AppError::ValidationError(format!(
+            "Root directory '{}' not found in the repository",
+            settings.root_dir
+        ))
+    };
+    // A symlink could point the root directory outside of the checkout.
+    let dir = checkout
+        .join(&settings.root_dir)
+        .canonicalize()
+        .map_err(|_| not_found())?;
+    if !dir.is_dir() || !dir.starts_with(checkout.canonicalize().map_err(|_| not_found())?) {
+        return Err(not_found());
+    }
+    Ok(dir)
+}
+
+/// Detects how to build the source in `dir`. The `web` process of a
+/// Procfile overrides the start command of any language.
+pub fn detect(dir: &Path) -> Result<BuildPlan, AppError> {
+    let source = Source { dir };
+    let language = Language::ALL
+        .into_iter()
+        .find(|language| language.manifests().iter().any(|file| source.has(file)))
+        .ok_or_else(|| {
+            let manifests: Vec<&str> = Language::ALL
+                .iter()
+                .flat_map(|language| language.manifests())
+                .copied()
+                .collect();
+            AppError::ValidationError(format!(
+                "Could not detect how to build the app; add one of {}",
+                manifests.join(", ")
+            ))
+        })?;
+
+    let mut plan = match language {
+        Language::Docker => BuildPlan::new(Language::Docker),
+        Language::Rust => rust(&source)?,
+        Language::Go => go(&source)?,
+        Language::Ruby => ruby(&source)?,
+        Language::Python => python(&source)?,
+        Language::Node => node(&source)?,
+    };
+    if let Some(web) = procfile_web(&source)? {
+        plan.start_command = Some(web);
+    }
+    Ok(plan)
+}
+
+fn rust(source: &Source) -> Result<BuildPlan, AppError> {
+    let manifest = source
+        .toml("Cargo.toml")?
+        .unwrap_or(toml::Value::Boolean(false));
+    let package = manifest.get("package");
+    let package_str = |key: &str| {
+        package
+            .and_then(|package| package.get(key))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+    };
+
+    let toolchain = match source.read("rust-toolchain.toml")? {
+        Some(toolchain) => Some(toolchain),
+        None => source.read("rust-toolchain")?,
+    };
+    // rust-toolchain files hold either TOML or just the channel.
+    let channel = toolchain.and_then(|toolchain| match toolchain.parse::<toml::Value>() {
+        Ok(toolchain) => toolchain
+            .get("toolchain")
+            .and_then(|toolchain| toolchain.get("channel"))
+            .and_then(|channel| channel.as_str())
+            .map(|channel| channel.to_string()),
+        Err(_) => first_line(&toolchain),
+    });
+
+    let binary = manifest
+        .get("bin")
+        .and_then(|bins| bins.as_array())
+        .and_then(|bins| bins.first())
+        .and_then(|bin| bin.get("name"))
+        .and_then(|name| name.as_str())
+        .map(|name| name.to_string())
+        .or_else(|| package_str("name"));
+
+    let mut plan = BuildPlan::new(Language::Rust);
+    plan.runtime_version = channel.or_else(|| package_str("rust-version"));
+    plan.build_commands = vec![if source.has("Cargo.lock") {
+        "cargo build --release --locked".to_string()
+    } else {
+        "cargo build --release".to_string()
+    }];
+    plan.start_command = binary.map(|binary| format!("./target/release/{}", binary));
+    Ok(plan)
+}
+
+fn go(source: &Source) -> Result<BuildPlan, AppError> {
+    let go_mod = source.read("go.mod")?.unwrap_or_default();
+    let directive = |name: &str| {
+        go_mod.lines().find_map(|line| {
+            let (directive, value) = line.trim().split_once(char::is_whitespace)?;
+            (directive == name).then(|| value.trim().to_string())
+        })
+    };
+
+    // The main package is at the root, or alone under cmd/.
+    let package = match source.subdirs("cmd")?.as_slice() {
+        [command] if !source.has_files_ending(".go")? => format!("./cmd/{}", command),
+        _ => ".".to_string(),
+    };
+
+    let mut plan = BuildPlan::new(Language::Go);
+    plan.runtime_version = directive("toolchain")
+        .map(|toolchain| toolchain.trim_start_matches("go").to_string())
+        .or_else(|| directive("go"));
+    plan.build_commands = vec![format!("go build -o bin/app {}", package)];
+    plan.start_command = Some("./bin/app".to_string());
+    Ok(plan)
+}
+
+fn ruby(source: &Source) -> Result<BuildPlan, AppError> {
+    let gemfile = source.read("Gemfile")?.unwrap_or_default();
+    // e.g. `ruby "3.2.2"` or `ruby '~> 3.2'`
+    let gemfile_ruby = gemfile.lines().find_map(|line| {
+        let version = line.trim().strip_prefix("ruby ")?;
+        Some(version.trim().trim_matches(['"', '\'']).to_string())
+    });
+
+    let mut plan = BuildPlan::new(Language::Ruby);
+    plan.runtime_version = source
+        .version_file(".ruby-version")?
+        .map(|version| version.trim_start_matches("ruby-").to_string())
+        .or(gemfile_ruby);
+    plan.build_commands = vec!["bundle install".to_string()];
+    plan.start_command = if source.has("bin/rails") {
+        Some("bin/rails server -b 0.0.0.0 -p $PORT".to_string())
+    } else if source.has("config.ru") {
+        Some("bundle exec rackup -o 0.0.0.0 -p $PORT".to_string())
+    } else {
+        None
+    };
+    Ok(plan)
+}
+
+fn python(source: &Source) -> Result<BuildPlan, AppError> {
+    let requires_python = || -> Result<Option<String>, AppError> {
+        Ok(source.toml("pyproject.toml")?.and_then(|pyproject| {
+            pyproject
+                .get("project")?
+                .get("requires-python")?
+                .as_str()
+                .map(|version| version.to_string())
+        }))
+    };
+
+    let mut plan = BuildPlan::new(Language::Python);
+    plan.runtime_version = match source.version_file(".python-version")? {
+        Some(version) => Some(version),
+        None => match source.version_file("runtime.txt")? {
+            Some(runtime) => Some(runtime.trim_start_matches("python-").to_string()),
+            None => requires_python()?,
+        },
+    };
+    plan.build_commands = vec![if source.has("requirements.txt") {
+        "pip install -r requirements.txt".to_string()
+    } else {
+        "pip install .".to_string()
+    }];
+    plan.start_command = ["main.py", "app.py"]
+        .into_iter()
+        .find(|script| source.has(script))
+        .map(|script| format!("python {}", script));
+    Ok(plan)
+}
+
+fn node(source: &Source) -> Result<BuildPlan, AppError> {
+    let package = source.json("package.json")?.unwrap_or_default();
+    let script = |name: &str| package["scripts"][name].is_string();
+
+    // `packageManager` is e.g. "pnpm@8.15.0".
+    let manager = match package["packageManager"].as_str() {
+        Some(manager) if manager.starts_with("pnpm@") => "pnpm",
+        Some(manager) if manager.starts_with("yarn@") => "yarn",
+        Some(_) => "npm",
+        None if source.has("pnpm-lock.yaml") => "pnpm",
+        None if source.has("yarn.lock") => "yarn",
+        None => "npm",
+    };
+    let install = match manager {
+        "pnpm" if source.has("pnpm-lock.yaml") => "pnpm install --frozen-lockfile",
+        "pnpm" => "pnpm install",
+        "yarn" if source.has("yarn.lock") => "yarn install --frozen-lockfile",
+        "yarn" => "yarn install",
+        _ if source.has("package-lock.json") || source.has("npm-shrinkwrap.json") => "npm ci",
+        _ => "npm install",
+    };
+
+    let mut plan = BuildPlan::new(Language::Node);
+    plan.runtime_version = match package["engines"]["node"].as_str() {
+        Some(version) => Some(version.to_string()),
+        None => match source.version_file(".nvmrc")? {
+            Some(version) => Some(version),
+            None => source.version_file(".node-version")?,
+        }
+        .map(|version| version.trim_start_matches('v').to_string()),
+    };
+    plan.build_commands.push(install.to_string());
+    if script("build") {
+        plan.build_commands.push(format!("{} run build", manager));
+    }
+    plan.start_command = if script("start") {
+        Some(format!("{} start", manager))
+    } else if let Some(main) = package["main"].as_str() {
+        Some(format!("node {}", main))
+    } else {
+        ["server.js", "index.js", "app.js"]
+            .into_iter()
+            .find(|script| source.has(script))
+            .map(|script| format!("node {}", script))
+    };
+    Ok(plan)
+}
+
+/// The command of the `web` process, from lines like `web: npm start`.
+fn procfile_web(source: &Source) -> Result<Option<String>, AppError> {
+    let Some(procfile) = source.read("Procfile")? else {
+        return Ok(None);
+    };
+    Ok(procfile.lines().find_map(|line| {
+        let (process, command) = line.split_once(':')?;
+        (process.trim() == "web" && !command.trim().is_empty()).then(|| command.trim().to_string())
+    }))
+}
+
+fn first_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+}
+
+/// The files of a source tree. Symlinks are ignored, so a repository
+/// cannot have files outside of it read.
+struct Source<'a> {
+    dir: &'a Path,
+}
+
+impl Source<'_> {
+    fn has(&self, file: &str) -> bool {
+        fs::symlink_metadata(self.dir.join(file))
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false)
+    }
+
+    fn read(&self, file: &str) -> Result<Option<String>, AppError> {
+        let path = self.dir.join(file);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > MAX_FILE_BYTES => Err(
+                AppError::ValidationError(format!("{} is larger than 1 MiB", file)),
+            ),
+            Ok(metadata) if metadata.is_file() => fs::read_to_string(&path)
+                .map(Some)
+                .map_err(|e| AppError::ValidationError(format!("Cannot read {}: {}", file, e))),
+            _ => Ok(None),
+        }
+    }
+
+    /// The first line of a file holding just a version, like `.nvmrc`.
+    fn version_file(&self, file: &str) -> Result<Option<String>, AppError> {
+        Ok(self.read(file)?.as_deref().and_then(first_line))
+    }
+
+    fn json(&self, file: &str) -> Result<Option<Value>, AppError> {
+        self.read(file)?
+            .map(|text| {
+                serde_json::from_str(&text)
+                    .map_err(|e| AppError::ValidationError(format!("Invalid {}: {}", file, e)))
+            })
+            .transpose()
+    }
+
+    fn toml(&self, file: &str) -> Result<Option<toml::Value>, AppError> {
+        self.read(file)?
+            .map(|text| {
+                text.parse()
+                    .map_err(|e| AppError::ValidationError(format!("Invalid {}: {}", file, e)))
+            })
+            .transpose()
+    }
+
+    /// The names of the directories in `dir`, sorted.
+    fn subdirs(&self, dir: &str) -> Result<Vec<String>, AppError> {
+        let mut names: Vec<String> = self
+            .entries(dir)?
+            .into_iter()
+            .filter(|(_, metadata)| metadata.is_dir())
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn has_files_ending(&self, suffix: &str) -> Result<bool, AppError> {
+        Ok(self
+            .entries("")?
+            .iter()
+            .any(|(name, metadata)| metadata.is_file() && name.ends_with(suffix)))
+    }
+
+    fn entries(&self, dir: &str) -> Result<Vec<(String, fs::Metadata)>, AppError> {
+        let path = self.dir.join(dir);
+        if !fs::symlink_metadata(&path)
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false)
+        {
+            return Ok(Vec::new());
+        }
+
+        let unreadable = |e: std::io::Error| {
+            AppError::ValidationError(format!("Cannot read directory '{}': {}", dir, e))
+        };
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&path).map_err(unreadable)? {
+            let entry = entry.map_err(unreadable)?;
+            // DirEntry::metadata does not follow symlinks.
+            let metadata = entry.metadata().map_err(unreadable)?;
+            entries.push((entry.file_name().to_string_lossy().into_owned(), metadata));
+        }
+        Ok(entries)
+    }
+}
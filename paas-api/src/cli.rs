@@ -0,0 +1,199 @@
+use crate::{
+    config::OAuthProvider,
+    crypto::Keyring,
+    error::AppError,
+    models::{self, NewProviderInstance, ProviderInstance, User},
+};
+use clap::{Parser, Subcommand};
+use sqlx::SqlitePool;
+
+#[derive(Parser)]
+#[command(
+    name = "paas-api",
+    about = "PaaS API server and administration commands"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default)
+    Serve,
+    /// Manage the provider instances users can sign in with
+    #[command(subcommand)]
+    Providers(ProviderCommand),
+    /// Manage the encryption of stored provider tokens
+    #[command(subcommand)]
+    Tokens(TokenCommand),
+    /// Manage the site admins, who operate the instance
+    #[command(subcommand)]
+    Admins(AdminCommand),
+}
+
+#[derive(Subcommand)]
+pub enum ProviderCommand {
+    /// List registered provider instances
+    List,
+    /// Register a provider instance, e.g. a self-managed GitLab
+    Add {
+        /// Identifier used in login URLs, e.g. "gitlab-internal"
+        #[arg(long)]
+        slug: String,
+        /// github, gitlab, bitbucket, bitbucket-server or oidc
+        #[arg(long = "type")]
+        provider_type: OAuthProvider,
+        /// Name shown on the login page
+        #[arg(long)]
+        name: String,
+        /// Web URL of the instance (the issuer URL for oidc)
+        #[arg(long)]
+        base_url: String,
+        #[arg(long)]
+        client_id: String,
+        #[arg(long, env = "PROVIDER_CLIENT_SECRET", hide_env_values = true)]
+        client_secret: String,
+    },
+    /// Remove a provider instance that no user signs in with
+    Remove { slug: String },
+}
+
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    /// Re-encrypt all stored tokens with the first key in TOKEN_ENCRYPTION_KEYS
+    RotateKey,
+}
+
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// List the site admins
+    List,
+    /// Make a user a site admin
+    Add {
+        username: String,
+        /// The provider instance the user signed up with, when several users
+        /// share the username
+        #[arg(long)]
+        provider: Option<String>,
+    },
+    /// Take the site admin role from a user
+    Remove {
+        username: String,
+        #[arg(long)]
+        provider: Option<String>,
+    },
+}
+
+pub async fn providers(pool: &SqlitePool, command: ProviderCommand) -> Result<(), AppError> {
+    match command {
+        ProviderCommand::List => {
+            for instance in ProviderInstance::list(pool).await? {
+                println!(
+                    "{}\t{}\t{}\t{}{}",
+                    instance.slug,
+                    instance.provider_type,
+                    instance.display_name,
+                    instance.base_url.as_deref().unwrap_or("(environment)"),
+                    if instance.is_configured() {
+                        ""
+                    } else {
+                        "\t(not configured)"
+                    }
+                );
+            }
+        }
+        ProviderCommand::Add {
+            slug,
+            provider_type,
+            name,
+            base_url,
+            client_id,
+            client_secret,
+        } => {
+            let instance = NewProviderInstance {
+                slug,
+                provider_type,
+                display_name: name,
+                base_url,
+                client_id,
+                client_secret,
+            };
+            instance.validate()?;
+
+            let instance = ProviderInstance::create(pool, &instance).await?;
+            println!("Registered provider instance '{}'", instance.slug);
+            println!(
+                "Callback URL: {}/api/auth/{}/callback",
+                crate::config::get_base_url(),
+                instance.slug
+            );
+        }
+        ProviderCommand::Remove { slug } => {
+            if !ProviderInstance::delete(pool, &slug).await? {
+                return Err(AppError::NotFound(format!("Unknown provider: {}", slug)));
+            }
+            println!("Removed provider instance '{}'", slug);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn tokens(pool: &SqlitePool, command: TokenCommand) -> Result<(), AppError> {
+    match command {
+        TokenCommand::RotateKey => {
+            let key_id = Keyring::from_env()?.active_key_id().to_string();
+            let rotated = models::rotate_token_key(pool).await?;
+            println!("Re-encrypted {} rows with key '{}'", rotated, key_id);
+            println!("Keys other than '{}' can now be removed", key_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// The one user named `username`, of the provider instance `provider` if
+/// given.
+async fn find_user(
+    pool: &SqlitePool,
+    keyring: &Keyring,
+    username: &str,
+    provider: Option<&str>,
+) -> Result<User, AppError> {
+    let mut users = User::find_by_username(pool, keyring, username, provider).await?;
+    match users.len() {
+        0 => Err(AppError::NotFound(format!("No user named {}", username))),
+        1 => Ok(users.remove(0)),
+        _ => Err(AppError::ValidationError(format!(
+            "Several users are named {}; pick one with --provider",
+            username
+        ))),
+    }
+}
+
+pub async fn admins(pool: &SqlitePool, command: AdminCommand) -> Result<(), AppError> {
+    let keyring = Keyring::from_env()?;
+    match command {
+        AdminCommand::List => {
+            for user in User::list_admins(pool, &keyring).await? {
+                println!("{}\t{}", user.username, user.provider);
+            }
+        }
+        AdminCommand::Add { username, provider } => {
+            let user = find_user(pool, &keyring, &username, provider.as_deref()).await?;
+            User::set_admin(pool, user.id, true).await?;
+            println!("{} ({}) is now a site admin", user.username, user.provider);
+        }
+        AdminCommand::Remove { username, provider } => {
+            let user = find_user(pool, &keyring, &username, provider.as_deref()).await?;
+            User::set_admin(pool, user.id, false).await?;
+            println!(
+                "{} ({}) is no longer a site admin",
+                user.username, user.provider
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -1,64 +1,43 @@
-use log::debug;
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, Scope, TokenUrl};
+use crate::{
+    builders::{OciTool, DEFAULT_IMAGE_PREFIX},
+    error::AppError,
+    models::ProviderInstance,
+    oidc::OAuthTokenResponse,
+    providers::{self, Endpoints, Provider},
+    runtimes,
+};
+use actix_web::cookie::{time::Duration, Key, SameSite};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{debug, warn};
+use oauth2::{
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenType,
+    },
+    AuthUrl, Client, ClientId, ClientSecret, RedirectUrl, StandardRevocableToken, TokenUrl,
+};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::{env, ops::RangeInclusive, path::PathBuf};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OAuthProvider {
     GitHub,
     GitLab,
     Bitbucket,
+    #[serde(rename = "bitbucket-server")]
+    BitbucketServer,
+    Oidc,
 }
 
 impl OAuthProvider {
-    pub fn get_auth_url(&self) -> String {
-        match self {
-            OAuthProvider::GitHub => env::var("GITHUB_AUTH_URL")
-                .unwrap_or_else(|_| "https://github.com/login/oauth/authorize".to_string()),
-            OAuthProvider::GitLab => env::var("GITLAB_AUTH_URL")
-                .unwrap_or_else(|_| "https://gitlab.com/oauth/authorize".to_string()),
-            OAuthProvider::Bitbucket => env::var("BITBUCKET_AUTH_URL")
-                .unwrap_or_else(|_| "https://bitbucket.org/site/oauth2/authorize".to_string()),
-        }
-    }
-
-    pub fn get_token_url(&self) -> String {
-        match self {
-            OAuthProvider::GitHub => env::var("GITHUB_TOKEN_URL")
-                .unwrap_or_else(|_| "https://github.com/login/oauth/access_token".to_string()),
-            OAuthProvider::GitLab => env::var("GITLAB_TOKEN_URL")
-                .unwrap_or_else(|_| "https://gitlab.com/oauth/token".to_string()),
-            OAuthProvider::Bitbucket => env::var("BITBUCKET_TOKEN_URL")
-                .unwrap_or_else(|_| "https://bitbucket.org/site/oauth2/access_token".to_string()),
-        }
-    }
-
-    pub fn get_user_api_url(&self) -> String {
-        match self {
-            OAuthProvider::GitHub => env::var("GITHUB_API_URL")
-                .unwrap_or_else(|_| "https://api.github.com/user".to_string()),
-            OAuthProvider::GitLab => env::var("GITLAB_API_URL")
-                .unwrap_or_else(|_| "https://gitlab.com/api/v4/user".to_string()),
-            OAuthProvider::Bitbucket => env::var("BITBUCKET_API_URL")
-                .unwrap_or_else(|_| "https://api.bitbucket.org/2.0/user".to_string()),
-        }
-    }
-
-    pub fn get_scopes(&self) -> Vec<Scope> {
+    pub fn provider(&self) -> &'static dyn Provider {
         match self {
-            OAuthProvider::GitHub => vec![
-                Scope::new("read:user".to_string()),
-                Scope::new("user:email".to_string()),
-                Scope::new("repo".to_string()),
-            ],
-            OAuthProvider::GitLab => vec![
-                Scope::new("read_user".to_string()),
-                Scope::new("read_repository".to_string()),
-            ],
-            OAuthProvider::Bitbucket => vec![
-                Scope::new("account".to_string()),
-                Scope::new("repository".to_string()),
-            ],
+            OAuthProvider::GitHub => &providers::GitHub,
+            OAuthProvider::GitLab => &providers::GitLab,
+            OAuthProvider::Bitbucket => &providers::Bitbucket,
+            OAuthProvider::BitbucketServer => &providers::BitbucketServer,
+            OAuthProvider::Oidc => &providers::Oidc,
         }
     }
 }
@@ -68,66 +47,449 @@ pub struct OAuthUser {
     pub id: String,
     pub username: String,
     pub email: Option<String>,
+    /// Whether the provider vouches for `email`, which the user may otherwise
+    /// have set to anything.
+    #[serde(default)]
+    pub email_verified: bool,
     pub avatar_url: Option<String>,
 }
 
+/// OAuth client whose token responses may carry an OpenID Connect ID token.
+pub type OAuthClient = Client<
+    BasicErrorResponse,
+    OAuthTokenResponse,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
 pub fn get_frontend_url() -> String {
     env::var("FRONTEND_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string())
 }
 
-pub fn github_oauth_client() -> BasicClient {
-    create_oauth_client(&OAuthProvider::GitHub).expect("Failed to create GitHub OAuth client")
+pub fn redirect_url(instance: &ProviderInstance) -> String {
+    format!("{}/api/auth/{}/callback", get_base_url(), instance.slug)
 }
 
-pub fn gitlab_oauth_client() -> BasicClient {
-    create_oauth_client(&OAuthProvider::GitLab).expect("Failed to create GitLab OAuth client")
-}
+pub fn oauth_client(
+    instance: &ProviderInstance,
+    endpoints: &Endpoints,
+) -> Result<OAuthClient, AppError> {
+    let redirect_url = redirect_url(instance);
+
+    debug!("Creating OAuth client for {}", instance.slug);
+    debug!("Auth URL: {}", endpoints.auth_url);
+    debug!("Token URL: {}", endpoints.token_url);
+    debug!("Redirect URL: {}", redirect_url);
 
-pub fn bitbucket_oauth_client() -> BasicClient {
-    create_oauth_client(&OAuthProvider::Bitbucket).expect("Failed to create Bitbucket OAuth client")
+    // The URLs come from admin-entered instances and discovery documents,
+    // so a bad one is a configuration error rather than a bug.
+    let invalid = |name: &str, e: url::ParseError| {
+        AppError::ConfigError(format!("Invalid {} URL for {}: {}", name, instance.slug, e))
+    };
+    let client = OAuthClient::new(
+        ClientId::new(instance.effective_client_id()?),
+        Some(ClientSecret::new(instance.effective_client_secret()?)),
+        AuthUrl::new(endpoints.auth_url.clone()).map_err(|e| invalid("auth", e))?,
+        Some(TokenUrl::new(endpoints.token_url.clone()).map_err(|e| invalid("token", e))?),
+    )
+    .set_redirect_uri(RedirectUrl::new(redirect_url).map_err(|e| invalid("redirect", e))?);
+
+    Ok(client)
 }
 
-fn create_oauth_client(
-    provider: &OAuthProvider,
-) -> Result<BasicClient, oauth2::ConfigurationError> {
-    let (client_id_var, redirect_path) = match provider {
-        OAuthProvider::GitHub => ("GITHUB", "/api/auth/github/callback"),
-        OAuthProvider::GitLab => ("GITLAB", "/api/auth/gitlab/callback"),
-        OAuthProvider::Bitbucket => ("BITBUCKET", "/api/auth/bitbucket/callback"),
+/// Public URL of the API. Defaults to `http://HOST:PORT`; set `BASE_URL` when
+/// the server is reached through a proxy or over https.
+pub fn get_base_url() -> String {
+    let base_url = match env::var("BASE_URL") {
+        Ok(base_url) if !base_url.is_empty() => base_url.trim_end_matches('/').to_string(),
+        _ => {
+            let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+            let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+            format!("http://{}:{}", host, port)
+        }
     };
+    debug!("Base URL: {}", base_url);
+    base_url
+}
 
-    let client_id_env = format!("{}_CLIENT_ID", client_id_var);
-    let client_secret_env = format!("{}_CLIENT_SECRET", client_id_var);
+pub const DEFAULT_SESSION_IDLE_TTL_SECS: i64 = 24 * 60 * 60;
+pub const DEFAULT_SESSION_ABSOLUTE_TTL_SECS: i64 = 30 * 24 * 60 * 60;
 
-    let base_url = get_base_url();
-    let redirect_url = format!("{}{}", base_url, redirect_path);
+/// Session cookie settings.
+#[derive(Clone)]
+pub struct SessionConfig {
+    /// Keys encrypting the session cookie, newest first. Cookies are always
+    /// written with the first key; the others are still accepted so rotating
+    /// the key does not sign everyone out.
+    pub keys: Vec<Key>,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+    /// Sessions end after this long without a request.
+    pub idle_ttl: Duration,
+    /// Sessions end this long after sign-in, however active they are.
+    pub absolute_ttl: Duration,
+}
 
-    debug!("Creating OAuth client for {:?}", provider);
-    debug!("Auth URL: {}", provider.get_auth_url());
-    debug!("Token URL: {}", provider.get_token_url());
-    debug!("Redirect URL: {}", redirect_url);
+impl SessionConfig {
+    /// Reads the `SESSION_*` variables. Cookies default to `Secure` when
+    /// `BASE_URL` is https, and insecure settings are refused there.
+    pub fn from_env() -> Result<Self, AppError> {
+        let https = get_base_url().starts_with("https://");
 
-    let client = BasicClient::new(
-        ClientId::new(
-            env::var(&client_id_env)
-                .unwrap_or_else(|_| panic!("Missing {} environment variable", client_id_env)),
-        ),
-        Some(ClientSecret::new(
-            env::var(&client_secret_env)
-                .unwrap_or_else(|_| panic!("Missing {} environment variable", client_secret_env)),
-        )),
-        AuthUrl::new(provider.get_auth_url()).expect("Invalid auth URL"),
-        Some(TokenUrl::new(provider.get_token_url()).expect("Invalid token URL")),
-    )
-    .set_redirect_uri(RedirectUrl::new(redirect_url).expect("Invalid redirect URL"));
+        let keys = match env::var("SESSION_COOKIE_KEYS") {
+            Ok(keys) if !keys.is_empty() => parse_cookie_keys(&keys)?,
+            _ if https => {
+                return Err(AppError::ConfigError(
+                    "SESSION_COOKIE_KEYS must be set when BASE_URL is https".to_string(),
+                ))
+            }
+            _ => {
+                warn!("SESSION_COOKIE_KEYS is not set; sessions will not survive a restart");
+                vec![Key::generate()]
+            }
+        };
 
-    Ok(client)
+        let secure = match env::var("SESSION_COOKIE_SECURE") {
+            Ok(secure) => parse_bool("SESSION_COOKIE_SECURE", &secure)?,
+            Err(_) => https,
+        };
+        if https && !secure {
+            return Err(AppError::ConfigError(
+                "Refusing to send session cookies without Secure when BASE_URL is https"
+                    .to_string(),
+            ));
+        }
+
+        let same_site = match env::var("SESSION_COOKIE_SAME_SITE") {
+            Ok(same_site) => match same_site.to_ascii_lowercase().as_str() {
+                "lax" => SameSite::Lax,
+                "strict" => SameSite::Strict,
+                "none" => SameSite::None,
+                _ => {
+                    return Err(AppError::ConfigError(format!(
+                        "SESSION_COOKIE_SAME_SITE must be lax, strict or none, got {:?}",
+                        same_site
+                    )))
+                }
+            },
+            Err(_) => SameSite::Lax,
+        };
+        if same_site == SameSite::None && !secure {
+            return Err(AppError::ConfigError(
+                "SESSION_COOKIE_SAME_SITE=none requires Secure cookies".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            keys,
+            secure,
+            same_site,
+            domain: env::var("SESSION_COOKIE_DOMAIN")
+                .ok()
+                .filter(|domain| !domain.is_empty()),
+            idle_ttl: ttl_from_env("SESSION_IDLE_TTL_SECS", DEFAULT_SESSION_IDLE_TTL_SECS)?,
+            absolute_ttl: ttl_from_env(
+                "SESSION_ABSOLUTE_TTL_SECS",
+                DEFAULT_SESSION_ABSOLUTE_TTL_SECS,
+            )?,
+        })
+    }
 }
 
-fn get_base_url() -> String {
-    let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let base_url = format!("http://{}:{}", host, port);
-    debug!("Base URL: {}", base_url);
-    base_url
+pub const DEFAULT_JWT_AUDIENCE: &str = "paas-api";
+pub const DEFAULT_JWT_TTL_SECS: i64 = 15 * 60;
+pub const DEFAULT_REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Key id given to `JWT_SECRET` when `JWT_KEYS` is not set.
+const DEFAULT_JWT_KEY_ID: &str = "default";
+const MIN_JWT_SECRET_LEN: usize = 16;
+
+/// Settings for the JWTs issued by `/api/auth/token`.
+#[derive(Clone)]
+pub struct JwtConfig {
+    /// `(kid, secret)` pairs, newest first. Tokens are signed with the first
+    /// key; the others still verify, so rotating the key does not invalidate
+    /// tokens already handed out.
+    pub keys: Vec<(String, Vec<u8>)>,
+    pub issuer: String,
+    pub audience: String,
+    /// Lifetime of access tokens.
+    pub ttl: Duration,
+    /// Refresh tokens expire after this long without being used.
+    pub refresh_ttl: Duration,
+}
+
+impl JwtConfig {
+    /// Reads the signing keys from `JWT_KEYS` (comma-separated
+    /// `<kid>:<secret>` pairs) or, failing that, `JWT_SECRET`, along with
+    /// `JWT_AUDIENCE`, `JWT_TTL_SECS` and `REFRESH_TOKEN_TTL_SECS`. The issuer
+    /// is the base URL.
+    pub fn from_env() -> Result<Self, AppError> {
+        let keys = match (env::var("JWT_KEYS"), env::var("JWT_SECRET")) {
+            (Ok(keys), _) if !keys.is_empty() => parse_jwt_keys(&keys)?,
+            (_, Ok(secret)) if !secret.is_empty() => {
+                check_jwt_secret("JWT_SECRET", &secret)?;
+                vec![(DEFAULT_JWT_KEY_ID.to_string(), secret.into_bytes())]
+            }
+            _ => {
+                return Err(AppError::ConfigError(
+                    "JWT_KEYS or JWT_SECRET must be set".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self {
+            keys,
+            issuer: get_base_url(),
+            audience: env::var("JWT_AUDIENCE")
+                .ok()
+                .filter(|audience| !audience.is_empty())
+                .unwrap_or_else(|| DEFAULT_JWT_AUDIENCE.to_string()),
+            ttl: ttl_from_env("JWT_TTL_SECS", DEFAULT_JWT_TTL_SECS)?,
+            refresh_ttl: ttl_from_env("REFRESH_TOKEN_TTL_SECS", DEFAULT_REFRESH_TOKEN_TTL_SECS)?,
+        })
+    }
+}
+
+const DEFAULT_JOB_WORKERS: usize = 4;
+const DEFAULT_JOB_POLL_INTERVAL_SECS: i64 = 1;
+const DEFAULT_JOB_VISIBILITY_TIMEOUT_SECS: i64 = 3600;
+
+/// Settings of the background job workers.
+#[derive(Clone, Debug)]
+pub struct JobConfig {
+    /// Number of workers; 0 leaves the jobs to other instances.
+    pub workers: usize,
+    /// How long idle workers wait before looking for jobs again.
+    pub poll_interval: Duration,
+    /// How long a worker may run a job before it is given to another.
+    pub visibility_timeout: Duration,
+}
+
+impl JobConfig {
+    /// Reads `JOB_WORKERS`, `JOB_POLL_INTERVAL_SECS` and
+    /// `JOB_VISIBILITY_TIMEOUT_SECS`.
+    pub fn from_env() -> Result<Self, AppError> {
+        let workers = match env::var("JOB_WORKERS") {
+            Ok(workers) => workers.parse().map_err(|_| {
+                AppError::ConfigError(format!(
+                    "JOB_WORKERS must be a number of workers, got {:?}",
+                    workers
+                ))
+            })?,
+            Err(_) => DEFAULT_JOB_WORKERS,
+        };
+
+        Ok(Self {
+            workers,
+            poll_interval: ttl_from_env("JOB_POLL_INTERVAL_SECS", DEFAULT_JOB_POLL_INTERVAL_SECS)?,
+            visibility_timeout: ttl_from_env(
+                "JOB_VISIBILITY_TIMEOUT_SECS",
+                DEFAULT_JOB_VISIBILITY_TIMEOUT_SECS,
+            )?,
+        })
+    }
+}
+
+pub const DEFAULT_SOURCE_CACHE_DIR: &str = "data/sources";
+const DEFAULT_GIT_TIMEOUT_SECS: i64 = 600;
+
+/// Where deployment sources are checked out.
+#[derive(Clone, Debug)]
+pub struct SourceConfig {
+    /// Holds one clone per app, kept between deployments.
+    pub cache_dir: PathBuf,
+    /// How long a single git command may run.
+    pub git_timeout: Duration,
+}
+
+impl SourceConfig {
+    /// Reads `SOURCE_CACHE_DIR` and `GIT_TIMEOUT_SECS`.
+    pub fn from_env() -> Result<Self, AppError> {
+        let cache_dir = match env::var("SOURCE_CACHE_DIR") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(DEFAULT_SOURCE_CACHE_DIR),
+        };
+
+        Ok(Self {
+            cache_dir,
+            git_timeout: ttl_from_env("GIT_TIMEOUT_SECS", DEFAULT_GIT_TIMEOUT_SECS)?,
+        })
+    }
+}
+
+const DEFAULT_BUILD_TIMEOUT_SECS: i64 = 1800;
+
+/// How deployments are built into images.
+#[derive(Clone, Debug)]
+pub struct BuildConfig {
+    pub tool: OciTool,
+    /// How long a build may run before it is killed.
+    pub timeout: Duration,
+    /// Repository images are tagged in, e.g. `registry.local/paas`.
+    pub image_prefix: String,
+}
+
+impl BuildConfig {
+    /// Reads `BUILDER`, `BUILD_TIMEOUT_SECS` and `BUILD_IMAGE_PREFIX`.
+    pub fn from_env() -> Result<Self, AppError> {
+        let tool = match env::var("BUILDER") {
+            Ok(tool) if !tool.is_empty() => tool.parse()?,
+            _ => OciTool::Docker,
+        };
+        let image_prefix = match env::var("BUILD_IMAGE_PREFIX") {
+            Ok(prefix) if !prefix.is_empty() => prefix,
+            _ => DEFAULT_IMAGE_PREFIX.to_string(),
+        };
+        let valid = image_prefix.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-' | '/' | ':')
+        });
+        if !valid {
+            return Err(AppError::ConfigError(format!(
+                "BUILD_IMAGE_PREFIX must be an image repository, got {:?}",
+                image_prefix
+            )));
+        }
+
+        Ok(Self {
+            tool,
+            timeout: ttl_from_env("BUILD_TIMEOUT_SECS", DEFAULT_BUILD_TIMEOUT_SECS)?,
+            image_prefix,
+        })
+    }
+}
+
+/// How released apps are run.
+#[derive(Clone, Debug)]
+pub struct RuntimeConfig {
+    /// Ports assigned to apps.
+    pub ports: RangeInclusive<u16>,
+    /// How long a starting app may take to pass its health probe.
+    pub start_timeout: Duration,
+    /// How long an app may take to exit after SIGTERM before it is killed.
+    pub stop_timeout: Duration,
+    /// How often running apps are probed.
+    pub probe_interval: Duration,
+}
+
+impl RuntimeConfig {
+    /// Reads `RUNTIME_PORTS` (e.g. `20000-29999`), `RUNTIME_START_TIMEOUT_SECS`,
+    /// `RUNTIME_STOP_TIMEOUT_SECS` and `RUNTIME_PROBE_INTERVAL_SECS`.
+    pub fn from_env() -> Result<Self, AppError> {
+        let ports = match env::var("RUNTIME_PORTS") {
+            Ok(ports) if !ports.is_empty() => ports
+                .split_once('-')
+                .and_then(|(first, last)| {
+                    Some(first.trim().parse().ok()?..=last.trim().parse().ok()?)
+                })
+                .filter(|ports: &RangeInclusive<u16>| *ports.start() > 0 && !ports.is_empty())
+                .ok_or_else(|| {
+                    AppError::ConfigError(format!(
+                        "RUNTIME_PORTS must be a range of ports like 20000-29999, got {:?}",
+                        ports
+                    ))
+                })?,
+            _ => runtimes::DEFAULT_PORTS,
+        };
+
+        Ok(Self {
+            ports,
+            start_timeout: ttl_from_env(
+                "RUNTIME_START_TIMEOUT_SECS",
+                runtimes::DEFAULT_START_TIMEOUT_SECS as i64,
+            )?,
+            stop_timeout: ttl_from_env(
+                "RUNTIME_STOP_TIMEOUT_SECS",
+                runtimes::DEFAULT_STOP_TIMEOUT_SECS as i64,
+            )?,
+            probe_interval: ttl_from_env(
+                "RUNTIME_PROBE_INTERVAL_SECS",
+                runtimes::DEFAULT_PROBE_INTERVAL_SECS as i64,
+            )?,
+        })
+    }
+}
+
+fn parse_jwt_keys(value: &str) -> Result<Vec<(String, Vec<u8>)>, AppError> {
+    let mut keys: Vec<(String, Vec<u8>)> = Vec::new();
+    for (position, entry) in value.split(',').map(str::trim).enumerate() {
+        // Reported by position so secrets never end up in logs.
+        let name = format!("JWT_KEYS entry {}", position + 1);
+        let (kid, secret) = entry
+            .split_once(':')
+            .ok_or_else(|| AppError::ConfigError(format!("{}: expected <kid>:<secret>", name)))?;
+        if kid.is_empty() {
+            return Err(AppError::ConfigError(format!("{}: empty key id", name)));
+        }
+        if keys.iter().any(|(existing, _)| existing == kid) {
+            return Err(AppError::ConfigError(format!(
+                "{}: duplicate key id '{}'",
+                name, kid
+            )));
+        }
+        check_jwt_secret(&name, secret)?;
+        keys.push((kid.to_string(), secret.as_bytes().to_vec()));
+    }
+    Ok(keys)
+}
+
+fn check_jwt_secret(name: &str, secret: &str) -> Result<(), AppError> {
+    if secret.len() < MIN_JWT_SECRET_LEN {
+        return Err(AppError::ConfigError(format!(
+            "{}: secret must be at least {} bytes",
+            name, MIN_JWT_SECRET_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Parses comma-separated base64 keys of at least 64 bytes.
+fn parse_cookie_keys(value: &str) -> Result<Vec<Key>, AppError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .enumerate()
+        .map(|(position, key)| {
+            // Reported by position so key material never ends up in logs.
+            let invalid = |reason: &str| {
+                AppError::ConfigError(format!(
+                    "SESSION_COOKIE_KEYS entry {}: {}",
+                    position + 1,
+                    reason
+                ))
+            };
+            let key = STANDARD
+                .decode(key)
+                .map_err(|_| invalid("key is not valid base64"))?;
+            Key::try_from(key.as_slice()).map_err(|_| invalid("key must be at least 64 bytes"))
+        })
+        .collect()
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, AppError> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(AppError::ConfigError(format!(
+            "{} must be true or false, got {:?}",
+            name, value
+        ))),
+    }
+}
+
+fn ttl_from_env(name: &str, default_secs: i64) -> Result<Duration, AppError> {
+    let secs = match env::var(name) {
+        Ok(secs) => secs
+            .parse::<i64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| {
+                AppError::ConfigError(format!("{} must be a positive number of seconds", name))
+            })?,
+        Err(_) => default_secs,
+    };
+    Ok(Duration::seconds(secs))
 }
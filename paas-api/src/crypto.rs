@@ -0,0 +1,121 @@
+use crate::error::AppError;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::env;
+
+/// Comma-separated `<key id>:<base64 key>` pairs. The first key encrypts new
+/// values, the others are only used to read values written before a rotation.
+pub const KEYS_VAR: &str = "TOKEN_ENCRYPTION_KEYS";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM keys used to encrypt provider tokens at rest.
+pub struct Keyring {
+    /// Newest first.
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl Keyring {
+    pub fn from_env() -> Result<Self, AppError> {
+        let value = env::var(KEYS_VAR)
+            .map_err(|_| AppError::ConfigError(format!("{} must be set", KEYS_VAR)))?;
+        Self::parse(&value)
+    }
+
+    /// Parses a `TOKEN_ENCRYPTION_KEYS` value.
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let mut keys: Vec<(String, Aes256Gcm)> = Vec::new();
+
+        // Entries are reported by position so key material never ends up in logs.
+        for (position, entry) in value.split(',').map(str::trim).enumerate() {
+            let invalid = |reason: &str| {
+                AppError::ConfigError(format!("{} entry {}: {}", KEYS_VAR, position + 1, reason))
+            };
+
+            let (id, key) = entry
+                .split_once(':')
+                .ok_or_else(|| invalid("expected <key id>:<base64 key>"))?;
+            let id = id.trim();
+            if id.is_empty() {
+                return Err(invalid("key id is empty"));
+            }
+            if keys.iter().any(|(existing, _)| existing == id) {
+                return Err(invalid("duplicate key id"));
+            }
+
+            let key = STANDARD
+                .decode(key.trim())
+                .map_err(|_| invalid("key is not valid base64"))?;
+            if key.len() != KEY_LEN {
+                return Err(invalid("key must be 32 bytes"));
+            }
+            let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| invalid("invalid key"))?;
+
+            keys.push((id.to_string(), cipher));
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Id of the key new values are encrypted with.
+    pub fn active_key_id(&self) -> &str {
+        &self.keys[0].0
+    }
+
+    /// Encrypts `plaintext` with the active key. `context` names the column
+    /// the value is stored in; it is authenticated along with the value, so
+    /// ciphertexts cannot be moved between columns.
+    pub fn encrypt(&self, context: &str, plaintext: &str) -> Result<String, AppError> {
+        let (_, cipher) = &self.keys[0];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| AppError::DatabaseError(format!("Failed to encrypt {}", context)))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(STANDARD.encode(sealed))
+    }
+
+    /// Decrypts a value written by [`Keyring::encrypt`] with the key `key_id`.
+    pub fn decrypt(&self, key_id: &str, context: &str, sealed: &str) -> Result<String, AppError> {
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .ok_or_else(|| {
+                AppError::ConfigError(format!(
+                    "Token encryption key '{}' is not in {}",
+                    key_id, KEYS_VAR
+                ))
+            })?;
+        let undecryptable = || AppError::DatabaseError(format!("Failed to decrypt {}", context));
+
+        let sealed = STANDARD.decode(sealed).map_err(|_| undecryptable())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(undecryptable());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| undecryptable())?;
+
+        String::from_utf8(plaintext).map_err(|_| undecryptable())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::{error::AppError, models};
+use log::info;
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
 use std::env;
 
@@ -17,8 +19,17 @@ pub async fn create_pool() -> Result<SqlitePool, sqlx::Error> {
         .await
 }
 
-pub async fn init_db(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    sqlx::migrate!("./migrations").run(pool).await?;
+pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
+    sqlx::migrate!("./migrations")
+        .run(pool)
+        .await
+        .map_err(sqlx::Error::from)?;
+
+    // Tokens stored before encryption at rest was introduced.
+    let encrypted = models::encrypt_plaintext_tokens(pool).await?;
+    if encrypted > 0 {
+        info!("Encrypted the tokens of {} existing rows", encrypted);
+    }
 
     Ok(())
 }
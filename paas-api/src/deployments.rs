@@ -0,0 +1,139 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Default and maximum numbers of log lines returned at once.
+pub const DEFAULT_LOG_PAGE_SIZE: i64 = 1000;
+pub const MAX_LOG_PAGE_SIZE: i64 = 10000;
+
+/// Where a deployment is in its lifecycle:
+///
+/// ```text
+/// queued -> building -> releasing -> running -> superseded
+///    |          |           |           |
+///    +----------+-----------+-----------+-----> failed
+///    |          |
+///    +----------+-----> cancelled
+///    |
+///    +-----> superseded
+/// ```
+///
+/// Failed, cancelled and superseded deployments never change again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum DeploymentStatus {
+    /// Waiting for a worker.
+    Queued,
+    /// The image is being built from the commit.
+    Building,
+    /// The built image is being started.
+    Releasing,
+    /// Serving the app.
+    Running,
+    Failed,
+    Cancelled,
+    /// Replaced by a newer deployment of the app, either while queued or
+    /// once the newer one runs.
+    Superseded,
+}
+
+impl DeploymentStatus {
+    pub const ALL: [DeploymentStatus; 7] = [
+        DeploymentStatus::Queued,
+        DeploymentStatus::Building,
+        DeploymentStatus::Releasing,
+        DeploymentStatus::Running,
+        DeploymentStatus::Failed,
+        DeploymentStatus::Cancelled,
+        DeploymentStatus::Superseded,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentStatus::Queued => "queued",
+            DeploymentStatus::Building => "building",
+            DeploymentStatus::Releasing => "releasing",
+            DeploymentStatus::Running => "running",
+            DeploymentStatus::Failed => "failed",
+            DeploymentStatus::Cancelled => "cancelled",
+            DeploymentStatus::Superseded => "superseded",
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            DeploymentStatus::Failed | DeploymentStatus::Cancelled | DeploymentStatus::Superseded
+        )
+    }
+
+    pub fn can_transition_to(&self, next: DeploymentStatus) -> bool {
+        use DeploymentStatus::*;
+
+        matches!(
+            (self, next),
+            (Queued, Building | Failed | Cancelled | Superseded)
+                | (Building, Releasing | Failed | Cancelled)
+                | (Releasing, Running | Failed)
+                | (Running, Failed | Superseded)
+        )
+    }
+
+    /// Checks that a deployment may move from this status to `next`.
+    pub fn transition(&self, next: DeploymentStatus) -> Result<DeploymentStatus, AppError> {
+        if !self.can_transition_to(next) {
+            return Err(AppError::ValidationError(format!(
+                "Cannot move a deployment from {} to {}",
+                self, next
+            )));
+        }
+        Ok(next)
+    }
+
+    /// The column recording when a deployment entered this status.
+    pub(crate) fn timestamp_column(&self) -> &'static str {
+        match self {
+            DeploymentStatus::Queued => "created_at",
+            DeploymentStatus::Building => "building_at",
+            DeploymentStatus::Releasing => "releasing_at",
+            DeploymentStatus::Running => "running_at",
+            DeploymentStatus::Failed
+            | DeploymentStatus::Cancelled
+            | DeploymentStatus::Superseded => "finished_at",
+        }
+    }
+}
+
+impl fmt::Display for DeploymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DeploymentStatus {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeploymentStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| AppError::ValidationError(format!("Unknown deployment status: {}", s)))
+    }
+}
+
+/// Lowercases a full SHA-1 or SHA-256 commit id, rejecting anything else.
+/// Abbreviated ids are refused as they may become ambiguous.
+pub fn normalize_commit_sha(sha: &str) -> Result<String, AppError> {
+    let sha = sha.trim().to_ascii_lowercase();
+    if !matches!(sha.len(), 40 | 64) || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::ValidationError(format!(
+            "Invalid commit SHA: {}",
+            sha
+        )));
+    }
+    Ok(sha)
+}
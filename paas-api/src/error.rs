@@ -1,3 +1,4 @@
+use crate::{builders::BuildError, git::GitError, runtimes::RuntimeError};
 use actix_session::{SessionGetError, SessionInsertError};
 use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
 use derive_more::Display;
@@ -11,8 +12,10 @@ pub enum AppError {
     #[display(fmt = "Authentication error: {}", _0)]
     AuthError(String),
 
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(String),
+
     #[display(fmt = "Validation error: {}", _0)]
-    #[allow(dead_code)]
     ValidationError(String),
 
     #[display(fmt = "External service error: {}", _0)]
@@ -21,8 +24,10 @@ pub enum AppError {
     #[display(fmt = "Session error: {}", _0)]
     SessionError(String),
 
+    #[display(fmt = "Configuration error: {}", _0)]
+    ConfigError(String),
+
     #[display(fmt = "Not found: {}", _0)]
-    #[allow(dead_code)]
     NotFound(String),
 }
 
@@ -32,6 +37,9 @@ impl ResponseError for AppError {
             AppError::AuthError(msg) => HttpResponse::Unauthorized().json(json!({
                 "error": msg
             })),
+            AppError::Forbidden(msg) => HttpResponse::Forbidden().json(json!({
+                "error": msg
+            })),
             AppError::ValidationError(msg) => HttpResponse::BadRequest().json(json!({
                 "error": msg
             })),
@@ -50,6 +58,7 @@ impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
         match *self {
             AppError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::ExternalServiceError(_) => StatusCode::BAD_GATEWAY,
@@ -75,3 +84,39 @@ impl From<SessionInsertError> for AppError {
         AppError::SessionError(err.to_string())
     }
 }
+
+impl From<GitError> for AppError {
+    fn from(err: GitError) -> Self {
+        match err {
+            GitError::InvalidUrl(_) | GitError::InvalidCommit(_) | GitError::InvalidBranch(_) => {
+                AppError::ValidationError(err.to_string())
+            }
+            GitError::NotInstalled | GitError::Io(_) => AppError::ConfigError(err.to_string()),
+            _ => AppError::ExternalServiceError(err.to_string()),
+        }
+    }
+}
+
+impl From<BuildError> for AppError {
+    fn from(err: BuildError) -> Self {
+        match err {
+            BuildError::NotInstalled(_) | BuildError::Io(_) => {
+                AppError::ConfigError(err.to_string())
+            }
+            _ => AppError::ExternalServiceError(err.to_string()),
+        }
+    }
+}
+
+impl From<RuntimeError> for AppError {
+    fn from(err: RuntimeError) -> Self {
+        match err {
+            RuntimeError::NotFound(_) => AppError::NotFound(err.to_string()),
+            RuntimeError::AlreadyRunning(_) => AppError::ValidationError(err.to_string()),
+            RuntimeError::NoFreePort(_) | RuntimeError::Io(_) => {
+                AppError::ConfigError(err.to_string())
+            }
+            RuntimeError::StartFailed { .. } => AppError::ExternalServiceError(err.to_string()),
+        }
+    }
+}
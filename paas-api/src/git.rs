@@ -0,0 +1,538 @@
+use crate::{
+    apps::validate_branch,
+    config::SourceConfig,
+    deployments::normalize_commit_sha,
+    error::AppError,
+    models::{GitProvider, ProviderInstance, Repository},
+    orgs::Owner,
+    policy,
+    tokens::TokenService,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use derive_more::Display;
+use log::debug;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::process::Command;
+
+/// Protocols git may use unless `SourceCache::protocols` says otherwise.
+/// They also apply to submodules, so a repository cannot point one at a
+/// local path of the server.
+pub const DEFAULT_PROTOCOLS: &[&str] = &["https"];
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// Stderr of a fetch refused because the server does not serve the commit
+/// by itself, or does not have it. Errors are matched against this list
+/// and the two below, in order.
+const COMMIT_ERRORS: &[&str] = &[
+    "not our ref",
+    "couldn't find remote ref",
+    "unadvertised object",
+    "no such remote ref",
+];
+const AUTH_ERRORS: &[&str] = &[
+    "authentication failed",
+    "could not read username",
+    "could not read password",
+    "terminal prompts disabled",
+    "returned error: 401",
+    "returned error: 403",
+    "permission denied",
+];
+const NOT_FOUND_ERRORS: &[&str] = &[
+    "not found",
+    "could not be found",
+    "does not appear to be a git repository",
+    "returned error: 404",
+];
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum GitError {
+    #[display(fmt = "git is not installed")]
+    NotInstalled,
+
+    #[display(fmt = "Invalid repository URL: {}", _0)]
+    InvalidUrl(String),
+
+    #[display(fmt = "Invalid commit SHA: {}", _0)]
+    InvalidCommit(String),
+
+    #[display(fmt = "Invalid branch name: {}", _0)]
+    InvalidBranch(String),
+
+    #[display(fmt = "Authentication failed for {}", _0)]
+    AuthFailed(String),
+
+    #[display(fmt = "Repository not found: {}", _0)]
+    RepositoryNotFound(String),
+
+    #[display(fmt = "Commit {} not found in {}", sha, url)]
+    CommitNotFound { url: String, sha: String },
+
+    #[display(fmt = "git {} timed out after {} seconds", command, secs)]
+    TimedOut { command: String, secs: u64 },
+
+    #[display(fmt = "git {} failed: {}", command, stderr)]
+    CommandFailed { command: String, stderr: String },
+
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(String),
+}
+
+impl std::error::Error for GitError {}
+
+impl From<io::Error> for GitError {
+    fn from(err: io::Error) -> Self {
+        GitError::Io(err.to_string())
+    }
+}
+
+/// Sent as basic auth to the host of the clone URL, and nowhere else.
+#[derive(Clone)]
+pub struct GitCredentials {
+    pub username: String,
+    pub token: String,
+}
+
+impl fmt::Debug for GitCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitCredentials")
+            .field("username", &self.username)
+            .field("token", &"[redacted]")
+            .finish()
+    }
+}
+
+/// The credentials of the connection a repository was imported through,
+/// with its access token refreshed if needed, for building an app of
+/// `app_owner`.
+///
+/// The connection's user must still belong to the organization sharing
+/// their token: the repository's, or for a personal repository, the app's.
+/// Members who leave take their token with them.
+pub async fn repository_credentials(
+    pool: &sqlx::SqlitePool,
+    token_service: &TokenService,
+    repository: &Repository,
+    app_owner: Owner,
+) -> Result<GitCredentials, AppError> {
+    let connection = GitProvider::find_by_id(pool, token_service.keyring(), repository.provider_id)
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("No connection for repository {}", repository.name))
+        })?;
+    let sharing = match repository.owner() {
+        Owner::User(_) => app_owner,
+        owner => owner,
+    };
+    if policy::role(pool, connection.user_id, sharing)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::Forbidden(format!(
+            "Repository {} was connected by a user who is no longer a member of the organization",
+            repository.name
+        )));
+    }
+    let instance = ProviderInstance::find_by_slug(pool, &connection.provider)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown provider: {}", connection.provider)))?;
+
+    Ok(GitCredentials {
+        username: instance.spec()?.git_username().to_string(),
+        token: token_service
+            .access_token(connection.user_id, &connection.provider)
+            .await?,
+    })
+}
+
+/// A commit of an app to check out, e.g.
+///
+/// ```ignore
+/// let request = FetchRequest::new(app.id, &repository.clone_url, &deployment.commit_sha)
+///     .branch(app.settings.branch.clone())
+///     .credentials(git::repository_credentials(pool, &tokens, &repository, app.owner()).await?);
+/// let checkout = sources.checkout(&request).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct FetchRequest {
+    pub app_id: i64,
+    pub clone_url: String,
+    pub commit_sha: String,
+    /// Branch searched for the commit when the server refuses to send a
+    /// commit by its SHA. All branches are searched when unset.
+    pub branch: Option<String>,
+    pub credentials: Option<GitCredentials>,
+    /// Fetch the commit without its history.
+    pub shallow: bool,
+    pub submodules: bool,
+}
+
+impl FetchRequest {
+    pub fn new(app_id: i64, clone_url: &str, commit_sha: &str) -> Self {
+        Self {
+            app_id,
+            clone_url: clone_url.to_string(),
+            commit_sha: commit_sha.to_string(),
+            branch: None,
+            credentials: None,
+            shallow: true,
+            submodules: true,
+        }
+    }
+
+    pub fn branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    pub fn credentials(mut self, credentials: GitCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn shallow(mut self, shallow: bool) -> Self {
+        self.shallow = shallow;
+        self
+    }
+
+    pub fn submodules(mut self, submodules: bool) -> Self {
+        self.submodules = submodules;
+        self
+    }
+}
+
+/// A working tree at the requested commit.
+#[derive(Debug, Clone)]
+pub struct Checkout {
+    pub path: PathBuf,
+    pub commit_sha: String,
+    /// Whether the commit had to be fetched, rather than being in the cache
+    /// already.
+    pub fetched: bool,
+}
+
+/// Keeps a clone of each app's repository, so later deployments only fetch
+/// the new commits.
+pub struct SourceCache {
+    root: PathBuf,
+    protocols: Vec<String>,
+    timeout: Duration,
+    /// One lock per app, as two checkouts cannot share a working tree.
+    locks: Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl SourceCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            protocols: DEFAULT_PROTOCOLS.iter().map(|p| p.to_string()).collect(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(config: &SourceConfig) -> Self {
+        let timeout = Duration::try_from(config.git_timeout)
+            .unwrap_or(Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+        Self::new(&config.cache_dir).timeout(timeout)
+    }
+
+    /// Replaces the protocols git may use. Local paths count as `file`.
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// How long a single git command may run.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The working tree of an app.
+    pub fn path(&self, app_id: i64) -> PathBuf {
+        self.root.join(app_id.to_string())
+    }
+
+    /// Checks out the requested commit in the app's working tree, fetching
+    /// it unless it is cached. Files not in the commit are removed.
+    pub async fn checkout(&self, request: &FetchRequest) -> Result<Checkout, GitError> {
+        let sha = normalize_commit_sha(&request.commit_sha)
+            .map_err(|_| GitError::InvalidCommit(request.commit_sha.clone()))?;
+        if let Some(branch) = &request.branch {
+            validate_branch(branch).map_err(|_| GitError::InvalidBranch(branch.clone()))?;
+        }
+        let url = self.check_url(&request.clone_url)?;
+
+        let lock = self.lock(request.app_id);
+        let _guard = lock.lock().await;
+
+        let path = self.path(request.app_id);
+        tokio::fs::create_dir_all(&path).await?;
+        let git = Git {
+            dir: &path,
+            url: &url,
+            sha: &sha,
+            protocols: self.protocols.join(":"),
+            timeout: self.timeout,
+            auth: request
+                .credentials
+                .as_ref()
+                .and_then(|credentials| auth_header(&request.clone_url, credentials)),
+            secret: request.credentials.as_ref().map(|c| c.token.as_str()),
+        };
+
+        if !path.join(".git").is_dir() {
+            git.run(&["init", "--quiet"]).await?;
+        }
+        // The token is passed to each command instead, so it is never stored.
+        git.run(&["config", "remote.origin.url", &request.clone_url])
+            .await?;
+        git.run(&[
+            "config",
+            "remote.origin.fetch",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ])
+        .await?;
+
+        let fetched = !git.has_commit().await?;
+        if fetched {
+            self.fetch(&git, request).await?;
+        }
+
+        git.run(&["checkout", "--quiet", "--force", "--detach", &sha])
+            .await?;
+        git.run(&["clean", "-ffdxq"]).await?;
+        if request.submodules {
+            git.run(&["submodule", "sync", "--quiet", "--recursive"])
+                .await?;
+            let mut args = vec![
+                "submodule",
+                "update",
+                "--quiet",
+                "--init",
+                "--recursive",
+                "--force",
+            ];
+            if request.shallow {
+                args.push("--depth=1");
+            }
+            git.run(&args).await?;
+            git.run(&[
+                "submodule",
+                "foreach",
+                "--quiet",
+                "--recursive",
+                "git clean -ffdxq",
+            ])
+            .await?;
+        } else {
+            // Empties the submodules an earlier checkout left behind.
+            git.run(&["submodule", "deinit", "--quiet", "--all", "--force"])
+                .await?;
+        }
+
+        debug!("Checked out {} of app {}", sha, request.app_id);
+        Ok(Checkout {
+            path,
+            commit_sha: sha,
+            fetched,
+        })
+    }
+
+    /// Deletes the working tree of an app, e.g. once the app is deleted or
+    /// its clone is broken.
+    pub async fn remove(&self, app_id: i64) -> Result<(), GitError> {
+        let lock = self.lock(app_id);
+        let _guard = lock.lock().await;
+
+        match tokio::fs::remove_dir_all(self.path(app_id)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn fetch(&self, git: &Git<'_>, request: &FetchRequest) -> Result<(), GitError> {
+        let mut args = vec!["fetch", "--quiet", "--no-tags"];
+        if request.shallow {
+            args.push("--depth=1");
+        }
+        args.extend(["origin", git.sha]);
+        match git.run(&args).await {
+            Err(GitError::CommitNotFound { .. }) => {}
+            result => return result.map(|_| ()),
+        }
+
+        // Servers may only send the commits of their branches, so look for
+        // the commit in their whole history.
+        debug!("Fetching the history of {} to find {}", git.url, git.sha);
+        let refspec = match &request.branch {
+            Some(branch) => format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch),
+            None => "+refs/heads/*:refs/remotes/origin/*".to_string(),
+        };
+        let mut args = vec!["fetch", "--quiet", "--no-tags"];
+        if git.dir.join(".git/shallow").exists() {
+            args.push("--unshallow");
+        }
+        args.extend(["origin", &refspec]);
+        git.run(&args).await?;
+
+        if !git.has_commit().await? {
+            return Err(git.commit_not_found());
+        }
+        Ok(())
+    }
+
+    /// Checks that git may clone from `clone_url`, and returns it without
+    /// any credentials for messages.
+    fn check_url(&self, clone_url: &str) -> Result<String, GitError> {
+        let (protocol, url) = match url::Url::parse(clone_url) {
+            Ok(mut url) => {
+                let protocol = url.scheme().to_string();
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                (protocol, url.to_string())
+            }
+            Err(_) if Path::new(clone_url).is_absolute() => {
+                ("file".to_string(), clone_url.to_string())
+            }
+            Err(_) => return Err(GitError::InvalidUrl(clone_url.to_string())),
+        };
+
+        if clone_url.starts_with('-') || !self.protocols.contains(&protocol) {
+            return Err(GitError::InvalidUrl(url));
+        }
+        Ok(url)
+    }
+
+    fn lock(&self, app_id: i64) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks
+            .lock()
+            .expect("source lock map poisoned")
+            .entry(app_id)
+            .or_default()
+            .clone()
+    }
+}
+
+/// The `http.<url>.extraHeader` setting sending the credentials to the
+/// host of `clone_url` only, so submodules elsewhere never see them.
+fn auth_header(clone_url: &str, credentials: &GitCredentials) -> Option<(String, String)> {
+    let url = url::Url::parse(clone_url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    let basic = STANDARD.encode(format!("{}:{}", credentials.username, credentials.token));
+    Some((
+        format!("http.{}/.extraHeader", url.origin().ascii_serialization()),
+        format!("Authorization: Basic {}", basic),
+    ))
+}
+
+/// Runs git in a working tree of the cache.
+struct Git<'a> {
+    dir: &'a Path,
+    /// Clone URL without credentials.
+    url: &'a str,
+    sha: &'a str,
+    protocols: String,
+    timeout: Duration,
+    auth: Option<(String, String)>,
+    /// Removed from error messages.
+    secret: Option<&'a str>,
+}
+
+impl Git<'_> {
+    async fn run(&self, args: &[&str]) -> Result<String, GitError> {
+        let name = args.first().copied().unwrap_or_default();
+
+        // Settings go through the environment so they stay out of the
+        // command line and `.git/config`.
+        let mut config = vec![("credential.helper", ""), ("advice.detachedHead", "false")];
+        if let Some((key, value)) = &self.auth {
+            config.push((key.as_str(), value.as_str()));
+        }
+
+        let mut command = Command::new("git");
+        command
+            .current_dir(self.dir)
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_ALLOW_PROTOCOL", &self.protocols)
+            .env("GIT_CONFIG_COUNT", config.len().to_string())
+            .env_remove("GIT_ASKPASS")
+            .env_remove("SSH_ASKPASS")
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        for (n, (key, value)) in config.iter().enumerate() {
+            command
+                .env(format!("GIT_CONFIG_KEY_{}", n), key)
+                .env(format!("GIT_CONFIG_VALUE_{}", n), value);
+        }
+
+        let output = match tokio::time::timeout(self.timeout, command.output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(GitError::NotInstalled)
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                return Err(GitError::TimedOut {
+                    command: name.to_string(),
+                    secs: self.timeout.as_secs(),
+                })
+            }
+        };
+
+        if !output.status.success() {
+            let mut stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if let Some(secret) = self.secret.filter(|secret| !secret.is_empty()) {
+                stderr = stderr.replace(secret, "[redacted]");
+            }
+            return Err(self.classify(name, stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    async fn has_commit(&self) -> Result<bool, GitError> {
+        let object = format!("{}^{{commit}}", self.sha);
+        match self.run(&["cat-file", "-e", &object]).await {
+            Ok(_) => Ok(true),
+            Err(GitError::CommandFailed { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn commit_not_found(&self) -> GitError {
+        GitError::CommitNotFound {
+            url: self.url.to_string(),
+            sha: self.sha.to_string(),
+        }
+    }
+
+    fn classify(&self, command: &str, stderr: String) -> GitError {
+        let lower = stderr.to_ascii_lowercase();
+        let matches = |errors: &[&str]| errors.iter().any(|error| lower.contains(error));
+
+        if matches(COMMIT_ERRORS) {
+            self.commit_not_found()
+        } else if matches(AUTH_ERRORS) {
+            GitError::AuthFailed(self.url.to_string())
+        } else if matches(NOT_FOUND_ERRORS) {
+            GitError::RepositoryNotFound(self.url.to_string())
+        } else {
+            GitError::CommandFailed {
+                command: command.to_string(),
+                stderr,
+            }
+        }
+    }
+}
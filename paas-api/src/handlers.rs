@@ -1,92 +1,124 @@
 use crate::{
-    auth::{self, SessionUser},
-    config::{self, OAuthProvider},
+    api_tokens::{self, Scope},
+    apps,
+    audit::{self, Event, RequestContext},
+    auth::{self, AuthenticatedUser, Credentials, SessionUser},
+    config::{self, JwtConfig, OAuthUser},
+    crypto::Keyring,
+    deployments::{self, DeploymentStatus},
     error::AppError,
-    models,
+    jobs::{self, JobStatus, NewJob},
+    jwt,
+    models::{
+        ApiToken, AppSettings, Application, AuditEvent, ConnectedProvider, Deployment,
+        DeploymentLogLine, GitProvider, Invitation, Job, ListedApplication, Member, NewApplication,
+        Organization, OrganizationMembership, ProviderInstance, RefreshToken, Repository,
+        RepositorySync, User,
+    },
+    oidc::{self, OAuthTokenResponse},
+    orgs::{self, Invitee, Owner, Role},
+    policy::{self, Action, Resource},
+    providers::{self, Endpoints, Provider},
+    tokens::TokenService,
 };
-use actix_session::Session;
-use actix_web::{web, HttpResponse};
+use actix_session::{Session, SessionExt};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::{stream, TryStreamExt};
 use log::debug;
-use oauth2::{AuthorizationCode, CsrfToken, TokenResponse};
+use oauth2::{AuthorizationCode, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, TokenResponse};
 use serde::Deserialize;
 use serde_json::json;
 use sqlx::SqlitePool;
 
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    name: String,
+    scopes: Vec<Scope>,
+    expires_in_days: Option<i64>,
+}
+
 #[derive(Deserialize)]
 pub struct OAuthCallback {
     code: Option<String>,
-    #[allow(dead_code)]
     state: Option<String>,
     error: Option<String>,
     error_description: Option<String>,
 }
 
-pub async fn github_auth() -> Result<HttpResponse, AppError> {
-    let client = config::github_oauth_client();
-    let (auth_url, _csrf_token) = client
-        .authorize_url(CsrfToken::new_random)
-        .add_scopes(OAuthProvider::GitHub.get_scopes())
-        .url();
+/// Frontend pages an OAuth flow returns to: the login page when signing in,
+/// the settings page when connecting a provider to a signed-in user.
+const LOGIN_PAGE: &str = "/login";
+const SETTINGS_PAGE: &str = "/settings";
 
-    debug!("GitHub auth URL: {}", auth_url);
+fn error_redirect(page: &str, message: &str) -> HttpResponse {
+    let message: String = url::form_urlencoded::byte_serialize(message.as_bytes()).collect();
+    HttpResponse::Found()
+        .append_header((
+            "Location",
+            format!("{}{}?error={}", config::get_frontend_url(), page, message),
+        ))
+        .finish()
+}
 
-    Ok(HttpResponse::Ok().json(json!({
-        "auth_url": auth_url.to_string()
-    })))
+async fn find_instance(pool: &SqlitePool, slug: &str) -> Result<ProviderInstance, AppError> {
+    ProviderInstance::find_by_slug(pool, slug)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Unknown provider: {}", slug)))
 }
 
-pub async fn github_callback(
-    pool: web::Data<SqlitePool>,
-    session: Session,
-    params: web::Query<OAuthCallback>,
-) -> Result<HttpResponse, AppError> {
-    if let Some(error) = &params.error {
-        debug!("GitHub OAuth error: {}", error);
-        let error_msg = params
-            .error_description
-            .as_deref()
-            .unwrap_or("OAuth consent was denied");
-        return Ok(HttpResponse::Found()
-            .append_header((
-                "Location",
-                format!("{}/login?error={}", config::get_frontend_url(), error_msg),
-            ))
-            .finish());
-    }
+async fn fetch_user_profile(
+    spec: &dyn Provider,
+    endpoints: &Endpoints,
+    token: &OAuthTokenResponse,
+) -> Result<serde_json::Value, AppError> {
+    let user_api_url = endpoints.user_api_url.as_deref().ok_or_else(|| {
+        AppError::ExternalServiceError(format!("{} has no user API", spec.name()))
+    })?;
 
-    let code = params
-        .code
-        .as_ref()
-        .ok_or_else(|| AppError::ValidationError("Missing authorization code".to_string()))?;
+    debug!("Getting {} user info...", spec.name());
+    let mut body = get_with_token(spec, user_api_url, token).await?;
+    if let Some(profile_url) = spec.profile_url(&endpoints.base_url, &body) {
+        body = get_with_token(spec, &profile_url, token).await?;
+    }
 
-    debug!("GitHub callback received with code: {}", code);
-    let client = config::github_oauth_client();
+    serde_json::from_str(&body).map_err(|e| {
+        debug!("{} user info parse error: {:?}", spec.name(), e);
+        AppError::AuthError(format!("Failed to parse user info: {}", e))
+    })
+}
 
-    debug!("Exchanging GitHub code for token...");
-    let token = match client
-        .exchange_code(AuthorizationCode::new(code.clone()))
-        .request_async(oauth2::reqwest::async_http_client)
-        .await
-    {
-        Ok(token) => {
-            debug!("GitHub token exchange successful");
-            token
-        }
-        Err(e) => {
-            debug!("GitHub token exchange error: {:?}", e);
-            return Ok(HttpResponse::Found()
-                .append_header((
-                    "Location",
-                    format!("{}/login?error={}", config::get_frontend_url(), e),
-                ))
-                .finish());
-        }
+/// Asks providers whose profile does not say whether the user's email is
+/// verified. Failures leave it unverified rather than failing the sign-in.
+async fn check_email_verified(
+    spec: &dyn Provider,
+    endpoints: &Endpoints,
+    token: &OAuthTokenResponse,
+    oauth_user: &mut OAuthUser,
+) {
+    let (Some(url), Some(email)) = (spec.emails_url(endpoints), oauth_user.email.as_deref()) else {
+        return;
     };
+    let emails = get_with_token(spec, &url, token).await.and_then(|body| {
+        serde_json::from_str::<serde_json::Value>(&body)
+            .map_err(|e| AppError::ExternalServiceError(e.to_string()))
+    });
+    match emails {
+        Ok(emails) => oauth_user.email_verified = spec.email_verified(&emails, email),
+        Err(e) => debug!(
+            "Could not get the {} emails of the user: {}",
+            spec.name(),
+            e
+        ),
+    }
+}
 
-    debug!("Getting GitHub user info...");
-    let client = reqwest::Client::new();
-    let user_data = client
-        .get(OAuthProvider::GitHub.get_user_api_url())
+async fn get_with_token(
+    spec: &dyn Provider,
+    url: &str,
+    token: &OAuthTokenResponse,
+) -> Result<String, AppError> {
+    reqwest::Client::new()
+        .get(url)
         .header(
             reqwest::header::AUTHORIZATION,
             format!("Bearer {}", token.access_token().secret()),
@@ -95,155 +127,350 @@ pub async fn github_callback(
         .send()
         .await
         .map_err(|e| {
-            debug!("GitHub user info error: {:?}", e);
+            debug!("{} user info error: {:?}", spec.name(), e);
             AppError::AuthError(format!("Failed to get user info: {}", e))
         })?
-        .json::<serde_json::Value>()
+        .text()
         .await
         .map_err(|e| {
-            debug!("GitHub user info parse error: {:?}", e);
-            AppError::AuthError(format!("Failed to parse user info: {}", e))
-        })?;
+            debug!("{} user info error: {:?}", spec.name(), e);
+            AppError::AuthError(format!("Failed to get user info: {}", e))
+        })
+}
 
-    debug!("Creating or updating user in database...");
-    let user = models::User::find_or_create(
-        pool.get_ref(),
-        &OAuthProvider::GitHub,
-        &user_data["id"].to_string(),
-        user_data["login"].as_str().unwrap_or(""),
-        user_data["email"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
-        user_data["avatar_url"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
-    )
-    .await?;
+/// Lists the provider instances that can be used to sign in.
+pub async fn list_login_providers(pool: web::Data<SqlitePool>) -> Result<HttpResponse, AppError> {
+    let instances = ProviderInstance::list(pool.get_ref()).await?;
+    let providers: Vec<_> = instances
+        .into_iter()
+        .filter(|instance| instance.is_configured())
+        .map(|instance| {
+            json!({
+                "slug": instance.slug,
+                "provider_type": instance.provider_type,
+                "display_name": instance.display_name
+            })
+        })
+        .collect();
 
-    debug!("Setting session user...");
-    auth::set_session_user(
-        &session,
-        SessionUser {
-            id: user.id,
-            username: user.username.clone(),
-            email: user.email.clone(),
-            provider: "github".to_string(),
-            access_token: token.access_token().secret().to_string(),
-            refresh_token: token.refresh_token().map(|t| t.secret().to_string()),
-        },
-    )?;
+    Ok(HttpResponse::Ok().json(providers))
+}
 
-    debug!("GitHub auth flow completed successfully");
-    Ok(HttpResponse::Found()
-        .append_header((
-            "Location",
-            format!("{}/dashboard", config::get_frontend_url()),
-        ))
-        .finish())
+pub async fn oauth_authorize(
+    pool: web::Data<SqlitePool>,
+    session: Session,
+    provider: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    authorization_url(pool.get_ref(), &session, &provider, None).await
 }
 
-pub async fn gitlab_auth() -> Result<HttpResponse, AppError> {
-    let client = config::gitlab_oauth_client();
-    let (auth_url, _csrf_token) = client
+/// Starts an OAuth flow connecting the provider instance to the signed-in user.
+pub async fn connect_provider(
+    pool: web::Data<SqlitePool>,
+    session: Session,
+    caller: AuthenticatedUser,
+    provider: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    caller.require_session()?;
+    authorization_url(pool.get_ref(), &session, &provider, Some(caller.id())).await
+}
+
+/// Responds with the authorization URL of the provider instance `slug`. The
+/// callback connects the provider to `user_id` if set, and signs in otherwise.
+async fn authorization_url(
+    pool: &SqlitePool,
+    session: &Session,
+    slug: &str,
+    user_id: Option<i64>,
+) -> Result<HttpResponse, AppError> {
+    let instance = find_instance(pool, slug).await?;
+    let spec = instance.spec()?;
+    let endpoints = providers::resolve_endpoints(&instance).await?;
+
+    let client = config::oauth_client(&instance, &endpoints)?;
+    let mut request = client
         .authorize_url(CsrfToken::new_random)
-        .add_scopes(OAuthProvider::GitLab.get_scopes())
-        .url();
+        .add_scopes(spec.scopes());
 
-    debug!("GitLab auth URL: {}", auth_url);
+    let nonce = endpoints
+        .oidc
+        .as_ref()
+        .map(|_| CsrfToken::new_random().secret().to_string());
+    if let Some(nonce) = &nonce {
+        request = request.add_extra_param("nonce", nonce);
+    }
+
+    let pkce_verifier = if spec.uses_pkce() {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        request = request.set_pkce_challenge(pkce_challenge);
+        Some(pkce_verifier)
+    } else {
+        None
+    };
+    let (auth_url, csrf_token) = request.url();
+
+    auth::store_oauth_state(
+        pool,
+        session,
+        &instance.slug,
+        csrf_token.secret(),
+        pkce_verifier.as_ref().map(|v| v.secret().as_str()),
+        nonce.as_deref(),
+        user_id,
+    )
+    .await?;
+
+    debug!("{} auth URL: {}", instance.slug, auth_url);
 
     Ok(HttpResponse::Ok().json(json!({
         "auth_url": auth_url.to_string()
     })))
 }
 
-pub async fn gitlab_callback(
+/// Records a rejected sign-in (or provider connection, when `linking_user_id`
+/// is set) and sends the user back to `page` with `message`.
+async fn callback_failed(
+    pool: &SqlitePool,
+    context: &RequestContext,
+    slug: &str,
+    linking_user_id: Option<i64>,
+    page: &str,
+    message: &str,
+) -> Result<HttpResponse, AppError> {
+    let action = match linking_user_id {
+        Some(_) => "provider.connect.failed",
+        None => "auth.login.failed",
+    };
+    Event {
+        actor_id: linking_user_id,
+        ..Event::new(action)
+    }
+    .target("provider", slug)
+    .metadata(json!({ "reason": message }))
+    .record(pool, context)
+    .await?;
+    Ok(error_redirect(page, message))
+}
+
+pub async fn oauth_callback(
     pool: web::Data<SqlitePool>,
+    token_service: web::Data<TokenService>,
     session: Session,
+    context: RequestContext,
+    provider: web::Path<String>,
     params: web::Query<OAuthCallback>,
 ) -> Result<HttpResponse, AppError> {
+    let instance = find_instance(pool.get_ref(), &provider).await?;
+    let spec = instance.spec()?;
+
     if let Some(error) = &params.error {
-        debug!("GitLab OAuth error: {}", error);
+        debug!("{} OAuth error: {}", spec.name(), error);
         let error_msg = params
             .error_description
             .as_deref()
             .unwrap_or("OAuth consent was denied");
-        return Ok(HttpResponse::Found()
-            .append_header((
-                "Location",
-                format!("{}/login?error={}", config::get_frontend_url(), error_msg),
-            ))
-            .finish());
+        // Consumed either way, so the state cannot be redeemed afterwards.
+        let linking_user_id = auth::verify_oauth_state(
+            pool.get_ref(),
+            &session,
+            &instance.slug,
+            params.state.as_deref(),
+        )
+        .await
+        .ok()
+        .and_then(|oauth_state| oauth_state.user_id);
+        let page = match linking_user_id {
+            Some(_) => SETTINGS_PAGE,
+            None => LOGIN_PAGE,
+        };
+        return callback_failed(
+            pool.get_ref(),
+            &context,
+            &instance.slug,
+            linking_user_id,
+            page,
+            error_msg,
+        )
+        .await;
     }
 
+    let oauth_state = match auth::verify_oauth_state(
+        pool.get_ref(),
+        &session,
+        &instance.slug,
+        params.state.as_deref(),
+    )
+    .await
+    {
+        Ok(oauth_state) => oauth_state,
+        Err(AppError::AuthError(msg)) => {
+            debug!("{} OAuth state rejected: {}", spec.name(), msg);
+            return callback_failed(
+                pool.get_ref(),
+                &context,
+                &instance.slug,
+                None,
+                LOGIN_PAGE,
+                &msg,
+            )
+            .await;
+        }
+        Err(e) => return Err(e),
+    };
+
+    let linking_user_id = oauth_state.user_id;
+    let error_page = match linking_user_id {
+        Some(_) => SETTINGS_PAGE,
+        None => LOGIN_PAGE,
+    };
+
     let code = params
         .code
         .as_ref()
         .ok_or_else(|| AppError::ValidationError("Missing authorization code".to_string()))?;
 
-    debug!("GitLab callback received with code: {}", code);
-    let client = config::gitlab_oauth_client();
+    debug!("{} callback received with code: {}", spec.name(), code);
+    let endpoints = providers::resolve_endpoints(&instance).await?;
+    let client = config::oauth_client(&instance, &endpoints)?;
 
-    debug!("Exchanging GitLab code for token...");
-    let token = match client
-        .exchange_code(AuthorizationCode::new(code.clone()))
+    debug!("Exchanging {} code for token...", spec.name());
+    let mut request = client.exchange_code(AuthorizationCode::new(code.clone()));
+    if let Some(pkce_verifier) = oauth_state.pkce_verifier {
+        request = request.set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier));
+    }
+    let token = match request
         .request_async(oauth2::reqwest::async_http_client)
         .await
     {
         Ok(token) => {
-            debug!("GitLab token exchange successful");
+            debug!("{} token exchange successful", spec.name());
             token
         }
         Err(e) => {
-            debug!("GitLab token exchange error: {:?}", e);
-            return Ok(HttpResponse::Found()
-                .append_header((
-                    "Location",
-                    format!("{}/login?error={}", config::get_frontend_url(), e),
-                ))
-                .finish());
+            debug!("{} token exchange error: {:?}", spec.name(), e);
+            return callback_failed(
+                pool.get_ref(),
+                &context,
+                &instance.slug,
+                linking_user_id,
+                error_page,
+                &e.to_string(),
+            )
+            .await;
         }
     };
 
-    debug!("Getting GitLab user info...");
-    let client = reqwest::Client::new();
-    let user_data = client
-        .get(OAuthProvider::GitLab.get_user_api_url())
-        .header(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {}", token.access_token().secret()),
+    let identity = match &endpoints.oidc {
+        Some(metadata) => match oidc::id_token(&token) {
+            Some(id_token) => {
+                debug!("Verifying {} ID token...", spec.name());
+                oidc::verify_id_token(
+                    metadata,
+                    id_token,
+                    &instance.effective_client_id()?,
+                    oauth_state.nonce.as_deref().unwrap_or_default(),
+                )
+                .await
+            }
+            None => Err(AppError::AuthError(
+                "Token response did not include an ID token".to_string(),
+            )),
+        },
+        None => fetch_user_profile(spec, &endpoints, &token).await,
+    };
+    let mut oauth_user = match identity.and_then(|profile| spec.map_user(&profile)) {
+        Ok(oauth_user) => oauth_user,
+        Err(AppError::AuthError(msg)) => {
+            debug!("{} identity rejected: {}", spec.name(), msg);
+            return callback_failed(
+                pool.get_ref(),
+                &context,
+                &instance.slug,
+                linking_user_id,
+                error_page,
+                &msg,
+            )
+            .await;
+        }
+        Err(e) => return Err(e),
+    };
+
+    if let Some(user_id) = linking_user_id {
+        let user = match user_to_link(
+            pool.get_ref(),
+            token_service.keyring(),
+            &session,
+            user_id,
+            &instance,
+            &oauth_user,
         )
-        .send()
-        .await
-        .map_err(|e| {
-            debug!("GitLab user info error: {:?}", e);
-            AppError::AuthError(format!("Failed to get user info: {}", e))
-        })?
-        .json::<serde_json::Value>()
         .await
-        .map_err(|e| {
-            debug!("GitLab user info parse error: {:?}", e);
-            AppError::AuthError(format!("Failed to parse user info: {}", e))
-        })?;
+        {
+            Ok(user) => user,
+            Err(AppError::AuthError(msg)) => {
+                debug!("{} connection rejected: {}", spec.name(), msg);
+                return callback_failed(
+                    pool.get_ref(),
+                    &context,
+                    &instance.slug,
+                    Some(user_id),
+                    SETTINGS_PAGE,
+                    &msg,
+                )
+                .await;
+            }
+            Err(e) => return Err(e),
+        };
 
+        debug!("Storing {} tokens...", instance.slug);
+        token_service
+            .store(user.id, &instance.slug, &oauth_user.id, &token)
+            .await?;
+
+        Event::new("provider.connect")
+            .actor(&user)
+            .target("provider", &instance.slug)
+            .metadata(json!({ "provider_user_id": oauth_user.id }))
+            .record(pool.get_ref(), &context)
+            .await?;
+        debug!("{} connected to user {}", instance.slug, user.id);
+        return Ok(HttpResponse::Found()
+            .append_header((
+                "Location",
+                format!(
+                    "{}{}?connected={}",
+                    config::get_frontend_url(),
+                    SETTINGS_PAGE,
+                    instance.slug
+                ),
+            ))
+            .finish());
+    }
+
+    check_email_verified(spec, &endpoints, &token, &mut oauth_user).await;
     debug!("Creating or updating user in database...");
-    let user = models::User::find_or_create(
+    let user = User::find_or_create(
         pool.get_ref(),
-        &OAuthProvider::GitLab,
-        &user_data["id"].to_string(),
-        user_data["username"].as_str().unwrap_or(""),
-        user_data["email"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
-        user_data["avatar_url"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
+        token_service.keyring(),
+        &instance.slug,
+        &oauth_user.id,
+        &oauth_user.username,
+        oauth_user.email.as_deref(),
+        oauth_user.avatar_url.as_deref(),
+    )
+    .await?
+    .set_email_verified(
+        pool.get_ref(),
+        oauth_user.email.as_deref(),
+        oauth_user.email_verified,
     )
     .await?;
 
+    debug!("Storing {} tokens...", instance.slug);
+    token_service
+        .store(user.id, &instance.slug, &oauth_user.id, &token)
+        .await?;
+
     debug!("Setting session user...");
     auth::set_session_user(
         &session,
@@ -251,13 +478,16 @@ pub async fn gitlab_callback(
             id: user.id,
             username: user.username.clone(),
             email: user.email.clone(),
-            provider: "gitlab".to_string(),
-            access_token: token.access_token().secret().to_string(),
-            refresh_token: token.refresh_token().map(|t| t.secret().to_string()),
+            provider: instance.slug.clone(),
         },
     )?;
 
-    debug!("GitLab auth flow completed successfully");
+    Event::new("auth.login")
+        .actor(&user)
+        .target("provider", &instance.slug)
+        .record(pool.get_ref(), &context)
+        .await?;
+    debug!("{} auth flow completed successfully", spec.name());
     Ok(HttpResponse::Found()
         .append_header((
             "Location",
@@ -266,143 +496,1326 @@ pub async fn gitlab_callback(
         .finish())
 }
 
-pub async fn bitbucket_auth() -> Result<HttpResponse, AppError> {
-    let client = config::bitbucket_oauth_client();
-    let (auth_url, _csrf_token) = client
-        .authorize_url(CsrfToken::new_random)
-        .add_scopes(OAuthProvider::Bitbucket.get_scopes())
-        .url();
+/// Checks that the provider account `oauth_user` may be connected to the
+/// signed-in user `user_id`. Rejections are reported as `AppError::AuthError`.
+async fn user_to_link(
+    pool: &SqlitePool,
+    keyring: &Keyring,
+    session: &Session,
+    user_id: i64,
+    instance: &ProviderInstance,
+    oauth_user: &OAuthUser,
+) -> Result<User, AppError> {
+    let sign_in_again = || {
+        AppError::AuthError(format!(
+            "Sign in again to connect {}",
+            instance.display_name
+        ))
+    };
+    let signed_in = auth::get_session_user(session).await?;
+    if signed_in.map(|user| user.id) != Some(user_id) {
+        return Err(sign_in_again());
+    }
+    let user = User::find(pool, keyring, user_id)
+        .await?
+        .ok_or_else(sign_in_again)?;
 
-    debug!("Bitbucket auth URL: {}", auth_url);
+    if let Some(owner) =
+        User::find_by_identity(pool, keyring, &instance.slug, &oauth_user.id).await?
+    {
+        if owner.id != user.id {
+            return Err(AppError::AuthError(format!(
+                "This {} account is already linked to another user",
+                instance.display_name
+            )));
+        }
+    }
 
+    let connected_as = if user.provider == instance.slug {
+        Some(user.provider_user_id.clone())
+    } else {
+        GitProvider::find(pool, keyring, user.id, &instance.slug)
+            .await?
+            .and_then(|connection| connection.provider_user_id)
+    };
+    if connected_as.is_some_and(|id| id != oauth_user.id) {
+        return Err(AppError::AuthError(format!(
+            "Disconnect your current {} account first",
+            instance.display_name
+        )));
+    }
+
+    Ok(user)
+}
+
+/// Lists the signed-in user's provider connections.
+pub async fn list_connected_providers(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let providers = ConnectedProvider::list(pool.get_ref(), caller.id()).await?;
+    Ok(HttpResponse::Ok().json(providers))
+}
+
+pub async fn disconnect_provider(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    provider: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    if !GitProvider::delete(pool.get_ref(), caller.id(), &provider).await? {
+        return Err(AppError::NotFound(format!("No {} connection", provider)));
+    }
+    Event::new("provider.disconnect")
+        .actor(&caller.user)
+        .target("provider", provider.as_str())
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn logout(
+    pool: web::Data<SqlitePool>,
+    keyring: web::Data<Keyring>,
+    session: Session,
+    context: RequestContext,
+) -> Result<HttpResponse, AppError> {
+    if let Some(session_user) = auth::get_session_user(&session).await? {
+        if let Some(user) = User::find(pool.get_ref(), &keyring, session_user.id).await? {
+            Event::new("auth.logout")
+                .actor(&user)
+                .record(pool.get_ref(), &context)
+                .await?;
+        }
+    }
+    // Refresh tokens obtained with the session end with it.
+    RefreshToken::revoke_families(pool.get_ref(), &auth::refresh_families(&session)?).await?;
+    auth::clear_session(&session)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_current_user(caller: AuthenticatedUser) -> Result<HttpResponse, AppError> {
+    let user = caller.user;
     Ok(HttpResponse::Ok().json(json!({
-        "auth_url": auth_url.to_string()
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "is_admin": user.is_admin,
     })))
 }
 
-pub async fn bitbucket_callback(
+pub async fn list_api_tokens(
     pool: web::Data<SqlitePool>,
-    session: Session,
-    params: web::Query<OAuthCallback>,
+    caller: AuthenticatedUser,
 ) -> Result<HttpResponse, AppError> {
-    if let Some(error) = &params.error {
-        debug!("Bitbucket OAuth error: {}", error);
-        let error_msg = params
-            .error_description
-            .as_deref()
-            .unwrap_or("OAuth consent was denied");
-        return Ok(HttpResponse::Found()
-            .append_header((
-                "Location",
-                format!("{}/login?error={}", config::get_frontend_url(), error_msg),
-            ))
-            .finish());
+    let tokens = ApiToken::list(pool.get_ref(), caller.id()).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Creates a personal access token. The response is the only time the token
+/// is shown. Tokens can only be created from a browser session, so a leaked
+/// token cannot be used to mint more.
+pub async fn create_api_token(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    body: web::Json<NewApiToken>,
+) -> Result<HttpResponse, AppError> {
+    caller.require_session()?;
+
+    let name = body.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AppError::ValidationError(
+            "Token name must be between 1 and 100 characters".to_string(),
+        ));
+    }
+    if body.scopes.is_empty() {
+        return Err(AppError::ValidationError(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    let expires_in_days = body
+        .expires_in_days
+        .unwrap_or(api_tokens::DEFAULT_EXPIRY_DAYS);
+    if !(1..=api_tokens::MAX_EXPIRY_DAYS).contains(&expires_in_days) {
+        return Err(AppError::ValidationError(format!(
+            "Tokens must expire within 1 to {} days",
+            api_tokens::MAX_EXPIRY_DAYS
+        )));
     }
 
-    let code = params
-        .code
-        .as_ref()
-        .ok_or_else(|| AppError::ValidationError("Missing authorization code".to_string()))?;
+    let scopes: Vec<Scope> = [Scope::Read, Scope::Write]
+        .into_iter()
+        .filter(|scope| body.scopes.contains(scope))
+        .collect();
+    let (token, token_prefix) = api_tokens::generate();
+    let api_token = ApiToken::create(
+        pool.get_ref(),
+        caller.id(),
+        name,
+        &api_tokens::hash(&token),
+        &token_prefix,
+        &scopes,
+        expires_in_days * 24 * 60 * 60,
+    )
+    .await?;
+    Event::new("api_token.create")
+        .actor(&caller.user)
+        .target("api_token", api_token.id)
+        .metadata(json!({
+            "name": api_token.name,
+            "scopes": api_tokens::format_scopes(&scopes),
+            "expires_at": api_token.expires_at,
+        }))
+        .record(pool.get_ref(), &context)
+        .await?;
+
+    let mut body = serde_json::to_value(&api_token)
+        .map_err(|e| AppError::DatabaseError(format!("Failed to serialize token: {}", e)))?;
+    body["token"] = json!(token);
+    Ok(HttpResponse::Created().json(body))
+}
 
-    debug!("Bitbucket callback received with code: {}", code);
-    let client = config::bitbucket_oauth_client();
+pub async fn revoke_api_token(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, AppError> {
+    if !ApiToken::delete(pool.get_ref(), caller.id(), *id).await? {
+        return Err(AppError::NotFound(format!("No API token {}", id)));
+    }
+    Event::new("api_token.revoke")
+        .actor(&caller.user)
+        .target("api_token", *id)
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    debug!("Exchanging Bitbucket code for token...");
-    let token = match client
-        .exchange_code(AuthorizationCode::new(code.clone()))
-        .request_async(oauth2::reqwest::async_http_client)
-        .await
-    {
-        Ok(token) => {
-            debug!("Bitbucket token exchange successful");
-            token
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub refresh_token: Option<String>,
+}
+
+/// Issues a short-lived access token (a JWT) and a refresh token.
+///
+/// With a `refresh_token` in the body, that token is redeemed for a new pair.
+/// Otherwise the caller's session or API token is exchanged; the access token
+/// gets the scopes of the credential it was issued for.
+pub async fn issue_token(
+    pool: web::Data<SqlitePool>,
+    keyring: web::Data<Keyring>,
+    jwt_config: web::Data<JwtConfig>,
+    context: RequestContext,
+    req: HttpRequest,
+    body: Option<web::Json<TokenRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let refresh_ttl = jwt_config.refresh_ttl.whole_seconds();
+    let refresh_token = jwt::generate_refresh_token();
+    let refresh_hash = api_tokens::hash(&refresh_token);
+
+    let presented = body.and_then(|body| body.into_inner().refresh_token);
+    let stored = match presented {
+        Some(presented) => RefreshToken::rotate(
+            pool.get_ref(),
+            &api_tokens::hash(&presented),
+            &refresh_hash,
+            refresh_ttl,
+        )
+        .await?
+        .ok_or_else(|| AppError::AuthError("Invalid refresh token".to_string()))?,
+        None => {
+            let caller = auth::identify(&req).await?;
+            let from_session = caller.credentials == Credentials::Session;
+            let (scopes, api_token_id) = match caller.credentials {
+                Credentials::Session => (vec![Scope::Read, Scope::Write], None),
+                Credentials::ApiToken { id, scopes } => (scopes, Some(id)),
+                Credentials::Jwt { .. } => {
+                    return Err(AppError::AuthError(
+                        "Use the refresh token to renew an access token".to_string(),
+                    ))
+                }
+            };
+            let family_id = jwt::generate_family_id();
+            let stored = RefreshToken::create(
+                pool.get_ref(),
+                caller.user_id,
+                api_token_id,
+                &family_id,
+                &refresh_hash,
+                &scopes,
+                refresh_ttl,
+            )
+            .await?;
+            if from_session {
+                auth::add_refresh_family(&req.get_session(), &family_id)?;
+            }
+            // Refreshes continue the same family and are not recorded.
+            if let Some(user) = User::find(pool.get_ref(), &keyring, caller.user_id).await? {
+                Event::new("auth.token.issue")
+                    .actor(&user)
+                    .metadata(json!({
+                        "scopes": api_tokens::format_scopes(&scopes),
+                        "api_token_id": api_token_id,
+                    }))
+                    .record(pool.get_ref(), &context)
+                    .await?;
+            }
+            stored
         }
-        Err(e) => {
-            debug!("Bitbucket token exchange error: {:?}", e);
-            return Ok(HttpResponse::Found()
-                .append_header((
-                    "Location",
-                    format!("{}/login?error={}", config::get_frontend_url(), e),
-                ))
-                .finish());
+    };
+
+    let scopes = stored.scopes();
+    let access_token = jwt::issue(&jwt_config, stored.user_id, &scopes)?;
+    debug!("Issued access token for user {}", stored.user_id);
+    Ok(HttpResponse::Ok().json(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": jwt_config.ttl.whole_seconds(),
+        "refresh_token": refresh_token,
+        "scope": api_tokens::format_scopes(&scopes),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewOrganization {
+    pub name: String,
+    /// Derived from the name when omitted.
+    pub slug: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewInvitation {
+    /// A username or an email address.
+    pub invitee: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+/// The organization `slug` with the caller's role in it. Organizations the
+/// caller does not belong to are reported as not found.
+async fn membership(
+    pool: &SqlitePool,
+    slug: &str,
+    caller: &AuthenticatedUser,
+) -> Result<OrganizationMembership, AppError> {
+    Organization::find_for_member(pool, slug, caller.id())
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No organization {}", slug)))
+}
+
+/// Checks `action` against the caller's role, and that their role manages
+/// `role`, the role of the member or invitation acted on.
+fn require_manager(
+    membership: &OrganizationMembership,
+    action: Action,
+    role: Role,
+) -> Result<(), AppError> {
+    policy::require(membership.role, action)?;
+    if !membership.role.manages(role) {
+        return Err(AppError::Forbidden(
+            "Only owners can manage owners".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn list_organizations(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let organizations = Organization::list_for_member(pool.get_ref(), caller.id()).await?;
+    Ok(HttpResponse::Ok().json(organizations))
+}
+
+/// Creates an organization with the caller as its owner.
+pub async fn create_organization(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    body: web::Json<NewOrganization>,
+) -> Result<HttpResponse, AppError> {
+    let name = body.name.trim();
+    orgs::validate_name(name)?;
+    let slug = match body.slug.as_deref().map(str::trim) {
+        Some(slug) if !slug.is_empty() => slug.to_string(),
+        _ => orgs::slugify(name),
+    };
+    orgs::validate_slug(&slug)?;
+
+    let organization = Organization::create(pool.get_ref(), &slug, name, caller.id()).await?;
+    Event::new("org.create")
+        .actor(&caller.user)
+        .organization(organization.id)
+        .target("organization", organization.id)
+        .metadata(json!({ "slug": organization.slug, "name": organization.name }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    debug!(
+        "User {} created organization {}",
+        caller.id(),
+        organization.slug
+    );
+    Ok(HttpResponse::Created().json(OrganizationMembership {
+        organization,
+        role: Role::Owner,
+    }))
+}
+
+pub async fn get_organization(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    policy::require(membership.role, Action::OrgRead)?;
+    Ok(HttpResponse::Ok().json(membership))
+}
+
+/// Deletes an organization along with everything it owns.
+pub async fn delete_organization(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    policy::require(membership.role, Action::OrgDelete)?;
+    let organization = membership.organization;
+    Organization::delete(pool.get_ref(), organization.id).await?;
+    Event::new("org.delete")
+        .actor(&caller.user)
+        .organization(organization.id)
+        .target("organization", organization.id)
+        .metadata(json!({ "slug": organization.slug, "name": organization.name }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn list_members(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    policy::require(membership.role, Action::OrgRead)?;
+    let members = Member::list(pool.get_ref(), membership.organization.id).await?;
+    Ok(HttpResponse::Ok().json(members))
+}
+
+pub async fn update_member(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    path: web::Path<(String, i64)>,
+    body: web::Json<RoleUpdate>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, user_id) = path.into_inner();
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    let organization_id = membership.organization.id;
+    let member = Member::find(pool.get_ref(), organization_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No member {}", user_id)))?;
+    require_manager(&membership, Action::OrgMemberUpdate, member.role)?;
+    require_manager(&membership, Action::OrgMemberUpdate, body.role)?;
+
+    Member::set_role(pool.get_ref(), organization_id, user_id, body.role).await?;
+    Event::new("org.member.update")
+        .actor(&caller.user)
+        .organization(organization_id)
+        .target("user", user_id)
+        .metadata(json!({
+            "username": member.username,
+            "role": body.role,
+            "previous_role": member.role,
+        }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    let member = Member::find(pool.get_ref(), organization_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No member {}", user_id)))?;
+    Ok(HttpResponse::Ok().json(member))
+}
+
+/// Removes a member. Any member may leave; removing someone else takes a
+/// role that manages theirs.
+pub async fn remove_member(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    path: web::Path<(String, i64)>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, user_id) = path.into_inner();
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    let organization_id = membership.organization.id;
+    let member = Member::find(pool.get_ref(), organization_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No member {}", user_id)))?;
+    if user_id != caller.id() {
+        require_manager(&membership, Action::OrgMemberRemove, member.role)?;
+    }
+
+    if !Member::remove(pool.get_ref(), organization_id, user_id).await? {
+        return Err(AppError::NotFound(format!("No member {}", user_id)));
+    }
+    let action = match user_id == caller.id() {
+        true => "org.member.leave",
+        false => "org.member.remove",
+    };
+    Event::new(action)
+        .actor(&caller.user)
+        .organization(organization_id)
+        .target("user", user_id)
+        .metadata(json!({ "username": member.username, "role": member.role }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn list_invitations(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    policy::require(membership.role, Action::OrgMemberInvite)?;
+    let invitations = Invitation::list(pool.get_ref(), membership.organization.id).await?;
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+/// Invites a user by username or email address. Inviting someone again
+/// replaces their earlier invitation.
+pub async fn create_invitation(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    slug: web::Path<String>,
+    body: web::Json<NewInvitation>,
+) -> Result<HttpResponse, AppError> {
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    require_manager(&membership, Action::OrgMemberInvite, body.role)?;
+    let invitee: Invitee = body.invitee.parse()?;
+
+    let invitation = Invitation::create(
+        pool.get_ref(),
+        membership.organization.id,
+        &invitee,
+        body.role,
+        caller.id(),
+    )
+    .await?;
+    Event::new("org.invitation.create")
+        .actor(&caller.user)
+        .organization(membership.organization.id)
+        .target("invitation", invitation.id)
+        .metadata(json!({ "invitee": invitation.invitee, "role": invitation.role }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::Created().json(invitation))
+}
+
+pub async fn delete_invitation(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    path: web::Path<(String, i64)>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, id) = path.into_inner();
+    let membership = membership(pool.get_ref(), &slug, &caller).await?;
+    policy::require(membership.role, Action::OrgMemberInvite)?;
+    if !Invitation::delete(pool.get_ref(), membership.organization.id, id).await? {
+        return Err(AppError::NotFound(format!("No invitation {}", id)));
+    }
+    Event::new("org.invitation.delete")
+        .actor(&caller.user)
+        .organization(membership.organization.id)
+        .target("invitation", id)
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lists the invitations addressed to the caller.
+pub async fn list_my_invitations(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let invitations = Invitation::list_for_user(pool.get_ref(), &caller.user).await?;
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+pub async fn accept_invitation(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, AppError> {
+    let membership = Invitation::accept(pool.get_ref(), *id, &caller.user)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No invitation {}", id)))?;
+    Event::new("org.invitation.accept")
+        .actor(&caller.user)
+        .organization(membership.organization.id)
+        .target("invitation", *id)
+        .metadata(json!({ "role": membership.role }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    debug!(
+        "User {} joined organization {}",
+        caller.id(),
+        membership.organization.slug
+    );
+    Ok(HttpResponse::Ok().json(membership))
+}
+
+pub async fn decline_invitation(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, AppError> {
+    let organization_id = Invitation::decline(pool.get_ref(), *id, &caller.user)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No invitation {}", id)))?;
+    Event::new("org.invitation.decline")
+        .actor(&caller.user)
+        .organization(organization_id)
+        .target("invitation", *id)
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositorySyncRequest {
+    /// The provider instance to sync; every connection if omitted.
+    pub provider: Option<String>,
+    /// Sync in a background job instead of during the request.
+    #[serde(default)]
+    pub background: bool,
+}
+
+/// Imports the caller's repositories from their provider connections.
+/// Connections whose provider cannot list repositories are skipped; a failing
+/// connection is reported without stopping the others.
+pub async fn sync_repositories(
+    pool: web::Data<SqlitePool>,
+    token_service: web::Data<TokenService>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    body: Option<web::Json<RepositorySyncRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let (only, background) = match body {
+        Some(body) => {
+            let body = body.into_inner();
+            (body.provider, body.background)
         }
+        None => (None, false),
     };
 
-    debug!("Getting Bitbucket user info...");
-    let client = reqwest::Client::new();
-    let user_data = client
-        .get(OAuthProvider::Bitbucket.get_user_api_url())
-        .header(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {}", token.access_token().secret()),
+    if background {
+        let job = NewJob::new(
+            jobs::REPOSITORY_SYNC,
+            json!({ "user_id": caller.id(), "provider": only }),
         )
-        .send()
-        .await
-        .map_err(|e| {
-            debug!("Bitbucket user info error: {:?}", e);
-            AppError::AuthError(format!("Failed to get user info: {}", e))
-        })?
-        .json::<serde_json::Value>()
-        .await
-        .map_err(|e| {
-            debug!("Bitbucket user info parse error: {:?}", e);
-            AppError::AuthError(format!("Failed to parse user info: {}", e))
-        })?;
+        .enqueue(pool.get_ref())
+        .await?;
+        Event::new("repository.sync")
+            .actor(&caller.user)
+            .target("job", job.id)
+            .record(pool.get_ref(), &context)
+            .await?;
+        return Ok(HttpResponse::Accepted().json(job));
+    }
 
-    debug!("Creating or updating user in database...");
-    let user = models::User::find_or_create(
+    let results =
+        sync_user_repositories(pool.get_ref(), &token_service, caller.id(), only.as_deref())
+            .await?;
+    Event::new("repository.sync")
+        .actor(&caller.user)
+        .metadata(json!({ "results": results }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Syncs the repositories of user `user_id` from each of their connections,
+/// or only the one to `only`. Returns the outcome for each connection.
+pub async fn sync_user_repositories(
+    pool: &SqlitePool,
+    token_service: &TokenService,
+    user_id: i64,
+    only: Option<&str>,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    let mut connections = ConnectedProvider::list(pool, user_id).await?;
+    if let Some(only) = only {
+        connections.retain(|connection| connection.provider == only);
+        if connections.is_empty() {
+            return Err(AppError::NotFound(format!("No {} connection", only)));
+        }
+    }
+
+    let mut results = Vec::new();
+    for connection in connections {
+        let instance = find_instance(pool, &connection.provider).await?;
+        let spec = instance.spec()?;
+        let endpoints = providers::resolve_endpoints(&instance).await?;
+        if spec.repositories_url(&endpoints).is_none() {
+            continue;
+        }
+
+        let synced = sync_connection(
+            pool,
+            token_service,
+            user_id,
+            &connection.provider,
+            spec,
+            &endpoints,
+        )
+        .await;
+        results.push(match synced {
+            Ok(sync) => json!({
+                "provider": connection.provider,
+                "synced": sync.synced,
+                "removed": sync.removed,
+            }),
+            Err(e) => {
+                debug!("{} repository sync failed: {}", connection.provider, e);
+                json!({ "provider": connection.provider, "error": e.to_string() })
+            }
+        });
+    }
+    Ok(results)
+}
+
+async fn sync_connection(
+    pool: &SqlitePool,
+    token_service: &TokenService,
+    user_id: i64,
+    provider: &str,
+    spec: &dyn Provider,
+    endpoints: &Endpoints,
+) -> Result<RepositorySync, AppError> {
+    let access_token = token_service.access_token(user_id, provider).await?;
+    let repositories = providers::fetch_repositories(spec, endpoints, &access_token).await?;
+    let connection = GitProvider::find(pool, token_service.keyring(), user_id, provider)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No {} connection", provider)))?;
+    Ok(Repository::sync(pool, user_id, connection.id, &repositories).await?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryQuery {
+    /// Matches anywhere in the repository name.
+    pub q: Option<String>,
+    pub provider: Option<String>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+/// Lists the caller's imported repositories by name, a page at a time.
+pub async fn list_repositories(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    query: web::Query<RepositoryQuery>,
+) -> Result<HttpResponse, AppError> {
+    const DEFAULT_PER_PAGE: i64 = 30;
+    const MAX_PER_PAGE: i64 = 100;
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page < 1 || !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(AppError::ValidationError(format!(
+            "Page must be at least 1 and per_page between 1 and {}",
+            MAX_PER_PAGE
+        )));
+    }
+    let search = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let provider = query.provider.as_deref().filter(|p| !p.is_empty());
+
+    let (repositories, total) = Repository::list(
         pool.get_ref(),
-        &OAuthProvider::Bitbucket,
-        &user_data["uuid"].to_string(),
-        user_data["username"].as_str().unwrap_or(""),
-        user_data["email"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
-        user_data["links"]["avatar"]["href"]
-            .as_str()
-            .map(|s| s.to_string())
-            .as_deref(),
+        caller.id(),
+        search,
+        provider,
+        per_page,
+        (page - 1) * per_page,
     )
     .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "repositories": repositories,
+        "total": total,
+        "page": page,
+        "per_page": per_page,
+    })))
+}
 
-    debug!("Setting session user...");
-    auth::set_session_user(
-        &session,
-        SessionUser {
-            id: user.id,
-            username: user.username.clone(),
-            email: user.email.clone(),
-            provider: "bitbucket".to_string(),
-            access_token: token.access_token().secret().to_string(),
-            refresh_token: token.refresh_token().map(|t| t.secret().to_string()),
+#[derive(Debug, Deserialize)]
+pub struct AppQuery {
+    /// The organization whose apps to list; the caller's own otherwise.
+    pub org: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApp {
+    pub name: String,
+    /// Derived from the name when omitted.
+    pub slug: Option<String>,
+    /// The organization to create the app in; the caller's own account
+    /// otherwise.
+    pub org: Option<String>,
+    #[serde(flatten)]
+    pub settings: AppSettings,
+}
+
+/// Replaces the name and settings of an app.
+#[derive(Debug, Deserialize)]
+pub struct AppUpdate {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: AppSettings,
+}
+
+/// The owner named by `org`, the caller's own account when None, with the
+/// caller's role over it.
+async fn owner_role(
+    pool: &SqlitePool,
+    caller: &AuthenticatedUser,
+    org: Option<&str>,
+) -> Result<(Owner, Role), AppError> {
+    match org.map(str::trim).filter(|org| !org.is_empty()) {
+        Some(slug) => {
+            let membership = membership(pool, slug, caller).await?;
+            Ok((
+                Owner::Organization(membership.organization.id),
+                membership.role,
+            ))
+        }
+        None => Ok((Owner::User(caller.id()), Role::Owner)),
+    }
+}
+
+/// The app `slug` if the caller's role allows `action` on it. Apps the
+/// caller has no access to are reported as not found.
+async fn find_app(
+    pool: &SqlitePool,
+    caller: &AuthenticatedUser,
+    slug: &str,
+    action: Action,
+) -> Result<ListedApplication, AppError> {
+    let not_found = || AppError::NotFound(format!("No app {}", slug));
+    let app = Application::find_by_slug(pool, slug)
+        .await?
+        .ok_or_else(not_found)?;
+    let role = policy::role(pool, caller.id(), app.app.owner())
+        .await?
+        .ok_or_else(not_found)?;
+    policy::require(role, action)?;
+    Ok(app)
+}
+
+/// Validates an app's name and settings. Apps may be built from any
+/// repository the caller can read, so an organization's app can use a
+/// member's personal repository while they remain a member; see
+/// `git::repository_credentials`.
+async fn app_settings(
+    pool: &SqlitePool,
+    caller: &AuthenticatedUser,
+    name: &str,
+    settings: AppSettings,
+) -> Result<AppSettings, AppError> {
+    apps::validate_name(name)?;
+    let settings = apps::normalize_settings(settings)?;
+    if let Some(id) = settings.repository_id {
+        let not_found = || AppError::NotFound(format!("No repository {}", id));
+        let repository = Repository::find(pool, id).await?.ok_or_else(not_found)?;
+        let resource = Resource::Repository(repository.owner());
+        if !policy::can(pool, &caller.user, Action::RepoRead, resource).await? {
+            return Err(not_found());
+        }
+    }
+    Ok(settings)
+}
+
+fn app_event(action: &'static str, caller: &AuthenticatedUser, app: &Application) -> Event {
+    let mut event = Event::new(action)
+        .actor(&caller.user)
+        .target("app", app.id)
+        .metadata(json!({ "slug": app.slug, "name": app.name }));
+    event.organization_id = app.organization_id;
+    event
+}
+
+pub async fn list_apps(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    query: web::Query<AppQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (owner, role) = owner_role(pool.get_ref(), &caller, query.org.as_deref()).await?;
+    policy::require(role, Action::AppRead)?;
+    let apps = Application::list(pool.get_ref(), owner).await?;
+    Ok(HttpResponse::Ok().json(apps))
+}
+
+pub async fn create_app(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    body: web::Json<NewApp>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner();
+    let (owner, role) = owner_role(pool.get_ref(), &caller, body.org.as_deref()).await?;
+    policy::require(role, Action::AppCreate)?;
+
+    let name = body.name.trim();
+    let settings = app_settings(pool.get_ref(), &caller, name, body.settings).await?;
+    let slug = match body.slug.as_deref().map(str::trim) {
+        Some(slug) if !slug.is_empty() => slug.to_string(),
+        _ => orgs::slugify(name),
+    };
+    apps::validate_slug(&slug)?;
+
+    let app = Application::create(
+        pool.get_ref(),
+        &NewApplication {
+            slug,
+            name: name.to_string(),
+            user_id: caller.id(),
+            organization_id: owner.organization_id(),
+            settings,
         },
-    )?;
+    )
+    .await?;
+    app_event("app.create", &caller, &app)
+        .record(pool.get_ref(), &context)
+        .await?;
+    debug!("User {} created app {}", caller.id(), app.slug);
 
-    debug!("Bitbucket auth flow completed successfully");
-    Ok(HttpResponse::Found()
-        .append_header((
-            "Location",
-            format!("{}/dashboard", config::get_frontend_url()),
+    let app = Application::find_by_slug(pool.get_ref(), &app.slug)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No app {}", app.slug)))?;
+    Ok(HttpResponse::Created().json(app))
+}
+
+pub async fn get_app(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let app = find_app(pool.get_ref(), &caller, &slug, Action::AppRead).await?;
+    Ok(HttpResponse::Ok().json(app))
+}
+
+pub async fn update_app(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    slug: web::Path<String>,
+    body: web::Json<AppUpdate>,
+) -> Result<HttpResponse, AppError> {
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppUpdate).await?;
+    let body = body.into_inner();
+    let name = body.name.trim();
+    let settings = app_settings(pool.get_ref(), &caller, name, body.settings).await?;
+
+    let app = Application {
+        name: name.to_string(),
+        settings,
+        ..app
+    }
+    .update(pool.get_ref())
+    .await?;
+    app_event("app.update", &caller, &app)
+        .record(pool.get_ref(), &context)
+        .await?;
+
+    let app = find_app(pool.get_ref(), &caller, &slug, Action::AppRead).await?;
+    Ok(HttpResponse::Ok().json(app))
+}
+
+pub async fn delete_app(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    slug: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppDelete).await?;
+    Application::delete(pool.get_ref(), app.id).await?;
+    app_event("app.delete", &caller, &app)
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDeployment {
+    pub commit_sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentQuery {
+    pub limit: Option<i64>,
+}
+
+/// Deployment `id` of `app`.
+async fn find_deployment(
+    pool: &SqlitePool,
+    app: &Application,
+    id: i64,
+) -> Result<Deployment, AppError> {
+    Deployment::find(pool, id)
+        .await?
+        .filter(|deployment| deployment.app_id == app.id)
+        .ok_or_else(|| AppError::NotFound(format!("No deployment {}", id)))
+}
+
+fn deployment_event(
+    action: &'static str,
+    caller: &AuthenticatedUser,
+    app: &Application,
+    deployment: &Deployment,
+) -> Event {
+    let mut event = Event::new(action)
+        .actor(&caller.user)
+        .target("deployment", deployment.id)
+        .metadata(json!({ "app": app.slug, "commit_sha": deployment.commit_sha }));
+    event.organization_id = app.organization_id;
+    event
+}
+
+/// Lists an app's deployments, newest first.
+pub async fn list_deployments(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    slug: web::Path<String>,
+    query: web::Query<DeploymentQuery>,
+) -> Result<HttpResponse, AppError> {
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppRead).await?;
+    let limit = query.limit.unwrap_or(deployments::DEFAULT_PAGE_SIZE);
+    if !(1..=deployments::MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "Limit must be between 1 and {}",
+            deployments::MAX_PAGE_SIZE
+        )));
+    }
+
+    let deployments = Deployment::list(pool.get_ref(), app.id, limit).await?;
+    Ok(HttpResponse::Ok().json(deployments))
+}
+
+/// Queues a deployment of a commit of the app, to be built by a job.
+pub async fn create_deployment(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    slug: web::Path<String>,
+    body: web::Json<NewDeployment>,
+) -> Result<HttpResponse, AppError> {
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppDeploy).await?;
+    let commit_sha = deployments::normalize_commit_sha(&body.commit_sha)?;
+
+    // Queued deployments without their job would never be built.
+    let mut tx = pool.begin().await?;
+    let deployment = Deployment::create_in(&mut tx, app.id, &commit_sha, caller.id()).await?;
+    NewJob::new(
+        jobs::DEPLOYMENT_BUILD,
+        json!({ "deployment_id": deployment.id }),
+    )
+    .enqueue_in(&mut tx)
+    .await?;
+    tx.commit().await?;
+    deployment_event("deployment.create", &caller, &app, &deployment)
+        .record(pool.get_ref(), &context)
+        .await?;
+    debug!(
+        "User {} queued deployment {} of app {}",
+        caller.id(),
+        deployment.id,
+        app.slug
+    );
+    Ok(HttpResponse::Created().json(deployment))
+}
+
+pub async fn get_deployment(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    path: web::Path<(String, i64)>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, id) = path.into_inner();
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppRead).await?;
+    let deployment = find_deployment(pool.get_ref(), &app, id).await?;
+    Ok(HttpResponse::Ok().json(deployment))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentLogQuery {
+    /// Id of the last line already read.
+    pub after: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Lines of a deployment's build log, oldest first. Pass the id of the
+/// last line read as `after` to follow a running build.
+pub async fn get_deployment_logs(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    path: web::Path<(String, i64)>,
+    query: web::Query<DeploymentLogQuery>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, id) = path.into_inner();
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppRead).await?;
+    let deployment = find_deployment(pool.get_ref(), &app, id).await?;
+    let limit = query.limit.unwrap_or(deployments::DEFAULT_LOG_PAGE_SIZE);
+    if !(1..=deployments::MAX_LOG_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "Limit must be between 1 and {}",
+            deployments::MAX_LOG_PAGE_SIZE
+        )));
+    }
+
+    let lines = DeploymentLogLine::list(
+        pool.get_ref(),
+        deployment.id,
+        query.after.unwrap_or(0),
+        limit,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(lines))
+}
+
+/// Cancels a deployment that is queued or building.
+pub async fn cancel_deployment(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    path: web::Path<(String, i64)>,
+) -> Result<HttpResponse, AppError> {
+    let (slug, id) = path.into_inner();
+    let ListedApplication { app, .. } =
+        find_app(pool.get_ref(), &caller, &slug, Action::AppDeploy).await?;
+    let deployment = find_deployment(pool.get_ref(), &app, id)
+        .await?
+        .transition(pool.get_ref(), DeploymentStatus::Cancelled)
+        .await?;
+    deployment_event("deployment.cancel", &caller, &app, &deployment)
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::Ok().json(deployment))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobQuery {
+    pub status: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Lists background jobs, newest first. Site admins only.
+pub async fn list_jobs(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    query: web::Query<JobQuery>,
+) -> Result<HttpResponse, AppError> {
+    caller.require_admin()?;
+    let status = query
+        .status
+        .as_deref()
+        .filter(|status| !status.is_empty())
+        .map(str::parse::<JobStatus>)
+        .transpose()?;
+    let kind = query.kind.as_deref().filter(|kind| !kind.is_empty());
+    let limit = query.limit.unwrap_or(jobs::DEFAULT_PAGE_SIZE);
+    if !(1..=jobs::MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "Limit must be between 1 and {}",
+            jobs::MAX_PAGE_SIZE
+        )));
+    }
+
+    let jobs = Job::list(pool.get_ref(), status, kind, limit).await?;
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+pub async fn get_job(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, AppError> {
+    caller.require_admin()?;
+    let job = Job::find(pool.get_ref(), *id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No job {}", id)))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+/// Runs a dead or pending job again right away. Site admins only.
+pub async fn retry_job(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    context: RequestContext,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, AppError> {
+    caller.require_admin()?;
+    let job = Job::find(pool.get_ref(), *id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No job {}", id)))?;
+    let job = Job::retry(pool.get_ref(), job.id).await?.ok_or_else(|| {
+        AppError::ValidationError(format!(
+            "Job {} is {} and cannot be retried",
+            id, job.status
         ))
-        .finish())
+    })?;
+    Event::new("job.retry")
+        .actor(&caller.user)
+        .target("job", job.id)
+        .metadata(json!({ "kind": job.kind }))
+        .record(pool.get_ref(), &context)
+        .await?;
+    Ok(HttpResponse::Ok().json(job))
 }
 
-pub async fn logout(session: Session) -> Result<HttpResponse, AppError> {
-    auth::clear_session(&session)?;
-    Ok(HttpResponse::Ok().finish())
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    /// The organization whose events to list; the caller's own otherwise.
+    pub org: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
 }
 
-pub async fn get_current_user(session: Session) -> Result<HttpResponse, AppError> {
-    if let Some(user) = auth::get_session_user(&session).await? {
-        Ok(HttpResponse::Ok().json(json!({
-            "id": user.id,
-            "username": user.username,
-            "email": user.email
-        })))
-    } else {
-        Ok(HttpResponse::Unauthorized().json(json!({
-            "error": "Not authenticated"
-        })))
+/// The audit filter for `query`. Anyone may read the events they took;
+/// reading an organization's events takes the `audit.read` permission.
+async fn audit_filter(
+    pool: &SqlitePool,
+    caller: &AuthenticatedUser,
+    query: &AuditQuery,
+) -> Result<audit::Filter, AppError> {
+    let scope = match &query.org {
+        Some(slug) => {
+            let membership = membership(pool, slug, caller).await?;
+            policy::require(membership.role, Action::AuditRead)?;
+            Owner::Organization(membership.organization.id)
+        }
+        None => Owner::User(caller.id()),
+    };
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    let timestamp = |value: &Option<String>| {
+        non_empty(value)
+            .map(|value| audit::parse_timestamp(&value))
+            .transpose()
+    };
+
+    Ok(audit::Filter {
+        scope,
+        actor: non_empty(&query.actor),
+        action: non_empty(&query.action),
+        target_type: non_empty(&query.target_type),
+        target_id: non_empty(&query.target_id),
+        since: timestamp(&query.since)?,
+        until: timestamp(&query.until)?,
+    })
+}
+
+/// Lists audit events, newest first, a page at a time.
+pub async fn list_audit_events(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, AppError> {
+    let filter = audit_filter(pool.get_ref(), &caller, &query).await?;
+    let limit = query.limit.unwrap_or(audit::DEFAULT_PAGE_SIZE);
+    if !(1..=audit::MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "Limit must be between 1 and {}",
+            audit::MAX_PAGE_SIZE
+        )));
     }
+
+    let mut events = AuditEvent::list(pool.get_ref(), &filter, query.cursor, limit + 1).await?;
+    let next_cursor = if events.len() as i64 > limit {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.id)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "events": events,
+        "next_cursor": next_cursor,
+    })))
+}
+
+/// Exports all matching audit events, oldest first, as newline-delimited
+/// JSON for ingestion by a SIEM.
+///
+/// The log grows without bound, so events are read and written a batch at a
+/// time rather than collected first. Each batch is its own query, so the
+/// export holds no database connection while the client reads.
+pub async fn export_audit_events(
+    pool: web::Data<SqlitePool>,
+    caller: AuthenticatedUser,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, AppError> {
+    const BATCH_SIZE: i64 = 1000;
+
+    let filter = audit_filter(pool.get_ref(), &caller, &query).await?;
+    let pool = pool.get_ref().clone();
+    // The cursor is the id of the last event written, or None once the last
+    // batch was.
+    let batches = stream::try_unfold(Some(None), move |cursor| {
+        let pool = pool.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(after) = cursor else {
+                return Ok(None);
+            };
+            let events = AuditEvent::list_since(&pool, &filter, after, BATCH_SIZE).await?;
+            let Some(last) = events.last().map(|event| event.id) else {
+                return Ok(None);
+            };
+            let mut batch = String::new();
+            for event in &events {
+                let line = serde_json::to_string(event).map_err(|e| {
+                    AppError::DatabaseError(format!("Failed to serialize audit event: {}", e))
+                })?;
+                batch.push_str(&line);
+                batch.push('\n');
+            }
+            let next = ((events.len() as i64) == BATCH_SIZE).then_some(Some(last));
+            Ok::<_, AppError>(Some((web::Bytes::from(batch), next)))
+        }
+    })
+    // Errors past the headers can only cut the response short.
+    .map_err(|e| e.to_string());
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .append_header((
+            "Content-Disposition",
+            "attachment; filename=\"audit.ndjson\"",
+        ))
+        .streaming(batches))
 }
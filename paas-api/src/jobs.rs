@@ -0,0 +1,374 @@
+use crate::{
+    config::JobConfig, error::AppError, handlers, models::Job, pipeline::Pipeline,
+    tokens::TokenService,
+};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+
+pub const DEFAULT_MAX_ATTEMPTS: i64 = 5;
+
+/// Default and maximum page sizes of `GET /api/admin/jobs`.
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+/// Failed jobs are retried after 10s, 20s, 40s... up to an hour.
+const BACKOFF_BASE_SECS: i64 = 10;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+/// Attempts time out a tenth of their claim before it expires, within these
+/// bounds, so their failure is recorded before another worker can take the
+/// job over. Claims expire on whole seconds.
+const MIN_CLAIM_MARGIN_SECS: i64 = 2;
+const MAX_CLAIM_MARGIN_SECS: i64 = 60;
+
+/// Syncs the repositories of a user, like `POST /api/repositories/sync`.
+/// The payload holds the `user_id` and optionally the `provider` to sync.
+pub const REPOSITORY_SYNC: &str = "repository.sync";
+
+/// Builds a queued deployment; see `Pipeline::build`. The payload holds the
+/// `deployment_id`.
+pub const DEPLOYMENT_BUILD: &str = "deployment.build";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for its `run_at`, or for a free worker.
+    Pending,
+    /// Claimed by a worker until `locked_until`.
+    Running,
+    Succeeded,
+    /// Out of attempts, or failed for good. Kept until retried by an admin.
+    Dead,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Pending => write!(f, "pending"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Succeeded => write!(f, "succeeded"),
+            JobStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(JobStatus::Pending),
+            "running" => Ok(JobStatus::Running),
+            "succeeded" => Ok(JobStatus::Succeeded),
+            "dead" => Ok(JobStatus::Dead),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown job status: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// How long to wait before running a job again after its `attempts`th
+/// attempt failed.
+pub fn backoff_secs(attempts: i64) -> i64 {
+    let doublings = (attempts.max(1) - 1).min(32) as u32;
+    BACKOFF_BASE_SECS
+        .saturating_mul(2_i64.saturating_pow(doublings))
+        .min(MAX_BACKOFF_SECS)
+}
+
+/// A job about to be enqueued, e.g.
+///
+/// ```ignore
+/// NewJob::new(jobs::REPOSITORY_SYNC, json!({ "user_id": user.id }))
+///     .priority(10)
+///     .enqueue(pool)
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub kind: &'static str,
+    pub payload: Value,
+    pub priority: i64,
+    pub max_attempts: i64,
+    /// Seconds to wait before the first attempt.
+    pub delay_secs: i64,
+}
+
+impl NewJob {
+    pub fn new(kind: &'static str, payload: Value) -> Self {
+        Self {
+            kind,
+            payload,
+            priority: 0,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            delay_secs: 0,
+        }
+    }
+
+    /// Jobs with a higher priority run first.
+    pub fn priority(mut self, priority: i64) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: i64) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn delay(mut self, secs: i64) -> Self {
+        self.delay_secs = secs.max(0);
+        self
+    }
+
+    pub async fn enqueue(self, pool: &SqlitePool) -> Result<Job, AppError> {
+        Ok(Job::create(pool, &self).await?)
+    }
+
+    /// Enqueues the job as part of `tx`, with the changes it is about.
+    pub async fn enqueue_in(
+        self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ) -> Result<Job, AppError> {
+        Ok(Job::create_in(tx, &self).await?)
+    }
+}
+
+/// Runs the jobs of one kind. An error fails the attempt, which is retried
+/// with backoff until the job runs out of attempts.
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    async fn run(&self, pool: &SqlitePool, job: &Job) -> Result<(), AppError>;
+}
+
+/// The handlers of each job kind.
+#[derive(Clone, Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, kind: &'static str, handler: impl JobHandler + 'static) -> Self {
+        self.handlers.insert(kind, Arc::new(handler));
+        self
+    }
+
+    fn get(&self, kind: &str) -> Option<Arc<dyn JobHandler>> {
+        self.handlers.get(kind).cloned()
+    }
+}
+
+/// The handlers of the jobs the server enqueues.
+pub fn registry(token_service: Arc<TokenService>, pipeline: Arc<Pipeline>) -> Registry {
+    Registry::new()
+        .register(REPOSITORY_SYNC, RepositorySync { token_service })
+        .register(DEPLOYMENT_BUILD, DeploymentBuild { pipeline })
+}
+
+struct RepositorySync {
+    token_service: Arc<TokenService>,
+}
+
+#[derive(Deserialize)]
+struct RepositorySyncPayload {
+    user_id: i64,
+    provider: Option<String>,
+}
+
+#[async_trait]
+impl JobHandler for RepositorySync {
+    async fn run(&self, pool: &SqlitePool, job: &Job) -> Result<(), AppError> {
+        let payload: RepositorySyncPayload = job.payload()?;
+        let results = handlers::sync_user_repositories(
+            pool,
+            &self.token_service,
+            payload.user_id,
+            payload.provider.as_deref(),
+        )
+        .await?;
+
+        // Retry the whole sync if a connection failed; syncing is idempotent.
+        let errors: Vec<String> = results
+            .iter()
+            .filter_map(|result| {
+                let error = result["error"].as_str()?;
+                Some(format!("{}: {}", result["provider"], error))
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(AppError::ExternalServiceError(errors.join("; ")));
+        }
+        Ok(())
+    }
+}
+
+struct DeploymentBuild {
+    pipeline: Arc<Pipeline>,
+}
+
+#[derive(Deserialize)]
+struct DeploymentBuildPayload {
+    deployment_id: i64,
+}
+
+#[async_trait]
+impl JobHandler for DeploymentBuild {
+    async fn run(&self, pool: &SqlitePool, job: &Job) -> Result<(), AppError> {
+        let payload: DeploymentBuildPayload = job.payload()?;
+        self.pipeline.build(pool, payload.deployment_id).await?;
+        Ok(())
+    }
+}
+
+/// Claims jobs and runs them with the handler of their kind.
+#[derive(Clone)]
+pub struct Worker {
+    pool: SqlitePool,
+    registry: Arc<Registry>,
+    name: String,
+    /// How long a job stays claimed. Jobs still running shortly before fail
+    /// the attempt, and jobs of workers that died are claimed again.
+    visibility_timeout_secs: i64,
+}
+
+impl Worker {
+    pub fn new(
+        pool: SqlitePool,
+        registry: Arc<Registry>,
+        name: impl Into<String>,
+        visibility_timeout_secs: i64,
+    ) -> Self {
+        Self {
+            pool,
+            registry,
+            name: name.into(),
+            visibility_timeout_secs,
+        }
+    }
+
+    /// Claims the next ready job and runs it. Returns the job as the attempt
+    /// left it, or None if no job was ready.
+    pub async fn run_next(&self) -> Result<Option<Job>, AppError> {
+        let Some(job) = Job::claim(&self.pool, &self.name, self.visibility_timeout_secs).await?
+        else {
+            return Ok(None);
+        };
+        debug!(
+            "Worker {} running job {} ({}), attempt {}",
+            self.name, job.id, job.kind, job.attempts
+        );
+
+        let Some(handler) = self.registry.get(&job.kind) else {
+            let error = format!("No handler for job kind {}", job.kind);
+            return self
+                .finish(&job, job.fail(&self.pool, &error, None).await?)
+                .await;
+        };
+        let timeout_secs = self.attempt_timeout_secs();
+        let timeout = Duration::from_secs(timeout_secs as u64);
+        let result = match tokio::time::timeout(timeout, handler.run(&self.pool, &job)).await {
+            Ok(result) => result,
+            Err(_) => Err(AppError::ExternalServiceError(format!(
+                "Timed out after {} seconds",
+                timeout_secs
+            ))),
+        };
+
+        let finished = match result {
+            Ok(()) => job.complete(&self.pool).await?,
+            Err(e) => {
+                let retry_in =
+                    (job.attempts < job.max_attempts).then(|| backoff_secs(job.attempts));
+                warn!("Job {} ({}) failed: {}", job.id, job.kind, e);
+                job.fail(&self.pool, &e.to_string(), retry_in).await?
+            }
+        };
+        self.finish(&job, finished).await
+    }
+
+    /// How long an attempt may run; see `MIN_CLAIM_MARGIN_SECS`.
+    fn attempt_timeout_secs(&self) -> i64 {
+        let margin =
+            (self.visibility_timeout_secs / 10).clamp(MIN_CLAIM_MARGIN_SECS, MAX_CLAIM_MARGIN_SECS);
+        (self.visibility_timeout_secs - margin).max(1)
+    }
+
+    /// The job as left by an attempt; None from `complete` or `fail` means
+    /// the claim expired and another worker took the job over.
+    async fn finish(&self, job: &Job, finished: Option<Job>) -> Result<Option<Job>, AppError> {
+        match finished {
+            Some(job) => Ok(Some(job)),
+            None => {
+                warn!(
+                    "Worker {} lost its claim on job {} ({})",
+                    self.name, job.id, job.kind
+                );
+                Ok(Job::find(&self.pool, job.id).await?)
+            }
+        }
+    }
+
+    async fn run(self, mut shutdown: watch::Receiver<bool>, poll_interval: Duration) {
+        while !*shutdown.borrow() {
+            match self.run_next().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(e) => error!("Job worker {} failed: {}", self.name, e),
+            }
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = tokio::time::sleep(poll_interval) => {}
+            }
+        }
+    }
+}
+
+/// Workers running in the background of the server.
+pub struct WorkerPool {
+    shutdown: watch::Sender<bool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn spawn(pool: SqlitePool, registry: Registry, config: &JobConfig) -> Self {
+        let (shutdown, receiver) = watch::channel(false);
+        let registry = Arc::new(registry);
+        let poll_interval =
+            Duration::try_from(config.poll_interval).unwrap_or(Duration::from_secs(1));
+        let workers = (0..config.workers)
+            .map(|n| {
+                let name = format!("{}-{}", std::process::id(), n);
+                let worker = Worker::new(
+                    pool.clone(),
+                    registry.clone(),
+                    name,
+                    config.visibility_timeout.whole_seconds(),
+                );
+                tokio::spawn(worker.run(receiver.clone(), poll_interval))
+            })
+            .collect();
+        Self { shutdown, workers }
+    }
+
+    /// Stops the workers once they finish the jobs they are running.
+    pub async fn shutdown(self) {
+        self.shutdown.send_replace(true);
+        for worker in self.workers {
+            if let Err(e) = worker.await {
+                error!("Job worker panicked: {}", e);
+            }
+        }
+    }
+}
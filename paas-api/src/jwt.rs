@@ -0,0 +1,173 @@
+use crate::{
+    api_tokens::{self, Scope},
+    config::JwtConfig,
+    error::AppError,
+};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpMessage,
+};
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::debug;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of refresh tokens. They are not accepted as bearer tokens.
+pub const REFRESH_TOKEN_PREFIX: &str = "paasr_";
+
+const REFRESH_TOKEN_LEN: usize = 48;
+const FAMILY_ID_LEN: usize = 32;
+
+/// Claims of the access tokens issued by `/api/auth/token`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// Id of the user the token was issued to.
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// Space-separated scopes, as for API tokens.
+    pub scope: String,
+}
+
+impl Claims {
+    pub fn user_id(&self) -> Result<i64, AppError> {
+        self.sub.parse().map_err(|_| invalid_access_token())
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        api_tokens::parse_scopes(&self.scope)
+    }
+}
+
+/// Signs an access token for `user_id` with the first key of `config`.
+pub fn issue(config: &JwtConfig, user_id: i64, scopes: &[Scope]) -> Result<String, AppError> {
+    let (kid, secret) = &config.keys[0];
+    let now = chrono::Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
+        iat: now,
+        exp: now + config.ttl.whole_seconds(),
+        scope: api_tokens::format_scopes(scopes),
+    };
+
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some(kid.clone());
+    jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(secret))
+        .map_err(|e| AppError::ConfigError(format!("Failed to sign access token: {}", e)))
+}
+
+/// Checks the signature, expiry, issuer and audience of an access token.
+/// The key is picked by the token's `kid`.
+pub fn verify(config: &JwtConfig, token: &str) -> Result<Claims, AppError> {
+    let header = jsonwebtoken::decode_header(token).map_err(|e| {
+        debug!("Malformed access token: {}", e);
+        invalid_access_token()
+    })?;
+    let secret = header
+        .kid
+        .as_deref()
+        .and_then(|kid| {
+            config
+                .keys
+                .iter()
+                .find(|(id, _)| id == kid)
+                .map(|(_, secret)| secret)
+        })
+        .ok_or_else(|| {
+            debug!("Access token signed with unknown key {:?}", header.kid);
+            invalid_access_token()
+        })?;
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[&config.audience]);
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+    jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(secret), &validation)
+        .map(|data| data.claims)
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => {
+                AppError::AuthError("Access token has expired".to_string())
+            }
+            _ => {
+                debug!("Access token rejected: {}", e);
+                invalid_access_token()
+            }
+        })
+}
+
+/// Middleware verifying bearer JWTs before they reach a handler, so
+/// requests with a forged, expired or foreign token are turned away without
+/// touching the database. The claims are stored in the request extensions
+/// for `auth::Authenticated`. Requests with an API token or without an
+/// `Authorization` header pass through.
+pub async fn verify_bearer_jwt(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse, Error> {
+    if let Some(config) = req.app_data::<web::Data<JwtConfig>>().cloned() {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer_token)
+            .filter(|token| is_jwt(token))
+            .map(str::to_string);
+
+        if let Some(token) = token {
+            match verify(&config, &token) {
+                Ok(claims) => {
+                    req.extensions_mut().insert(claims);
+                }
+                Err(e) => {
+                    return Ok(req.error_response(e).map_into_boxed_body());
+                }
+            }
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// The token of a `Bearer` authorization header value.
+pub fn bearer_token(value: &str) -> Option<&str> {
+    value
+        .split_once(' ')
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim())
+}
+
+/// Whether a bearer token looks like a JWT (three dot-separated parts)
+/// rather than a personal access token.
+pub fn is_jwt(token: &str) -> bool {
+    !token.starts_with(api_tokens::TOKEN_PREFIX)
+        && !token.starts_with(REFRESH_TOKEN_PREFIX)
+        && token.split('.').count() == 3
+}
+
+pub fn generate_refresh_token() -> String {
+    REFRESH_TOKEN_PREFIX
+        .chars()
+        .chain(random_chars(REFRESH_TOKEN_LEN))
+        .collect()
+}
+
+/// Id shared by a refresh token and every token it is rotated into.
+pub fn generate_family_id() -> String {
+    random_chars(FAMILY_ID_LEN).collect()
+}
+
+fn random_chars(len: usize) -> impl Iterator<Item = char> {
+    (0..len).map(|_| OsRng.sample(Alphanumeric) as char)
+}
+
+fn invalid_access_token() -> AppError {
+    AppError::AuthError("Invalid access token".to_string())
+}
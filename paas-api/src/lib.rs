@@ -1,11 +1,31 @@
+pub mod api_tokens;
+pub mod apps;
+pub mod audit;
 pub mod auth;
+pub mod builders;
+pub mod buildpacks;
+pub mod cli;
 pub mod config;
+pub mod crypto;
 pub mod db;
+pub mod deployments;
 pub mod error;
+pub mod git;
 pub mod handlers;
+pub mod jobs;
+pub mod jwt;
 pub mod models;
+pub mod oidc;
+pub mod orgs;
+pub mod pipeline;
+pub mod policy;
+pub mod providers;
 pub mod routes;
-pub mod tests;
+pub mod runtimes;
+pub mod session;
+#[cfg(test)]
+mod tests;
+pub mod tokens;
 
 pub use crate::auth::*;
 pub use crate::config::*;
@@ -13,14 +33,26 @@ pub use crate::db::*;
 pub use crate::error::*;
 pub use crate::handlers::*;
 pub use crate::models::*;
+pub use crate::providers::*;
 pub use crate::routes::*;
+pub use crate::session::*;
+pub use crate::tokens::*;
 
-use actix_web::{web, App, HttpServer};
+use actix_cors::Cors;
+use actix_web::{
+    http::header,
+    middleware::{self, Logger},
+    web, App, HttpServer,
+};
+use clap::Parser;
 use dotenv::dotenv;
-use std::env;
+use sqlx::SqlitePool;
+use std::{env, sync::Arc};
 
 pub async fn run() -> std::io::Result<()> {
     dotenv().ok();
+    env_logger::init();
+    let cli = cli::Cli::parse();
 
     let pool = db::create_pool()
         .await
@@ -30,18 +62,87 @@ pub async fn run() -> std::io::Result<()> {
         .await
         .expect("Failed to initialize database");
 
+    let result = match cli.command {
+        None | Some(cli::Command::Serve) => return serve(pool).await,
+        Some(cli::Command::Providers(command)) => cli::providers(&pool, command).await,
+        Some(cli::Command::Tokens(command)) => cli::tokens(&pool, command).await,
+        Some(cli::Command::Admins(command)) => cli::admins(&pool, command).await,
+    };
+    result.map_err(|e| std::io::Error::other(e.to_string()))
+}
+
+async fn serve(pool: SqlitePool) -> std::io::Result<()> {
     let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let bind_address = format!("{}:{}", host, port);
 
-    println!("Starting server at http://{}", bind_address);
+    // Refuse to start without usable keys rather than failing on first sign-in.
+    let keyring = web::Data::new(
+        crypto::Keyring::from_env().map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
 
-    HttpServer::new(move || {
+    let session_config =
+        config::SessionConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let jwt_config = web::Data::new(
+        config::JwtConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let token_service = web::Data::new(tokens::TokenService::new(
+        pool.clone(),
+        keyring.clone().into_inner(),
+    ));
+    let job_config =
+        config::JobConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let source_config =
+        config::SourceConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let build_config =
+        config::BuildConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let runtime_config =
+        config::RuntimeConfig::from_env().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let pipeline = pipeline::Pipeline::new(
+        git::SourceCache::from_config(&source_config),
+        Arc::new(builders::OciBuilder::from_config(&build_config)),
+        token_service.clone().into_inner(),
+    );
+    // Dropped once the server stops, which kills the apps it runs.
+    let runtime = web::Data::new(runtimes::ProcessRuntime::from_config(&runtime_config));
+    let workers = jobs::WorkerPool::spawn(
+        pool.clone(),
+        jobs::registry(token_service.clone().into_inner(), Arc::new(pipeline)),
+        &job_config,
+    );
+
+    println!("Starting server at http://{}", bind_address);
+    let result = HttpServer::new(move || {
         App::new()
+            .wrap(session::session_middleware(pool.clone(), &session_config))
+            .wrap(middleware::from_fn(session::reseal_session_cookie))
+            .wrap(middleware::from_fn(jwt::verify_bearer_jwt))
+            .wrap(
+                Cors::default()
+                    .allowed_origin("http://127.0.0.1:8080")
+                    .allowed_origin("http://localhost:8080")
+                    .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+                    .allowed_headers(vec![
+                        header::AUTHORIZATION,
+                        header::ACCEPT,
+                        header::CONTENT_TYPE,
+                    ])
+                    .supports_credentials()
+                    .max_age(3600),
+            )
+            .wrap(Logger::default())
             .app_data(web::Data::new(pool.clone()))
+            .app_data(keyring.clone())
+            .app_data(token_service.clone())
+            .app_data(web::Data::new(session_config.clone()))
+            .app_data(jwt_config.clone())
+            .app_data(runtime.clone())
             .configure(routes::configure)
     })
     .bind(bind_address)?
     .run()
-    .await
+    .await;
+
+    workers.shutdown().await;
+    result
 }
@@ -1,6 +1,135 @@
-use crate::config::OAuthProvider;
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use crate::{
+    api_tokens::{self, Scope},
+    audit,
+    buildpacks::BuildPlan,
+    config::OAuthProvider,
+    crypto::Keyring,
+    deployments::DeploymentStatus,
+    error::AppError,
+    jobs::{JobStatus, NewJob},
+    orgs::{self, Invitee, Owner, Role},
+    providers::{Provider, RemoteRepository},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{types::Json, FromRow, SqlitePool};
+use std::{env, fmt, str::FromStr};
+
+/// Takes the row produced by an `INSERT/UPDATE/DELETE ... RETURNING` statement.
+///
+/// Such statements are run with `fetch_all`: with `fetch_one` the SQLite driver
+/// stops stepping after the first row, leaving the implicit transaction open
+/// until the statement is reset (never, if the process exits first).
+fn returned_row<T>(mut rows: Vec<T>) -> Result<T, sqlx::Error> {
+    rows.pop().ok_or(sqlx::Error::RowNotFound)
+}
+
+/// Tables whose `access_token` and `refresh_token` columns are encrypted with
+/// the key named in the row's `token_key_id`.
+const TOKEN_TABLES: &[&str] = &["users", "git_providers"];
+
+fn seal_token(
+    keyring: &Keyring,
+    table: &str,
+    column: &str,
+    token: Option<&str>,
+) -> Result<Option<String>, AppError> {
+    token
+        .map(|token| keyring.encrypt(&format!("{}.{}", table, column), token))
+        .transpose()
+}
+
+/// Decrypts a token column. Without a key id the value predates encryption
+/// and is returned as is.
+fn open_token(
+    keyring: &Keyring,
+    key_id: Option<&str>,
+    table: &str,
+    column: &str,
+    token: Option<String>,
+) -> Result<Option<String>, AppError> {
+    match (key_id, token) {
+        (Some(key_id), Some(token)) => keyring
+            .decrypt(key_id, &format!("{}.{}", table, column), &token)
+            .map(Some),
+        (_, token) => Ok(token),
+    }
+}
+
+/// Encrypts tokens stored before encryption at rest was introduced. Returns
+/// the number of rows updated.
+pub async fn encrypt_plaintext_tokens(pool: &SqlitePool) -> Result<u64, AppError> {
+    reencrypt_tokens(pool, true).await
+}
+
+/// Re-encrypts every stored token that is not encrypted with the active key,
+/// so older keys can be removed from the keyring. Returns the number of rows
+/// updated.
+pub async fn rotate_token_key(pool: &SqlitePool) -> Result<u64, AppError> {
+    reencrypt_tokens(pool, false).await
+}
+
+/// `id, token_key_id, access_token, refresh_token` of a row in a token table.
+type TokenRow = (i64, Option<String>, Option<String>, Option<String>);
+
+async fn reencrypt_tokens(pool: &SqlitePool, only_plaintext: bool) -> Result<u64, AppError> {
+    // Loaded on first use, so databases without tokens need no key yet.
+    let mut keyring = None;
+    let mut updated = 0;
+
+    for table in TOKEN_TABLES {
+        let mut tx = pool.begin().await?;
+        let rows: Vec<TokenRow> = sqlx::query_as(&format!(
+            "SELECT id, token_key_id, access_token, refresh_token FROM {}
+                 WHERE access_token IS NOT NULL OR refresh_token IS NOT NULL",
+            table
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for (id, key_id, access_token, refresh_token) in rows {
+            if only_plaintext && key_id.is_some() {
+                continue;
+            }
+            if keyring.is_none() {
+                keyring = Some(Keyring::from_env()?);
+            }
+            let keyring = keyring.as_ref().expect("keyring loaded above");
+            if key_id.as_deref() == Some(keyring.active_key_id()) {
+                continue;
+            }
+
+            let key_id = key_id.as_deref();
+            let access_token = open_token(keyring, key_id, table, "access_token", access_token)?;
+            let refresh_token = open_token(keyring, key_id, table, "refresh_token", refresh_token)?;
+
+            sqlx::query(&format!(
+                "UPDATE {} SET access_token = ?, refresh_token = ?, token_key_id = ? WHERE id = ?",
+                table
+            ))
+            .bind(seal_token(
+                keyring,
+                table,
+                "access_token",
+                access_token.as_deref(),
+            )?)
+            .bind(seal_token(
+                keyring,
+                table,
+                "refresh_token",
+                refresh_token.as_deref(),
+            )?)
+            .bind(keyring.active_key_id())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            updated += 1;
+        }
+
+        tx.commit().await?;
+    }
+
+    Ok(updated)
+}
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct User {
@@ -10,29 +139,71 @@ pub struct User {
     pub username: String,
     pub email: Option<String>,
     pub avatar_url: Option<String>,
+    #[serde(skip_serializing)]
     pub access_token: Option<String>,
+    #[serde(skip_serializing)]
     pub refresh_token: Option<String>,
+    #[serde(skip)]
+    pub token_key_id: Option<String>,
+    /// Site admins operate the instance, e.g. its background jobs.
+    #[serde(default)]
+    pub is_admin: bool,
     pub created_at: String,
+    /// Whether the provider vouched for `email` at the last sign-in.
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 impl User {
-    pub async fn find_or_create(
+    pub async fn find(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        id: i64,
+    ) -> Result<Option<Self>, AppError> {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        user.map(|user| user.open_tokens(keyring)).transpose()
+    }
+
+    /// Finds the user a provider account belongs to: the account the user
+    /// signed up with, or one linked to it later.
+    pub async fn find_by_identity(
         pool: &SqlitePool,
-        provider: &OAuthProvider,
+        keyring: &Keyring,
+        provider: &str,
         provider_user_id: &str,
-        username: &str,
-        email: Option<&str>,
-        avatar_url: Option<&str>,
-    ) -> Result<Self, sqlx::Error> {
+    ) -> Result<Option<Self>, AppError> {
         let user = sqlx::query_as::<_, User>(
-            "SELECT * FROM users WHERE provider = ? AND provider_user_id = ?",
+            "SELECT * FROM users
+             WHERE (provider = ?1 AND provider_user_id = ?2)
+                OR id IN (SELECT user_id FROM git_providers WHERE provider = ?1 AND provider_user_id = ?2)
+             LIMIT 1",
         )
-        .bind(provider.to_string())
+        .bind(provider)
         .bind(provider_user_id)
         .fetch_optional(pool)
         .await?;
 
-        if let Some(user) = user {
+        user.map(|user| user.open_tokens(keyring)).transpose()
+    }
+
+    /// `provider` is the slug of the provider instance the user signed in with.
+    /// New users are created for provider accounts not linked to anyone yet.
+    pub async fn find_or_create(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        provider: &str,
+        provider_user_id: &str,
+        username: &str,
+        email: Option<&str>,
+        avatar_url: Option<&str>,
+    ) -> Result<Self, AppError> {
+        if let Some(user) =
+            Self::find_by_identity(pool, keyring, provider, provider_user_id).await?
+        {
             return Ok(user);
         }
 
@@ -41,69 +212,2451 @@ impl User {
              VALUES (?, ?, ?, ?, ?, datetime('now')) 
              RETURNING *"
         )
-        .bind(provider.to_string())
+        .bind(provider)
         .bind(provider_user_id)
         .bind(username)
         .bind(email)
         .bind(avatar_url)
-        .fetch_one(pool)
-        .await?;
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)?;
 
         Ok(user)
     }
 
-    #[allow(dead_code)]
-    pub async fn update_tokens(
+    /// Records whether the provider vouched for the user's email address on
+    /// signing in, where it reported `email` as `verified`.
+    pub async fn set_email_verified(
+        mut self,
         pool: &SqlitePool,
-        provider: &OAuthProvider,
-        provider_user_id: &str,
-        access_token: &str,
-        refresh_token: Option<&str>,
+        email: Option<&str>,
+        verified: bool,
     ) -> Result<Self, sqlx::Error> {
-        let user = sqlx::query_as::<_, User>(
-            "UPDATE users 
-             SET access_token = ?, refresh_token = ? 
-             WHERE provider = ? AND provider_user_id = ? 
-             RETURNING *",
+        let verified = verified
+            && matches!((email, self.email.as_deref()), (Some(email), Some(own)) if email.eq_ignore_ascii_case(own));
+        if verified != self.email_verified {
+            sqlx::query("UPDATE users SET email_verified = ? WHERE id = ?")
+                .bind(verified)
+                .bind(self.id)
+                .execute(pool)
+                .await?;
+            self.email_verified = verified;
+        }
+        Ok(self)
+    }
+
+    /// The user's email address if their provider verified it.
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+
+    /// The users named `username`, of the provider instance `provider` if
+    /// given. Usernames are only unique per provider.
+    pub async fn find_by_username(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        username: &str,
+        provider: Option<&str>,
+    ) -> Result<Vec<Self>, AppError> {
+        let users = sqlx::query_as::<_, User>(
+            "SELECT * FROM users
+             WHERE username = ? COLLATE NOCASE AND (?2 IS NULL OR provider = ?2)
+             ORDER BY id",
         )
-        .bind(access_token)
-        .bind(refresh_token)
-        .bind(provider.to_string())
-        .bind(provider_user_id)
-        .fetch_one(pool)
+        .bind(username)
+        .bind(provider)
+        .fetch_all(pool)
         .await?;
 
-        Ok(user)
+        users
+            .into_iter()
+            .map(|user| user.open_tokens(keyring))
+            .collect()
+    }
+
+    pub async fn list_admins(pool: &SqlitePool, keyring: &Keyring) -> Result<Vec<Self>, AppError> {
+        let users = sqlx::query_as::<_, User>("SELECT * FROM users WHERE is_admin ORDER BY id")
+            .fetch_all(pool)
+            .await?;
+
+        users
+            .into_iter()
+            .map(|user| user.open_tokens(keyring))
+            .collect()
+    }
+
+    pub async fn set_admin(pool: &SqlitePool, id: i64, is_admin: bool) -> Result<(), AppError> {
+        sqlx::query("UPDATE users SET is_admin = ? WHERE id = ?")
+            .bind(is_admin)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    fn open_tokens(mut self, keyring: &Keyring) -> Result<Self, AppError> {
+        let key_id = self.token_key_id.as_deref();
+        self.access_token = open_token(
+            keyring,
+            key_id,
+            "users",
+            "access_token",
+            self.access_token.take(),
+        )?;
+        self.refresh_token = open_token(
+            keyring,
+            key_id,
+            "users",
+            "refresh_token",
+            self.refresh_token.take(),
+        )?;
+        Ok(self)
     }
 }
 
-impl ToString for OAuthProvider {
-    fn to_string(&self) -> String {
+impl fmt::Display for OAuthProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            OAuthProvider::GitHub => "github".to_string(),
-            OAuthProvider::GitLab => "gitlab".to_string(),
-            OAuthProvider::Bitbucket => "bitbucket".to_string(),
+            OAuthProvider::GitHub => write!(f, "github"),
+            OAuthProvider::GitLab => write!(f, "gitlab"),
+            OAuthProvider::Bitbucket => write!(f, "bitbucket"),
+            OAuthProvider::BitbucketServer => write!(f, "bitbucket-server"),
+            OAuthProvider::Oidc => write!(f, "oidc"),
+        }
+    }
+}
+
+impl FromStr for OAuthProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(OAuthProvider::GitHub),
+            "gitlab" => Ok(OAuthProvider::GitLab),
+            "bitbucket" => Ok(OAuthProvider::Bitbucket),
+            "bitbucket-server" => Ok(OAuthProvider::BitbucketServer),
+            "oidc" => Ok(OAuthProvider::Oidc),
+            _ => Err(format!("Unknown provider type: {}", s)),
+        }
+    }
+}
+
+/// Slugs that would collide with other `/api/auth/...` routes.
+const RESERVED_INSTANCE_SLUGS: &[&str] = &["logout", "providers", "token"];
+
+/// A configured deployment of a provider, e.g. gitlab.com or a self-managed
+/// GitLab. `base_url`, `client_id` and `client_secret` fall back to the
+/// provider's environment variables when NULL.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ProviderInstance {
+    pub id: i64,
+    pub slug: String,
+    pub provider_type: String,
+    pub display_name: String,
+    pub base_url: Option<String>,
+    pub client_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewProviderInstance {
+    pub slug: String,
+    pub provider_type: OAuthProvider,
+    pub display_name: String,
+    pub base_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl NewProviderInstance {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let valid_slug = !self.slug.is_empty()
+            && !self.slug.starts_with('-')
+            && self
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid_slug {
+            return Err(AppError::ValidationError(
+                "Slug may only contain lowercase letters, digits and dashes".to_string(),
+            ));
+        }
+        if RESERVED_INSTANCE_SLUGS.contains(&self.slug.as_str()) {
+            return Err(AppError::ValidationError(format!(
+                "Slug '{}' is reserved",
+                self.slug
+            )));
+        }
+
+        match url::Url::parse(&self.base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            _ => Err(AppError::ValidationError(format!(
+                "Invalid base URL: {}",
+                self.base_url
+            ))),
+        }
+    }
+}
+
+impl ProviderInstance {
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ProviderInstance>("SELECT * FROM provider_instances ORDER BY id")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find_by_slug(pool: &SqlitePool, slug: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ProviderInstance>("SELECT * FROM provider_instances WHERE slug = ?")
+            .bind(slug)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn create(
+        pool: &SqlitePool,
+        instance: &NewProviderInstance,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, ProviderInstance>(
+            "INSERT INTO provider_instances (slug, provider_type, display_name, base_url, client_id, client_secret)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING *",
+        )
+        .bind(&instance.slug)
+        .bind(instance.provider_type.to_string())
+        .bind(&instance.display_name)
+        .bind(instance.base_url.trim_end_matches('/'))
+        .bind(&instance.client_id)
+        .bind(&instance.client_secret)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    /// Deletes an instance. Fails while users still sign in through it.
+    pub async fn delete(pool: &SqlitePool, slug: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM provider_instances WHERE slug = ?")
+            .bind(slug)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn kind(&self) -> Result<OAuthProvider, AppError> {
+        self.provider_type.parse().map_err(AppError::DatabaseError)
+    }
+
+    pub fn spec(&self) -> Result<&'static dyn Provider, AppError> {
+        Ok(self.kind()?.provider())
+    }
+
+    pub fn effective_base_url(&self) -> Result<String, AppError> {
+        if let Some(base_url) = &self.base_url {
+            return Ok(base_url.clone());
+        }
+
+        let spec = self.spec()?;
+        env::var(spec.base_url_var())
+            .ok()
+            .or_else(|| spec.default_base_url().map(|url| url.to_string()))
+            .ok_or_else(|| self.not_configured())
+    }
+
+    pub fn effective_client_id(&self) -> Result<String, AppError> {
+        self.credential(&self.client_id, "CLIENT_ID")
+    }
+
+    pub fn effective_client_secret(&self) -> Result<String, AppError> {
+        self.credential(&self.client_secret, "CLIENT_SECRET")
+    }
+
+    /// Whether the instance has everything needed to start a login.
+    pub fn is_configured(&self) -> bool {
+        self.effective_base_url().is_ok()
+            && self.effective_client_id().is_ok()
+            && self.effective_client_secret().is_ok()
+    }
+
+    fn credential(&self, value: &Option<String>, suffix: &str) -> Result<String, AppError> {
+        if let Some(value) = value {
+            return Ok(value.clone());
         }
+
+        env::var(format!("{}_{}", self.spec()?.env_prefix(), suffix))
+            .map_err(|_| self.not_configured())
+    }
+
+    fn not_configured(&self) -> AppError {
+        AppError::NotFound(format!("{} is not configured", self.display_name))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// SQLite `datetime()` format used for the timestamp columns.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Whether `timestamp` is earlier than `leeway_secs` from now. Unparsable
+/// timestamps count as expired.
+fn expires_within(timestamp: &str, leeway_secs: i64) -> bool {
+    chrono::NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .map(|expires_at| {
+            expires_at <= chrono::Utc::now().naive_utc() + chrono::Duration::seconds(leeway_secs)
+        })
+        .unwrap_or(true)
+}
+
+/// A user's OAuth connection to a provider instance, holding the tokens used
+/// for provider API calls.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct GitProvider {
     pub id: i64,
     pub user_id: i64,
-    pub provider_type: String,
+    /// Slug of the provider instance.
+    pub provider: String,
+    /// Provider account the connection was authorized as. NULL for
+    /// connections made before accounts could be linked.
+    pub provider_user_id: Option<String>,
+    #[serde(skip_serializing)]
     pub access_token: String,
+    #[serde(skip_serializing)]
     pub refresh_token: Option<String>,
+    #[serde(skip)]
+    pub token_key_id: Option<String>,
     pub expires_at: Option<String>,
+    pub needs_reauth: bool,
+    pub created_at: String,
+    pub updated_at: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Repository {
+impl GitProvider {
+    /// Stores the tokens of a fresh authorization, replacing any previous ones.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn upsert(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        user_id: i64,
+        provider: &str,
+        provider_user_id: &str,
+        access_token: &str,
+        refresh_token: Option<&str>,
+        expires_in_secs: Option<i64>,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, GitProvider>(
+            "INSERT INTO git_providers (user_id, provider, provider_user_id, access_token, refresh_token, token_key_id, expires_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, datetime('now', ?), datetime('now'))
+             ON CONFLICT (user_id, provider) DO UPDATE SET
+                provider_user_id = excluded.provider_user_id,
+                access_token = excluded.access_token,
+                refresh_token = excluded.refresh_token,
+                token_key_id = excluded.token_key_id,
+                expires_at = excluded.expires_at,
+                needs_reauth = 0,
+                updated_at = excluded.updated_at
+             RETURNING *",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(provider_user_id)
+        .bind(seal_token(keyring, "git_providers", "access_token", Some(access_token))?)
+        .bind(seal_token(keyring, "git_providers", "refresh_token", refresh_token)?)
+        .bind(keyring.active_key_id())
+        .bind(expires_in_secs.map(|secs| format!("{:+} seconds", secs)))
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)?
+        .open_tokens(keyring)
+    }
+
+    pub async fn find(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        user_id: i64,
+        provider: &str,
+    ) -> Result<Option<Self>, AppError> {
+        let connection = sqlx::query_as::<_, GitProvider>(
+            "SELECT * FROM git_providers WHERE user_id = ? AND provider = ?",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .fetch_optional(pool)
+        .await?;
+
+        match connection {
+            Some(connection) => Ok(Some(connection.open_tokens(keyring)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn find_by_id(
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        id: i64,
+    ) -> Result<Option<Self>, AppError> {
+        let connection =
+            sqlx::query_as::<_, GitProvider>("SELECT * FROM git_providers WHERE id = ?")
+                .bind(id)
+                .fetch_optional(pool)
+                .await?;
+
+        match connection {
+            Some(connection) => Ok(Some(connection.open_tokens(keyring)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores refreshed tokens. Providers that do not rotate refresh tokens
+    /// omit them from the response, in which case the current one is kept.
+    pub async fn update_tokens(
+        &self,
+        pool: &SqlitePool,
+        keyring: &Keyring,
+        access_token: &str,
+        refresh_token: Option<&str>,
+        expires_in_secs: Option<i64>,
+    ) -> Result<Self, AppError> {
+        let refresh_token = refresh_token.or(self.refresh_token.as_deref());
+
+        sqlx::query_as::<_, GitProvider>(
+            "UPDATE git_providers
+             SET access_token = ?,
+                 refresh_token = ?,
+                 token_key_id = ?,
+                 expires_at = datetime('now', ?),
+                 needs_reauth = 0,
+                 updated_at = datetime('now')
+             WHERE id = ?
+             RETURNING *",
+        )
+        .bind(seal_token(
+            keyring,
+            "git_providers",
+            "access_token",
+            Some(access_token),
+        )?)
+        .bind(seal_token(
+            keyring,
+            "git_providers",
+            "refresh_token",
+            refresh_token,
+        )?)
+        .bind(keyring.active_key_id())
+        .bind(expires_in_secs.map(|secs| format!("{:+} seconds", secs)))
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)?
+        .open_tokens(keyring)
+    }
+
+    /// Disconnects the user from the provider instance `provider`. Returns
+    /// false if there was no connection.
+    ///
+    /// Disconnecting the account the user signed up with makes the oldest
+    /// other linked account the one they signed up with; the last account
+    /// that can be used to sign in cannot be disconnected.
+    pub async fn delete(pool: &SqlitePool, user_id: i64, provider: &str) -> Result<bool, AppError> {
+        let mut tx = pool.begin().await?;
+
+        let signed_up_with: Option<String> = sqlx::query_scalar(
+            "SELECT users.provider FROM users
+             JOIN git_providers ON git_providers.user_id = users.id
+             WHERE users.id = ? AND git_providers.provider = ?",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(signed_up_with) = signed_up_with else {
+            return Ok(false);
+        };
+
+        if signed_up_with == provider {
+            let replacement: Option<(String, String)> = sqlx::query_as(
+                "SELECT provider, provider_user_id FROM git_providers
+                 WHERE user_id = ? AND provider != ? AND provider_user_id IS NOT NULL
+                 ORDER BY created_at, id
+                 LIMIT 1",
+            )
+            .bind(user_id)
+            .bind(provider)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let (new_provider, new_provider_user_id) = replacement.ok_or_else(|| {
+                AppError::ValidationError(format!(
+                    "Connect another provider before disconnecting {}, you would not be able to sign in",
+                    provider
+                ))
+            })?;
+
+            // The tokens kept on the user row belong to the old account.
+            sqlx::query(
+                "UPDATE users
+                 SET provider = ?, provider_user_id = ?, access_token = NULL, refresh_token = NULL, token_key_id = NULL
+                 WHERE id = ?",
+            )
+            .bind(new_provider)
+            .bind(new_provider_user_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM git_providers WHERE user_id = ? AND provider = ?")
+            .bind(user_id)
+            .bind(provider)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn mark_needs_reauth(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE git_providers SET needs_reauth = 1, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    fn open_tokens(mut self, keyring: &Keyring) -> Result<Self, AppError> {
+        let key_id = self.token_key_id.as_deref();
+        self.access_token = open_token(
+            keyring,
+            key_id,
+            "git_providers",
+            "access_token",
+            Some(self.access_token),
+        )?
+        .unwrap_or_default();
+        self.refresh_token = open_token(
+            keyring,
+            key_id,
+            "git_providers",
+            "refresh_token",
+            self.refresh_token.take(),
+        )?;
+        Ok(self)
+    }
+
+    /// Whether the access token expires within `leeway_secs`. Tokens without
+    /// an expiry never expire.
+    pub fn expires_within(&self, leeway_secs: i64) -> bool {
+        self.expires_at
+            .as_deref()
+            .map(|expires_at| expires_within(expires_at, leeway_secs))
+            .unwrap_or(false)
+    }
+}
+
+/// A provider connection as listed on the account settings page.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ConnectedProvider {
+    /// Slug of the provider instance.
+    pub provider: String,
+    pub provider_type: String,
+    pub display_name: String,
+    pub needs_reauth: bool,
+    /// Whether this is the account the user signed up with.
+    pub primary: bool,
+    pub connected_at: String,
+}
+
+impl ConnectedProvider {
+    pub async fn list(pool: &SqlitePool, user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ConnectedProvider>(
+            "SELECT git_providers.provider,
+                    provider_instances.provider_type,
+                    provider_instances.display_name,
+                    git_providers.needs_reauth,
+                    git_providers.provider = users.provider AS \"primary\",
+                    git_providers.created_at AS connected_at
+             FROM git_providers
+             JOIN users ON users.id = git_providers.user_id
+             JOIN provider_instances ON provider_instances.slug = git_providers.provider
+             WHERE git_providers.user_id = ?
+             ORDER BY git_providers.created_at, git_providers.id",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+/// A personal access token. The token itself is only shown once, when it is
+/// created; the table keeps its hash.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct ApiToken {
     pub id: i64,
+    #[serde(skip)]
     pub user_id: i64,
-    pub provider_id: i64,
     pub name: String,
-    pub url: String,
-    pub is_private: bool,
-    pub last_synced: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    /// First characters of the token, to tell tokens apart.
+    pub token_prefix: String,
+    #[serde(serialize_with = "api_tokens::serialize_scopes")]
+    pub scopes: String,
+    pub expires_at: String,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+impl ApiToken {
+    pub async fn create(
+        pool: &SqlitePool,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        token_prefix: &str,
+        scopes: &[Scope],
+        expires_in_secs: i64,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, ApiToken>(
+            "INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scopes, expires_at)
+             VALUES (?, ?, ?, ?, ?, datetime('now', ?))
+             RETURNING *",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(token_prefix)
+        .bind(api_tokens::format_scopes(scopes))
+        .bind(format!("{:+} seconds", expires_in_secs))
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    pub async fn list(pool: &SqlitePool, user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ApiToken>(
+            "SELECT * FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC, id DESC",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Finds an unexpired token by the hash of its value.
+    pub async fn find_active(
+        pool: &SqlitePool,
+        token_hash: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ApiToken>(
+            "SELECT * FROM api_tokens WHERE token_hash = ? AND expires_at > datetime('now')",
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Records that the token was used. Written at most once a minute per
+    /// token, so busy CI jobs do not turn every request into a write.
+    pub async fn touch(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE api_tokens SET last_used_at = datetime('now')
+             WHERE id = ? AND (last_used_at IS NULL OR last_used_at <= datetime('now', '-1 minute'))",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Revokes one of the user's tokens. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        api_tokens::parse_scopes(&self.scopes)
+    }
+}
+
+/// A refresh token issued with a JWT by `/api/auth/token`. Only the hash of
+/// the token is stored.
+#[derive(Debug, FromRow, Clone)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub user_id: i64,
+    /// The API token that was exchanged, if any. Revoking it revokes its
+    /// refresh tokens too.
+    pub api_token_id: Option<i64>,
+    pub family_id: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub expires_at: String,
+    pub used_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub created_at: String,
+}
+
+impl RefreshToken {
+    pub async fn create(
+        pool: &SqlitePool,
+        user_id: i64,
+        api_token_id: Option<i64>,
+        family_id: &str,
+        token_hash: &str,
+        scopes: &[Scope],
+        ttl_secs: i64,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= datetime('now')")
+            .execute(pool)
+            .await?;
+
+        sqlx::query_as::<_, RefreshToken>(
+            "INSERT INTO refresh_tokens (user_id, api_token_id, family_id, token_hash, scopes, expires_at)
+             VALUES (?, ?, ?, ?, ?, datetime('now', ?))
+             RETURNING *",
+        )
+        .bind(user_id)
+        .bind(api_token_id)
+        .bind(family_id)
+        .bind(token_hash)
+        .bind(api_tokens::format_scopes(scopes))
+        .bind(format!("{:+} seconds", ttl_secs))
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    /// Redeems the refresh token with hash `token_hash`, replacing it with
+    /// `new_token_hash`. Returns the replacement, or None if the token is
+    /// unknown, expired or revoked, or the API token it was exchanged for has
+    /// expired.
+    ///
+    /// A token can only be redeemed once. Presenting it again means it was
+    /// stolen (or the client lost the replacement), so every token of its
+    /// family is revoked.
+    pub async fn rotate(
+        pool: &SqlitePool,
+        token_hash: &str,
+        new_token_hash: &str,
+        ttl_secs: i64,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let current = sqlx::query_as::<_, RefreshToken>(
+            "SELECT * FROM refresh_tokens
+             WHERE token_hash = ?
+               AND revoked_at IS NULL
+               AND expires_at > datetime('now')
+               AND (api_token_id IS NULL OR api_token_id IN (
+                    SELECT id FROM api_tokens WHERE expires_at > datetime('now')))",
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(current) = current else {
+            return Ok(None);
+        };
+
+        let redeemed = sqlx::query(
+            "UPDATE refresh_tokens SET used_at = datetime('now') WHERE id = ? AND used_at IS NULL",
+        )
+        .bind(current.id)
+        .execute(&mut *tx)
+        .await?;
+        if redeemed.rows_affected() == 0 {
+            sqlx::query(
+                "UPDATE refresh_tokens SET revoked_at = datetime('now')
+                 WHERE family_id = ? AND revoked_at IS NULL",
+            )
+            .bind(&current.family_id)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        let replacement = sqlx::query_as::<_, RefreshToken>(
+            "INSERT INTO refresh_tokens (user_id, api_token_id, family_id, token_hash, scopes, expires_at)
+             VALUES (?, ?, ?, ?, ?, datetime('now', ?))
+             RETURNING *",
+        )
+        .bind(current.user_id)
+        .bind(current.api_token_id)
+        .bind(&current.family_id)
+        .bind(new_token_hash)
+        .bind(&current.scopes)
+        .bind(format!("{:+} seconds", ttl_secs))
+        .fetch_all(&mut *tx)
+        .await
+        .and_then(returned_row)?;
+
+        tx.commit().await?;
+        Ok(Some(replacement))
+    }
+
+    /// Revokes every token of the families `family_ids`.
+    pub async fn revoke_families(
+        pool: &SqlitePool,
+        family_ids: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        for family_id in family_ids {
+            sqlx::query(
+                "UPDATE refresh_tokens SET revoked_at = datetime('now')
+                 WHERE family_id = ? AND revoked_at IS NULL",
+            )
+            .bind(family_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    pub fn scopes(&self) -> Vec<Scope> {
+        api_tokens::parse_scopes(&self.scopes)
+    }
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(e) if e.is_unique_violation())
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Organization {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    pub created_at: String,
+}
+
+/// An organization as seen by one of its members.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct OrganizationMembership {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub organization: Organization,
+    pub role: Role,
+}
+
+impl Organization {
+    /// Creates an organization with `owner_id` as its first owner.
+    pub async fn create(
+        pool: &SqlitePool,
+        slug: &str,
+        name: &str,
+        owner_id: i64,
+    ) -> Result<Self, AppError> {
+        let mut tx = pool.begin().await?;
+        let organization = sqlx::query_as::<_, Organization>(
+            "INSERT INTO organizations (slug, name) VALUES (?, ?) RETURNING *",
+        )
+        .bind(slug)
+        .bind(name)
+        .fetch_all(&mut *tx)
+        .await
+        .and_then(returned_row)
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                AppError::ValidationError(format!("Slug '{}' is already taken", slug))
+            } else {
+                e.into()
+            }
+        })?;
+
+        sqlx::query("INSERT INTO memberships (organization_id, user_id, role) VALUES (?, ?, ?)")
+            .bind(organization.id)
+            .bind(owner_id)
+            .bind(Role::Owner)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(organization)
+    }
+
+    pub async fn list_for_member(
+        pool: &SqlitePool,
+        user_id: i64,
+    ) -> Result<Vec<OrganizationMembership>, sqlx::Error> {
+        sqlx::query_as::<_, OrganizationMembership>(
+            "SELECT organizations.*, memberships.role FROM organizations
+             JOIN memberships ON memberships.organization_id = organizations.id
+             WHERE memberships.user_id = ?
+             ORDER BY organizations.name COLLATE NOCASE",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// The organization `slug` if `user_id` is a member of it.
+    pub async fn find_for_member(
+        pool: &SqlitePool,
+        slug: &str,
+        user_id: i64,
+    ) -> Result<Option<OrganizationMembership>, sqlx::Error> {
+        sqlx::query_as::<_, OrganizationMembership>(
+            "SELECT organizations.*, memberships.role FROM organizations
+             JOIN memberships ON memberships.organization_id = organizations.id
+             WHERE organizations.slug = ? AND memberships.user_id = ?",
+        )
+        .bind(slug)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Deletes an organization with its memberships, invitations and
+    /// resources.
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM organizations WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// A member of an organization.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Member {
+    pub user_id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub avatar_url: Option<String>,
+    pub role: Role,
+    pub joined_at: String,
+}
+
+impl Member {
+    pub async fn list(pool: &SqlitePool, organization_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Member>(
+            "SELECT users.id AS user_id, users.username, users.email, users.avatar_url,
+                    memberships.role, memberships.created_at AS joined_at
+             FROM memberships JOIN users ON users.id = memberships.user_id
+             WHERE memberships.organization_id = ?
+             ORDER BY memberships.created_at, memberships.id",
+        )
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find(
+        pool: &SqlitePool,
+        organization_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Member>(
+            "SELECT users.id AS user_id, users.username, users.email, users.avatar_url,
+                    memberships.role, memberships.created_at AS joined_at
+             FROM memberships JOIN users ON users.id = memberships.user_id
+             WHERE memberships.organization_id = ? AND memberships.user_id = ?",
+        )
+        .bind(organization_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// The role of `user_id` in the organization, if they are a member.
+    pub async fn role(
+        pool: &SqlitePool,
+        organization_id: i64,
+        user_id: i64,
+    ) -> Result<Option<Role>, sqlx::Error> {
+        sqlx::query_scalar("SELECT role FROM memberships WHERE organization_id = ? AND user_id = ?")
+            .bind(organization_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Changes a member's role. Returns false if `user_id` is not a member.
+    /// The last owner cannot be demoted.
+    pub async fn set_role(
+        pool: &SqlitePool,
+        organization_id: i64,
+        user_id: i64,
+        role: Role,
+    ) -> Result<bool, AppError> {
+        let mut tx = pool.begin().await?;
+        let Some(current) = member_role(&mut tx, organization_id, user_id).await? else {
+            return Ok(false);
+        };
+        if current == Role::Owner && role != Role::Owner {
+            ensure_other_owner(&mut tx, organization_id).await?;
+        }
+
+        sqlx::query("UPDATE memberships SET role = ? WHERE organization_id = ? AND user_id = ?")
+            .bind(role)
+            .bind(organization_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Removes a member. Returns false if `user_id` is not a member. The last
+    /// owner cannot leave.
+    pub async fn remove(
+        pool: &SqlitePool,
+        organization_id: i64,
+        user_id: i64,
+    ) -> Result<bool, AppError> {
+        let mut tx = pool.begin().await?;
+        let Some(current) = member_role(&mut tx, organization_id, user_id).await? else {
+            return Ok(false);
+        };
+        if current == Role::Owner {
+            ensure_other_owner(&mut tx, organization_id).await?;
+        }
+
+        sqlx::query("DELETE FROM memberships WHERE organization_id = ? AND user_id = ?")
+            .bind(organization_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+async fn member_role(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    organization_id: i64,
+    user_id: i64,
+) -> Result<Option<Role>, sqlx::Error> {
+    sqlx::query_scalar("SELECT role FROM memberships WHERE organization_id = ? AND user_id = ?")
+        .bind(organization_id)
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await
+}
+
+async fn ensure_other_owner(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    organization_id: i64,
+) -> Result<(), AppError> {
+    let owners: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM memberships WHERE organization_id = ? AND role = 'owner'",
+    )
+    .bind(organization_id)
+    .fetch_one(&mut **tx)
+    .await?;
+    if owners <= 1 {
+        return Err(AppError::ValidationError(
+            "An organization needs at least one owner".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Invitation {
+    pub id: i64,
+    pub organization_id: i64,
+    /// The username or email address the invitation was sent to.
+    pub invitee: String,
+    #[serde(skip)]
+    pub user_id: Option<i64>,
+    #[serde(skip)]
+    pub email: Option<String>,
+    pub role: Role,
+    pub invited_by: Option<i64>,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+/// An invitation as shown to the user it is addressed to.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct PendingInvitation {
+    pub id: i64,
+    pub organization: String,
+    pub organization_name: String,
+    pub role: Role,
+    /// Username of the member who sent the invitation.
+    pub invited_by: Option<String>,
+    pub expires_at: String,
+}
+
+/// Matches the invitations addressed to a user: bound to their id, or sent to
+/// their email address once verified. Takes the user's id and verified email
+/// as parameters.
+const INVITATION_FOR_USER: &str = "(invitations.user_id = ?
+     OR (invitations.email IS NOT NULL AND invitations.email = ?))
+    AND invitations.expires_at > datetime('now')";
+
+impl Invitation {
+    /// Invites `invitee` to an organization, replacing any earlier invitation
+    /// of theirs. Usernames must name exactly one existing user; email
+    /// addresses may belong to someone who has not signed up yet.
+    pub async fn create(
+        pool: &SqlitePool,
+        organization_id: i64,
+        invitee: &Invitee,
+        role: Role,
+        invited_by: i64,
+    ) -> Result<Self, AppError> {
+        sqlx::query("DELETE FROM invitations WHERE expires_at <= datetime('now')")
+            .execute(pool)
+            .await?;
+
+        let (display, user_id, email) = match invitee {
+            Invitee::Username(username) => {
+                let mut users = sqlx::query_as::<_, User>(
+                    "SELECT * FROM users WHERE username = ? COLLATE NOCASE",
+                )
+                .bind(username)
+                .fetch_all(pool)
+                .await?;
+                let user = match users.len() {
+                    0 => return Err(AppError::NotFound(format!("No user named {}", username))),
+                    1 => users.remove(0),
+                    _ => {
+                        return Err(AppError::ValidationError(format!(
+                            "Several users are named {}; invite them by email instead",
+                            username
+                        )))
+                    }
+                };
+                if Member::find(pool, organization_id, user.id)
+                    .await?
+                    .is_some()
+                {
+                    return Err(AppError::ValidationError(format!(
+                        "{} is already a member",
+                        user.username
+                    )));
+                }
+                (user.username, Some(user.id), None)
+            }
+            Invitee::Email(email) => {
+                let member: Option<i64> = sqlx::query_scalar(
+                    "SELECT users.id FROM memberships JOIN users ON users.id = memberships.user_id
+                     WHERE memberships.organization_id = ? AND users.email = ? COLLATE NOCASE",
+                )
+                .bind(organization_id)
+                .bind(email)
+                .fetch_optional(pool)
+                .await?;
+                if member.is_some() {
+                    return Err(AppError::ValidationError(format!(
+                        "{} is already a member",
+                        email
+                    )));
+                }
+                (email.clone(), None, Some(email.clone()))
+            }
+        };
+
+        sqlx::query_as::<_, Invitation>(
+            "INSERT INTO invitations (organization_id, invitee, user_id, email, role, invited_by, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, datetime('now', ?))
+             ON CONFLICT(organization_id, invitee) DO UPDATE SET
+                role = excluded.role,
+                invited_by = excluded.invited_by,
+                expires_at = excluded.expires_at,
+                created_at = datetime('now')
+             RETURNING *",
+        )
+        .bind(organization_id)
+        .bind(display)
+        .bind(user_id)
+        .bind(email)
+        .bind(role)
+        .bind(invited_by)
+        .bind(format!("{:+} seconds", orgs::INVITATION_TTL_SECS))
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+        .map_err(AppError::from)
+    }
+
+    /// Open invitations of an organization.
+    pub async fn list(pool: &SqlitePool, organization_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Invitation>(
+            "SELECT * FROM invitations
+             WHERE organization_id = ? AND expires_at > datetime('now')
+             ORDER BY created_at, id",
+        )
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Withdraws an invitation. Returns false if the organization has no such
+    /// invitation.
+    pub async fn delete(
+        pool: &SqlitePool,
+        organization_id: i64,
+        id: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM invitations WHERE id = ? AND organization_id = ?")
+            .bind(id)
+            .bind(organization_id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Open invitations addressed to `user`.
+    pub async fn list_for_user(
+        pool: &SqlitePool,
+        user: &User,
+    ) -> Result<Vec<PendingInvitation>, sqlx::Error> {
+        sqlx::query_as::<_, PendingInvitation>(&format!(
+            "SELECT invitations.id, organizations.slug AS organization,
+                    organizations.name AS organization_name, invitations.role,
+                    inviter.username AS invited_by, invitations.expires_at
+             FROM invitations
+             JOIN organizations ON organizations.id = invitations.organization_id
+             LEFT JOIN users AS inviter ON inviter.id = invitations.invited_by
+             WHERE {}
+             ORDER BY invitations.created_at, invitations.id",
+            INVITATION_FOR_USER
+        ))
+        .bind(user.id)
+        .bind(user.verified_email())
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Makes `user` a member with the invited role. Returns None if no open
+    /// invitation `id` is addressed to them. Accepting an invitation to an
+    /// organization the user already belongs to leaves their role unchanged.
+    pub async fn accept(
+        pool: &SqlitePool,
+        id: i64,
+        user: &User,
+    ) -> Result<Option<OrganizationMembership>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let invitation = sqlx::query_as::<_, Invitation>(&format!(
+            "SELECT * FROM invitations WHERE id = ? AND {}",
+            INVITATION_FOR_USER
+        ))
+        .bind(id)
+        .bind(user.id)
+        .bind(user.verified_email())
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(invitation) = invitation else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            "INSERT INTO memberships (organization_id, user_id, role) VALUES (?, ?, ?)
+             ON CONFLICT(organization_id, user_id) DO NOTHING",
+        )
+        .bind(invitation.organization_id)
+        .bind(user.id)
+        .bind(invitation.role)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM invitations WHERE id = ?")
+            .bind(invitation.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let membership = sqlx::query_as::<_, OrganizationMembership>(
+            "SELECT organizations.*, memberships.role FROM organizations
+             JOIN memberships ON memberships.organization_id = organizations.id
+             WHERE organizations.id = ? AND memberships.user_id = ?",
+        )
+        .bind(invitation.organization_id)
+        .bind(user.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(membership))
+    }
+
+    /// Declines an invitation addressed to `user`. Returns the id of the
+    /// organization it was from, or None if there is no such open invitation.
+    pub async fn decline(
+        pool: &SqlitePool,
+        id: i64,
+        user: &User,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let mut organization_ids = sqlx::query_scalar(&format!(
+            "DELETE FROM invitations WHERE id = ? AND {} RETURNING organization_id",
+            INVITATION_FOR_USER
+        ))
+        .bind(id)
+        .bind(user.id)
+        .bind(user.verified_email())
+        .fetch_all(pool)
+        .await?;
+        Ok(organization_ids.pop())
+    }
+}
+
+/// An entry of the audit log. `metadata` holds a JSON object.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    /// The actor's username when the event was recorded.
+    pub actor: Option<String>,
+    pub organization_id: Option<i64>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(serialize_with = "serialize_json_text")]
+    pub metadata: String,
+    pub created_at: String,
+}
+
+fn serialize_json_text<S: serde::Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(serde::ser::Error::custom)?;
+    value.serialize(serializer)
+}
+
+impl AuditEvent {
+    pub async fn create(
+        pool: &SqlitePool,
+        event: &audit::Event,
+        context: &audit::RequestContext,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, AuditEvent>(
+            "INSERT INTO audit_events
+                 (actor_id, actor, organization_id, action, target_type, target_id, ip, user_agent, metadata)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING *",
+        )
+        .bind(event.actor_id)
+        .bind(&event.actor)
+        .bind(event.organization_id)
+        .bind(event.action)
+        .bind(event.target_type)
+        .bind(&event.target_id)
+        .bind(&context.ip)
+        .bind(&context.user_agent)
+        .bind(event.metadata.to_string())
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    /// Lists matching events, newest first, starting below the id `before`.
+    pub async fn list(
+        pool: &SqlitePool,
+        filter: &audit::Filter,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut query = Self::select(filter);
+        if let Some(before) = before {
+            query.push(" AND id < ").push_bind(before);
+        }
+        query.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    /// Lists matching events, oldest first, starting above the id `after`.
+    pub async fn list_since(
+        pool: &SqlitePool,
+        filter: &audit::Filter,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut query = Self::select(filter);
+        if let Some(after) = after {
+            query.push(" AND id > ").push_bind(after);
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit);
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    fn select(filter: &audit::Filter) -> sqlx::QueryBuilder<'_, sqlx::Sqlite> {
+        let mut query = sqlx::QueryBuilder::new("SELECT * FROM audit_events WHERE ");
+        match filter.scope {
+            Owner::User(id) => query.push("actor_id = ").push_bind(id),
+            Owner::Organization(id) => query.push("organization_id = ").push_bind(id),
+        };
+        if let Some(actor) = &filter.actor {
+            query
+                .push(" AND actor = ")
+                .push_bind(actor)
+                .push(" COLLATE NOCASE");
+        }
+        if let Some(action) = &filter.action {
+            query
+                .push(" AND (action = ")
+                .push_bind(action)
+                .push(" OR substr(action, 1, length(")
+                .push_bind(action)
+                .push(") + 1) = ")
+                .push_bind(action)
+                .push(" || '.')");
+        }
+        if let Some(target_type) = &filter.target_type {
+            query.push(" AND target_type = ").push_bind(target_type);
+        }
+        if let Some(target_id) = &filter.target_id {
+            query.push(" AND target_id = ").push_bind(target_id);
+        }
+        if let Some(since) = &filter.since {
+            query.push(" AND created_at >= ").push_bind(since);
+        }
+        if let Some(until) = &filter.until {
+            query.push(" AND created_at < ").push_bind(until);
+        }
+        query
+    }
+}
+
+/// Who can see a repository. GitLab's internal projects are visible to every
+/// user of the instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    Internal,
+    Private,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Repository {
+    pub id: i64,
+    pub user_id: i64,
+    /// Set when the repository belongs to an organization.
+    pub organization_id: Option<i64>,
+    /// The `git_providers` connection the repository was imported through.
+    pub provider_id: i64,
+    /// The provider's id of the repository.
+    pub provider_repo_id: Option<String>,
+    /// Name including the owner, e.g. "octocat/hello-world".
+    pub name: String,
+    pub description: Option<String>,
+    /// Web page of the repository.
+    pub url: String,
+    /// HTTPS URL to clone from.
+    pub clone_url: String,
+    /// None for empty repositories.
+    pub default_branch: Option<String>,
+    pub visibility: Visibility,
+    pub last_synced: Option<String>,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewRepository {
+    pub user_id: i64,
+    pub organization_id: Option<i64>,
+    pub provider_id: i64,
+    pub provider_repo_id: Option<String>,
+    pub name: String,
+    pub description: Option<String>,
+    pub url: String,
+    pub clone_url: String,
+    pub default_branch: Option<String>,
+    pub visibility: Visibility,
+}
+
+/// A repository with the slug of the provider instance it was imported from.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct ListedRepository {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub repository: Repository,
+    pub provider: String,
+}
+
+/// Outcome of syncing the repositories of one connection.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct RepositorySync {
+    pub synced: u64,
+    pub removed: u64,
+}
+
+impl Repository {
+    pub fn owner(&self) -> Owner {
+        Owner::from_columns(self.user_id, self.organization_id)
+    }
+
+    pub async fn create(
+        pool: &SqlitePool,
+        repository: &NewRepository,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Repository>(
+            "INSERT INTO repositories
+                 (user_id, organization_id, provider_id, provider_repo_id, name, description,
+                  url, clone_url, default_branch, visibility)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING *",
+        )
+        .bind(repository.user_id)
+        .bind(repository.organization_id)
+        .bind(repository.provider_id)
+        .bind(&repository.provider_repo_id)
+        .bind(&repository.name)
+        .bind(&repository.description)
+        .bind(&repository.url)
+        .bind(&repository.clone_url)
+        .bind(&repository.default_branch)
+        .bind(repository.visibility)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    pub async fn find(pool: &SqlitePool, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Repository>("SELECT * FROM repositories WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Stores the repository's name, description, URLs, default branch,
+    /// visibility and owner.
+    pub async fn update(&self, pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Repository>(
+            "UPDATE repositories
+             SET organization_id = ?,
+                 name = ?,
+                 description = ?,
+                 url = ?,
+                 clone_url = ?,
+                 default_branch = ?,
+                 visibility = ?,
+                 updated_at = datetime('now')
+             WHERE id = ?
+             RETURNING *",
+        )
+        .bind(self.organization_id)
+        .bind(&self.name)
+        .bind(&self.description)
+        .bind(&self.url)
+        .bind(&self.clone_url)
+        .bind(&self.default_branch)
+        .bind(self.visibility)
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM repositories WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Makes the repositories the user imported from `provider_id` match
+    /// `repositories`: new ones are added, known ones updated and those no
+    /// longer listed removed. All of them get the same `last_synced`.
+    pub async fn sync(
+        pool: &SqlitePool,
+        user_id: i64,
+        provider_id: i64,
+        repositories: &[RemoteRepository],
+    ) -> Result<RepositorySync, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let now: String = sqlx::query_scalar("SELECT datetime('now')")
+            .fetch_one(&mut *tx)
+            .await?;
+
+        // Unmark everything, so that whatever is not listed again is stale.
+        sqlx::query(
+            "UPDATE repositories SET last_synced = NULL WHERE user_id = ? AND provider_id = ?",
+        )
+        .bind(user_id)
+        .bind(provider_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let mut synced = 0;
+        for repository in repositories {
+            synced += sqlx::query(
+                "INSERT INTO repositories
+                     (user_id, provider_id, provider_repo_id, name, description, url,
+                      clone_url, default_branch, visibility, last_synced)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT(user_id, provider_id, provider_repo_id) DO UPDATE SET
+                     name = excluded.name,
+                     description = excluded.description,
+                     url = excluded.url,
+                     clone_url = excluded.clone_url,
+                     default_branch = excluded.default_branch,
+                     visibility = excluded.visibility,
+                     last_synced = excluded.last_synced",
+            )
+            .bind(user_id)
+            .bind(provider_id)
+            .bind(&repository.id)
+            .bind(&repository.full_name)
+            .bind(&repository.description)
+            .bind(&repository.url)
+            .bind(&repository.clone_url)
+            .bind(&repository.default_branch)
+            .bind(repository.visibility)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        let removed = sqlx::query(
+            "DELETE FROM repositories
+             WHERE user_id = ? AND provider_id = ? AND last_synced IS NULL",
+        )
+        .bind(user_id)
+        .bind(provider_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok(RepositorySync { synced, removed })
+    }
+
+    /// A page of the user's personal repositories, ordered by name, with the
+    /// number of repositories matching in total. `search` matches anywhere in
+    /// the name.
+    pub async fn list(
+        pool: &SqlitePool,
+        user_id: i64,
+        search: Option<&str>,
+        provider: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<ListedRepository>, i64), sqlx::Error> {
+        const MATCHING: &str = "FROM repositories
+             JOIN git_providers ON git_providers.id = repositories.provider_id
+             WHERE repositories.user_id = ?1
+               AND repositories.organization_id IS NULL
+               AND (?2 IS NULL OR repositories.name LIKE ?2 ESCAPE '\\')
+               AND (?3 IS NULL OR git_providers.provider = ?3)";
+
+        let pattern = search.map(|search| {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        });
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) {}", MATCHING))
+            .bind(user_id)
+            .bind(&pattern)
+            .bind(provider)
+            .fetch_one(pool)
+            .await?;
+        let repositories = sqlx::query_as::<_, ListedRepository>(&format!(
+            "SELECT repositories.*, git_providers.provider {}
+             ORDER BY repositories.name COLLATE NOCASE, repositories.id
+             LIMIT ?4 OFFSET ?5",
+            MATCHING
+        ))
+        .bind(user_id)
+        .bind(&pattern)
+        .bind(provider)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok((repositories, total))
+    }
+}
+
+/// What an app is built from and how.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone, Default, PartialEq)]
+pub struct AppSettings {
+    pub repository_id: Option<i64>,
+    /// The repository's default branch when None.
+    pub branch: Option<String>,
+    /// Directory of the app inside the repository; "" for the root.
+    #[serde(default)]
+    pub root_dir: String,
+    /// Detected from the source when None.
+    pub build_command: Option<String>,
+    /// Detected from the source when None.
+    pub start_command: Option<String>,
+    /// Language version to build with; the one the source asks for when
+    /// None.
+    pub runtime_version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Application {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    /// The user who created the app.
+    pub user_id: i64,
+    /// Set when the app belongs to an organization.
+    pub organization_id: Option<i64>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub settings: AppSettings,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// An app with the name of its repository.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct ListedApplication {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub app: Application,
+    pub repository: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApplication {
+    pub slug: String,
+    pub name: String,
+    pub user_id: i64,
+    pub organization_id: Option<i64>,
+    pub settings: AppSettings,
+}
+
+const LISTED_APP: &str = "SELECT apps.*, repositories.name AS repository FROM apps
+     LEFT JOIN repositories ON repositories.id = apps.repository_id";
+
+impl Application {
+    pub fn owner(&self) -> Owner {
+        Owner::from_columns(self.user_id, self.organization_id)
+    }
+
+    pub async fn create(pool: &SqlitePool, app: &NewApplication) -> Result<Self, AppError> {
+        sqlx::query_as::<_, Application>(
+            "INSERT INTO apps
+                 (slug, name, user_id, organization_id, repository_id, branch, root_dir,
+                  build_command, start_command, runtime_version)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING *",
+        )
+        .bind(&app.slug)
+        .bind(&app.name)
+        .bind(app.user_id)
+        .bind(app.organization_id)
+        .bind(app.settings.repository_id)
+        .bind(&app.settings.branch)
+        .bind(&app.settings.root_dir)
+        .bind(&app.settings.build_command)
+        .bind(&app.settings.start_command)
+        .bind(&app.settings.runtime_version)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                AppError::ValidationError(format!("Slug '{}' is already taken", app.slug))
+            } else {
+                e.into()
+            }
+        })
+    }
+
+    /// The apps of `owner`, ordered by name.
+    pub async fn list(
+        pool: &SqlitePool,
+        owner: Owner,
+    ) -> Result<Vec<ListedApplication>, sqlx::Error> {
+        let query = format!(
+            "{} WHERE {} ORDER BY apps.name COLLATE NOCASE",
+            LISTED_APP,
+            match owner {
+                Owner::User(_) => "apps.user_id = ? AND apps.organization_id IS NULL",
+                Owner::Organization(_) => "apps.organization_id = ?",
+            }
+        );
+        let id = match owner {
+            Owner::User(id) | Owner::Organization(id) => id,
+        };
+        sqlx::query_as::<_, ListedApplication>(&query)
+            .bind(id)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find(pool: &SqlitePool, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Application>("SELECT * FROM apps WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn find_by_slug(
+        pool: &SqlitePool,
+        slug: &str,
+    ) -> Result<Option<ListedApplication>, sqlx::Error> {
+        sqlx::query_as::<_, ListedApplication>(&format!("{} WHERE apps.slug = ?", LISTED_APP))
+            .bind(slug)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Stores the app's name and settings. The slug and owner do not change.
+    pub async fn update(&self, pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Application>(
+            "UPDATE apps
+             SET name = ?,
+                 repository_id = ?,
+                 branch = ?,
+                 root_dir = ?,
+                 build_command = ?,
+                 start_command = ?,
+                 runtime_version = ?,
+                 updated_at = datetime('now')
+             WHERE id = ?
+             RETURNING *",
+        )
+        .bind(&self.name)
+        .bind(self.settings.repository_id)
+        .bind(&self.settings.branch)
+        .bind(&self.settings.root_dir)
+        .bind(&self.settings.build_command)
+        .bind(&self.settings.start_command)
+        .bind(&self.settings.runtime_version)
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM apps WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Deployment {
+    pub id: i64,
+    pub app_id: i64,
+    pub commit_sha: String,
+    pub status: DeploymentStatus,
+    /// The user who triggered the deployment, None once they are deleted.
+    pub triggered_by: Option<i64>,
+    /// Why the deployment failed.
+    pub error: Option<String>,
+    pub created_at: String,
+    pub building_at: Option<String>,
+    pub releasing_at: Option<String>,
+    pub running_at: Option<String>,
+    /// When the deployment failed, was cancelled or was superseded.
+    pub finished_at: Option<String>,
+    /// How the deployment is built, once its source has been looked at.
+    pub build_plan: Option<Json<BuildPlan>>,
+    /// The image the deployment was built into.
+    pub image: Option<String>,
+}
+
+impl Deployment {
+    /// Queues a deployment of `commit_sha`. Deployments of the app still
+    /// waiting in the queue are superseded by it.
+    pub async fn create(
+        pool: &SqlitePool,
+        app_id: i64,
+        commit_sha: &str,
+        triggered_by: i64,
+    ) -> Result<Self, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let deployment = Self::create_in(&mut tx, app_id, commit_sha, triggered_by).await?;
+        tx.commit().await?;
+        Ok(deployment)
+    }
+
+    /// `create` within `tx`, e.g. to enqueue the deployment's build with it.
+    pub async fn create_in(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        app_id: i64,
+        commit_sha: &str,
+        triggered_by: i64,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query(
+            "UPDATE deployments SET status = 'superseded', finished_at = datetime('now')
+             WHERE app_id = ? AND status = 'queued'",
+        )
+        .bind(app_id)
+        .execute(&mut **tx)
+        .await?;
+        sqlx::query_as::<_, Deployment>(
+            "INSERT INTO deployments (app_id, commit_sha, triggered_by)
+             VALUES (?, ?, ?)
+             RETURNING *",
+        )
+        .bind(app_id)
+        .bind(commit_sha)
+        .bind(triggered_by)
+        .fetch_all(&mut **tx)
+        .await
+        .and_then(returned_row)
+    }
+
+    /// The latest `limit` deployments of an app, newest first.
+    pub async fn list(
+        pool: &SqlitePool,
+        app_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Deployment>(
+            "SELECT * FROM deployments WHERE app_id = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(app_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find(pool: &SqlitePool, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Deployment>("SELECT * FROM deployments WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Moves the deployment to `status`, recording when. A deployment that
+    /// starts running supersedes the one of the app running before it.
+    ///
+    /// Fails if the state machine does not allow the transition, including
+    /// when the deployment has moved on since it was read.
+    pub async fn transition(
+        &self,
+        pool: &SqlitePool,
+        status: DeploymentStatus,
+    ) -> Result<Self, AppError> {
+        self.set_status(pool, status, None).await
+    }
+
+    /// Marks the deployment failed with the reason in `error`.
+    pub async fn fail(&self, pool: &SqlitePool, error: &str) -> Result<Self, AppError> {
+        self.set_status(pool, DeploymentStatus::Failed, Some(error))
+            .await
+    }
+
+    /// Records how the deployment is built, which is only decided while it
+    /// is building.
+    pub async fn set_build_plan(
+        &self,
+        pool: &SqlitePool,
+        plan: &BuildPlan,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, Deployment>(
+            "UPDATE deployments SET build_plan = ?
+             WHERE id = ? AND status = 'building'
+             RETURNING *",
+        )
+        .bind(Json(plan))
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or_else(|| AppError::ValidationError(format!("Deployment {} is not building", self.id)))
+    }
+
+    /// Records the image the deployment was built into, while it is still
+    /// building.
+    pub async fn set_image(&self, pool: &SqlitePool, image: &str) -> Result<Self, AppError> {
+        sqlx::query_as::<_, Deployment>(
+            "UPDATE deployments SET image = ?
+             WHERE id = ? AND status = 'building'
+             RETURNING *",
+        )
+        .bind(image)
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or_else(|| AppError::ValidationError(format!("Deployment {} is not building", self.id)))
+    }
+
+    async fn set_status(
+        &self,
+        pool: &SqlitePool,
+        status: DeploymentStatus,
+        error: Option<&str>,
+    ) -> Result<Self, AppError> {
+        self.status.transition(status)?;
+        let mut tx = pool.begin().await?;
+
+        // Matching the status read guards against concurrent transitions.
+        let updated = sqlx::query_as::<_, Deployment>(&format!(
+            "UPDATE deployments SET status = ?, error = ?, {} = datetime('now')
+             WHERE id = ? AND status = ?
+             RETURNING *",
+            status.timestamp_column()
+        ))
+        .bind(status)
+        .bind(error)
+        .bind(self.id)
+        .bind(self.status)
+        .fetch_all(&mut *tx)
+        .await?
+        .pop();
+        let Some(updated) = updated else {
+            drop(tx);
+            let current = Deployment::find(pool, self.id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("No deployment {}", self.id)))?;
+            current.status.transition(status)?;
+            return Err(AppError::ValidationError(format!(
+                "Deployment {} is already {}",
+                self.id, current.status
+            )));
+        };
+
+        if status == DeploymentStatus::Running {
+            sqlx::query(
+                "UPDATE deployments SET status = 'superseded', finished_at = datetime('now')
+                 WHERE app_id = ? AND status = 'running' AND id != ?",
+            )
+            .bind(self.app_id)
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(updated)
+    }
+}
+
+/// A line of a deployment's build output.
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct DeploymentLogLine {
+    pub id: i64,
+    pub deployment_id: i64,
+    pub line: String,
+    pub created_at: String,
+}
+
+impl DeploymentLogLine {
+    pub async fn append(
+        pool: &SqlitePool,
+        deployment_id: i64,
+        line: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, DeploymentLogLine>(
+            "INSERT INTO deployment_logs (deployment_id, line) VALUES (?, ?) RETURNING *",
+        )
+        .bind(deployment_id)
+        .bind(line)
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)
+    }
+
+    /// Up to `limit` lines of a deployment's log written after the line
+    /// with id `after`, oldest first. Pass 0 to read from the start.
+    pub async fn list(
+        pool: &SqlitePool,
+        deployment_id: i64,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, DeploymentLogLine>(
+            "SELECT * FROM deployment_logs
+             WHERE deployment_id = ? AND id > ?
+             ORDER BY id LIMIT ?",
+        )
+        .bind(deployment_id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    #[serde(serialize_with = "serialize_json_text")]
+    pub payload: String,
+    pub status: JobStatus,
+    pub priority: i64,
+    /// Attempts started so far, including the running one.
+    pub attempts: i64,
+    pub max_attempts: i64,
+    /// When the job may run, or run again.
+    pub run_at: String,
+    pub locked_by: Option<String>,
+    pub locked_until: Option<String>,
+    /// Why the last attempt failed.
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+}
+
+/// Matches the claimed job only while the claim that read it still holds.
+const OWN_CLAIM: &str = "id = ? AND status = 'running' AND locked_by = ? AND attempts = ?";
+
+impl Job {
+    pub async fn create(pool: &SqlitePool, job: &NewJob) -> Result<Self, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let job = Self::create_in(&mut tx, job).await?;
+        tx.commit().await?;
+        Ok(job)
+    }
+
+    /// `create` within `tx`, so the job is only enqueued if `tx` commits.
+    pub async fn create_in(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        job: &NewJob,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            "INSERT INTO jobs (kind, payload, priority, max_attempts, run_at)
+             VALUES (?, ?, ?, ?, datetime('now', ?))
+             RETURNING *",
+        )
+        .bind(job.kind)
+        .bind(job.payload.to_string())
+        .bind(job.priority)
+        .bind(job.max_attempts)
+        .bind(format!("{:+} seconds", job.delay_secs))
+        .fetch_all(&mut **tx)
+        .await
+        .and_then(returned_row)
+    }
+
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, AppError> {
+        serde_json::from_str(&self.payload).map_err(|e| {
+            AppError::ValidationError(format!("Invalid payload for job {}: {}", self.id, e))
+        })
+    }
+
+    /// Claims the ready job with the highest priority for `worker`, for
+    /// `visibility_timeout_secs`. Jobs whose claim expired are ready again,
+    /// or dead if that was their last attempt.
+    pub async fn claim(
+        pool: &SqlitePool,
+        worker: &str,
+        visibility_timeout_secs: i64,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            "UPDATE jobs
+             SET status = 'dead',
+                 last_error = 'Timed out',
+                 locked_by = NULL,
+                 locked_until = NULL,
+                 updated_at = datetime('now'),
+                 finished_at = datetime('now')
+             WHERE status = 'running'
+               AND locked_until <= datetime('now')
+               AND attempts >= max_attempts",
+        )
+        .execute(&mut *tx)
+        .await?;
+        let job = sqlx::query_as::<_, Job>(
+            "UPDATE jobs
+             SET last_error = CASE WHEN status = 'running' THEN 'Timed out' ELSE last_error END,
+                 status = 'running',
+                 attempts = attempts + 1,
+                 locked_by = ?,
+                 locked_until = datetime('now', ?),
+                 updated_at = datetime('now')
+             WHERE id = (
+                 SELECT id FROM jobs
+                 WHERE (status = 'pending' AND run_at <= datetime('now'))
+                    OR (status = 'running' AND locked_until <= datetime('now'))
+                 ORDER BY priority DESC, run_at, id
+                 LIMIT 1)
+             RETURNING *",
+        )
+        .bind(worker)
+        .bind(format!("{:+} seconds", visibility_timeout_secs))
+        .fetch_all(&mut *tx)
+        .await?
+        .pop();
+
+        tx.commit().await?;
+        Ok(job)
+    }
+
+    /// Marks a claimed job as succeeded. Returns None if the claim was lost.
+    pub async fn complete(&self, pool: &SqlitePool) -> Result<Option<Self>, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(&format!(
+            "UPDATE jobs
+             SET status = 'succeeded',
+                 locked_by = NULL,
+                 locked_until = NULL,
+                 updated_at = datetime('now'),
+                 finished_at = datetime('now')
+             WHERE {}
+             RETURNING *",
+            OWN_CLAIM
+        ))
+        .bind(self.id)
+        .bind(&self.locked_by)
+        .bind(self.attempts)
+        .fetch_all(pool)
+        .await?;
+        Ok(job.into_iter().next())
+    }
+
+    /// Fails the attempt of a claimed job. The job runs again after
+    /// `retry_in_secs`, or is dead if None. Returns None if the claim was
+    /// lost.
+    pub async fn fail(
+        &self,
+        pool: &SqlitePool,
+        error: &str,
+        retry_in_secs: Option<i64>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let (status, delay_secs) = match retry_in_secs {
+            Some(secs) => (JobStatus::Pending, secs),
+            None => (JobStatus::Dead, 0),
+        };
+        let job = sqlx::query_as::<_, Job>(&format!(
+            "UPDATE jobs
+             SET status = ?,
+                 run_at = datetime('now', ?),
+                 finished_at = CASE WHEN ? THEN datetime('now') END,
+                 last_error = ?,
+                 locked_by = NULL,
+                 locked_until = NULL,
+                 updated_at = datetime('now')
+             WHERE {}
+             RETURNING *",
+            OWN_CLAIM
+        ))
+        .bind(status)
+        .bind(format!("{:+} seconds", delay_secs))
+        .bind(status == JobStatus::Dead)
+        .bind(error)
+        .bind(self.id)
+        .bind(&self.locked_by)
+        .bind(self.attempts)
+        .fetch_all(pool)
+        .await?;
+        Ok(job.into_iter().next())
+    }
+
+    pub async fn find(pool: &SqlitePool, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// The latest `limit` jobs, newest first, optionally of one status and
+    /// kind.
+    pub async fn list(
+        pool: &SqlitePool,
+        status: Option<JobStatus>,
+        kind: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Job>(
+            "SELECT * FROM jobs
+             WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR kind = ?2)
+             ORDER BY id DESC
+             LIMIT ?3",
+        )
+        .bind(status)
+        .bind(kind)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Makes a dead or pending job ready to run right away. Dead jobs get
+    /// their attempts back. Returns None for jobs running or succeeded.
+    pub async fn retry(pool: &SqlitePool, id: i64) -> Result<Option<Self>, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            "UPDATE jobs
+             SET attempts = CASE WHEN status = 'dead' THEN 0 ELSE attempts END,
+                 status = 'pending',
+                 run_at = datetime('now'),
+                 finished_at = NULL,
+                 updated_at = datetime('now')
+             WHERE id = ? AND status IN ('pending', 'dead')
+             RETURNING *",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(job.into_iter().next())
+    }
+}
+
+pub const OAUTH_STATE_TTL_SECS: i64 = 600;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct OAuthState {
+    pub state: String,
+    pub provider: String,
+    pub pkce_verifier: Option<String>,
+    pub nonce: Option<String>,
+    pub expires_at: String,
+    pub created_at: String,
+    /// The signed-in user the provider is being connected to; NULL for
+    /// sign-in flows.
+    pub user_id: Option<i64>,
+}
+
+impl OAuthState {
+    pub async fn create(
+        pool: &SqlitePool,
+        provider: &str,
+        state: &str,
+        pkce_verifier: Option<&str>,
+        nonce: Option<&str>,
+        user_id: Option<i64>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query("DELETE FROM oauth_states WHERE expires_at <= datetime('now')")
+            .execute(pool)
+            .await?;
+
+        let oauth_state = sqlx::query_as::<_, OAuthState>(
+            "INSERT INTO oauth_states (state, provider, pkce_verifier, nonce, user_id, expires_at, created_at)
+             VALUES (?, ?, ?, ?, ?, datetime('now', ?), datetime('now'))
+             RETURNING *",
+        )
+        .bind(state)
+        .bind(provider)
+        .bind(pkce_verifier)
+        .bind(nonce)
+        .bind(user_id)
+        .bind(format!("+{} seconds", OAUTH_STATE_TTL_SECS))
+        .fetch_all(pool)
+        .await
+        .and_then(returned_row)?;
+
+        Ok(oauth_state)
+    }
+
+    /// Deletes and returns the stored state, so each state can only be redeemed once.
+    pub async fn consume(
+        pool: &SqlitePool,
+        provider: &str,
+        state: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, OAuthState>(
+            "DELETE FROM oauth_states WHERE state = ? AND provider = ? RETURNING *",
+        )
+        .bind(state)
+        .bind(provider)
+        .fetch_all(pool)
+        .await
+        .map(|mut rows| rows.pop())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        expires_within(&self.expires_at, 0)
+    }
+}
+
+/// Server-side state of a browser session (see `session::SqliteSessionStore`).
+#[derive(Debug, FromRow, Clone)]
+pub struct SessionRecord {
+    /// SHA-256 of the session key held in the cookie.
+    pub id: String,
+    pub user_id: Option<i64>,
+    /// JSON object of session entries.
+    pub state: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub absolute_expires_at: String,
+}
+
+impl SessionRecord {
+    /// Stores a new session that expires after `ttl_secs` without activity,
+    /// and after `absolute_ttl_secs` regardless.
+    pub async fn create(
+        pool: &SqlitePool,
+        id: &str,
+        user_id: Option<i64>,
+        state: &str,
+        ttl_secs: i64,
+        absolute_ttl_secs: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM sessions WHERE expires_at <= datetime('now')")
+            .execute(pool)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO sessions (id, user_id, state, expires_at, absolute_expires_at, created_at)
+             VALUES (?1, ?2, ?3, MIN(datetime('now', ?4), datetime('now', ?5)), datetime('now', ?5), datetime('now'))",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(state)
+        .bind(format!("{:+} seconds", ttl_secs))
+        .bind(format!("{:+} seconds", absolute_ttl_secs))
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_active(pool: &SqlitePool, id: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, SessionRecord>(
+            "SELECT * FROM sessions WHERE id = ? AND expires_at > datetime('now')",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Replaces the state of an active session and extends its idle expiry.
+    /// Returns false if the session has expired or does not exist.
+    pub async fn update_state(
+        pool: &SqlitePool,
+        id: &str,
+        user_id: Option<i64>,
+        state: &str,
+        ttl_secs: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE sessions
+             SET user_id = ?, state = ?, expires_at = MIN(datetime('now', ?), absolute_expires_at)
+             WHERE id = ? AND expires_at > datetime('now')",
+        )
+        .bind(user_id)
+        .bind(state)
+        .bind(format!("{:+} seconds", ttl_secs))
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Extends the idle expiry of an active session, never past its absolute expiry.
+    pub async fn touch(pool: &SqlitePool, id: &str, ttl_secs: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE sessions SET expires_at = MIN(datetime('now', ?), absolute_expires_at)
+             WHERE id = ? AND expires_at > datetime('now')",
+        )
+        .bind(format!("{:+} seconds", ttl_secs))
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(pool: &SqlitePool, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,122 @@
+use crate::error::AppError;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use log::debug;
+use oauth2::{basic::BasicTokenType, ExtraTokenFields, StandardTokenResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Algorithms accepted for ID tokens. Symmetric algorithms are rejected since
+/// the keys come from the issuer's public JWKS.
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The subset of `.well-known/openid-configuration` used by the login flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+}
+
+/// Extra token response fields carrying the OpenID Connect ID token.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdTokenFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+pub type OAuthTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+
+pub fn id_token(token: &OAuthTokenResponse) -> Option<&str> {
+    token.extra_fields().id_token.as_deref()
+}
+
+pub async fn discover(issuer_url: &str) -> Result<ProviderMetadata, AppError> {
+    let issuer = issuer_url.trim_end_matches('/');
+    let discovery_url = format!("{}/.well-known/openid-configuration", issuer);
+    debug!("Fetching OpenID configuration from {}", discovery_url);
+
+    let metadata = reqwest::get(&discovery_url)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| AppError::ExternalServiceError(format!("OpenID discovery failed: {}", e)))?
+        .json::<ProviderMetadata>()
+        .await
+        .map_err(|e| {
+            AppError::ExternalServiceError(format!("Invalid OpenID configuration: {}", e))
+        })?;
+
+    if metadata.issuer.trim_end_matches('/') != issuer {
+        return Err(AppError::ExternalServiceError(format!(
+            "OpenID issuer mismatch: expected {}, got {}",
+            issuer, metadata.issuer
+        )));
+    }
+
+    Ok(metadata)
+}
+
+/// Verifies the ID token's signature against the issuer's JWKS and checks
+/// `iss`, `aud`, `exp` and `nonce`, returning the token's claims.
+pub async fn verify_id_token(
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<Value, AppError> {
+    let header = decode_header(id_token)
+        .map_err(|e| AppError::AuthError(format!("Invalid ID token: {}", e)))?;
+
+    if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+        return Err(AppError::AuthError(format!(
+            "Unsupported ID token algorithm: {:?}",
+            header.alg
+        )));
+    }
+
+    let jwks = reqwest::get(&metadata.jwks_uri)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| AppError::ExternalServiceError(format!("Failed to fetch JWKS: {}", e)))?
+        .json::<JwkSet>()
+        .await
+        .map_err(|e| AppError::ExternalServiceError(format!("Invalid JWKS: {}", e)))?;
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| AppError::AuthError("No matching ID token signing key".to_string()))?;
+
+    let key = DecodingKey::from_jwk(jwk)
+        .map_err(|e| AppError::AuthError(format!("Unusable ID token signing key: {}", e)))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = decode::<Value>(id_token, &key, &validation)
+        .map_err(|e| AppError::AuthError(format!("Invalid ID token: {}", e)))?
+        .claims;
+
+    if claims["nonce"].as_str() != Some(nonce) {
+        return Err(AppError::AuthError("ID token nonce mismatch".to_string()));
+    }
+
+    Ok(claims)
+}
@@ -0,0 +1,169 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Invitations expire after a week.
+pub const INVITATION_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+const MAX_SLUG_LEN: usize = 39;
+const MAX_NAME_LEN: usize = 100;
+
+/// Slugs that would collide with other `/api/orgs/...` routes.
+const RESERVED_SLUGS: &[&str] = &["invitations"];
+
+/// A member's role in an organization, from least to most privileged.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the organization's resources.
+    Viewer,
+    /// Deploys and configures apps.
+    Developer,
+    /// Also manages members and invitations.
+    Admin,
+    /// Also grants the owner role and deletes the organization.
+    Owner,
+}
+
+impl Role {
+    /// Whether a member with this role may invite, remove and change the role
+    /// of members holding `role`. Only owners manage other owners.
+    pub fn manages(&self, role: Role) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Admin => role != Role::Owner,
+            Role::Developer | Role::Viewer => false,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Owner => write!(f, "owner"),
+            Role::Admin => write!(f, "admin"),
+            Role::Developer => write!(f, "developer"),
+            Role::Viewer => write!(f, "viewer"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(Role::Owner),
+            "admin" => Ok(Role::Admin),
+            "developer" => Ok(Role::Developer),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(AppError::ValidationError(format!("Unknown role: {}", s))),
+        }
+    }
+}
+
+/// Who a repository or app belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    User(i64),
+    Organization(i64),
+}
+
+impl Owner {
+    /// Resources keep the user that created them in `user_id` and are owned
+    /// by the organization in `organization_id` when it is set.
+    pub fn from_columns(user_id: i64, organization_id: Option<i64>) -> Self {
+        match organization_id {
+            Some(organization_id) => Owner::Organization(organization_id),
+            None => Owner::User(user_id),
+        }
+    }
+
+    pub fn organization_id(&self) -> Option<i64> {
+        match self {
+            Owner::User(_) => None,
+            Owner::Organization(id) => Some(*id),
+        }
+    }
+}
+
+/// The person an invitation is addressed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invitee {
+    Username(String),
+    /// Lowercased.
+    Email(String),
+}
+
+impl FromStr for Invitee {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AppError::ValidationError(
+                "A username or email address is required".to_string(),
+            ));
+        }
+        match s.split_once('@') {
+            Some((local, domain)) if !local.is_empty() && domain.contains('.') => {
+                Ok(Invitee::Email(s.to_lowercase()))
+            }
+            Some(_) => Err(AppError::ValidationError(format!(
+                "Invalid email address: {}",
+                s
+            ))),
+            None => Ok(Invitee::Username(s.to_string())),
+        }
+    }
+}
+
+pub fn validate_slug(slug: &str) -> Result<(), AppError> {
+    let valid = !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LEN
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(AppError::ValidationError(format!(
+            "Slug must be 1 to {} lowercase letters, digits and dashes",
+            MAX_SLUG_LEN
+        )));
+    }
+    if RESERVED_SLUGS.contains(&slug) {
+        return Err(AppError::ValidationError(format!(
+            "Slug '{}' is reserved",
+            slug
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(AppError::ValidationError(format!(
+            "Organization name must be between 1 and {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Derives a slug from an organization name, e.g. "Acme Labs" -> "acme-labs".
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    slug.trim_end_matches('-').to_string()
+}
@@ -0,0 +1,211 @@
+use crate::{
+    builders::{BuildLog, BuildRequest, Builder, CancelSignal, DeploymentLog},
+    buildpacks,
+    deployments::DeploymentStatus,
+    error::AppError,
+    git::{self, FetchRequest, SourceCache},
+    models::{Application, Deployment, Repository},
+    tokens::TokenService,
+};
+use log::{info, warn};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+const INTERRUPTED: &str = "Build interrupted before it finished; deploy again";
+
+/// Takes queued deployments through their build: checks out the commit,
+/// plans the build, builds the image and hands the deployment over to be
+/// released.
+pub struct Pipeline {
+    sources: SourceCache,
+    builder: Arc<dyn Builder>,
+    token_service: Arc<TokenService>,
+    /// One build per app at a time, as builds share the app's working tree.
+    locks: Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>,
+    cancel_poll_interval: Duration,
+}
+
+impl Pipeline {
+    pub fn new(
+        sources: SourceCache,
+        builder: Arc<dyn Builder>,
+        token_service: Arc<TokenService>,
+    ) -> Self {
+        Self {
+            sources,
+            builder,
+            token_service,
+            locks: Mutex::new(HashMap::new()),
+            cancel_poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// How often a build checks whether its deployment was cancelled.
+    pub fn cancel_poll_interval(mut self, interval: Duration) -> Self {
+        self.cancel_poll_interval = interval;
+        self
+    }
+
+    /// Builds a queued deployment, leaving it releasing once its image is
+    /// built. A build that fails fails the deployment, with the reason in
+    /// its error and log; only errors of the pipeline itself are returned.
+    /// Deployments no longer queued, e.g. cancelled, are left as they are,
+    /// and cancelling a deployment while it builds stops the build. One
+    /// still building is left from an attempt that died, and fails.
+    pub async fn build(
+        &self,
+        pool: &SqlitePool,
+        deployment_id: i64,
+    ) -> Result<Deployment, AppError> {
+        let app_id = find_deployment(pool, deployment_id).await?.app_id;
+        let lock = self.lock(app_id);
+        let _guard = lock.lock().await;
+
+        // Read once the lock is held, as the deployment may have been
+        // cancelled while waiting for it.
+        let deployment = find_deployment(pool, deployment_id).await?;
+        let log = DeploymentLog::new(pool.clone(), deployment.id);
+        match deployment.status {
+            DeploymentStatus::Queued => {}
+            // Builds end with the attempt of their job, so the job was
+            // claimed again after its worker died or timed out mid-build.
+            DeploymentStatus::Building => {
+                warn!(
+                    "Deployment {} was left building by an earlier attempt",
+                    deployment_id
+                );
+                log.line(&format!("Error: {}", INTERRUPTED)).await;
+                return deployment.fail(pool, INTERRUPTED).await;
+            }
+            _ => return Ok(deployment),
+        }
+        let app = Application::find(pool, app_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No app {}", app_id)))?;
+        let deployment = deployment
+            .transition(pool, DeploymentStatus::Building)
+            .await?;
+
+        let (cancel, signal) = CancelSignal::channel();
+        let run = self.run(pool, &app, deployment, signal, &log);
+        tokio::pin!(run);
+        let result = tokio::select! {
+            result = &mut run => result,
+            () = self.watch_for_cancel(pool, deployment_id, cancel) => run.await,
+        };
+        match result {
+            Ok(deployment) => {
+                info!("Built deployment {} of app {}", deployment.id, app.slug);
+                Ok(deployment)
+            }
+            Err(e) => {
+                warn!(
+                    "Deployment {} of app {} failed: {}",
+                    deployment_id, app.slug, e
+                );
+                log.line(&format!("Error: {}", e)).await;
+                let current = find_deployment(pool, deployment_id).await?;
+                // Cancelled while building.
+                if current.status != DeploymentStatus::Building {
+                    return Ok(current);
+                }
+                current.fail(pool, &e.to_string()).await
+            }
+        }
+    }
+
+    async fn run(
+        &self,
+        pool: &SqlitePool,
+        app: &Application,
+        deployment: Deployment,
+        cancel: CancelSignal,
+        log: &dyn BuildLog,
+    ) -> Result<Deployment, AppError> {
+        let repository_id = app.settings.repository_id.ok_or_else(|| {
+            AppError::ValidationError(format!("App {} has no repository", app.slug))
+        })?;
+        let repository = Repository::find(pool, repository_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No repository {}", repository_id)))?;
+
+        log.line(&format!(
+            "Fetching {} of {}",
+            deployment.commit_sha, repository.name
+        ))
+        .await;
+        let request = FetchRequest::new(app.id, &repository.clone_url, &deployment.commit_sha)
+            .branch(app.settings.branch.clone())
+            .credentials(
+                git::repository_credentials(pool, &self.token_service, &repository, app.owner())
+                    .await?,
+            );
+        let checkout = self.sources.checkout(&request).await?;
+
+        let plan = buildpacks::plan(&checkout.path, &app.settings)?;
+        let deployment = deployment.set_build_plan(pool, &plan).await?;
+        log.line(&format!("Detected a {} app", plan.language)).await;
+
+        let context = buildpacks::app_dir(&checkout.path, &app.settings)?;
+        let request = BuildRequest {
+            app_slug: &app.slug,
+            commit_sha: &deployment.commit_sha,
+            context: &context,
+            plan: &plan,
+            cancel,
+        };
+        let image = self.builder.build(&request, log).await?;
+        log.line(&format!("Built {}", image)).await;
+
+        deployment
+            .set_image(pool, &image)
+            .await?
+            .transition(pool, DeploymentStatus::Releasing)
+            .await
+    }
+
+    /// Fires `cancel` once the deployment is no longer building, which
+    /// only a cancellation does while the build runs.
+    async fn watch_for_cancel(
+        &self,
+        pool: &SqlitePool,
+        deployment_id: i64,
+        cancel: watch::Sender<bool>,
+    ) {
+        loop {
+            tokio::time::sleep(self.cancel_poll_interval).await;
+            match Deployment::find(pool, deployment_id).await {
+                Ok(Some(deployment)) if deployment.status == DeploymentStatus::Building => {}
+                Ok(_) => {
+                    info!("Cancelling the build of deployment {}", deployment_id);
+                    cancel.send_replace(true);
+                    return;
+                }
+                Err(e) => warn!(
+                    "Could not check deployment {} for cancellation: {}",
+                    deployment_id, e
+                ),
+            }
+        }
+    }
+
+    fn lock(&self, app_id: i64) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(app_id)
+            .or_default()
+            .clone()
+    }
+}
+
+async fn find_deployment(pool: &SqlitePool, id: i64) -> Result<Deployment, AppError> {
+    Deployment::find(pool, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No deployment {}", id)))
+}
@@ -0,0 +1,182 @@
+use crate::{
+    error::AppError,
+    models::{Member, User},
+    orgs::{Owner, Role},
+};
+use sqlx::SqlitePool;
+use std::{fmt, str::FromStr};
+
+/// Something a user may try to do to a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    OrgRead,
+    OrgDelete,
+    OrgMemberInvite,
+    OrgMemberUpdate,
+    OrgMemberRemove,
+    RepoRead,
+    RepoWrite,
+    RepoDelete,
+    AppRead,
+    AppCreate,
+    AppUpdate,
+    AppDelete,
+    AppDeploy,
+    AppEnvRead,
+    AppEnvWrite,
+    AuditRead,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::OrgRead,
+        Action::OrgDelete,
+        Action::OrgMemberInvite,
+        Action::OrgMemberUpdate,
+        Action::OrgMemberRemove,
+        Action::RepoRead,
+        Action::RepoWrite,
+        Action::RepoDelete,
+        Action::AppRead,
+        Action::AppCreate,
+        Action::AppUpdate,
+        Action::AppDelete,
+        Action::AppDeploy,
+        Action::AppEnvRead,
+        Action::AppEnvWrite,
+        Action::AuditRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::OrgRead => "org.read",
+            Action::OrgDelete => "org.delete",
+            Action::OrgMemberInvite => "org.member.invite",
+            Action::OrgMemberUpdate => "org.member.update",
+            Action::OrgMemberRemove => "org.member.remove",
+            Action::RepoRead => "repo.read",
+            Action::RepoWrite => "repo.write",
+            Action::RepoDelete => "repo.delete",
+            Action::AppRead => "app.read",
+            Action::AppCreate => "app.create",
+            Action::AppUpdate => "app.update",
+            Action::AppDelete => "app.delete",
+            Action::AppDeploy => "app.deploy",
+            Action::AppEnvRead => "app.env.read",
+            Action::AppEnvWrite => "app.env.write",
+            Action::AuditRead => "audit.read",
+        }
+    }
+
+    /// The least privileged role allowed to take this action. Environment
+    /// variables hold secrets, so viewers cannot read them.
+    pub fn min_role(&self) -> Role {
+        match self {
+            Action::OrgRead | Action::RepoRead | Action::AppRead => Role::Viewer,
+            Action::RepoWrite
+            | Action::AppCreate
+            | Action::AppUpdate
+            | Action::AppDeploy
+            | Action::AppEnvRead
+            | Action::AppEnvWrite => Role::Developer,
+            Action::OrgMemberInvite
+            | Action::OrgMemberUpdate
+            | Action::OrgMemberRemove
+            | Action::RepoDelete
+            | Action::AppDelete
+            | Action::AuditRead => Role::Admin,
+            Action::OrgDelete => Role::Owner,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Action {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .iter()
+            .find(|action| action.as_str() == s)
+            .copied()
+            .ok_or_else(|| AppError::ValidationError(format!("Unknown action: {}", s)))
+    }
+}
+
+/// What an action is taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Organization(i64),
+    Repository(Owner),
+    App(Owner),
+}
+
+impl Resource {
+    pub fn owner(&self) -> Owner {
+        match self {
+            Resource::Organization(id) => Owner::Organization(*id),
+            Resource::Repository(owner) | Resource::App(owner) => *owner,
+        }
+    }
+}
+
+/// Whether `role` allows `action`. Roles are ordered, so every role may do
+/// whatever the roles below it may.
+pub fn allows(role: Role, action: Action) -> bool {
+    role >= action.min_role()
+}
+
+/// The role `user_id` holds over resources of `owner`: users own their
+/// personal resources, and hold their membership role over those of an
+/// organization. `None` means no access at all.
+pub async fn role(pool: &SqlitePool, user_id: i64, owner: Owner) -> Result<Option<Role>, AppError> {
+    match owner {
+        Owner::User(id) => Ok((id == user_id).then_some(Role::Owner)),
+        Owner::Organization(id) => Ok(Member::role(pool, id, user_id).await?),
+    }
+}
+
+/// Whether `user` may take `action` on `resource`.
+pub async fn can(
+    pool: &SqlitePool,
+    user: &User,
+    action: Action,
+    resource: Resource,
+) -> Result<bool, AppError> {
+    let role = role(pool, user.id, resource.owner()).await?;
+    Ok(role.is_some_and(|role| allows(role, action)))
+}
+
+/// Guard for handlers that already know the caller's role, e.g. from
+/// `Organization::find_for_member`.
+pub fn require(role: Role, action: Action) -> Result<(), AppError> {
+    if allows(role, action) {
+        return Ok(());
+    }
+    Err(AppError::Forbidden(format!(
+        "The {} role does not allow {}",
+        role, action
+    )))
+}
+
+/// Guard returning `AppError::Forbidden` unless `user` may take `action` on
+/// `resource`. Returns the user's role over it.
+pub async fn authorize(
+    pool: &SqlitePool,
+    user: &User,
+    action: Action,
+    resource: Resource,
+) -> Result<Role, AppError> {
+    let Some(role) = role(pool, user.id, resource.owner()).await? else {
+        return Err(AppError::Forbidden(
+            "You have no access to this resource".to_string(),
+        ));
+    };
+    require(role, action)?;
+    Ok(role)
+}
@@ -0,0 +1,699 @@
+use crate::{
+    config::OAuthUser,
+    error::AppError,
+    models::{ProviderInstance, Visibility},
+    oidc,
+};
+use log::debug;
+use oauth2::Scope;
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+
+/// Stops following `next` links after this many pages of repositories.
+const MAX_REPOSITORY_PAGES: usize = 100;
+
+/// Where a provider's endpoints come from.
+pub enum EndpointSource {
+    /// Fixed OAuth endpoints plus the profile API queried with the access token.
+    Static {
+        auth_url: String,
+        token_url: String,
+        user_api_url: String,
+    },
+    /// OpenID Connect discovery from `<issuer>/.well-known/openid-configuration`.
+    Discovery { issuer_url: String },
+}
+
+/// Endpoints resolved for a single login.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub base_url: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub user_api_url: Option<String>,
+    /// Set for OpenID Connect providers, whose identity comes from the ID token.
+    pub oidc: Option<oidc::ProviderMetadata>,
+}
+
+/// A repository as listed by a provider's API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteRepository {
+    /// The provider's id of the repository.
+    pub id: String,
+    /// Name including the owner, e.g. "octocat/hello-world".
+    pub full_name: String,
+    pub description: Option<String>,
+    /// Web page of the repository.
+    pub url: String,
+    /// HTTPS URL to clone from.
+    pub clone_url: String,
+    /// None for empty repositories.
+    pub default_branch: Option<String>,
+    pub visibility: Visibility,
+}
+
+/// Everything the generic OAuth flow needs to know about a type of Git
+/// provider. Individual deployments are `ProviderInstance`s.
+///
+/// Instances configured through the environment read `<PREFIX>_CLIENT_ID`
+/// and `<PREFIX>_CLIENT_SECRET`.
+pub trait Provider: Sync {
+    /// Human readable name, e.g. "GitHub".
+    fn name(&self) -> &'static str;
+
+    /// Prefix of the provider's environment variables, e.g. "GITHUB".
+    fn env_prefix(&self) -> &'static str;
+
+    /// Base URL of the hosted service, if there is one (e.g. https://gitlab.com).
+    fn default_base_url(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Environment variable overriding the base URL of the environment-configured instance.
+    fn base_url_var(&self) -> String {
+        format!("{}_BASE_URL", self.env_prefix())
+    }
+
+    /// Endpoints of the instance served from `base_url`.
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource;
+
+    fn scopes(&self) -> Vec<Scope>;
+
+    /// Whether PKCE is sent unless `<PREFIX>_PKCE` says otherwise.
+    fn pkce_by_default(&self) -> bool {
+        true
+    }
+
+    /// Whether token refresh requests must repeat the `redirect_uri`.
+    fn refresh_requires_redirect_uri(&self) -> bool {
+        false
+    }
+
+    /// Second profile request for user APIs that only return an identifier.
+    /// Receives the raw body of the user API response.
+    fn profile_url(&self, _base_url: &str, _response: &str) -> Option<String> {
+        None
+    }
+
+    /// Maps the user API profile (or ID token claims) into an `OAuthUser`.
+    fn map_user(&self, profile: &Value) -> Result<OAuthUser, AppError>;
+
+    /// API listing the user's email addresses and whether each is verified,
+    /// for providers whose profile does not say. Emails of providers with
+    /// neither are never taken as verified.
+    fn emails_url(&self, _endpoints: &Endpoints) -> Option<String> {
+        None
+    }
+
+    /// Whether `emails`, as answered by `emails_url`, verifies `email`.
+    fn email_verified(&self, _emails: &Value, _email: &str) -> bool {
+        false
+    }
+
+    /// First page of the repositories the user can access, or None if
+    /// repositories cannot be imported from this provider.
+    fn repositories_url(&self, _endpoints: &Endpoints) -> Option<String> {
+        None
+    }
+
+    /// The repositories of a page of the repository list.
+    fn repository_page(&self, body: Value) -> Vec<Value> {
+        match body {
+            Value::Array(repositories) => repositories,
+            _ => Vec::new(),
+        }
+    }
+
+    /// URL of the page after the one answered with `link` (the `Link`
+    /// header) and `body`.
+    fn next_page_url(&self, link: Option<&str>, _body: &Value) -> Option<String> {
+        link.and_then(next_link)
+    }
+
+    fn map_repository(&self, _repository: &Value) -> Result<RemoteRepository, AppError> {
+        Err(AppError::ValidationError(format!(
+            "Repositories cannot be imported from {}",
+            self.name()
+        )))
+    }
+
+    /// User name sent with an access token when cloning over HTTPS.
+    fn git_username(&self) -> &'static str {
+        "oauth2"
+    }
+
+    fn uses_pkce(&self) -> bool {
+        env::var(format!("{}_PKCE", self.env_prefix()))
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or_else(|_| self.pkce_by_default())
+    }
+}
+
+/// Applies the `<PREFIX>_AUTH_URL`, `<PREFIX>_TOKEN_URL` and `<PREFIX>_API_URL`
+/// overrides of environment-configured instances.
+fn with_env_overrides(prefix: &str, source: EndpointSource) -> EndpointSource {
+    let env_or = |suffix: &str, default: String| {
+        env::var(format!("{}_{}", prefix, suffix)).unwrap_or(default)
+    };
+
+    match source {
+        EndpointSource::Static {
+            auth_url,
+            token_url,
+            user_api_url,
+        } => EndpointSource::Static {
+            auth_url: env_or("AUTH_URL", auth_url),
+            token_url: env_or("TOKEN_URL", token_url),
+            user_api_url: env_or("API_URL", user_api_url),
+        },
+        discovery => discovery,
+    }
+}
+
+pub async fn resolve_endpoints(instance: &ProviderInstance) -> Result<Endpoints, AppError> {
+    let spec = instance.spec()?;
+    let base_url = instance.effective_base_url()?;
+
+    let mut source = spec.endpoint_source(&base_url);
+    if instance.base_url.is_none() {
+        source = with_env_overrides(spec.env_prefix(), source);
+    }
+
+    match source {
+        EndpointSource::Static {
+            auth_url,
+            token_url,
+            user_api_url,
+        } => Ok(Endpoints {
+            base_url,
+            auth_url,
+            token_url,
+            user_api_url: Some(user_api_url),
+            oidc: None,
+        }),
+        EndpointSource::Discovery { issuer_url } => {
+            let metadata = oidc::discover(&issuer_url).await?;
+            Ok(Endpoints {
+                base_url,
+                auth_url: metadata.authorization_endpoint.clone(),
+                token_url: metadata.token_endpoint.clone(),
+                user_api_url: metadata.userinfo_endpoint.clone(),
+                oidc: Some(metadata),
+            })
+        }
+    }
+}
+
+/// Finds the `rel="next"` URL of an RFC 8288 `Link` header.
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|entry| {
+        let (url, params) = entry.split_once(';')?;
+        let is_next = params.split(';').any(|param| {
+            param
+                .trim()
+                .strip_prefix("rel=")
+                .is_some_and(|rel| rel.trim_matches('"') == "next")
+        });
+        is_next.then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// The API root next to the user API, e.g. https://api.github.com for
+/// https://api.github.com/user.
+fn api_root(endpoints: &Endpoints) -> Option<&str> {
+    endpoints.user_api_url.as_deref()?.strip_suffix("/user")
+}
+
+/// The origin of `url`, e.g. `https://api.github.com`.
+fn origin(url: &str) -> Result<url::Origin, AppError> {
+    url::Url::parse(url)
+        .map(|url| url.origin())
+        .map_err(|e| AppError::ExternalServiceError(format!("Invalid URL {}: {}", url, e)))
+}
+
+/// Lists every repository the access token can see, following the
+/// provider's pagination. The access token is only sent to the API the
+/// list started on, wherever the provider's next page links point.
+pub async fn fetch_repositories(
+    spec: &dyn Provider,
+    endpoints: &Endpoints,
+    access_token: &str,
+) -> Result<Vec<RemoteRepository>, AppError> {
+    let mut url = spec.repositories_url(endpoints).ok_or_else(|| {
+        AppError::ValidationError(format!(
+            "Repositories cannot be imported from {}",
+            spec.name()
+        ))
+    })?;
+    let external_error = |e: reqwest::Error| {
+        debug!("{} repository list error: {:?}", spec.name(), e);
+        AppError::ExternalServiceError(format!(
+            "Failed to list {} repositories: {}",
+            spec.name(),
+            e
+        ))
+    };
+
+    let api_origin = origin(&url)?;
+
+    let client = reqwest::Client::new();
+    let mut repositories = Vec::new();
+    for _ in 0..MAX_REPOSITORY_PAGES {
+        debug!("Listing {} repositories: {}", spec.name(), url);
+        let response = client
+            .get(&url)
+            .bearer_auth(access_token)
+            .header(reqwest::header::USER_AGENT, "rust-app")
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(external_error)?;
+        let link = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body: Value = response.json().await.map_err(external_error)?;
+
+        let next = spec.next_page_url(link.as_deref(), &body);
+        for repository in spec.repository_page(body) {
+            repositories.push(spec.map_repository(&repository)?);
+        }
+        match next {
+            Some(next) if origin(&next)? != api_origin => {
+                return Err(AppError::ExternalServiceError(format!(
+                    "{} linked to a page of repositories outside its API: {}",
+                    spec.name(),
+                    next
+                )))
+            }
+            Some(next) => url = next,
+            None => return Ok(repositories),
+        }
+    }
+
+    Err(AppError::ExternalServiceError(format!(
+        "{} listed more than {} pages of repositories",
+        spec.name(),
+        MAX_REPOSITORY_PAGES
+    )))
+}
+
+fn repository_id(value: &Value) -> Result<String, AppError> {
+    match value {
+        Value::Null => Err(AppError::ExternalServiceError(
+            "Repository is missing its id".to_string(),
+        )),
+        Value::String(id) => Ok(id.clone()),
+        id => Ok(id.to_string()),
+    }
+}
+
+fn required_str(value: &Value, field: &str) -> Result<String, AppError> {
+    value[field].as_str().map(str::to_string).ok_or_else(|| {
+        AppError::ExternalServiceError(format!("Repository is missing its {}", field))
+    })
+}
+
+fn optional_str(value: &Value) -> Option<String> {
+    value.as_str().map(|s| s.to_string())
+}
+
+fn required_id(value: &Value) -> Result<String, AppError> {
+    if value.is_null() {
+        return Err(AppError::AuthError(
+            "Provider profile is missing a user id".to_string(),
+        ));
+    }
+    Ok(value.to_string())
+}
+
+pub struct GitHub;
+
+impl Provider for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        "GITHUB"
+    }
+
+    fn default_base_url(&self) -> Option<&'static str> {
+        Some("https://github.com")
+    }
+
+    fn git_username(&self) -> &'static str {
+        "x-access-token"
+    }
+
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource {
+        let base_url = base_url.trim_end_matches('/');
+        // GitHub Enterprise Server serves its REST API under /api/v3.
+        let user_api_url = if base_url == "https://github.com" {
+            "https://api.github.com/user".to_string()
+        } else {
+            format!("{}/api/v3/user", base_url)
+        };
+
+        EndpointSource::Static {
+            auth_url: format!("{}/login/oauth/authorize", base_url),
+            token_url: format!("{}/login/oauth/access_token", base_url),
+            user_api_url,
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("read:user".to_string()),
+            Scope::new("user:email".to_string()),
+            Scope::new("repo".to_string()),
+        ]
+    }
+
+    fn map_user(&self, profile: &Value) -> Result<OAuthUser, AppError> {
+        Ok(OAuthUser {
+            id: required_id(&profile["id"])?,
+            username: profile["login"].as_str().unwrap_or("").to_string(),
+            email: optional_str(&profile["email"]),
+            // Told by `emails_url`.
+            email_verified: false,
+            avatar_url: optional_str(&profile["avatar_url"]),
+        })
+    }
+
+    fn emails_url(&self, endpoints: &Endpoints) -> Option<String> {
+        Some(format!("{}/user/emails", api_root(endpoints)?))
+    }
+
+    fn email_verified(&self, emails: &Value, email: &str) -> bool {
+        emails.as_array().into_iter().flatten().any(|listed| {
+            listed["verified"] == true
+                && listed["email"]
+                    .as_str()
+                    .is_some_and(|listed| listed.eq_ignore_ascii_case(email))
+        })
+    }
+
+    fn repositories_url(&self, endpoints: &Endpoints) -> Option<String> {
+        Some(format!(
+            "{}/user/repos?per_page=100&affiliation=owner,collaborator,organization_member",
+            api_root(endpoints)?
+        ))
+    }
+
+    fn map_repository(&self, repository: &Value) -> Result<RemoteRepository, AppError> {
+        Ok(RemoteRepository {
+            id: repository_id(&repository["id"])?,
+            full_name: required_str(repository, "full_name")?,
+            description: optional_str(&repository["description"]),
+            url: required_str(repository, "html_url")?,
+            clone_url: required_str(repository, "clone_url")?,
+            default_branch: optional_str(&repository["default_branch"]),
+            visibility: match repository["private"].as_bool() {
+                Some(false) => Visibility::Public,
+                _ => Visibility::Private,
+            },
+        })
+    }
+}
+
+pub struct GitLab;
+
+impl Provider for GitLab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        "GITLAB"
+    }
+
+    fn default_base_url(&self) -> Option<&'static str> {
+        Some("https://gitlab.com")
+    }
+
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource {
+        let base_url = base_url.trim_end_matches('/');
+        EndpointSource::Static {
+            auth_url: format!("{}/oauth/authorize", base_url),
+            token_url: format!("{}/oauth/token", base_url),
+            user_api_url: format!("{}/api/v4/user", base_url),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("read_user".to_string()),
+            // Listing projects goes through the API.
+            Scope::new("read_api".to_string()),
+            Scope::new("read_repository".to_string()),
+        ]
+    }
+
+    fn refresh_requires_redirect_uri(&self) -> bool {
+        true
+    }
+
+    fn map_user(&self, profile: &Value) -> Result<OAuthUser, AppError> {
+        Ok(OAuthUser {
+            id: required_id(&profile["id"])?,
+            username: profile["username"].as_str().unwrap_or("").to_string(),
+            email: optional_str(&profile["email"]),
+            email_verified: false,
+            avatar_url: optional_str(&profile["avatar_url"]),
+        })
+    }
+
+    fn repositories_url(&self, endpoints: &Endpoints) -> Option<String> {
+        Some(format!(
+            "{}/projects?membership=true&per_page=100&order_by=id&sort=asc",
+            api_root(endpoints)?
+        ))
+    }
+
+    fn map_repository(&self, repository: &Value) -> Result<RemoteRepository, AppError> {
+        Ok(RemoteRepository {
+            id: repository_id(&repository["id"])?,
+            full_name: required_str(repository, "path_with_namespace")?,
+            description: optional_str(&repository["description"]),
+            url: required_str(repository, "web_url")?,
+            clone_url: required_str(repository, "http_url_to_repo")?,
+            default_branch: optional_str(&repository["default_branch"]),
+            // Internal projects are visible to every user of the instance.
+            visibility: match repository["visibility"].as_str() {
+                Some("public") => Visibility::Public,
+                Some("internal") => Visibility::Internal,
+                _ => Visibility::Private,
+            },
+        })
+    }
+}
+
+/// Bitbucket Cloud. Self-hosted installations use `BitbucketServer`.
+pub struct Bitbucket;
+
+impl Provider for Bitbucket {
+    fn name(&self) -> &'static str {
+        "Bitbucket"
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        "BITBUCKET"
+    }
+
+    fn default_base_url(&self) -> Option<&'static str> {
+        Some("https://bitbucket.org")
+    }
+
+    fn git_username(&self) -> &'static str {
+        "x-token-auth"
+    }
+
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource {
+        let base_url = base_url.trim_end_matches('/');
+        EndpointSource::Static {
+            auth_url: format!("{}/site/oauth2/authorize", base_url),
+            token_url: format!("{}/site/oauth2/access_token", base_url),
+            user_api_url: "https://api.bitbucket.org/2.0/user".to_string(),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("account".to_string()),
+            Scope::new("repository".to_string()),
+        ]
+    }
+
+    // Bitbucket Cloud does not support PKCE.
+    fn pkce_by_default(&self) -> bool {
+        false
+    }
+
+    fn map_user(&self, profile: &Value) -> Result<OAuthUser, AppError> {
+        Ok(OAuthUser {
+            // Kept as the JSON-encoded uuid (quotes included) to match existing rows.
+            id: required_id(&profile["uuid"])?,
+            username: profile["username"].as_str().unwrap_or("").to_string(),
+            email: optional_str(&profile["email"]),
+            email_verified: false,
+            avatar_url: optional_str(&profile["links"]["avatar"]["href"]),
+        })
+    }
+
+    fn repositories_url(&self, endpoints: &Endpoints) -> Option<String> {
+        Some(format!(
+            "{}/repositories?role=member&pagelen=100",
+            api_root(endpoints)?
+        ))
+    }
+
+    fn repository_page(&self, mut body: Value) -> Vec<Value> {
+        match body["values"].take() {
+            Value::Array(repositories) => repositories,
+            _ => Vec::new(),
+        }
+    }
+
+    // Bitbucket pages through a `next` URL in the body.
+    fn next_page_url(&self, _link: Option<&str>, body: &Value) -> Option<String> {
+        optional_str(&body["next"])
+    }
+
+    fn map_repository(&self, repository: &Value) -> Result<RemoteRepository, AppError> {
+        Ok(RemoteRepository {
+            id: repository_id(&repository["uuid"])?,
+            full_name: required_str(repository, "full_name")?,
+            description: optional_str(&repository["description"]).filter(|d| !d.is_empty()),
+            url: optional_str(&repository["links"]["html"]["href"]).ok_or_else(|| {
+                AppError::ExternalServiceError("Repository is missing its html link".to_string())
+            })?,
+            clone_url: repository["links"]["clone"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|link| link["name"] == "https")
+                .and_then(|link| optional_str(&link["href"]))
+                .ok_or_else(|| {
+                    AppError::ExternalServiceError(
+                        "Repository is missing its https clone link".to_string(),
+                    )
+                })?,
+            default_branch: optional_str(&repository["mainbranch"]["name"]),
+            visibility: match repository["is_private"].as_bool() {
+                Some(false) => Visibility::Public,
+                _ => Visibility::Private,
+            },
+        })
+    }
+}
+
+/// Bitbucket Server / Data Center (8.0+, OAuth 2.0 incoming application links).
+pub struct BitbucketServer;
+
+impl Provider for BitbucketServer {
+    fn name(&self) -> &'static str {
+        "Bitbucket Data Center"
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        "BITBUCKET_SERVER"
+    }
+
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource {
+        let base_url = base_url.trim_end_matches('/');
+        EndpointSource::Static {
+            auth_url: format!("{}/rest/oauth2/latest/authorize", base_url),
+            token_url: format!("{}/rest/oauth2/latest/token", base_url),
+            user_api_url: format!("{}/plugins/servlet/applinks/whoami", base_url),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![Scope::new("REPO_READ".to_string())]
+    }
+
+    // `whoami` answers with the plain user name, the profile lives under the REST API.
+    fn profile_url(&self, base_url: &str, response: &str) -> Option<String> {
+        let username = response.trim();
+        if username.is_empty() {
+            return None;
+        }
+
+        let username: String = url::form_urlencoded::byte_serialize(username.as_bytes()).collect();
+        Some(format!(
+            "{}/rest/api/latest/users/{}?avatarSize=64",
+            base_url.trim_end_matches('/'),
+            username
+        ))
+    }
+
+    fn map_user(&self, profile: &Value) -> Result<OAuthUser, AppError> {
+        Ok(OAuthUser {
+            id: required_id(&profile["id"])?,
+            username: profile["name"].as_str().unwrap_or("").to_string(),
+            email: optional_str(&profile["emailAddress"]),
+            email_verified: false,
+            avatar_url: optional_str(&profile["avatarUrl"]),
+        })
+    }
+}
+
+/// A generic OpenID Connect identity provider (Keycloak, Authentik, Dex,
+/// Azure AD, ...). The instance's base URL is the issuer.
+pub struct Oidc;
+
+impl Provider for Oidc {
+    fn name(&self) -> &'static str {
+        "OpenID Connect"
+    }
+
+    fn env_prefix(&self) -> &'static str {
+        "OIDC"
+    }
+
+    fn base_url_var(&self) -> String {
+        "OIDC_ISSUER_URL".to_string()
+    }
+
+    fn endpoint_source(&self, base_url: &str) -> EndpointSource {
+        EndpointSource::Discovery {
+            issuer_url: base_url.to_string(),
+        }
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("openid".to_string()),
+            Scope::new("profile".to_string()),
+            Scope::new("email".to_string()),
+        ]
+    }
+
+    fn map_user(&self, claims: &Value) -> Result<OAuthUser, AppError> {
+        let subject = claims["sub"]
+            .as_str()
+            .ok_or_else(|| AppError::AuthError("ID token is missing a subject".to_string()))?;
+        let username = claims["preferred_username"]
+            .as_str()
+            .or_else(|| claims["email"].as_str())
+            .unwrap_or(subject);
+
+        Ok(OAuthUser {
+            id: subject.to_string(),
+            username: username.to_string(),
+            email: optional_str(&claims["email"]),
+            // Some providers send the claim as a string.
+            email_verified: claims["email_verified"] == true || claims["email_verified"] == "true",
+            avatar_url: optional_str(&claims["picture"]),
+        })
+    }
+}
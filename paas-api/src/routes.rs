@@ -1,25 +1,124 @@
-use crate::handlers;
-use actix_web::web;
+use crate::{auth, handlers};
+use actix_web::{middleware::from_fn, web};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api")
-            .route("/auth/github", web::get().to(handlers::github_auth))
-            .route(
-                "/auth/github/callback",
-                web::get().to(handlers::github_callback),
-            )
-            .route("/auth/gitlab", web::get().to(handlers::gitlab_auth))
+            .route("/auth/logout", web::post().to(handlers::logout))
             .route(
-                "/auth/gitlab/callback",
-                web::get().to(handlers::gitlab_callback),
+                "/auth/providers",
+                web::get().to(handlers::list_login_providers),
             )
-            .route("/auth/bitbucket", web::get().to(handlers::bitbucket_auth))
+            .route("/auth/token", web::post().to(handlers::issue_token))
+            .route("/auth/{provider}", web::get().to(handlers::oauth_authorize))
             .route(
-                "/auth/bitbucket/callback",
-                web::get().to(handlers::bitbucket_callback),
+                "/auth/{provider}/callback",
+                web::get().to(handlers::oauth_callback),
             )
-            .route("/auth/logout", web::post().to(handlers::logout))
-            .route("/user/me", web::get().to(handlers::get_current_user)),
+            // Everything outside /api/auth requires a signed-in user.
+            .service(
+                web::scope("")
+                    .wrap(from_fn(auth::require_user))
+                    .route("/user/me", web::get().to(handlers::get_current_user))
+                    .route(
+                        "/user/providers",
+                        web::get().to(handlers::list_connected_providers),
+                    )
+                    .route(
+                        "/user/providers/{provider}",
+                        web::delete().to(handlers::disconnect_provider),
+                    )
+                    .route(
+                        "/user/providers/{provider}/connect",
+                        web::get().to(handlers::connect_provider),
+                    )
+                    .route("/user/tokens", web::get().to(handlers::list_api_tokens))
+                    .route("/user/tokens", web::post().to(handlers::create_api_token))
+                    .route(
+                        "/user/tokens/{id}",
+                        web::delete().to(handlers::revoke_api_token),
+                    )
+                    .route("/repositories", web::get().to(handlers::list_repositories))
+                    .route(
+                        "/repositories/sync",
+                        web::post().to(handlers::sync_repositories),
+                    )
+                    .route("/apps", web::get().to(handlers::list_apps))
+                    .route("/apps", web::post().to(handlers::create_app))
+                    .route("/apps/{slug}", web::get().to(handlers::get_app))
+                    .route("/apps/{slug}", web::put().to(handlers::update_app))
+                    .route("/apps/{slug}", web::delete().to(handlers::delete_app))
+                    .route(
+                        "/apps/{slug}/deployments",
+                        web::get().to(handlers::list_deployments),
+                    )
+                    .route(
+                        "/apps/{slug}/deployments",
+                        web::post().to(handlers::create_deployment),
+                    )
+                    .route(
+                        "/apps/{slug}/deployments/{id}",
+                        web::get().to(handlers::get_deployment),
+                    )
+                    .route(
+                        "/apps/{slug}/deployments/{id}/logs",
+                        web::get().to(handlers::get_deployment_logs),
+                    )
+                    .route(
+                        "/apps/{slug}/deployments/{id}/cancel",
+                        web::post().to(handlers::cancel_deployment),
+                    )
+                    .route("/admin/jobs", web::get().to(handlers::list_jobs))
+                    .route("/admin/jobs/{id}", web::get().to(handlers::get_job))
+                    .route(
+                        "/admin/jobs/{id}/retry",
+                        web::post().to(handlers::retry_job),
+                    )
+                    .route("/audit", web::get().to(handlers::list_audit_events))
+                    .route(
+                        "/audit/export",
+                        web::get().to(handlers::export_audit_events),
+                    )
+                    .route("/orgs", web::get().to(handlers::list_organizations))
+                    .route("/orgs", web::post().to(handlers::create_organization))
+                    .route(
+                        "/orgs/invitations",
+                        web::get().to(handlers::list_my_invitations),
+                    )
+                    .route(
+                        "/orgs/invitations/{id}",
+                        web::delete().to(handlers::decline_invitation),
+                    )
+                    .route(
+                        "/orgs/invitations/{id}/accept",
+                        web::post().to(handlers::accept_invitation),
+                    )
+                    .route("/orgs/{org}", web::get().to(handlers::get_organization))
+                    .route(
+                        "/orgs/{org}",
+                        web::delete().to(handlers::delete_organization),
+                    )
+                    .route("/orgs/{org}/members", web::get().to(handlers::list_members))
+                    .route(
+                        "/orgs/{org}/members/{user_id}",
+                        web::put().to(handlers::update_member),
+                    )
+                    .route(
+                        "/orgs/{org}/members/{user_id}",
+                        web::delete().to(handlers::remove_member),
+                    )
+                    .route(
+                        "/orgs/{org}/invitations",
+                        web::get().to(handlers::list_invitations),
+                    )
+                    .route(
+                        "/orgs/{org}/invitations",
+                        web::post().to(handlers::create_invitation),
+                    )
+                    .route(
+                        "/orgs/{org}/invitations/{id}",
+                        web::delete().to(handlers::delete_invitation),
+                    ),
+            ),
     );
 }
@@ -0,0 +1,579 @@
+use crate::config::RuntimeConfig;
+use async_trait::async_trait;
+use derive_more::Display;
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, io,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    ops::RangeInclusive,
+    path::PathBuf,
+    process::Stdio,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    net::TcpStream,
+    process::{Child, Command},
+    sync::watch,
+    task::JoinHandle,
+    time::Instant,
+};
+
+pub const DEFAULT_PORTS: RangeInclusive<u16> = 20000..=29999;
+pub const DEFAULT_START_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_STOP_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_PROBE_INTERVAL_SECS: u64 = 10;
+/// Consecutive failed health probes after which an app is restarted.
+const DEFAULT_PROBE_FAILURES: u32 = 3;
+
+/// Crashed apps are restarted after 1s, 2s, 4s... up to a minute. An app
+/// that ran for longer than that before crashing starts over at 1s.
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How often a starting app is probed until it first passes.
+const STARTUP_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Variables of the server's environment apps inherit. Anything else, like
+/// the server's keys, stays out of reach of the app.
+const INHERITED_ENV: &[&str] = &["PATH", "HOME", "LANG", "TZ"];
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum RuntimeError {
+    #[display(fmt = "Instance {} is already running", _0)]
+    AlreadyRunning(String),
+
+    #[display(fmt = "No instance {}", _0)]
+    NotFound(String),
+
+    #[display(fmt = "No free port left to run {} on", _0)]
+    NoFreePort(String),
+
+    #[display(fmt = "Instance {} failed to start: {}", name, reason)]
+    StartFailed { name: String, reason: String },
+
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(String),
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        RuntimeError::Io(err.to_string())
+    }
+}
+
+/// An app to run, e.g.
+///
+/// ```ignore
+/// let spec = RunSpec::new(format!("{}-{}", app.slug, deployment.id), start_command, dir)
+///     .env("RAILS_ENV", "production")
+///     .health_path("/health");
+/// let instance = runtime.start(&spec).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RunSpec {
+    /// Names the instance in the runtime, e.g. `shop-42`.
+    pub name: String,
+    /// Run by `sh -c`, so it may use `$PORT`.
+    pub command: String,
+    pub working_dir: PathBuf,
+    /// Set on top of `INHERITED_ENV`. `PORT` is always the assigned port.
+    pub env: BTreeMap<String, String>,
+    /// Path the health probe requests over HTTP, expecting a 2xx response.
+    /// Accepting connections on the port is enough when None.
+    pub health_path: Option<String>,
+}
+
+impl RunSpec {
+    pub fn new(name: impl Into<String>, command: &str, working_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            command: command.to_string(),
+            working_dir: working_dir.into(),
+            env: BTreeMap::new(),
+            health_path: None,
+        }
+    }
+
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn health_path(mut self, path: &str) -> Self {
+        self.health_path = Some(path.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstanceState {
+    /// Started, and not yet passing its health probe.
+    Starting,
+    /// Passing its health probes.
+    Running,
+    /// Crashed or unhealthy, and waiting to be started again.
+    Restarting,
+    /// Could not be started again; see `last_error`.
+    Crashed,
+    Stopped,
+}
+
+/// An app as the runtime runs it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Instance {
+    pub name: String,
+    pub port: u16,
+    pub state: InstanceState,
+    /// The process running the app's command, while there is one.
+    pub pid: Option<u32>,
+    /// Times the app was restarted after crashing or failing its probes.
+    pub restarts: u32,
+    /// Why the app was last restarted.
+    pub last_error: Option<String>,
+}
+
+/// Runs apps and keeps them running.
+#[async_trait]
+pub trait Runtime: Send + Sync {
+    /// Starts an instance of `spec`, returning once it passes its health
+    /// probe. An instance that exits or stays unhealthy is stopped, and its
+    /// reason returned.
+    async fn start(&self, spec: &RunSpec) -> Result<Instance, RuntimeError>;
+
+    /// Stops the instance `name`.
+    async fn stop(&self, name: &str) -> Result<(), RuntimeError>;
+
+    /// The instance `name`, unless it was stopped.
+    async fn status(&self, name: &str) -> Option<Instance>;
+}
+
+/// How `ProcessRuntime` supervises its apps.
+#[derive(Debug, Clone)]
+struct Supervision {
+    start_timeout: Duration,
+    stop_timeout: Duration,
+    probe_interval: Duration,
+    probe_failures: u32,
+    backoff_base: Duration,
+    max_backoff: Duration,
+}
+
+impl Supervision {
+    /// How long to wait before the `crashes`th restart in a row.
+    fn backoff(&self, crashes: u32) -> Duration {
+        let doublings = crashes.max(1).saturating_sub(1).min(16);
+        self.backoff_base
+            .saturating_mul(2_u32.pow(doublings))
+            .min(self.max_backoff)
+    }
+}
+
+struct Supervised {
+    state: watch::Receiver<Instance>,
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+/// Runs apps as child processes of the server, each in its own process
+/// group with a port of its own in `PORT`. Crashed apps are restarted with
+/// backoff, and so are apps failing their health probes.
+pub struct ProcessRuntime {
+    ports: RangeInclusive<u16>,
+    supervision: Supervision,
+    client: reqwest::Client,
+    instances: Mutex<HashMap<String, Supervised>>,
+}
+
+impl Default for ProcessRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessRuntime {
+    pub fn new() -> Self {
+        Self {
+            ports: DEFAULT_PORTS,
+            supervision: Supervision {
+                start_timeout: Duration::from_secs(DEFAULT_START_TIMEOUT_SECS),
+                stop_timeout: Duration::from_secs(DEFAULT_STOP_TIMEOUT_SECS),
+                probe_interval: Duration::from_secs(DEFAULT_PROBE_INTERVAL_SECS),
+                probe_failures: DEFAULT_PROBE_FAILURES,
+                backoff_base: BACKOFF_BASE,
+                max_backoff: MAX_BACKOFF,
+            },
+            client: reqwest::Client::builder()
+                .timeout(PROBE_TIMEOUT)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap_or_default(),
+            instances: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(config: &RuntimeConfig) -> Self {
+        let secs = |duration: actix_web::cookie::time::Duration, default: u64| {
+            Duration::try_from(duration).unwrap_or(Duration::from_secs(default))
+        };
+        Self::new()
+            .ports(config.ports.clone())
+            .start_timeout(secs(config.start_timeout, DEFAULT_START_TIMEOUT_SECS))
+            .stop_timeout(secs(config.stop_timeout, DEFAULT_STOP_TIMEOUT_SECS))
+            .probe_interval(secs(config.probe_interval, DEFAULT_PROBE_INTERVAL_SECS))
+    }
+
+    /// Ports assigned to apps, on 127.0.0.1.
+    pub fn ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// How long a starting app may take to pass its first health probe.
+    pub fn start_timeout(mut self, timeout: Duration) -> Self {
+        self.supervision.start_timeout = timeout;
+        self
+    }
+
+    /// How long an app may take to exit after SIGTERM before it is killed.
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.supervision.stop_timeout = timeout;
+        self
+    }
+
+    /// How often running apps are probed.
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.supervision.probe_interval = interval;
+        self
+    }
+
+    /// Consecutive failed probes after which a running app is restarted.
+    pub fn probe_failures(mut self, failures: u32) -> Self {
+        self.supervision.probe_failures = failures.max(1);
+        self
+    }
+
+    /// Replaces the restart delays of 1s doubling up to a minute.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.supervision.backoff_base = base;
+        self.supervision.max_backoff = max;
+        self
+    }
+
+    /// A port in `ports` no instance uses and nothing else listens on.
+    fn free_port(&self, instances: &HashMap<String, Supervised>) -> Option<u16> {
+        let used: Vec<u16> = instances
+            .values()
+            .map(|instance| instance.state.borrow().port)
+            .collect();
+        self.ports.clone().find(|port| {
+            !used.contains(port) && TcpListener::bind((Ipv4Addr::LOCALHOST, *port)).is_ok()
+        })
+    }
+}
+
+impl Drop for ProcessRuntime {
+    /// Kills the process groups of the apps still running, then their
+    /// supervisors. Dropping a supervisor only kills the process it spawned,
+    /// not the processes that one started.
+    fn drop(&mut self) {
+        if let Ok(instances) = self.instances.get_mut() {
+            for instance in instances.values() {
+                signal_group(instance.state.borrow().pid, libc::SIGKILL);
+                instance.task.abort();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Runtime for ProcessRuntime {
+    async fn start(&self, spec: &RunSpec) -> Result<Instance, RuntimeError> {
+        let mut state = {
+            let mut instances = self.instances.lock().unwrap();
+            if let Some(existing) = instances.get(&spec.name) {
+                if !existing.task.is_finished() {
+                    return Err(RuntimeError::AlreadyRunning(spec.name.clone()));
+                }
+            }
+            let port = self
+                .free_port(&instances)
+                .ok_or_else(|| RuntimeError::NoFreePort(spec.name.clone()))?;
+
+            let (state, receiver) = watch::channel(Instance {
+                name: spec.name.clone(),
+                port,
+                state: InstanceState::Starting,
+                pid: None,
+                restarts: 0,
+                last_error: None,
+            });
+            let (stop, stopped) = watch::channel(false);
+            let supervisor = Supervisor {
+                spec: spec.clone(),
+                port,
+                supervision: self.supervision.clone(),
+                client: self.client.clone(),
+                state,
+            };
+            let task = tokio::spawn(supervisor.run(stopped));
+            instances.insert(
+                spec.name.clone(),
+                Supervised {
+                    state: receiver.clone(),
+                    stop,
+                    task,
+                },
+            );
+            receiver
+        };
+
+        let instance = state
+            .wait_for(|instance| instance.state != InstanceState::Starting)
+            .await
+            .map(|instance| instance.clone())
+            .ok();
+        match instance {
+            Some(instance) if instance.state == InstanceState::Running => {
+                info!("Started {} on port {}", instance.name, instance.port);
+                Ok(instance)
+            }
+            instance => {
+                self.stop(&spec.name).await?;
+                Err(RuntimeError::StartFailed {
+                    name: spec.name.clone(),
+                    reason: instance
+                        .and_then(|instance| instance.last_error)
+                        .unwrap_or_else(|| "stopped".to_string()),
+                })
+            }
+        }
+    }
+
+    async fn stop(&self, name: &str) -> Result<(), RuntimeError> {
+        let instance = self
+            .instances
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| RuntimeError::NotFound(name.to_string()))?;
+        instance.stop.send_replace(true);
+        if let Err(e) = instance.task.await {
+            warn!("Supervisor of {} panicked: {}", name, e);
+        }
+        info!("Stopped {}", name);
+        Ok(())
+    }
+
+    async fn status(&self, name: &str) -> Option<Instance> {
+        let instances = self.instances.lock().unwrap();
+        let instance = instances.get(name)?.state.borrow().clone();
+        Some(instance)
+    }
+}
+
+/// Why an app's process ended.
+enum Outcome {
+    Exited(String),
+    Unhealthy(String),
+    Stop,
+}
+
+/// Runs one app, restarting it until told to stop.
+struct Supervisor {
+    spec: RunSpec,
+    port: u16,
+    supervision: Supervision,
+    client: reqwest::Client,
+    state: watch::Sender<Instance>,
+}
+
+impl Supervisor {
+    async fn run(self, mut stop: watch::Receiver<bool>) {
+        let mut crashes = 0;
+        loop {
+            let mut child = match self.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    warn!("Could not start {}: {}", self.spec.name, e);
+                    self.state.send_modify(|instance| {
+                        instance.state = InstanceState::Crashed;
+                        instance.last_error = Some(e.to_string());
+                    });
+                    return;
+                }
+            };
+            let pid = child.id();
+            let started = Instant::now();
+            self.state.send_modify(|instance| instance.pid = pid);
+
+            let outcome = tokio::select! {
+                status = child.wait() => Outcome::Exited(match status {
+                    Ok(status) => format!("exited with {}", status),
+                    Err(e) => e.to_string(),
+                }),
+                reason = self.watch_health() => Outcome::Unhealthy(reason),
+                _ = stop.changed() => Outcome::Stop,
+            };
+            let reason = match outcome {
+                Outcome::Stop => {
+                    self.terminate(&mut child, pid).await;
+                    self.state.send_modify(|instance| {
+                        instance.state = InstanceState::Stopped;
+                        instance.pid = None;
+                    });
+                    return;
+                }
+                Outcome::Unhealthy(reason) => {
+                    self.terminate(&mut child, pid).await;
+                    reason
+                }
+                Outcome::Exited(reason) => {
+                    // Whatever the app left behind in its group goes too.
+                    signal_group(pid, libc::SIGKILL);
+                    reason
+                }
+            };
+
+            crashes = if started.elapsed() > self.supervision.max_backoff {
+                1
+            } else {
+                crashes + 1
+            };
+            let delay = self.supervision.backoff(crashes);
+            warn!("{} {}; restarting in {:?}", self.spec.name, reason, delay);
+            self.state.send_modify(|instance| {
+                instance.state = InstanceState::Restarting;
+                instance.pid = None;
+                instance.restarts += 1;
+                instance.last_error = Some(reason);
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = stop.changed() => {
+                    self.state.send_modify(|instance| instance.state = InstanceState::Stopped);
+                    return;
+                }
+            }
+            self.state
+                .send_modify(|instance| instance.state = InstanceState::Starting);
+        }
+    }
+
+    fn spawn(&self) -> io::Result<Child> {
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg(&self.spec.command)
+            .current_dir(&self.spec.working_dir)
+            .env_clear()
+            .envs(
+                INHERITED_ENV
+                    .iter()
+                    .filter_map(|name| Some((*name, env::var_os(name)?))),
+            )
+            .envs(&self.spec.env)
+            .env("PORT", self.port.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Signals sent to the group reach the processes the command
+            // starts, not only the shell.
+            .process_group(0)
+            .kill_on_drop(true);
+        let mut child = command.spawn()?;
+        tokio::spawn(forward(self.spec.name.clone(), child.stdout.take()));
+        tokio::spawn(forward(self.spec.name.clone(), child.stderr.take()));
+        Ok(child)
+    }
+
+    /// Waits for the app to pass its first probe, then probes it until it
+    /// fails `probe_failures` probes in a row. Returns why it is unhealthy.
+    async fn watch_health(&self) -> String {
+        let deadline = Instant::now() + self.supervision.start_timeout;
+        while !self.probe().await {
+            if Instant::now() >= deadline {
+                return format!(
+                    "did not pass its health probe within {:?}",
+                    self.supervision.start_timeout
+                );
+            }
+            tokio::time::sleep(STARTUP_PROBE_INTERVAL).await;
+        }
+        self.state
+            .send_modify(|instance| instance.state = InstanceState::Running);
+
+        let mut failures = 0;
+        loop {
+            tokio::time::sleep(self.supervision.probe_interval).await;
+            if self.probe().await {
+                failures = 0;
+                continue;
+            }
+            failures += 1;
+            if failures >= self.supervision.probe_failures {
+                return format!("failed {} health probes in a row", failures);
+            }
+        }
+    }
+
+    async fn probe(&self) -> bool {
+        let Some(path) = &self.spec.health_path else {
+            let address = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+            return matches!(
+                tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(address)).await,
+                Ok(Ok(_))
+            );
+        };
+        let url = format!("http://127.0.0.1:{}{}", self.port, path);
+        match self.client.get(url).send().await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+
+    /// Sends SIGTERM to the app's processes, then SIGKILL to those still
+    /// running after `stop_timeout`.
+    async fn terminate(&self, child: &mut Child, pid: Option<u32>) {
+        signal_group(pid, libc::SIGTERM);
+        let stopped = tokio::time::timeout(self.supervision.stop_timeout, child.wait()).await;
+        if stopped.is_err() {
+            warn!(
+                "{} did not stop within {:?}; killing it",
+                self.spec.name, self.supervision.stop_timeout
+            );
+        }
+        signal_group(pid, libc::SIGKILL);
+        if let Err(e) = child.wait().await {
+            warn!("Could not wait for {}: {}", self.spec.name, e);
+        }
+    }
+}
+
+/// Sends `signal` to the process group led by `pid`.
+fn signal_group(pid: Option<u32>, signal: libc::c_int) {
+    let Some(pid) = pid.and_then(|pid| libc::pid_t::try_from(pid).ok()) else {
+        return;
+    };
+    // SAFETY: kill has no memory safety requirements. The group was
+    // created for the app, so it only holds the app's processes.
+    unsafe {
+        libc::kill(-pid, signal);
+    }
+}
+
+/// Logs the lines an app writes to `output`.
+async fn forward(name: String, output: Option<impl AsyncRead + Unpin>) {
+    let Some(output) = output else { return };
+    let mut lines = BufReader::new(output).split(b'\n');
+    while let Ok(Some(line)) = lines.next_segment().await {
+        info!("[{}] {}", name, String::from_utf8_lossy(&line).trim_end());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{auth::USER_ID_KEY, config::SessionConfig, models::SessionRecord};
+use actix_session::{
+    config::{PersistentSession, TtlExtensionPolicy},
+    storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError},
+    SessionMiddleware,
+};
+use actix_web::{
+    body::MessageBody,
+    cookie::{time::Duration, Cookie, CookieJar, Key},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    middleware::Next,
+    web, Error,
+};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+pub const SESSION_COOKIE_NAME: &str = "id";
+
+const SESSION_KEY_LEN: usize = 64;
+
+type SessionState = HashMap<String, String>;
+
+/// Session middleware keeping the session state server-side, so the cookie
+/// only carries an opaque session key.
+pub fn session_middleware(
+    pool: SqlitePool,
+    config: &SessionConfig,
+) -> SessionMiddleware<SqliteSessionStore> {
+    SessionMiddleware::builder(
+        SqliteSessionStore::new(pool, config.absolute_ttl),
+        config.keys[0].clone(),
+    )
+    .cookie_name(SESSION_COOKIE_NAME.to_string())
+    .session_lifecycle(
+        PersistentSession::default()
+            .session_ttl(config.idle_ttl)
+            .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+    )
+    .cookie_secure(config.secure)
+    .cookie_same_site(config.same_site)
+    .cookie_domain(config.domain.clone())
+    .cookie_http_only(true)
+    .build()
+}
+
+/// Middleware re-encrypting session cookies written with an older key of the
+/// `SessionConfig` app data under the current one. The session middleware,
+/// which must be wrapped by this one, then sends the cookie back encrypted
+/// with the current key.
+pub async fn reseal_session_cookie(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse, Error> {
+    if let Some(config) = req.app_data::<web::Data<SessionConfig>>().cloned() {
+        reseal(&mut req, &config.keys);
+    }
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+fn reseal(req: &mut ServiceRequest, keys: &[Key]) {
+    let Some((current, previous)) = keys.split_first() else {
+        return;
+    };
+    if previous.is_empty() {
+        return;
+    }
+
+    let mut parts: Vec<String> = req
+        .headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect();
+    let Some(part) = parts.iter_mut().find(|part| {
+        part.split_once('=')
+            .is_some_and(|(name, _)| name == SESSION_COOKIE_NAME)
+    }) else {
+        return;
+    };
+    let Ok(cookie) = Cookie::parse_encoded(part.clone()) else {
+        return;
+    };
+
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie);
+    if jar.private(current).get(SESSION_COOKIE_NAME).is_some() {
+        return;
+    }
+    let Some(session_key) = previous
+        .iter()
+        .find_map(|key| jar.private(key).get(SESSION_COOKIE_NAME))
+    else {
+        return;
+    };
+
+    let mut resealed = CookieJar::new();
+    resealed.private_mut(current).add(session_key);
+    let Some(cookie) = resealed.get(SESSION_COOKIE_NAME) else {
+        return;
+    };
+    *part = cookie.stripped().encoded().to_string();
+
+    if let Ok(value) = HeaderValue::from_str(&parts.join("; ")) {
+        req.headers_mut().insert(header::COOKIE, value);
+    }
+}
+
+/// Stores sessions in the `sessions` table. A session expires once it has
+/// not been used for the middleware's session TTL, and `absolute_ttl` after
+/// it was created.
+#[derive(Clone)]
+pub struct SqliteSessionStore {
+    pool: SqlitePool,
+    absolute_ttl: Duration,
+}
+
+impl SqliteSessionStore {
+    pub fn new(pool: SqlitePool, absolute_ttl: Duration) -> Self {
+        Self { pool, absolute_ttl }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for SqliteSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let Some(record) = SessionRecord::find_active(&self.pool, &record_id(session_key))
+            .await
+            .map_err(|e| LoadError::Other(e.into()))?
+        else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&record.state)
+            .map(Some)
+            .map_err(|e| LoadError::Deserialization(e.into()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| SaveError::Serialization(e.into()))?;
+        let session_key = generate_session_key();
+
+        SessionRecord::create(
+            &self.pool,
+            &record_id(&session_key),
+            session_user_id(&session_state),
+            &state,
+            ttl.whole_seconds(),
+            self.absolute_ttl.whole_seconds(),
+        )
+        .await
+        .map_err(|e| SaveError::Other(e.into()))?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| UpdateError::Serialization(e.into()))?;
+
+        let updated = SessionRecord::update_state(
+            &self.pool,
+            &record_id(&session_key),
+            session_user_id(&session_state),
+            &state,
+            ttl.whole_seconds(),
+        )
+        .await
+        .map_err(|e| UpdateError::Other(e.into()))?;
+
+        if updated {
+            return Ok(session_key);
+        }
+
+        // The session expired or was deleted while the request was in
+        // flight. Its state must not outlive it, or the absolute expiry
+        // could be dodged, so the client starts over signed out.
+        self.save(SessionState::new(), ttl)
+            .await
+            .map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        SessionRecord::touch(&self.pool, &record_id(session_key), ttl.whole_seconds()).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        SessionRecord::delete(&self.pool, &record_id(session_key)).await?;
+        Ok(())
+    }
+}
+
+fn generate_session_key() -> SessionKey {
+    let key: String = (0..SESSION_KEY_LEN)
+        .map(|_| OsRng.sample(Alphanumeric) as char)
+        .collect();
+    key.try_into()
+        .expect("session key within the cookie size limit")
+}
+
+/// Only a hash of the session key is stored, so a copy of the database
+/// cannot be used to hijack sessions.
+fn record_id(session_key: &SessionKey) -> String {
+    format!("{:x}", Sha256::digest(session_key.as_ref()))
+}
+
+fn session_user_id(session_state: &SessionState) -> Option<i64> {
+    session_state
+        .get(USER_ID_KEY)
+        .and_then(|user_id| user_id.parse().ok())
+}
@@ -0,0 +1,174 @@
+use crate::{
+    config,
+    crypto::Keyring,
+    error::AppError,
+    models::{GitProvider, ProviderInstance},
+    oidc::OAuthTokenResponse,
+    providers,
+};
+use log::debug;
+use oauth2::{RefreshToken, RequestTokenError, TokenResponse};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Access tokens are refreshed this long before they expire.
+const REFRESH_LEEWAY_SECS: i64 = 60;
+
+/// Hands out provider access tokens for outbound API calls, refreshing
+/// expired ones first.
+pub struct TokenService {
+    pool: SqlitePool,
+    keyring: Arc<Keyring>,
+    /// One lock per connection, since providers that rotate refresh tokens
+    /// reject the second of two concurrent refreshes.
+    refresh_locks: Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl TokenService {
+    pub fn new(pool: SqlitePool, keyring: Arc<Keyring>) -> Self {
+        Self {
+            pool,
+            keyring,
+            refresh_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The keys provider tokens are encrypted with.
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Persists the tokens of a completed authorization as the provider
+    /// account `provider_user_id`.
+    pub async fn store(
+        &self,
+        user_id: i64,
+        provider: &str,
+        provider_user_id: &str,
+        token: &OAuthTokenResponse,
+    ) -> Result<GitProvider, AppError> {
+        GitProvider::upsert(
+            &self.pool,
+            &self.keyring,
+            user_id,
+            provider,
+            provider_user_id,
+            token.access_token().secret(),
+            token.refresh_token().map(|t| t.secret().as_str()),
+            expires_in_secs(token),
+        )
+        .await
+    }
+
+    /// Returns a usable access token for the user's connection to the
+    /// provider instance `provider`, refreshing it if it has expired.
+    pub async fn access_token(&self, user_id: i64, provider: &str) -> Result<String, AppError> {
+        let connection = self.connection(user_id, provider).await?;
+        if !connection.expires_within(REFRESH_LEEWAY_SECS) {
+            return Ok(connection.access_token);
+        }
+
+        let lock = self.refresh_lock(connection.id);
+        let _guard = lock.lock().await;
+
+        // Another request may have refreshed the token while this one waited.
+        let connection = self.connection(user_id, provider).await?;
+        if !connection.expires_within(REFRESH_LEEWAY_SECS) {
+            return Ok(connection.access_token);
+        }
+
+        Ok(self.refresh(connection).await?.access_token)
+    }
+
+    async fn connection(&self, user_id: i64, provider: &str) -> Result<GitProvider, AppError> {
+        let connection = GitProvider::find(&self.pool, &self.keyring, user_id, provider)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No {} connection", provider)))?;
+
+        if connection.needs_reauth {
+            return Err(reauth_required(provider));
+        }
+        Ok(connection)
+    }
+
+    fn refresh_lock(&self, connection_id: i64) -> Arc<tokio::sync::Mutex<()>> {
+        self.refresh_locks
+            .lock()
+            .expect("refresh lock map poisoned")
+            .entry(connection_id)
+            .or_default()
+            .clone()
+    }
+
+    async fn refresh(&self, connection: GitProvider) -> Result<GitProvider, AppError> {
+        let Some(refresh_token) = connection.refresh_token.clone() else {
+            debug!(
+                "{} token expired without a refresh token",
+                connection.provider
+            );
+            return self.require_reauth(&connection).await;
+        };
+
+        let instance = ProviderInstance::find_by_slug(&self.pool, &connection.provider)
+            .await?
+            .ok_or_else(|| {
+                AppError::NotFound(format!("Unknown provider: {}", connection.provider))
+            })?;
+        let endpoints = providers::resolve_endpoints(&instance).await?;
+        let client = config::oauth_client(&instance, &endpoints)?;
+
+        debug!("Refreshing {} access token...", connection.provider);
+        let refresh_token = RefreshToken::new(refresh_token);
+        let mut request = client.exchange_refresh_token(&refresh_token);
+        if instance.spec()?.refresh_requires_redirect_uri() {
+            request = request.add_extra_param("redirect_uri", config::redirect_url(&instance));
+        }
+
+        match request
+            .request_async(oauth2::reqwest::async_http_client)
+            .await
+        {
+            Ok(token) => {
+                connection
+                    .update_tokens(
+                        &self.pool,
+                        &self.keyring,
+                        token.access_token().secret(),
+                        token.refresh_token().map(|t| t.secret().as_str()),
+                        expires_in_secs(&token),
+                    )
+                    .await
+            }
+            // The provider rejected the refresh token (revoked, expired, rotated).
+            Err(RequestTokenError::ServerResponse(e)) => {
+                debug!("{} token refresh rejected: {}", connection.provider, e);
+                self.require_reauth(&connection).await
+            }
+            Err(e) => Err(AppError::ExternalServiceError(format!(
+                "Failed to refresh {} token: {}",
+                connection.provider, e
+            ))),
+        }
+    }
+
+    async fn require_reauth(&self, connection: &GitProvider) -> Result<GitProvider, AppError> {
+        GitProvider::mark_needs_reauth(&self.pool, connection.id).await?;
+        Err(reauth_required(&connection.provider))
+    }
+}
+
+fn reauth_required(provider: &str) -> AppError {
+    AppError::AuthError(format!(
+        "The {} connection has expired, please sign in again",
+        provider
+    ))
+}
+
+fn expires_in_secs(token: &OAuthTokenResponse) -> Option<i64> {
+    token
+        .expires_in()
+        .and_then(|expires_in| i64::try_from(expires_in.as_secs()).ok())
+}
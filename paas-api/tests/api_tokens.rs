@@ -0,0 +1,225 @@
+mod common;
+
+use actix_web::{cookie::Cookie, dev::ServiceResponse, http::StatusCode, test, Error};
+use common::{bearer, mock_account, setup_test_db, setup_test_env, sign_in, signed_in_app};
+use serde_json::{json, Value};
+
+async fn create_token<S>(app: &S, cookie: &Cookie<'static>, body: Value) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::post()
+        .uri("/api/user/tokens")
+        .cookie(cookie.clone())
+        .set_json(body)
+        .to_request();
+    test::call_service(app, req).await
+}
+
+/// Creates a token with `scopes` and returns its id and value.
+async fn issue_token<S>(app: &S, cookie: &Cookie<'static>, scopes: &[&str]) -> (i64, String)
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let resp = create_token(app, cookie, json!({ "name": "ci", "scopes": scopes })).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(resp).await;
+    (
+        body["id"].as_i64().unwrap(),
+        body["token"].as_str().unwrap().to_string(),
+    )
+}
+
+#[actix_web::test]
+async fn test_create_and_use_api_token() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let resp = create_token(
+        &app,
+        &cookie,
+        json!({ "name": "  laptop  ", "scopes": ["read"], "expires_in_days": 7 }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(resp).await;
+    let token = body["token"].as_str().unwrap();
+    assert!(token.starts_with("paas_"));
+    assert!(token.starts_with(body["token_prefix"].as_str().unwrap()));
+    assert_eq!(body["name"], "laptop");
+    assert_eq!(body["scopes"], json!(["read"]));
+    assert!(body.get("token_hash").is_none());
+    assert_eq!(body["last_used_at"], Value::Null);
+
+    // Only the hash is stored.
+    let stored: String = sqlx::query_scalar("SELECT token_hash FROM api_tokens")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, token);
+    assert!(!stored.contains(&token[5..]));
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .insert_header(bearer(token))
+        .to_request();
+    let me: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(me["username"], "octocat");
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/tokens")
+        .insert_header(bearer(token))
+        .to_request();
+    let tokens: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+    assert!(tokens[0].get("token").is_none());
+    assert!(tokens[0]["last_used_at"].is_string());
+
+    // A read token cannot change anything.
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/tokens/{}", tokens[0]["id"]))
+        .insert_header(bearer(token))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "API token is missing the write scope");
+}
+
+#[actix_web::test]
+async fn test_revoked_and_expired_tokens_are_rejected() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let (id, token) = issue_token(&app, &cookie, &["read", "write"]).await;
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/tokens/{}", id))
+        .cookie(cookie.clone())
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NO_CONTENT
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .insert_header(bearer(&token))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let (id, token) = issue_token(&app, &cookie, &["read"]).await;
+    sqlx::query("UPDATE api_tokens SET expires_at = datetime('now', '-1 minute') WHERE id = ?")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .insert_header(bearer(&token))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn test_invalid_authorization_header_is_rejected() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    // An Authorization header is never ignored in favour of the session.
+    for value in [
+        "Bearer paas_unknown",
+        "Bearer not-a-token",
+        "Basic b2N0b2NhdDpodW50ZXIy",
+    ] {
+        let req = test::TestRequest::get()
+            .uri("/api/user/me")
+            .cookie(cookie.clone())
+            .insert_header(("Authorization", value))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", value);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Invalid API token");
+    }
+}
+
+#[actix_web::test]
+async fn test_tokens_cannot_create_tokens() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let (_, token) = issue_token(&app, &cookie, &["write"]).await;
+    let req = test::TestRequest::post()
+        .uri("/api/user/tokens")
+        .insert_header(bearer(&token))
+        .set_json(json!({ "name": "more", "scopes": ["write"] }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn test_token_requests_are_validated() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    for body in [
+        json!({ "name": " ", "scopes": ["read"] }),
+        json!({ "name": "ci", "scopes": [] }),
+        json!({ "name": "ci", "scopes": ["admin"] }),
+        json!({ "name": "ci", "scopes": ["read"], "expires_in_days": 0 }),
+        json!({ "name": "ci", "scopes": ["read"], "expires_in_days": 366 }),
+    ] {
+        let resp = create_token(&app, &cookie, body.clone()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/tokens")
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn test_tokens_belong_to_their_owner() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, mock_server) = signed_in_app(&pool).await;
+    let (id, _) = issue_token(&app, &cookie, &["read"]).await;
+
+    mock_server.reset().await;
+    mock_account(&mock_server, "github", 2, "hubot").await;
+    let other = sign_in(&app, "github").await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/tokens")
+        .cookie(other.clone())
+        .to_request();
+    let tokens: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens.is_empty());
+
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/tokens/{}", id))
+        .cookie(other)
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NOT_FOUND
+    );
+}
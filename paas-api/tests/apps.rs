@@ -0,0 +1,258 @@
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use common::{call, setup_test_app, setup_test_db, setup_test_env, sign_in_as, use_mock_providers};
+use paas_api::{NewRepository, Repository, Visibility};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use wiremock::MockServer;
+
+/// Adds a repository to the GitHub connection of the user signed in as
+/// `provider_user_id`.
+async fn create_repository(pool: &SqlitePool, provider_user_id: &str, name: &str) -> i64 {
+    let (user_id, provider_id): (i64, i64) = sqlx::query_as(
+        "SELECT user_id, id FROM git_providers WHERE provider = 'github' AND provider_user_id = ?",
+    )
+    .bind(provider_user_id)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    let repository = NewRepository {
+        user_id,
+        organization_id: None,
+        provider_id,
+        provider_repo_id: Some(name.to_string()),
+        name: name.to_string(),
+        description: None,
+        url: format!("https://github.com/{}", name),
+        clone_url: format!("https://github.com/{}.git", name),
+        default_branch: Some("main".to_string()),
+        visibility: Visibility::Private,
+    };
+    Repository::create(pool, &repository).await.unwrap().id
+}
+
+fn slugs(apps: &Value) -> Vec<&str> {
+    apps.as_array()
+        .unwrap()
+        .iter()
+        .map(|app| app["slug"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_app_crud() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let repository_id = create_repository(&pool, "1", "alice/shop").await;
+
+    let body = json!({
+        "name": "My Shop",
+        "repository_id": repository_id,
+        "branch": " release ",
+        "root_dir": "./web/",
+        "build_command": "",
+        "start_command": "npm start",
+        "runtime_version": " >=20 ",
+    });
+    let resp = call(&app, &cookie, "POST", "/api/apps", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let created: Value = test::read_body_json(resp).await;
+    assert_eq!(created["slug"], "my-shop");
+    assert_eq!(created["name"], "My Shop");
+    assert_eq!(created["organization_id"], Value::Null);
+    assert_eq!(created["repository_id"], repository_id);
+    assert_eq!(created["repository"], "alice/shop");
+    assert_eq!(created["branch"], "release");
+    assert_eq!(created["root_dir"], "web");
+    assert_eq!(created["build_command"], Value::Null);
+    assert_eq!(created["start_command"], "npm start");
+    assert_eq!(created["runtime_version"], ">=20");
+
+    let body = json!({ "name": "Blog", "slug": "alice-blog" });
+    let resp = call(&app, &cookie, "POST", "/api/apps", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let resp = call(&app, &cookie, "GET", "/api/apps", None).await;
+    let apps: Value = test::read_body_json(resp).await;
+    assert_eq!(slugs(&apps), ["alice-blog", "my-shop"]);
+
+    let body = json!({ "name": "Shop", "root_dir": "" });
+    let resp = call(&app, &cookie, "PUT", "/api/apps/my-shop", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let updated: Value = test::read_body_json(resp).await;
+    assert_eq!(updated["slug"], "my-shop");
+    assert_eq!(updated["name"], "Shop");
+    assert_eq!(updated["repository_id"], Value::Null);
+    assert_eq!(updated["repository"], Value::Null);
+    assert_eq!(updated["start_command"], Value::Null);
+    assert_eq!(updated["runtime_version"], Value::Null);
+    assert_eq!(updated["created_at"], created["created_at"]);
+
+    let resp = call(&app, &cookie, "GET", "/api/apps/my-shop", None).await;
+    let fetched: Value = test::read_body_json(resp).await;
+    assert_eq!(fetched, updated);
+
+    let resp = call(&app, &cookie, "DELETE", "/api/apps/my-shop", None).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = call(&app, &cookie, "GET", "/api/apps/my-shop", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = call(&app, &cookie, "DELETE", "/api/apps/my-shop", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = call(&app, &cookie, "GET", "/api/audit?action=app", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    let actions: Vec<_> = page["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        ["app.delete", "app.update", "app.create", "app.create"]
+    );
+
+    // Apps lose their repository when it goes away.
+    let body = json!({ "name": "Shop", "repository_id": repository_id });
+    call(&app, &cookie, "POST", "/api/apps", Some(body)).await;
+    Repository::delete(&pool, repository_id).await.unwrap();
+    let resp = call(&app, &cookie, "GET", "/api/apps/shop", None).await;
+    let fetched: Value = test::read_body_json(resp).await;
+    assert_eq!(fetched["repository_id"], Value::Null);
+}
+
+#[actix_web::test]
+async fn test_app_validation() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    let bobs_repository = create_repository(&pool, "2", "bob/secret").await;
+
+    let body = json!({ "name": "Shop" });
+    let resp = call(&app, &alice, "POST", "/api/apps", Some(body.clone())).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    for (body, message) in [
+        (json!({ "name": "Shop" }), "Slug 'shop' is already taken"),
+        (
+            json!({ "name": "Shop", "slug": "Shop_2" }),
+            "Slug must be 1 to 63 lowercase letters, digits and dashes",
+        ),
+        (
+            json!({ "name": "Shop", "slug": "new" }),
+            "Slug 'new' is reserved",
+        ),
+        (
+            json!({ "name": " " }),
+            "App name must be between 1 and 100 characters",
+        ),
+        (
+            json!({ "name": "Shop", "slug": "shop-2", "branch": "--upload-pack=evil" }),
+            "Invalid branch name: --upload-pack=evil",
+        ),
+        (
+            json!({ "name": "Shop", "slug": "shop-2", "branch": "a..b" }),
+            "Invalid branch name: a..b",
+        ),
+        (
+            json!({ "name": "Shop", "slug": "shop-2", "root_dir": "web/../../etc" }),
+            "Invalid root directory: web/../../etc",
+        ),
+        (
+            json!({ "name": "Shop", "slug": "shop-2", "runtime_version": "20; rm -rf /" }),
+            "Invalid runtime version: 20; rm -rf /",
+        ),
+    ] {
+        let resp = call(&app, &alice, "POST", "/api/apps", Some(body.clone())).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+        let error: Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"], message, "{}", body);
+    }
+
+    // Other users' repositories and apps are invisible.
+    let body = json!({ "name": "Shop 2", "repository_id": bobs_repository });
+    let resp = call(&app, &alice, "POST", "/api/apps", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = call(&app, &bob, "GET", "/api/apps/shop", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = call(&app, &bob, "DELETE", "/api/apps/shop", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = call(&app, &bob, "GET", "/api/apps", None).await;
+    let apps: Value = test::read_body_json(resp).await;
+    assert_eq!(apps, json!([]));
+}
+
+#[actix_web::test]
+async fn test_organization_apps_follow_roles() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    let carol = sign_in_as(&app, &mock_server, 3, "carol").await;
+
+    let body = json!({ "name": "Acme" });
+    call(&app, &alice, "POST", "/api/orgs", Some(body)).await;
+    for (invitee, role, cookie) in [("bob", "viewer", &bob), ("carol", "developer", &carol)] {
+        let body = json!({ "invitee": invitee, "role": role });
+        let resp = call(
+            &app,
+            &alice,
+            "POST",
+            "/api/orgs/acme/invitations",
+            Some(body),
+        )
+        .await;
+        let invitation: Value = test::read_body_json(resp).await;
+        let uri = format!("/api/orgs/invitations/{}/accept", invitation["id"]);
+        call(&app, cookie, "POST", &uri, None).await;
+    }
+
+    // Developers create and update apps with their own repositories.
+    let repository_id = create_repository(&pool, "3", "carol/api").await;
+    let body = json!({ "name": "API", "org": "acme", "repository_id": repository_id });
+    let resp = call(&app, &carol, "POST", "/api/apps", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let created: Value = test::read_body_json(resp).await;
+    assert!(created["organization_id"].is_i64());
+    assert_eq!(created["repository"], "carol/api");
+    let body = json!({ "name": "Acme API", "repository_id": repository_id });
+    let resp = call(&app, &carol, "PUT", "/api/apps/api", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = call(&app, &carol, "DELETE", "/api/apps/api", None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Viewers only look; other members cannot reuse a member's repository.
+    let resp = call(&app, &bob, "GET", "/api/apps?org=acme", None).await;
+    let apps: Value = test::read_body_json(resp).await;
+    assert_eq!(slugs(&apps), ["api"]);
+    let resp = call(&app, &bob, "GET", "/api/apps/api", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = json!({ "name": "Docs", "org": "acme" });
+    let resp = call(&app, &bob, "POST", "/api/apps", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body = json!({ "name": "Acme API", "repository_id": repository_id });
+    let resp = call(&app, &alice, "PUT", "/api/apps/api", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Organization apps stay out of personal lists, and go with the
+    // organization.
+    let resp = call(&app, &carol, "GET", "/api/apps", None).await;
+    let apps: Value = test::read_body_json(resp).await;
+    assert_eq!(apps, json!([]));
+    let resp = call(&app, &alice, "DELETE", "/api/orgs/acme", None).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = call(&app, &carol, "GET", "/api/apps/api", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
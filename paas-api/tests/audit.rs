@@ -0,0 +1,261 @@
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use common::{
+    call, keyring, setup_test_app, setup_test_db, setup_test_env, sign_in_as, start_auth,
+    use_mock_providers,
+};
+use paas_api::{
+    audit::{Event, RequestContext},
+    User,
+};
+use serde_json::{json, Value};
+use wiremock::MockServer;
+
+fn actions(page: &Value) -> Vec<&str> {
+    page["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["action"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_sign_in_and_out_are_recorded() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    // A callback with a forged state is turned away and recorded.
+    let (_, cookie) = start_auth(&app, "github").await;
+    let req = test::TestRequest::get()
+        .uri("/api/auth/github/callback?code=test_code&state=forged")
+        .cookie(cookie)
+        .insert_header(("User-Agent", "curl/8.0"))
+        .peer_addr("203.0.113.7:4321".parse().unwrap())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_redirection());
+    let (action, actor_id, target_id, ip, user_agent): (
+        String,
+        Option<i64>,
+        String,
+        String,
+        String,
+    ) = sqlx::query_as("SELECT action, actor_id, target_id, ip, user_agent FROM audit_events")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(action, "auth.login.failed");
+    assert_eq!(actor_id, None);
+    assert_eq!(target_id, "github");
+    assert_eq!(ip, "203.0.113.7");
+    assert_eq!(user_agent, "curl/8.0");
+
+    let cookie = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let resp = call(&app, &cookie, "POST", "/api/auth/logout", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookie = sign_in_as(&app, &mock_server, 1, "alice").await;
+
+    let resp = call(&app, &cookie, "GET", "/api/audit", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(actions(&page), ["auth.login", "auth.logout", "auth.login"]);
+    let login = &page["events"][0];
+    assert_eq!(login["actor"], "alice");
+    assert_eq!(login["target_type"], "provider");
+    assert_eq!(login["target_id"], "github");
+    assert_eq!(login["metadata"], json!({}));
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    // The log cannot be rewritten.
+    assert!(sqlx::query("UPDATE audit_events SET actor = 'mallory'")
+        .execute(&pool)
+        .await
+        .is_err());
+    assert!(sqlx::query("DELETE FROM audit_events")
+        .execute(&pool)
+        .await
+        .is_err());
+}
+
+#[actix_web::test]
+async fn test_organization_events_need_audit_read() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "Acme" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body = json!({ "invitee": "bob", "role": "developer" });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs/acme/invitations",
+        Some(body),
+    )
+    .await;
+    let invitation: Value = test::read_body_json(resp).await;
+    let uri = format!("/api/orgs/invitations/{}/accept", invitation["id"]);
+    let resp = call(&app, &bob, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = call(&app, &bob, "GET", "/api/audit?org=acme", None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = call(&app, &bob, "GET", "/api/audit?org=other", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = call(&app, &alice, "GET", "/api/audit?org=acme", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(
+        actions(&page),
+        [
+            "org.invitation.accept",
+            "org.invitation.create",
+            "org.create"
+        ]
+    );
+    assert_eq!(page["events"][1]["metadata"]["invitee"], "bob");
+    assert_eq!(page["events"][1]["metadata"]["role"], "developer");
+
+    // Actions match exactly or by prefix.
+    for (query, expected) in [
+        (
+            "action=org.invitation",
+            vec!["org.invitation.accept", "org.invitation.create"],
+        ),
+        (
+            "action=org.invitation.create",
+            vec!["org.invitation.create"],
+        ),
+        ("action=org.invite", vec![]),
+        ("actor=BOB", vec!["org.invitation.accept"]),
+        ("target_type=organization", vec!["org.create"]),
+        ("since=2000-01-01&until=2000-01-02", vec![]),
+    ] {
+        let uri = format!("/api/audit?org=acme&{}", query);
+        let resp = call(&app, &alice, "GET", &uri, None).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", query);
+        let page: Value = test::read_body_json(resp).await;
+        assert_eq!(actions(&page), expected, "{}", query);
+    }
+
+    // Members' personal logs only hold what they did themselves.
+    let resp = call(&app, &bob, "GET", "/api/audit", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(actions(&page), ["org.invitation.accept", "auth.login"]);
+}
+
+#[actix_web::test]
+async fn test_pagination_and_export() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "alice").await;
+
+    let user = User::find_by_identity(&pool, &keyring(), "github", "1")
+        .await
+        .unwrap()
+        .unwrap();
+    for i in 0..4 {
+        Event::new("test.event")
+            .actor(&user)
+            .target("thing", i)
+            .metadata(json!({ "n": i }))
+            .record(&pool, &RequestContext::default())
+            .await
+            .unwrap();
+    }
+
+    let mut seen = Vec::new();
+    let mut uri = "/api/audit?action=test&limit=3".to_string();
+    loop {
+        let resp = call(&app, &cookie, "GET", &uri, None).await;
+        let page: Value = test::read_body_json(resp).await;
+        for event in page["events"].as_array().unwrap() {
+            seen.push(event["metadata"]["n"].as_i64().unwrap());
+        }
+        match page["next_cursor"].as_i64() {
+            Some(cursor) => uri = format!("/api/audit?action=test&limit=3&cursor={}", cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, [3, 2, 1, 0]);
+
+    for limit in ["0", "201"] {
+        let uri = format!("/api/audit?limit={}", limit);
+        let resp = call(&app, &cookie, "GET", &uri, None).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+    let resp = call(&app, &cookie, "GET", "/api/audit?since=yesterday", None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = call(&app, &cookie, "GET", "/api/audit/export?action=test", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get("Content-Type").unwrap(),
+        "application/x-ndjson"
+    );
+    let body = test::read_body(resp).await;
+    let lines: Vec<Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let exported: Vec<_> = lines
+        .iter()
+        .map(|event| event["target_id"].clone())
+        .collect();
+    assert_eq!(exported, ["0", "1", "2", "3"]);
+    assert_eq!(lines[0]["actor"], "alice");
+}
+
+#[actix_web::test]
+async fn test_export_streams_every_batch() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "alice").await;
+
+    // More events than fit in one batch.
+    sqlx::query(
+        "WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 2499)
+         INSERT INTO audit_events (actor_id, actor, action, target_type, target_id)
+         SELECT users.id, 'alice', 'test.event', 'thing', n.i FROM n, users",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let resp = call(&app, &cookie, "GET", "/api/audit/export?action=test", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let exported: Vec<i64> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let event: Value = serde_json::from_str(line).unwrap();
+            event["target_id"].as_str().unwrap().parse().unwrap()
+        })
+        .collect();
+    assert_eq!(exported, (0..2500).collect::<Vec<_>>());
+}
@@ -0,0 +1,300 @@
+#![cfg(unix)]
+
+use async_trait::async_trait;
+use paas_api::{
+    builders::{
+        self, BuildError, BuildLog, BuildRequest, Builder, CancelSignal, FakeBuilder, OciBuilder,
+        OciTool,
+    },
+    buildpacks::{BuildPlan, Language},
+    AppError,
+};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use tempfile::TempDir;
+
+const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+// Running a script while another test's fork holds it open for writing
+// fails with "Text file busy", so tests writing scripts take turns.
+static CLI_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[derive(Default)]
+struct Lines(Mutex<Vec<String>>);
+
+impl Lines {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+#[async_trait]
+impl BuildLog for Lines {
+    async fn line(&self, line: &str) {
+        self.0.lock().unwrap().push(line.to_string());
+    }
+}
+
+fn node_plan() -> BuildPlan {
+    BuildPlan {
+        language: Language::Node,
+        runtime_version: Some(">=20.1".to_string()),
+        build_commands: vec![
+            "pnpm install --frozen-lockfile".to_string(),
+            "pnpm run build".to_string(),
+        ],
+        start_command: Some("node dist/index.js --port \"$PORT\"".to_string()),
+    }
+}
+
+fn request<'a>(context: &'a Path, plan: &'a BuildPlan) -> BuildRequest<'a> {
+    BuildRequest {
+        app_slug: "shop",
+        commit_sha: SHA,
+        context,
+        plan,
+        cancel: CancelSignal::default(),
+    }
+}
+
+/// An executable standing in for docker: it prints its arguments, then the
+/// Dockerfile it was given, then runs `rest` with the Dockerfile in `$file`.
+fn fake_cli(dir: &TempDir, rest: &str) -> PathBuf {
+    let path = dir.path().join("docker");
+    let script = format!(
+        "#!/bin/sh\n\
+         echo \"$@\"\n\
+         while [ $# -gt 0 ]; do\n\
+           if [ \"$1\" = --file ]; then cat \"$2\"; file=\"$2\"; fi\n\
+           shift\n\
+         done\n\
+         {}\n",
+        rest
+    );
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[test]
+fn test_generated_dockerfiles() {
+    assert_eq!(
+        builders::dockerfile(&node_plan()).unwrap(),
+        r#"FROM node:20.1
+WORKDIR /app
+COPY . .
+ENV PORT=8080
+RUN corepack enable
+RUN ["/bin/sh","-c","pnpm install --frozen-lockfile"]
+RUN ["/bin/sh","-c","pnpm run build"]
+EXPOSE 8080
+CMD ["/bin/sh","-c","node dist/index.js --port \"$PORT\""]
+"#
+    );
+
+    let rust = BuildPlan {
+        language: Language::Rust,
+        runtime_version: None,
+        build_commands: vec!["cargo build --release".to_string()],
+        start_command: Some("./target/release/shop".to_string()),
+    };
+    let dockerfile = builders::dockerfile(&rust).unwrap();
+    assert!(dockerfile.starts_with("FROM rust:latest\n"));
+    assert!(!dockerfile.contains("corepack"));
+    for (language, version, from) in [
+        (Language::Go, "1.22.1", "FROM golang:1.22.1\n"),
+        (Language::Python, "~=3.11", "FROM python:3.11\n"),
+        (Language::Ruby, "ruby-3.2.2", "FROM ruby:3.2.2\n"),
+    ] {
+        let plan = BuildPlan {
+            language,
+            runtime_version: Some(version.to_string()),
+            ..rust.clone()
+        };
+        assert!(builders::dockerfile(&plan).unwrap().starts_with(from));
+    }
+
+    let docker = BuildPlan {
+        language: Language::Docker,
+        runtime_version: None,
+        build_commands: vec![],
+        start_command: None,
+    };
+    assert_eq!(builders::dockerfile(&docker), None);
+    assert_eq!(
+        builders::image_name("registry.local:5000/paas/", "shop", SHA),
+        format!("registry.local:5000/paas/shop:{}", SHA)
+    );
+}
+
+#[actix_web::test]
+async fn test_oci_builder_streams_the_build() {
+    let _cli = CLI_LOCK.lock().await;
+    let dir = TempDir::new().unwrap();
+    let context = TempDir::new().unwrap();
+    let program = fake_cli(&dir, "");
+    let builder = OciBuilder::new(OciTool::Docker)
+        .program(&program)
+        .image_prefix("registry.local/paas");
+    let log = Lines::default();
+
+    let plan = node_plan();
+    let image = builder
+        .build(&request(context.path(), &plan), &log)
+        .await
+        .unwrap();
+    assert_eq!(image, format!("registry.local/paas/shop:{}", SHA));
+    let lines = log.take();
+    assert_eq!(lines[0], format!("Building {} with docker", image));
+    let args = &lines[1];
+    assert!(args.starts_with("build --progress=plain --file "));
+    assert!(args.ends_with(&format!(
+        "--tag {} --label paas.app=shop --label paas.commit={} {}",
+        image,
+        SHA,
+        context.path().display()
+    )));
+    // The generated Dockerfile was sent, kept out of the context, and
+    // removed after the build.
+    let dockerfile = builders::dockerfile(&plan).unwrap();
+    assert_eq!(lines[2..].join("\n") + "\n", dockerfile);
+    let file = args.split(' ').nth(3).unwrap();
+    assert!(!Path::new(file).starts_with(context.path()));
+    assert!(!Path::new(file).exists());
+
+    // Only the server can read it, and each build gets a fresh one.
+    let private = OciBuilder::new(OciTool::Docker).program(fake_cli(&dir, "stat -c %a \"$file\""));
+    private
+        .build(&request(context.path(), &plan), &log)
+        .await
+        .unwrap();
+    let lines = log.take();
+    assert_eq!(lines.last().unwrap(), "600");
+    assert_ne!(lines[1].split(' ').nth(3).unwrap(), file);
+
+    // Apps with a Dockerfile are built from it; other tools take the same
+    // arguments without the progress flag.
+    let docker = BuildPlan {
+        language: Language::Docker,
+        runtime_version: None,
+        build_commands: vec![],
+        start_command: None,
+    };
+    fs::write(context.path().join("Dockerfile"), "FROM scratch\n").unwrap();
+    for tool in [OciTool::Podman, OciTool::Buildah] {
+        let builder = OciBuilder::new(tool).program(&program);
+        let image = builder
+            .build(&request(context.path(), &docker), &log)
+            .await
+            .unwrap();
+        assert_eq!(image, format!("paas/shop:{}", SHA));
+        let lines = log.take();
+        assert_eq!(
+            lines[1],
+            format!(
+                "build --file {} --tag {} --label paas.app=shop --label paas.commit={} {}",
+                context.path().join("Dockerfile").display(),
+                image,
+                SHA,
+                context.path().display()
+            )
+        );
+        assert_eq!(lines[2], "FROM scratch");
+    }
+}
+
+#[actix_web::test]
+async fn test_oci_builder_errors() {
+    let _cli = CLI_LOCK.lock().await;
+    let dir = TempDir::new().unwrap();
+    let plan = node_plan();
+    let log = Lines::default();
+
+    let failing = OciBuilder::new(OciTool::Docker)
+        .program(fake_cli(&dir, "echo 'no space left on device' >&2; exit 3"));
+    let error = failing
+        .build(&request(dir.path(), &plan), &log)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error,
+        BuildError::Failed("docker exit status: 3".to_string())
+    );
+    // Stderr goes to the log too.
+    assert!(log.take().contains(&"no space left on device".to_string()));
+
+    let slow = OciBuilder::new(OciTool::Docker)
+        .program(fake_cli(&dir, "sleep 10"))
+        .timeout(Duration::from_millis(500));
+    let error = slow
+        .build(&request(dir.path(), &plan), &log)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, BuildError::TimedOut { .. }));
+
+    // Cancelling kills the build the same way.
+    let slow = slow.timeout(Duration::from_secs(30));
+    let (cancel, signal) = CancelSignal::channel();
+    let cancelled = BuildRequest {
+        cancel: signal,
+        ..request(dir.path(), &plan)
+    };
+    let started = std::time::Instant::now();
+    let (result, _) = tokio::join!(slow.build(&cancelled, &log), async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.send_replace(true);
+    });
+    assert_eq!(result.unwrap_err(), BuildError::Cancelled);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let missing = OciBuilder::new(OciTool::Podman).program(dir.path().join("podman"));
+    let error = missing
+        .build(&request(dir.path(), &plan), &log)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, BuildError::NotInstalled(_)));
+    let error: AppError = error.into();
+    assert!(matches!(error, AppError::ConfigError(_)));
+    let error: AppError = BuildError::Failed("docker exit status: 1".to_string()).into();
+    assert!(matches!(error, AppError::ExternalServiceError(_)));
+}
+
+#[actix_web::test]
+async fn test_fake_builder() {
+    let dir = TempDir::new().unwrap();
+    let plan = node_plan();
+    let log = Lines::default();
+
+    let builder = FakeBuilder::new();
+    let image = builder
+        .build(&request(dir.path(), &plan), &log)
+        .await
+        .unwrap();
+    assert_eq!(image, format!("paas/shop:{}", SHA));
+    let builds = builder.builds();
+    assert_eq!(builds.len(), 1);
+    assert_eq!(builds[0].plan, plan);
+    assert_eq!(builds[0].context, dir.path());
+    assert_eq!(
+        log.take(),
+        [
+            format!("Building {}", image),
+            "$ pnpm install --frozen-lockfile".to_string(),
+            "$ pnpm run build".to_string(),
+        ]
+    );
+
+    let error = FakeBuilder::failing("npm ERR! missing script: build")
+        .build(&request(dir.path(), &plan), &log)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Build failed: npm ERR! missing script: build"
+    );
+}
@@ -0,0 +1,201 @@
+use paas_api::{
+    buildpacks::{self, BuildPlan, Language},
+    AppSettings,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// A checkout holding one app per directory.
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/buildpacks")
+}
+
+fn detect(fixture: &str) -> BuildPlan {
+    buildpacks::detect(&fixtures().join(fixture)).unwrap()
+}
+
+fn plan(
+    language: Language,
+    version: Option<&str>,
+    build: &[&str],
+    start: Option<&str>,
+) -> BuildPlan {
+    BuildPlan {
+        language,
+        runtime_version: version.map(str::to_string),
+        build_commands: build.iter().map(|command| command.to_string()).collect(),
+        start_command: start.map(str::to_string),
+    }
+}
+
+fn settings(root_dir: &str) -> AppSettings {
+    AppSettings {
+        root_dir: root_dir.to_string(),
+        ..AppSettings::default()
+    }
+}
+
+fn error(result: Result<BuildPlan, paas_api::AppError>) -> String {
+    result.unwrap_err().to_string()
+}
+
+#[test]
+fn test_detects_each_language() {
+    for (fixture, expected) in [
+        (
+            "rust",
+            plan(
+                Language::Rust,
+                Some("1.79.0"),
+                &["cargo build --release --locked"],
+                Some("./target/release/hello"),
+            ),
+        ),
+        (
+            "go",
+            plan(
+                Language::Go,
+                Some("1.22.1"),
+                &["go build -o bin/app ./cmd/shop"],
+                Some("./bin/app"),
+            ),
+        ),
+        (
+            "node",
+            plan(
+                Language::Node,
+                Some(">=20"),
+                &["npm ci", "npm run build"],
+                Some("npm start"),
+            ),
+        ),
+        (
+            "node-pnpm",
+            plan(
+                Language::Node,
+                Some("18.19.0"),
+                &["pnpm install --frozen-lockfile"],
+                Some("node dist/index.js"),
+            ),
+        ),
+        (
+            "python",
+            plan(
+                Language::Python,
+                Some("3.11.4"),
+                &["pip install -r requirements.txt"],
+                Some("gunicorn app:app --bind 0.0.0.0:$PORT"),
+            ),
+        ),
+        (
+            "python-pyproject",
+            plan(
+                Language::Python,
+                Some(">=3.10"),
+                &["pip install ."],
+                Some("python main.py"),
+            ),
+        ),
+        // Rails apps with a package.json for their assets are Ruby apps.
+        (
+            "ruby",
+            plan(
+                Language::Ruby,
+                Some("3.2.2"),
+                &["bundle install"],
+                Some("bin/rails server -b 0.0.0.0 -p $PORT"),
+            ),
+        ),
+        ("docker", plan(Language::Docker, None, &[], None)),
+    ] {
+        assert_eq!(detect(fixture), expected, "{}", fixture);
+    }
+}
+
+#[test]
+fn test_plan_applies_app_settings() {
+    let detected = buildpacks::plan(&fixtures(), &settings("node")).unwrap();
+    assert_eq!(detected, detect("node"));
+
+    let overridden = buildpacks::plan(
+        &fixtures(),
+        &AppSettings {
+            build_command: Some("npm ci && npm run build:prod".to_string()),
+            start_command: Some("node dist/server.js".to_string()),
+            runtime_version: Some("22".to_string()),
+            ..settings("node")
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        overridden,
+        plan(
+            Language::Node,
+            Some("22"),
+            &["npm ci && npm run build:prod"],
+            Some("node dist/server.js"),
+        )
+    );
+
+    // Apps that cannot be started without a start command need one set.
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("package.json"), r#"{ "name": "lib" }"#).unwrap();
+    assert_eq!(
+        error(buildpacks::plan(dir.path(), &settings(""))),
+        "Validation error: Could not detect how to start this node app; \
+         set a start command or add a Procfile"
+    );
+    let started = AppSettings {
+        start_command: Some("npx serve".to_string()),
+        ..settings("")
+    };
+    let detected = buildpacks::plan(dir.path(), &started).unwrap();
+    assert_eq!(detected.build_commands, ["npm install"]);
+    assert_eq!(detected.start_command.as_deref(), Some("npx serve"));
+
+    // Docker images know how to start.
+    let detected = buildpacks::plan(&fixtures(), &settings("docker")).unwrap();
+    assert_eq!(detected.language, Language::Docker);
+}
+
+#[test]
+fn test_detection_errors() {
+    let dir = TempDir::new().unwrap();
+    assert_eq!(
+        error(buildpacks::detect(dir.path())),
+        "Validation error: Could not detect how to build the app; add one of \
+         Dockerfile, Cargo.toml, go.mod, Gemfile, requirements.txt, pyproject.toml, package.json"
+    );
+    assert_eq!(
+        error(buildpacks::plan(&fixtures(), &settings("php"))),
+        "Validation error: Root directory 'php' not found in the repository"
+    );
+
+    fs::write(dir.path().join("package.json"), "{ nope").unwrap();
+    assert!(error(buildpacks::detect(dir.path()))
+        .starts_with("Validation error: Invalid package.json: "));
+    fs::write(dir.path().join("package.json"), " ".repeat(2 * 1024 * 1024)).unwrap();
+    assert_eq!(
+        error(buildpacks::detect(dir.path())),
+        "Validation error: package.json is larger than 1 MiB"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks_stay_inside_the_checkout() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    symlink(fixtures().join("node"), dir.path().join("web")).unwrap();
+    symlink(fixtures().join("go/go.mod"), dir.path().join("go.mod")).unwrap();
+
+    assert_eq!(
+        error(buildpacks::plan(dir.path(), &settings("web"))),
+        "Validation error: Root directory 'web' not found in the repository"
+    );
+    assert!(error(buildpacks::detect(dir.path())).contains("Could not detect how to build"));
+}
@@ -0,0 +1,394 @@
+// Shared by several test binaries, not all of which use every helper.
+#![allow(dead_code)]
+
+use actix_web::{
+    cookie::{time::Duration, Cookie, Key, SameSite},
+    dev::ServiceResponse,
+    middleware::from_fn,
+    test,
+    web::Data,
+    App, Error,
+};
+use paas_api::{
+    builders::Builder,
+    config::{self, JwtConfig, SessionConfig},
+    crypto::{Keyring, KEYS_VAR},
+    git::SourceCache,
+    jobs::{self, Registry},
+    jwt::verify_bearer_jwt,
+    pipeline::Pipeline,
+    routes::configure,
+    session::{reseal_session_cookie, session_middleware},
+    tokens::TokenService,
+};
+use serde_json::{json, Value};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::{env, sync::Arc};
+use tokio::sync::{Mutex, MutexGuard};
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+// Provider URLs are read from the process environment, so tests that point
+// them at a mock server must not interleave.
+pub static ENV_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// `TOKEN_ENCRYPTION_KEYS` value used unless a test rotates keys.
+pub const TEST_ENCRYPTION_KEYS: &str = "test:MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+pub async fn setup_test_env() -> MutexGuard<'static, ()> {
+    let guard = ENV_LOCK.lock().await;
+    env_logger::try_init().ok();
+    dotenv::from_filename("tests.env").ok();
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("HOST", "127.0.0.1");
+    env::set_var("PORT", "3000");
+    env::set_var("BASE_URL", "http://127.0.0.1:3000");
+    env::set_var("FRONTEND_URL", "http://127.0.0.1:8080");
+    env::set_var("TOKEN_ENCRYPTION_KEYS", TEST_ENCRYPTION_KEYS);
+    env::set_var(
+        "GITHUB_AUTH_URL",
+        "https://github.com/login/oauth/authorize",
+    );
+    env::set_var(
+        "GITHUB_TOKEN_URL",
+        "https://github.com/login/oauth/access_token",
+    );
+    env::set_var("GITHUB_API_URL", "https://api.github.com/user");
+    env::set_var("GITLAB_AUTH_URL", "https://gitlab.com/oauth/authorize");
+    env::set_var("GITLAB_TOKEN_URL", "https://gitlab.com/oauth/token");
+    env::set_var("GITLAB_API_URL", "https://gitlab.com/api/v4/user");
+    env::set_var(
+        "BITBUCKET_AUTH_URL",
+        "https://bitbucket.org/site/oauth2/authorize",
+    );
+    env::set_var(
+        "BITBUCKET_TOKEN_URL",
+        "https://bitbucket.org/site/oauth2/access_token",
+    );
+    env::set_var("BITBUCKET_API_URL", "https://api.bitbucket.org/2.0/user");
+    env::remove_var("GITHUB_PKCE");
+    env::remove_var("GITLAB_PKCE");
+    env::remove_var("BITBUCKET_PKCE");
+    env::remove_var("OIDC_ISSUER_URL");
+    for name in [
+        "SESSION_COOKIE_KEYS",
+        "SESSION_COOKIE_SECURE",
+        "SESSION_COOKIE_SAME_SITE",
+        "SESSION_COOKIE_DOMAIN",
+        "SESSION_IDLE_TTL_SECS",
+        "SESSION_ABSOLUTE_TTL_SECS",
+        "JWT_KEYS",
+        "JWT_AUDIENCE",
+        "JWT_TTL_SECS",
+        "REFRESH_TOKEN_TTL_SECS",
+    ] {
+        env::remove_var(name);
+    }
+    guard
+}
+
+pub async fn setup_test_app(
+    pool: SqlitePool,
+) -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = actix_web::dev::ServiceResponse,
+    Error = Error,
+> {
+    setup_test_app_with_session(pool, test_session_config(vec![Key::generate()])).await
+}
+
+pub async fn setup_test_app_with_session(
+    pool: SqlitePool,
+    session_config: SessionConfig,
+) -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = actix_web::dev::ServiceResponse,
+    Error = Error,
+> {
+    let keyring = Data::new(Keyring::parse(TEST_ENCRYPTION_KEYS).unwrap());
+    test::init_service(
+        App::new()
+            .app_data(Data::new(TokenService::new(
+                pool.clone(),
+                keyring.clone().into_inner(),
+            )))
+            .app_data(keyring)
+            .app_data(Data::new(pool.clone()))
+            .app_data(Data::new(session_config.clone()))
+            .app_data(Data::new(test_jwt_config()))
+            .wrap(session_middleware(pool, &session_config))
+            .wrap(from_fn(reseal_session_cookie))
+            .wrap(from_fn(verify_bearer_jwt))
+            .configure(configure),
+    )
+    .await
+}
+
+pub fn test_session_config(keys: Vec<Key>) -> SessionConfig {
+    SessionConfig {
+        keys,
+        secure: false,
+        same_site: SameSite::Lax,
+        domain: None,
+        idle_ttl: Duration::seconds(config::DEFAULT_SESSION_IDLE_TTL_SECS),
+        absolute_ttl: Duration::seconds(config::DEFAULT_SESSION_ABSOLUTE_TTL_SECS),
+    }
+}
+
+pub fn test_jwt_config() -> JwtConfig {
+    JwtConfig {
+        keys: vec![("test".to_string(), b"test-jwt-signing-secret".to_vec())],
+        issuer: "http://127.0.0.1:3000".to_string(),
+        audience: config::DEFAULT_JWT_AUDIENCE.to_string(),
+        ttl: Duration::seconds(config::DEFAULT_JWT_TTL_SECS),
+        refresh_ttl: Duration::seconds(config::DEFAULT_REFRESH_TOKEN_TTL_SECS),
+    }
+}
+
+/// The keys of `TOKEN_ENCRYPTION_KEYS` as currently set, or the test keys
+/// for tests that do not set up the environment.
+pub fn keyring() -> Arc<Keyring> {
+    let keys = env::var(KEYS_VAR).unwrap_or_else(|_| TEST_ENCRYPTION_KEYS.to_string());
+    Arc::new(Keyring::parse(&keys).expect("Invalid TOKEN_ENCRYPTION_KEYS"))
+}
+
+/// Every SQLite connection to `:memory:` opens its own empty database, so the
+/// pool is pinned to a single connection that is never recycled.
+pub async fn setup_test_db() -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect(":memory:")
+        .await
+        .expect("Failed to create test database");
+
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .expect("Failed to run migrations");
+
+    pool
+}
+
+/// The server's job handlers, building deployments from `sources` with
+/// `builder`.
+pub fn job_registry(
+    pool: &SqlitePool,
+    sources: SourceCache,
+    builder: Arc<dyn Builder>,
+) -> Registry {
+    let keyring = Keyring::parse(TEST_ENCRYPTION_KEYS).unwrap();
+    let token_service = Arc::new(TokenService::new(pool.clone(), Arc::new(keyring)));
+    let pipeline = Pipeline::new(sources, builder, token_service.clone());
+    jobs::registry(token_service, Arc::new(pipeline))
+}
+
+/// Starts an OAuth flow and returns the issued `state` together with the
+/// session cookie it was bound to.
+pub async fn start_auth<S>(app: &S, provider: &str) -> (String, Cookie<'static>)
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/auth/{}", provider))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert!(resp.status().is_success());
+
+    let cookie = resp
+        .response()
+        .cookies()
+        .next()
+        .expect("session cookie")
+        .into_owned();
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let auth_url = url::Url::parse(body["auth_url"].as_str().unwrap()).unwrap();
+    let state = auth_url
+        .query_pairs()
+        .find(|(key, _)| key == "state")
+        .map(|(_, value)| value.into_owned())
+        .expect("state parameter");
+
+    (state, cookie)
+}
+
+pub fn assert_login_error(resp: &ServiceResponse, expected: &str) {
+    assert!(resp.status().is_redirection());
+    let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+    assert!(location.contains(&format!("{}/login?error=", config::get_frontend_url())));
+    assert!(
+        location.contains(expected),
+        "expected {:?} in {:?}",
+        expected,
+        location
+    );
+}
+
+pub async fn get_auth_url<S>(app: &S, provider: &str) -> url::Url
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/auth/{}", provider))
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert!(resp.status().is_success());
+
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    url::Url::parse(body["auth_url"].as_str().unwrap()).unwrap()
+}
+
+pub fn query_param(url: &url::Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Points the GitHub and GitLab endpoints at `mock_server`.
+pub fn use_mock_providers(mock_server: &MockServer) {
+    let uri = mock_server.uri();
+    env::set_var("GITHUB_AUTH_URL", format!("{}/login/oauth/authorize", uri));
+    env::set_var(
+        "GITHUB_TOKEN_URL",
+        format!("{}/login/oauth/access_token", uri),
+    );
+    env::set_var("GITHUB_API_URL", format!("{}/user", uri));
+    env::set_var("GITLAB_AUTH_URL", format!("{}/oauth/authorize", uri));
+    env::set_var("GITLAB_TOKEN_URL", format!("{}/oauth/token", uri));
+    env::set_var("GITLAB_API_URL", format!("{}/api/v4/user", uri));
+}
+
+/// Makes the provider authorize the account `id`.
+pub async fn mock_account(mock_server: &MockServer, provider: &str, id: i64, username: &str) {
+    let (token_path, user_path, profile) = match provider {
+        "github" => (
+            "/login/oauth/access_token",
+            "/user",
+            json!({ "id": id, "login": username }),
+        ),
+        "gitlab" => (
+            "/oauth/token",
+            "/api/v4/user",
+            json!({ "id": id, "username": username }),
+        ),
+        _ => unreachable!("no mock for {}", provider),
+    };
+
+    Mock::given(method("POST"))
+        .and(path(token_path))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": format!("{}_token_{}", provider, id),
+            "token_type": "bearer"
+        })))
+        .mount(mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(user_path))
+        .respond_with(ResponseTemplate::new(200).set_body_json(profile))
+        .mount(mock_server)
+        .await;
+}
+
+/// Cookie set by `resp`, or `cookie` if the session cookie was not reissued.
+pub fn session_cookie(resp: &ServiceResponse, cookie: Cookie<'static>) -> Cookie<'static> {
+    resp.response()
+        .cookies()
+        .next()
+        .map(|cookie| cookie.into_owned())
+        .unwrap_or(cookie)
+}
+
+pub fn location(resp: &ServiceResponse) -> String {
+    assert!(resp.status().is_redirection(), "{:?}", resp.status());
+    resp.headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+pub async fn sign_in<S>(app: &S, provider: &str) -> Cookie<'static>
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let (state, cookie) = start_auth(app, provider).await;
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/api/auth/{}/callback?code=test_code&state={}",
+            provider, state
+        ))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert!(location(&resp).ends_with("/dashboard"));
+    session_cookie(&resp, cookie)
+}
+
+/// Signs in as the GitHub account `id`, replacing earlier mocked accounts.
+pub async fn sign_in_as<S>(
+    app: &S,
+    mock_server: &MockServer,
+    id: i64,
+    username: &str,
+) -> Cookie<'static>
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    mock_server.reset().await;
+    mock_account(mock_server, "github", id, username).await;
+    sign_in(app, "github").await
+}
+
+/// The `Authorization` header carrying `token`.
+pub fn bearer(token: &str) -> (&'static str, String) {
+    ("Authorization", format!("Bearer {}", token))
+}
+
+/// An app with a session signed in as the GitHub account `octocat`. The
+/// mock server must outlive the app.
+pub async fn signed_in_app(
+    pool: &SqlitePool,
+) -> (
+    impl actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+    Cookie<'static>,
+    MockServer,
+) {
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in(&app, "github").await;
+    (app, cookie, mock_server)
+}
+
+/// Sends a request with the session `cookie`, and `body` as JSON if given.
+pub async fn call<S>(
+    app: &S,
+    cookie: &Cookie<'static>,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = match method {
+        "GET" => test::TestRequest::get(),
+        "POST" => test::TestRequest::post(),
+        "PUT" => test::TestRequest::put(),
+        "DELETE" => test::TestRequest::delete(),
+        _ => unreachable!(),
+    }
+    .uri(uri)
+    .cookie(cookie.clone());
+    let req = match body {
+        Some(body) => req.set_json(body),
+        None => req,
+    };
+    test::call_service(app, req.to_request()).await
+}
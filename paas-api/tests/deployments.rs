@@ -0,0 +1,387 @@
+mod common;
+
+use actix_web::{http::StatusCode, test::read_body_json};
+use common::{
+    call, keyring, setup_test_app, setup_test_db, setup_test_env, sign_in_as, use_mock_providers,
+};
+use paas_api::{
+    buildpacks::{BuildPlan, Language},
+    deployments::{normalize_commit_sha, DeploymentStatus},
+    AppSettings, Application, Deployment, NewApplication, User,
+};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use wiremock::MockServer;
+
+use DeploymentStatus::*;
+
+const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+/// Every transition the state machine allows; all others must be refused.
+const ALLOWED: &[(DeploymentStatus, DeploymentStatus)] = &[
+    (Queued, Building),
+    (Queued, Failed),
+    (Queued, Cancelled),
+    (Queued, Superseded),
+    (Building, Releasing),
+    (Building, Failed),
+    (Building, Cancelled),
+    (Releasing, Running),
+    (Releasing, Failed),
+    (Running, Failed),
+    (Running, Superseded),
+];
+
+#[test]
+fn test_status_transitions() {
+    for from in DeploymentStatus::ALL {
+        assert_eq!(from.to_string().parse::<DeploymentStatus>().unwrap(), from);
+        for to in DeploymentStatus::ALL {
+            let allowed = ALLOWED.contains(&(from, to));
+            assert_eq!(from.can_transition_to(to), allowed, "{} -> {}", from, to);
+            match from.transition(to) {
+                Ok(status) => assert!(allowed && status == to, "{} -> {}", from, to),
+                Err(e) => {
+                    assert!(!allowed, "{} -> {}", from, to);
+                    assert_eq!(
+                        e.to_string(),
+                        format!(
+                            "Validation error: Cannot move a deployment from {} to {}",
+                            from, to
+                        )
+                    );
+                }
+            }
+        }
+    }
+
+    for status in DeploymentStatus::ALL {
+        let has_exit = DeploymentStatus::ALL
+            .into_iter()
+            .any(|to| status.can_transition_to(to));
+        assert_eq!(status.is_terminal(), !has_exit, "{}", status);
+    }
+    assert!("deployed".parse::<DeploymentStatus>().is_err());
+}
+
+#[test]
+fn test_normalize_commit_sha() {
+    assert_eq!(normalize_commit_sha(&SHA.to_uppercase()).unwrap(), SHA);
+    assert_eq!(
+        normalize_commit_sha(&"a".repeat(64)).unwrap(),
+        "a".repeat(64)
+    );
+    for invalid in [
+        "",
+        "0123456",
+        &SHA[1..],
+        &format!("{}0", SHA),
+        &"g".repeat(40),
+    ] {
+        assert!(normalize_commit_sha(invalid).is_err(), "{}", invalid);
+    }
+}
+
+async fn create_app(pool: &SqlitePool) -> (User, Application) {
+    let user = User::find_or_create(pool, &keyring(), "github", "1", "alice", None, None)
+        .await
+        .unwrap();
+    let app = Application::create(
+        pool,
+        &NewApplication {
+            slug: "shop".to_string(),
+            name: "Shop".to_string(),
+            user_id: user.id,
+            organization_id: None,
+            settings: AppSettings::default(),
+        },
+    )
+    .await
+    .unwrap();
+    (user, app)
+}
+
+#[actix_web::test]
+async fn test_deployment_lifecycle() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (user, app) = create_app(&pool).await;
+
+    let first = Deployment::create(&pool, app.id, SHA, user.id)
+        .await
+        .unwrap();
+    assert_eq!(first.status, Queued);
+    assert_eq!(first.triggered_by, Some(user.id));
+
+    let building = first.transition(&pool, Building).await.unwrap();
+    assert!(building.building_at.is_some());
+    // The copy read before is stale, so it cannot move on.
+    let stale = first.transition(&pool, Building).await.unwrap_err();
+    assert_eq!(
+        stale.to_string(),
+        "Validation error: Cannot move a deployment from building to building"
+    );
+    let illegal = building.transition(&pool, Running).await.unwrap_err();
+    assert_eq!(
+        illegal.to_string(),
+        "Validation error: Cannot move a deployment from building to running"
+    );
+
+    let releasing = building.transition(&pool, Releasing).await.unwrap();
+    let running = releasing.transition(&pool, Running).await.unwrap();
+    assert!(running.releasing_at.is_some() && running.running_at.is_some());
+    assert!(running.finished_at.is_none());
+
+    // A newer queued deployment supersedes the queued one before it, and the
+    // running one once it runs itself.
+    let queued = Deployment::create(&pool, app.id, SHA, user.id)
+        .await
+        .unwrap();
+    let mut second = Deployment::create(&pool, app.id, SHA, user.id)
+        .await
+        .unwrap();
+    let queued = Deployment::find(&pool, queued.id).await.unwrap().unwrap();
+    assert_eq!(queued.status, Superseded);
+    assert!(queued.finished_at.is_some());
+
+    for status in [Building, Releasing, Running] {
+        second = second.transition(&pool, status).await.unwrap();
+    }
+    let first = Deployment::find(&pool, first.id).await.unwrap().unwrap();
+    assert_eq!(first.status, Superseded);
+    assert!(first.finished_at.is_some());
+
+    let failed = second.fail(&pool, "Out of memory").await.unwrap();
+    assert_eq!(failed.status, Failed);
+    assert_eq!(failed.error.as_deref(), Some("Out of memory"));
+    assert!(failed.fail(&pool, "Again").await.is_err());
+
+    let listed: Vec<i64> = Deployment::list(&pool, app.id, 10)
+        .await
+        .unwrap()
+        .iter()
+        .map(|deployment| deployment.id)
+        .collect();
+    assert_eq!(listed, [second.id, queued.id, first.id]);
+    assert_eq!(Deployment::list(&pool, app.id, 1).await.unwrap().len(), 1);
+
+    // Deployments go with their app.
+    Application::delete(&pool, app.id).await.unwrap();
+    assert!(Deployment::find(&pool, first.id).await.unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn test_build_plan_is_stored() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (user, app) = create_app(&pool).await;
+    let plan = BuildPlan {
+        language: Language::Node,
+        runtime_version: Some("20".to_string()),
+        build_commands: vec!["npm ci".to_string(), "npm run build".to_string()],
+        start_command: Some("npm start".to_string()),
+    };
+
+    let queued = Deployment::create(&pool, app.id, SHA, user.id)
+        .await
+        .unwrap();
+    assert!(queued.build_plan.is_none());
+    let error = queued.set_build_plan(&pool, &plan).await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("Validation error: Deployment {} is not building", queued.id)
+    );
+
+    let building = queued.transition(&pool, Building).await.unwrap();
+    let planned = building.set_build_plan(&pool, &plan).await.unwrap();
+    assert_eq!(planned.build_plan.as_deref(), Some(&plan));
+    let found = Deployment::find(&pool, queued.id).await.unwrap().unwrap();
+    assert_eq!(found.build_plan.as_deref(), Some(&plan));
+
+    let json = serde_json::to_value(&found).unwrap();
+    assert_eq!(
+        json["build_plan"],
+        json!({
+            "language": "node",
+            "runtime_version": "20",
+            "build_commands": ["npm ci", "npm run build"],
+            "start_command": "npm start",
+        })
+    );
+}
+
+#[actix_web::test]
+async fn test_deployment_endpoints() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+
+    let body = json!({ "name": "Shop" });
+    call(&app, &alice, "POST", "/api/apps", Some(body)).await;
+
+    let body = json!({ "commit_sha": SHA.to_uppercase() });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let first: Value = read_body_json(resp).await;
+    assert_eq!(first["commit_sha"], SHA);
+    assert_eq!(first["status"], "queued");
+
+    let body = json!({ "commit_sha": "main" });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let error: Value = read_body_json(resp).await;
+    assert_eq!(error["error"], "Invalid commit SHA: main");
+
+    let body = json!({ "commit_sha": SHA });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let second: Value = read_body_json(resp).await;
+
+    let resp = call(&app, &alice, "GET", "/api/apps/shop/deployments", None).await;
+    let listed: Value = read_body_json(resp).await;
+    assert_eq!(listed[0]["id"], second["id"]);
+    assert_eq!(listed[1]["id"], first["id"]);
+    assert_eq!(listed[1]["status"], "superseded");
+    let resp = call(
+        &app,
+        &alice,
+        "GET",
+        "/api/apps/shop/deployments?limit=0",
+        None,
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let uri = format!("/api/apps/shop/deployments/{}/cancel", second["id"]);
+    let resp = call(&app, &alice, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cancelled: Value = read_body_json(resp).await;
+    assert_eq!(cancelled["status"], "cancelled");
+    assert!(cancelled["finished_at"].is_string());
+    let resp = call(&app, &alice, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let error: Value = read_body_json(resp).await;
+    assert_eq!(
+        error["error"],
+        "Cannot move a deployment from cancelled to cancelled"
+    );
+
+    let uri = format!("/api/apps/shop/deployments/{}", second["id"]);
+    let resp = call(&app, &alice, "GET", &uri, None).await;
+    let fetched: Value = read_body_json(resp).await;
+    assert_eq!(fetched, cancelled);
+
+    // Other users see neither the app nor its deployments, and deployments
+    // are only found under their own app.
+    let resp = call(&app, &bob, "GET", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body = json!({ "commit_sha": SHA });
+    let resp = call(&app, &bob, "POST", "/api/apps/shop/deployments", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    call(
+        &app,
+        &bob,
+        "POST",
+        "/api/apps",
+        Some(json!({ "name": "Blog" })),
+    )
+    .await;
+    let uri = format!("/api/apps/blog/deployments/{}", second["id"]);
+    let resp = call(&app, &bob, "GET", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = call(&app, &alice, "GET", "/api/audit?action=deployment", None).await;
+    let page: Value = read_body_json(resp).await;
+    let actions: Vec<_> = page["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            "deployment.cancel",
+            "deployment.create",
+            "deployment.create"
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn test_deployments_are_queued_with_their_build() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps",
+        Some(json!({ "name": "Shop" })),
+    )
+    .await;
+    let body = json!({ "commit_sha": SHA });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body.clone()),
+    )
+    .await;
+    let first: Value = read_body_json(resp).await;
+
+    // Without its job, the deployment is not created, nor is the queued one
+    // superseded.
+    sqlx::query(
+        "CREATE TRIGGER no_jobs BEFORE INSERT ON jobs BEGIN SELECT RAISE(ABORT, 'no jobs'); END",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let deployments: Vec<(i64, String)> = sqlx::query_as("SELECT id, status FROM deployments")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        deployments,
+        [(first["id"].as_i64().unwrap(), "queued".to_string())]
+    );
+}
@@ -15,6 +15,13 @@ fn test_auth_error_response() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
 
+#[test]
+fn test_forbidden_response() {
+    let error = AppError::Forbidden("Requires the admin role".to_string());
+    let response = error.error_response();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
 #[test]
 fn test_not_found_response() {
     let error = AppError::NotFound("Resource not found".to_string());
@@ -0,0 +1,5 @@
+module example.com/shop
+
+go 1.21
+
+toolchain go1.22.1
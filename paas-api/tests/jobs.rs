@@ -0,0 +1,374 @@
+mod common;
+
+use actix_web::{http::StatusCode, test::read_body_json};
+use async_trait::async_trait;
+use common::{
+    call, keyring, setup_test_app, setup_test_db, setup_test_env, sign_in_as, use_mock_providers,
+};
+use paas_api::{
+    jobs::{backoff_secs, JobHandler, JobStatus, NewJob, Registry, Worker, WorkerPool},
+    AppError, Job, JobConfig, User,
+};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use wiremock::MockServer;
+
+/// Counts its runs, failing the first `failures` of them.
+#[derive(Clone, Default)]
+struct Flaky {
+    runs: Arc<AtomicUsize>,
+    failures: usize,
+}
+
+#[async_trait]
+impl JobHandler for Flaky {
+    async fn run(&self, _: &SqlitePool, job: &Job) -> Result<(), AppError> {
+        let run = self.runs.fetch_add(1, Ordering::SeqCst);
+        if run < self.failures {
+            return Err(AppError::ExternalServiceError(format!(
+                "Attempt {} of job {} failed",
+                job.attempts, job.id
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Runs until the attempt times out.
+struct Hangs;
+
+#[async_trait]
+impl JobHandler for Hangs {
+    async fn run(&self, _: &SqlitePool, _: &Job) -> Result<(), AppError> {
+        std::future::pending().await
+    }
+}
+
+fn worker(pool: &SqlitePool, registry: Registry) -> Worker {
+    Worker::new(pool.clone(), Arc::new(registry), "test", 60)
+}
+
+/// Makes a job waiting for its backoff ready to run.
+async fn make_ready(pool: &SqlitePool, id: i64) {
+    sqlx::query("UPDATE jobs SET run_at = datetime('now') WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// Whether `timestamp` is at least `secs` seconds from now.
+async fn is_later(pool: &SqlitePool, timestamp: &str, secs: i64) -> bool {
+    sqlx::query_scalar("SELECT ? >= datetime('now', ?)")
+        .bind(timestamp)
+        .bind(format!("+{} seconds", secs))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[test]
+fn test_backoff_doubles_up_to_an_hour() {
+    let delays: Vec<i64> = (1..=5).map(backoff_secs).collect();
+    assert_eq!(delays, [10, 20, 40, 80, 160]);
+    assert_eq!(backoff_secs(0), 10);
+    assert_eq!(backoff_secs(9), 2560);
+    assert_eq!(backoff_secs(10), 3600);
+    assert_eq!(backoff_secs(i64::MAX), 3600);
+}
+
+#[actix_web::test]
+async fn test_jobs_are_claimed_by_priority() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+
+    let low = NewJob::new("test", json!({ "n": 1 }))
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    let high = NewJob::new("test", json!({ "n": 2 }))
+        .priority(10)
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    let later = NewJob::new("test", json!({ "n": 3 }))
+        .priority(20)
+        .delay(3600)
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    assert_eq!(low.status, JobStatus::Pending);
+    assert_eq!(low.max_attempts, 5);
+
+    let claimed = Job::claim(&pool, "a", 60).await.unwrap().unwrap();
+    assert_eq!(claimed.id, high.id);
+    assert_eq!(claimed.status, JobStatus::Running);
+    assert_eq!(claimed.attempts, 1);
+    assert_eq!(claimed.locked_by.as_deref(), Some("a"));
+    assert!(is_later(&pool, claimed.locked_until.as_deref().unwrap(), 59).await);
+    let payload: Value = claimed.payload().unwrap();
+    assert_eq!(payload, json!({ "n": 2 }));
+
+    // Claimed and delayed jobs are not ready.
+    let next = Job::claim(&pool, "b", 60).await.unwrap().unwrap();
+    assert_eq!(next.id, low.id);
+    assert!(Job::claim(&pool, "b", 60).await.unwrap().is_none());
+
+    let done = claimed.complete(&pool).await.unwrap().unwrap();
+    assert_eq!(done.status, JobStatus::Succeeded);
+    assert!(done.finished_at.is_some() && done.locked_by.is_none());
+    assert!(claimed.complete(&pool).await.unwrap().is_none());
+
+    make_ready(&pool, later.id).await;
+    let claimed = Job::claim(&pool, "b", 60).await.unwrap().unwrap();
+    assert_eq!(claimed.id, later.id);
+}
+
+#[actix_web::test]
+async fn test_failed_jobs_back_off_then_die() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let flaky = Flaky {
+        failures: 5,
+        ..Flaky::default()
+    };
+    let worker = worker(&pool, Registry::new().register("flaky", flaky.clone()));
+
+    let job = NewJob::new("flaky", json!({}))
+        .max_attempts(3)
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    let failed = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(failed.status, JobStatus::Pending);
+    assert_eq!(failed.attempts, 1);
+    assert_eq!(
+        failed.last_error.as_deref(),
+        Some(format!("External service error: Attempt 1 of job {} failed", job.id).as_str())
+    );
+    assert!(is_later(&pool, &failed.run_at, 9).await);
+    assert!(failed.locked_by.is_none() && failed.finished_at.is_none());
+    // Not again before the backoff is over.
+    assert!(worker.run_next().await.unwrap().is_none());
+
+    make_ready(&pool, job.id).await;
+    let failed = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(failed.attempts, 2);
+    assert!(is_later(&pool, &failed.run_at, 19).await);
+
+    make_ready(&pool, job.id).await;
+    let dead = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(dead.status, JobStatus::Dead);
+    assert_eq!(dead.attempts, 3);
+    assert!(dead.finished_at.is_some());
+    assert_eq!(flaky.runs.load(Ordering::SeqCst), 3);
+    assert!(worker.run_next().await.unwrap().is_none());
+
+    // Jobs nobody handles die right away.
+    NewJob::new("unknown", json!({}))
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    let dead = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(dead.status, JobStatus::Dead);
+    assert_eq!(dead.attempts, 1);
+    assert_eq!(
+        dead.last_error.as_deref(),
+        Some("No handler for job kind unknown")
+    );
+
+    // Retried dead jobs get their attempts back.
+    let retried = Job::retry(&pool, job.id).await.unwrap().unwrap();
+    assert_eq!(retried.status, JobStatus::Pending);
+    assert_eq!(retried.attempts, 0);
+    assert!(retried.finished_at.is_none());
+    let failed = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(failed.status, JobStatus::Pending);
+    assert_eq!(failed.attempts, 1);
+}
+
+#[actix_web::test]
+async fn test_expired_claims_are_taken_over() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+
+    let job = NewJob::new("test", json!({}))
+        .max_attempts(2)
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    // A worker that dies while running the job never finishes it.
+    let lost = Job::claim(&pool, "a", 0).await.unwrap().unwrap();
+    let taken = Job::claim(&pool, "b", 0).await.unwrap().unwrap();
+    assert_eq!(taken.id, job.id);
+    assert_eq!(taken.attempts, 2);
+    assert_eq!(taken.locked_by.as_deref(), Some("b"));
+    assert_eq!(taken.last_error.as_deref(), Some("Timed out"));
+    assert!(lost.complete(&pool).await.unwrap().is_none());
+    assert!(lost.fail(&pool, "late", Some(10)).await.unwrap().is_none());
+
+    // Out of attempts, it dies instead of being claimed again.
+    assert!(Job::claim(&pool, "c", 60).await.unwrap().is_none());
+    let dead = Job::find(&pool, job.id).await.unwrap().unwrap();
+    assert_eq!(dead.status, JobStatus::Dead);
+    assert_eq!(dead.last_error.as_deref(), Some("Timed out"));
+    assert!(taken.complete(&pool).await.unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn test_attempts_time_out_before_their_claim_expires() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let registry = Registry::new().register("hangs", Hangs);
+    let slow = Worker::new(pool.clone(), Arc::new(registry), "slow", 4);
+    let job = NewJob::new("hangs", json!({}))
+        .enqueue(&pool)
+        .await
+        .unwrap();
+
+    // Another worker looking for jobs meanwhile never gets this one, which
+    // fails with backoff rather than being taken over.
+    let finished = AtomicBool::new(false);
+    let (attempt, taken) = tokio::join!(
+        async {
+            let attempt = slow.run_next().await;
+            finished.store(true, Ordering::SeqCst);
+            attempt
+        },
+        async {
+            while !finished.load(Ordering::SeqCst) {
+                if let Some(taken) = Job::claim(&pool, "other", 60).await.unwrap() {
+                    return Some(taken);
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            None
+        }
+    );
+    assert!(taken.is_none());
+    let attempt = attempt.unwrap().unwrap();
+    assert_eq!(attempt.id, job.id);
+    assert_eq!(attempt.status, JobStatus::Pending);
+    assert_eq!(attempt.attempts, 1);
+    assert_eq!(
+        attempt.last_error.as_deref(),
+        Some("External service error: Timed out after 2 seconds")
+    );
+}
+
+#[actix_web::test]
+async fn test_worker_pool_runs_jobs_until_shut_down() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let flaky = Flaky::default();
+    let registry = Registry::new().register("flaky", flaky.clone());
+    let config = JobConfig {
+        workers: 2,
+        poll_interval: actix_web::cookie::time::Duration::milliseconds(10),
+        visibility_timeout: actix_web::cookie::time::Duration::seconds(60),
+    };
+
+    let workers = WorkerPool::spawn(pool.clone(), registry, &config);
+    for _ in 0..3 {
+        NewJob::new("flaky", json!({}))
+            .enqueue(&pool)
+            .await
+            .unwrap();
+    }
+    for _ in 0..500 {
+        if flaky.runs.load(Ordering::SeqCst) == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    workers.shutdown().await;
+
+    let jobs = Job::list(&pool, Some(JobStatus::Succeeded), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(jobs.len(), 3);
+    assert_eq!(flaky.runs.load(Ordering::SeqCst), 3);
+}
+
+#[actix_web::test]
+async fn test_admin_job_endpoints() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    let users = User::find_by_username(&pool, &keyring(), "alice", Some("github"))
+        .await
+        .unwrap();
+    User::set_admin(&pool, users[0].id, true).await.unwrap();
+
+    let resp = call(&app, &bob, "GET", "/api/admin/jobs", None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = call(&app, &alice, "GET", "/api/user/me", None).await;
+    let user: Value = read_body_json(resp).await;
+    assert_eq!(user["is_admin"], true);
+
+    let dead = NewJob::new("unknown", json!({ "n": 1 }))
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    worker(&pool, Registry::new()).run_next().await.unwrap();
+    let pending = NewJob::new("other", json!({}))
+        .delay(60)
+        .enqueue(&pool)
+        .await
+        .unwrap();
+
+    let resp = call(&app, &alice, "GET", "/api/admin/jobs", None).await;
+    let jobs: Value = read_body_json(resp).await;
+    assert_eq!(jobs[0]["id"], pending.id);
+    assert_eq!(jobs[1]["id"], dead.id);
+    assert_eq!(jobs[1]["payload"], json!({ "n": 1 }));
+    let resp = call(&app, &alice, "GET", "/api/admin/jobs?status=dead", None).await;
+    let jobs: Value = read_body_json(resp).await;
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    assert_eq!(jobs[0]["status"], "dead");
+    let resp = call(&app, &alice, "GET", "/api/admin/jobs?kind=other", None).await;
+    let jobs: Value = read_body_json(resp).await;
+    assert_eq!(jobs[0]["id"], pending.id);
+    let resp = call(&app, &alice, "GET", "/api/admin/jobs?status=lost", None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let uri = format!("/api/admin/jobs/{}/retry", dead.id);
+    let resp = call(&app, &bob, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = call(&app, &alice, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let retried: Value = read_body_json(resp).await;
+    assert_eq!(retried["status"], "pending");
+    assert_eq!(retried["attempts"], 0);
+
+    Job::claim(&pool, "a", 60).await.unwrap();
+    let resp = call(&app, &alice, "POST", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let error: Value = read_body_json(resp).await;
+    assert_eq!(
+        error["error"],
+        format!("Job {} is running and cannot be retried", dead.id)
+    );
+
+    let uri = format!("/api/admin/jobs/{}", dead.id);
+    let resp = call(&app, &alice, "GET", &uri, None).await;
+    let job: Value = read_body_json(resp).await;
+    assert_eq!(job["locked_by"], "a");
+    let resp = call(&app, &alice, "GET", "/api/admin/jobs/999", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = call(&app, &alice, "GET", "/api/audit?action=job.retry", None).await;
+    let page: Value = read_body_json(resp).await;
+    assert_eq!(page["events"][0]["target_id"], dead.id.to_string());
+}
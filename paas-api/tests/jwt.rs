@@ -0,0 +1,330 @@
+mod common;
+
+use actix_web::{cookie::time::Duration, dev::ServiceResponse, http::StatusCode, test, Error};
+use common::{bearer, setup_test_db, setup_test_env, signed_in_app, test_jwt_config};
+use paas_api::{api_tokens::Scope, config::JwtConfig, jwt};
+use serde_json::{json, Value};
+use std::env;
+
+async fn refresh<S>(app: &S, refresh_token: &str) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .set_json(json!({ "refresh_token": refresh_token }))
+        .to_request();
+    test::call_service(app, req).await
+}
+
+async fn get_me<S>(app: &S, access_token: &str) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .insert_header(bearer(access_token))
+        .to_request();
+    test::call_service(app, req).await
+}
+
+#[actix_web::test]
+async fn test_exchange_session_for_access_token() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["expires_in"], 900);
+    assert_eq!(body["scope"], "read write");
+    assert!(body["refresh_token"]
+        .as_str()
+        .unwrap()
+        .starts_with("paasr_"));
+
+    let access_token = body["access_token"].as_str().unwrap();
+    let claims = jwt::verify(&test_jwt_config(), access_token).unwrap();
+    assert_eq!(claims.aud, "paas-api");
+    assert_eq!(claims.iss, "http://127.0.0.1:3000");
+    assert_eq!(claims.scopes(), vec![Scope::Read, Scope::Write]);
+
+    let resp = get_me(&app, access_token).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let me: Value = test::read_body_json(resp).await;
+    assert_eq!(me["username"], "octocat");
+    assert_eq!(me["id"].as_i64().unwrap(), claims.user_id().unwrap());
+
+    // Access tokens cannot be exchanged for more tokens, nor mint API tokens.
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .insert_header(bearer(access_token))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    let req = test::TestRequest::post()
+        .uri("/api/user/tokens")
+        .insert_header(bearer(access_token))
+        .set_json(json!({ "name": "ci", "scopes": ["read"] }))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Without credentials there is nothing to exchange.
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn test_refresh_tokens_rotate_and_reuse_revokes_the_family() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .cookie(cookie)
+        .to_request();
+    let first: Value = test::call_and_read_body_json(&app, req).await;
+    let first_refresh = first["refresh_token"].as_str().unwrap();
+
+    let resp = refresh(&app, first_refresh).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let second: Value = test::read_body_json(resp).await;
+    let second_refresh = second["refresh_token"].as_str().unwrap();
+    assert_ne!(second_refresh, first_refresh);
+    assert_eq!(second["scope"], "read write");
+    assert_eq!(
+        get_me(&app, second["access_token"].as_str().unwrap())
+            .await
+            .status(),
+        StatusCode::OK
+    );
+
+    // Replaying the redeemed token revokes its replacement as well.
+    let resp = refresh(&app, first_refresh).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Invalid refresh token");
+    assert_eq!(
+        refresh(&app, second_refresh).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let active: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM refresh_tokens WHERE revoked_at IS NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(active, 0);
+
+    // Refresh tokens are not bearer tokens.
+    let resp = get_me(&app, second_refresh).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_logout_revokes_the_sessions_refresh_tokens() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .cookie(cookie.clone())
+        .to_request();
+    let first: Value = test::call_and_read_body_json(&app, req).await;
+    let resp = refresh(&app, first["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let second: Value = test::read_body_json(resp).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/logout")
+        .cookie(cookie)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // The refreshed token belongs to the same family, so it ends too.
+    let resp = refresh(&app, second["refresh_token"].as_str().unwrap()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_invalid_access_tokens_are_rejected() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, _cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let config = test_jwt_config();
+    let expired = JwtConfig {
+        ttl: Duration::seconds(-120),
+        ..config.clone()
+    };
+    let resp = get_me(&app, &jwt::issue(&expired, 1, &[Scope::Read]).unwrap()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Access token has expired");
+
+    let foreign_audience = JwtConfig {
+        audience: "another-api".to_string(),
+        ..config.clone()
+    };
+    let foreign_issuer = JwtConfig {
+        issuer: "https://elsewhere.example.com".to_string(),
+        ..config.clone()
+    };
+    let unknown_key = JwtConfig {
+        keys: vec![("test".to_string(), b"some-other-signing-secret".to_vec())],
+        ..config.clone()
+    };
+    let mut tampered = jwt::issue(&config, 1, &[Scope::Read]).unwrap();
+    let payload_end = tampered.rfind('.').unwrap();
+    tampered.replace_range(payload_end - 4..payload_end, "AAAA");
+
+    for token in [
+        jwt::issue(&foreign_audience, 1, &[Scope::Read]).unwrap(),
+        jwt::issue(&foreign_issuer, 1, &[Scope::Read]).unwrap(),
+        jwt::issue(&unknown_key, 1, &[Scope::Read]).unwrap(),
+        tampered,
+        "not.a.jwt".to_string(),
+    ] {
+        let resp = get_me(&app, &token).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", token);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Invalid access token");
+    }
+}
+
+#[actix_web::test]
+async fn test_signing_key_rotation() {
+    let old = JwtConfig {
+        keys: vec![("2024".to_string(), b"old-jwt-signing-secret".to_vec())],
+        ..test_jwt_config()
+    };
+    let rotated = JwtConfig {
+        keys: vec![
+            ("2025".to_string(), b"new-jwt-signing-secret".to_vec()),
+            ("2024".to_string(), b"old-jwt-signing-secret".to_vec()),
+        ],
+        ..test_jwt_config()
+    };
+    let retired = JwtConfig {
+        keys: vec![("2025".to_string(), b"new-jwt-signing-secret".to_vec())],
+        ..test_jwt_config()
+    };
+
+    // Tokens signed before the rotation stay valid while the old key is listed.
+    let token = jwt::issue(&old, 7, &[Scope::Read]).unwrap();
+    assert_eq!(jwt::verify(&rotated, &token).unwrap().user_id().unwrap(), 7);
+    assert!(jwt::verify(&retired, &token).is_err());
+
+    // New tokens are signed with the first key.
+    let token = jwt::issue(&rotated, 7, &[Scope::Read]).unwrap();
+    let header = jsonwebtoken::decode_header(&token).unwrap();
+    assert_eq!(header.kid.as_deref(), Some("2025"));
+    assert!(jwt::verify(&retired, &token).is_ok());
+    assert!(jwt::verify(&old, &token).is_err());
+}
+
+#[actix_web::test]
+async fn test_api_token_exchange_keeps_its_scopes() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let (app, cookie, _mock_server) = signed_in_app(&pool).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/user/tokens")
+        .cookie(cookie.clone())
+        .set_json(json!({ "name": "ci", "scopes": ["read"] }))
+        .to_request();
+    let api_token: Value = test::call_and_read_body_json(&app, req).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .insert_header(bearer(api_token["token"].as_str().unwrap()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["scope"], "read");
+    let access_token = body["access_token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+
+    assert_eq!(get_me(&app, access_token).await.status(), StatusCode::OK);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/tokens/{}", api_token["id"]))
+        .insert_header(bearer(access_token))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Access token is missing the write scope");
+
+    // Revoking the API token ends its refresh tokens.
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/tokens/{}", api_token["id"]))
+        .cookie(cookie)
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        refresh(&app, refresh_token).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn test_jwt_config_from_env() {
+    let _env = setup_test_env().await;
+    env::set_var("JWT_SECRET", "test-jwt-secret-key");
+
+    let config = JwtConfig::from_env().unwrap();
+    assert_eq!(config.keys.len(), 1);
+    assert_eq!(config.keys[0].0, "default");
+    assert_eq!(config.audience, "paas-api");
+    assert_eq!(config.issuer, "http://127.0.0.1:3000");
+
+    // JWT_KEYS takes precedence, newest key first.
+    env::set_var(
+        "JWT_KEYS",
+        "2025:new-jwt-signing-secret, 2024:old-jwt-signing-secret",
+    );
+    env::set_var("JWT_AUDIENCE", "deployments");
+    env::set_var("JWT_TTL_SECS", "60");
+    let config = JwtConfig::from_env().unwrap();
+    let kids: Vec<&str> = config.keys.iter().map(|(kid, _)| kid.as_str()).collect();
+    assert_eq!(kids, ["2025", "2024"]);
+    assert_eq!(config.audience, "deployments");
+    assert_eq!(config.ttl, Duration::seconds(60));
+
+    for keys in [
+        "2025:too-short",
+        "no-key-id",
+        ":missing-key-id-secret",
+        "2025:new-jwt-signing-secret,2025:old-jwt-signing-secret",
+    ] {
+        env::set_var("JWT_KEYS", keys);
+        assert!(JwtConfig::from_env().is_err(), "{}", keys);
+    }
+
+    env::remove_var("JWT_KEYS");
+    env::remove_var("JWT_SECRET");
+    assert!(JwtConfig::from_env().is_err());
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use actix_web::{cookie::Cookie, dev::ServiceResponse, http::StatusCode, test, Error};
+use common::{
+    keyring, location, mock_account, session_cookie, setup_test_app, setup_test_db, setup_test_env,
+    sign_in, use_mock_providers,
+};
+use paas_api::{config, models::User};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use wiremock::MockServer;
+
+/// Runs the connect flow for `provider` and returns the callback response.
+async fn connect<S>(app: &S, cookie: &Cookie<'static>, provider: &str) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/user/providers/{}/connect", provider))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert!(resp.status().is_success());
+    let cookie = session_cookie(&resp, cookie.clone());
+    let body: Value = test::read_body_json(resp).await;
+    let auth_url = url::Url::parse(body["auth_url"].as_str().unwrap()).unwrap();
+    let state = common::query_param(&auth_url, "state").expect("state parameter");
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/api/auth/{}/callback?code=test_code&state={}",
+            provider, state
+        ))
+        .cookie(cookie)
+        .to_request();
+    test::call_service(app, req).await
+}
+
+async fn connected_providers<S>(app: &S, cookie: &Cookie<'static>) -> Vec<Value>
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri("/api/user/providers")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert!(resp.status().is_success());
+    test::read_body_json(resp).await
+}
+
+async fn disconnect<S>(app: &S, cookie: &Cookie<'static>, provider: &str) -> StatusCode
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/user/providers/{}", provider))
+        .cookie(cookie.clone())
+        .to_request();
+    test::call_service(app, req).await.status()
+}
+
+async fn current_user_id<S>(app: &S, cookie: &Cookie<'static>) -> i64
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie.clone())
+        .to_request();
+    let body: Value = test::call_and_read_body_json(app, req).await;
+    body["id"].as_i64().unwrap()
+}
+
+async fn user_count(pool: &SqlitePool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_connect_another_provider() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+    mock_account(&mock_server, "gitlab", 42, "tanuki").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    let cookie = sign_in(&app, "github").await;
+    let user_id = current_user_id(&app, &cookie).await;
+
+    let resp = connect(&app, &cookie, "gitlab").await;
+    assert_eq!(
+        location(&resp),
+        format!("{}/settings?connected=gitlab", config::get_frontend_url())
+    );
+
+    let providers = connected_providers(&app, &cookie).await;
+    assert_eq!(providers.len(), 2);
+    assert_eq!(providers[0]["provider"], "github");
+    assert_eq!(providers[0]["primary"], true);
+    assert_eq!(providers[1]["provider"], "gitlab");
+    assert_eq!(providers[1]["provider_type"], "gitlab");
+    assert_eq!(providers[1]["display_name"], "GitLab");
+    assert_eq!(providers[1]["primary"], false);
+    assert_eq!(providers[1]["needs_reauth"], false);
+    assert!(providers[1].get("access_token").is_none());
+
+    // Either provider now signs in to the same account.
+    let cookie = sign_in(&app, "gitlab").await;
+    assert_eq!(current_user_id(&app, &cookie).await, user_id);
+    assert_eq!(user_count(&pool).await, 1);
+}
+
+#[actix_web::test]
+async fn test_provider_endpoints_require_sign_in() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    for req in [
+        test::TestRequest::get().uri("/api/user/providers"),
+        test::TestRequest::get().uri("/api/user/providers/gitlab/connect"),
+        test::TestRequest::delete().uri("/api/user/providers/gitlab"),
+    ] {
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[actix_web::test]
+async fn test_account_of_another_user_cannot_be_connected() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+    mock_account(&mock_server, "gitlab", 42, "tanuki").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    sign_in(&app, "gitlab").await;
+    let cookie = sign_in(&app, "github").await;
+
+    let resp = connect(&app, &cookie, "gitlab").await;
+    let location = location(&resp);
+    assert!(location.starts_with(&format!("{}/settings?error=", config::get_frontend_url())));
+    assert!(location.contains("already+linked"), "{}", location);
+    assert_eq!(connected_providers(&app, &cookie).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn test_connecting_a_second_account_requires_disconnecting_the_first() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+    mock_account(&mock_server, "gitlab", 42, "tanuki").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    let cookie = sign_in(&app, "github").await;
+    assert!(location(&connect(&app, &cookie, "gitlab").await).contains("connected=gitlab"));
+    // Reconnecting the same account just replaces its tokens.
+    assert!(location(&connect(&app, &cookie, "gitlab").await).contains("connected=gitlab"));
+
+    mock_server.reset().await;
+    mock_account(&mock_server, "gitlab", 43, "other").await;
+    let resp = connect(&app, &cookie, "gitlab").await;
+    assert!(location(&resp).contains("Disconnect+your+current+GitLab+account"));
+
+    assert_eq!(
+        disconnect(&app, &cookie, "gitlab").await,
+        StatusCode::NO_CONTENT
+    );
+    assert!(location(&connect(&app, &cookie, "gitlab").await).contains("connected=gitlab"));
+}
+
+#[actix_web::test]
+async fn test_disconnect_provider() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+    mock_account(&mock_server, "gitlab", 42, "tanuki").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    let cookie = sign_in(&app, "github").await;
+    let user_id = current_user_id(&app, &cookie).await;
+    connect(&app, &cookie, "gitlab").await;
+
+    assert_eq!(
+        disconnect(&app, &cookie, "gitlab").await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        disconnect(&app, &cookie, "gitlab").await,
+        StatusCode::NOT_FOUND
+    );
+    // The last provider to sign in with cannot be disconnected.
+    assert_eq!(
+        disconnect(&app, &cookie, "github").await,
+        StatusCode::BAD_REQUEST
+    );
+
+    // Disconnecting the provider the user signed up with moves sign-in to
+    // the remaining one.
+    connect(&app, &cookie, "gitlab").await;
+    assert_eq!(
+        disconnect(&app, &cookie, "github").await,
+        StatusCode::NO_CONTENT
+    );
+
+    let providers = connected_providers(&app, &cookie).await;
+    assert_eq!(providers.len(), 1);
+    assert_eq!(providers[0]["provider"], "gitlab");
+    assert_eq!(providers[0]["primary"], true);
+
+    let user = User::find(&pool, &keyring(), user_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user.provider, "gitlab");
+    assert_eq!(user.provider_user_id, "42");
+
+    let cookie = sign_in(&app, "github").await;
+    assert_ne!(current_user_id(&app, &cookie).await, user_id);
+    assert_eq!(user_count(&pool).await, 2);
+}
+
+#[actix_web::test]
+async fn test_denied_connect_returns_to_settings() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    let cookie = sign_in(&app, "github").await;
+    let req = test::TestRequest::get()
+        .uri("/api/user/providers/gitlab/connect")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let cookie = session_cookie(&resp, cookie);
+    let body: Value = test::read_body_json(resp).await;
+    let auth_url = url::Url::parse(body["auth_url"].as_str().unwrap()).unwrap();
+    let state = common::query_param(&auth_url, "state").unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/api/auth/gitlab/callback?error=access_denied&error_description=The+user+has+denied+access&state={}",
+            state
+        ))
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let location = location(&resp);
+    assert!(location.starts_with(&format!("{}/settings?error=", config::get_frontend_url())));
+    assert!(location.contains("denied"));
+}
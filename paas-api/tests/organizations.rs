@@ -0,0 +1,405 @@
+mod common;
+
+use actix_web::{cookie::Cookie, dev::ServiceResponse, http::StatusCode, test, Error};
+use common::{call, setup_test_app, setup_test_db, setup_test_env, sign_in_as, use_mock_providers};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use wiremock::MockServer;
+
+async fn user_id(pool: &SqlitePool, username: &str) -> i64 {
+    sqlx::query_scalar("SELECT id FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// Invites `invitee` to `org` as `role` and returns the invitation id.
+async fn invite<S>(app: &S, cookie: &Cookie<'static>, org: &str, invitee: &str, role: &str) -> i64
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    let resp = call(
+        app,
+        cookie,
+        "POST",
+        &format!("/api/orgs/{}/invitations", org),
+        Some(json!({ "invitee": invitee, "role": role })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(resp).await;
+    body["id"].as_i64().unwrap()
+}
+
+async fn accept<S>(app: &S, cookie: &Cookie<'static>, id: i64) -> ServiceResponse
+where
+    S: actix_web::dev::Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+{
+    call(
+        app,
+        cookie,
+        "POST",
+        &format!("/api/orgs/invitations/{}/accept", id),
+        None,
+    )
+    .await
+}
+
+#[actix_web::test]
+async fn test_create_organization() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "  Acme Labs!  " })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let org: Value = test::read_body_json(resp).await;
+    assert_eq!(org["slug"], "acme-labs");
+    assert_eq!(org["name"], "Acme Labs!");
+    assert_eq!(org["role"], "owner");
+
+    let orgs: Vec<Value> =
+        test::read_body_json(call(&app, &alice, "GET", "/api/orgs", None).await).await;
+    assert_eq!(orgs.len(), 1);
+    assert_eq!(orgs[0]["slug"], "acme-labs");
+
+    let members: Vec<Value> =
+        test::read_body_json(call(&app, &alice, "GET", "/api/orgs/acme-labs/members", None).await)
+            .await;
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0]["username"], "alice");
+    assert_eq!(members[0]["role"], "owner");
+
+    for body in [
+        json!({ "name": "" }),
+        json!({ "name": "Acme", "slug": "acme-labs" }),
+        json!({ "name": "Acme", "slug": "Acme_Labs" }),
+        json!({ "name": "Acme", "slug": "invitations" }),
+        json!({ "name": "!!!" }),
+    ] {
+        let resp = call(&app, &alice, "POST", "/api/orgs", Some(body.clone())).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    // Outsiders cannot tell the organization exists.
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    for (method, uri) in [
+        ("GET", "/api/orgs/acme-labs"),
+        ("GET", "/api/orgs/acme-labs/members"),
+        ("DELETE", "/api/orgs/acme-labs"),
+    ] {
+        let resp = call(&app, &bob, method, uri, None).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{} {}", method, uri);
+    }
+}
+
+#[actix_web::test]
+async fn test_invite_by_username() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "Acme" })),
+    )
+    .await;
+
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs/acme/invitations",
+        Some(json!({ "invitee": "nobody", "role": "viewer" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let id = invite(&app, &alice, "acme", "BOB", "developer").await;
+    let pending: Vec<Value> =
+        test::read_body_json(call(&app, &alice, "GET", "/api/orgs/acme/invitations", None).await)
+            .await;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["invitee"], "bob");
+
+    // Only bob sees the invitation, and only bob can accept it.
+    let mine: Vec<Value> =
+        test::read_body_json(call(&app, &alice, "GET", "/api/orgs/invitations", None).await).await;
+    assert!(mine.is_empty());
+    assert_eq!(
+        accept(&app, &alice, id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let mine: Vec<Value> =
+        test::read_body_json(call(&app, &bob, "GET", "/api/orgs/invitations", None).await).await;
+    assert_eq!(mine.len(), 1);
+    assert_eq!(mine[0]["organization"], "acme");
+    assert_eq!(mine[0]["role"], "developer");
+    assert_eq!(mine[0]["invited_by"], "alice");
+
+    let resp = accept(&app, &bob, id).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let org: Value = test::read_body_json(resp).await;
+    assert_eq!(org["slug"], "acme");
+    assert_eq!(org["role"], "developer");
+    assert_eq!(accept(&app, &bob, id).await.status(), StatusCode::NOT_FOUND);
+
+    let members: Vec<Value> =
+        test::read_body_json(call(&app, &bob, "GET", "/api/orgs/acme/members", None).await).await;
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[1]["username"], "bob");
+
+    // Members are not invited again.
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs/acme/invitations",
+        Some(json!({ "invitee": "bob", "role": "admin" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_invite_by_email() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "Acme" })),
+    )
+    .await;
+
+    // The invitee has not signed up yet.
+    let id = invite(&app, &alice, "acme", "Carol@Example.com", "viewer").await;
+    let carol = sign_in_as(&app, &mock_server, 3, "carol").await;
+    let mallory = sign_in_as(&app, &mock_server, 4, "mallory").await;
+    // Only verified addresses match; anyone can claim an unverified one.
+    sqlx::query(
+        "UPDATE users SET email = 'carol@example.com', email_verified = 1 WHERE username = 'carol'",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("UPDATE users SET email = 'carol@example.com' WHERE username = 'mallory'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        accept(&app, &mallory, id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let resp = accept(&app, &carol, id).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let org: Value = test::read_body_json(resp).await;
+    assert_eq!(org["role"], "viewer");
+
+    // Declining removes the invitation; the organization can also withdraw one.
+    let id = invite(&app, &alice, "acme", "mallory", "viewer").await;
+    let resp = call(
+        &app,
+        &mallory,
+        "DELETE",
+        &format!("/api/orgs/invitations/{}", id),
+        None,
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        accept(&app, &mallory, id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let id = invite(&app, &alice, "acme", "dave@example.com", "viewer").await;
+    let uri = format!("/api/orgs/acme/invitations/{}", id);
+    let resp = call(&app, &alice, "DELETE", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = call(&app, &alice, "DELETE", &uri, None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_roles_limit_member_management() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let bob = sign_in_as(&app, &mock_server, 2, "bob").await;
+    let carol = sign_in_as(&app, &mock_server, 3, "carol").await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "Acme" })),
+    )
+    .await;
+    let id = invite(&app, &alice, "acme", "bob", "admin").await;
+    accept(&app, &bob, id).await;
+    let id = invite(&app, &alice, "acme", "carol", "developer").await;
+    accept(&app, &carol, id).await;
+    let alice_id = user_id(&pool, "alice").await;
+    let bob_id = user_id(&pool, "bob").await;
+    let carol_id = user_id(&pool, "carol").await;
+
+    // Developers manage nobody; admins manage everyone but owners.
+    let resp = call(
+        &app,
+        &carol,
+        "POST",
+        "/api/orgs/acme/invitations",
+        Some(json!({ "invitee": "dave@example.com", "role": "viewer" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = call(&app, &carol, "GET", "/api/orgs/acme/invitations", None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    for (method, body) in [("PUT", Some(json!({ "role": "owner" }))), ("DELETE", None)] {
+        let uri = format!("/api/orgs/acme/members/{}", alice_id);
+        let resp = call(&app, &bob, method, &uri, body).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", method);
+    }
+    let resp = call(
+        &app,
+        &bob,
+        "PUT",
+        &format!("/api/orgs/acme/members/{}", carol_id),
+        Some(json!({ "role": "owner" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = call(
+        &app,
+        &bob,
+        "PUT",
+        &format!("/api/orgs/acme/members/{}", carol_id),
+        Some(json!({ "role": "viewer" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let member: Value = test::read_body_json(resp).await;
+    assert_eq!(member["role"], "viewer");
+    let resp = call(&app, &bob, "DELETE", "/api/orgs/acme", None).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // The last owner can neither step down nor leave.
+    let alice_uri = format!("/api/orgs/acme/members/{}", alice_id);
+    let resp = call(
+        &app,
+        &alice,
+        "PUT",
+        &alice_uri,
+        Some(json!({ "role": "admin" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = call(&app, &alice, "DELETE", &alice_uri, None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Once bob is an owner too, alice can leave; members leave freely.
+    let resp = call(
+        &app,
+        &alice,
+        "PUT",
+        &format!("/api/orgs/acme/members/{}", bob_id),
+        Some(json!({ "role": "owner" })),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = call(&app, &alice, "DELETE", &alice_uri, None).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = call(
+        &app,
+        &carol,
+        "DELETE",
+        &format!("/api/orgs/acme/members/{}", carol_id),
+        None,
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let members: Vec<Value> =
+        test::read_body_json(call(&app, &bob, "GET", "/api/orgs/acme/members", None).await).await;
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0]["username"], "bob");
+}
+
+#[actix_web::test]
+async fn test_delete_organization() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/orgs",
+        Some(json!({ "name": "Acme" })),
+    )
+    .await;
+    invite(&app, &alice, "acme", "dave@example.com", "viewer").await;
+
+    // One repository of alice's own and one of the organization's.
+    for organization_id in [None, Some(1)] {
+        sqlx::query(
+            "INSERT INTO repositories (user_id, provider_id, name, url, clone_url, organization_id)
+             SELECT user_id, id, 'site', 'https://github.com/acme/site',
+                    'https://github.com/acme/site.git', ? FROM git_providers",
+        )
+        .bind(organization_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let resp = call(&app, &alice, "DELETE", "/api/orgs/acme", None).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = call(&app, &alice, "GET", "/api/orgs/acme", None).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    for table in ["memberships", "invitations"] {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0, "{}", table);
+    }
+    let repositories: Vec<Option<i64>> =
+        sqlx::query_scalar("SELECT organization_id FROM repositories")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(repositories, vec![None]);
+}
@@ -0,0 +1,453 @@
+mod common;
+
+use actix_web::{http::StatusCode, test::read_body_json};
+use common::{
+    call, job_registry, setup_test_app, setup_test_db, setup_test_env, sign_in_as,
+    use_mock_providers,
+};
+use paas_api::{
+    builders::{Builder, FakeBuilder},
+    buildpacks::Language,
+    deployments::DeploymentStatus,
+    git::SourceCache,
+    jobs::{JobStatus, Worker},
+    AppSettings, Application, Deployment, Job, NewApplication, NewRepository, Organization,
+    Repository, Visibility,
+};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::{fs, path::Path, process::Command, sync::Arc, time::Duration};
+use tempfile::TempDir;
+use wiremock::MockServer;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(["-c", "init.defaultBranch=main"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {:?}", args);
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// A bare repository holding a Node app under `web/`, and its commit.
+fn remote(dir: &TempDir) -> (String, String) {
+    let work = dir.path().join("work");
+    let bare = dir.path().join("origin.git");
+    git(dir.path(), &["init", "--quiet", "--bare", "origin.git"]);
+    git(dir.path(), &["init", "--quiet", "work"]);
+    fs::create_dir(work.join("web")).unwrap();
+    fs::write(
+        work.join("web/package.json"),
+        r#"{ "scripts": { "build": "tsc", "start": "node dist/index.js" } }"#,
+    )
+    .unwrap();
+    git(&work, &["add", "--all"]);
+    git(&work, &["commit", "--quiet", "-m", "App"]);
+    git(
+        &work,
+        &["push", "--quiet", bare.to_str().unwrap(), "HEAD:main"],
+    );
+    (
+        bare.to_str().unwrap().to_string(),
+        git(&work, &["rev-parse", "HEAD"]),
+    )
+}
+
+/// Alice's app `shop`, built from `clone_url`.
+async fn create_app(pool: &SqlitePool, clone_url: &str) -> Application {
+    let (user_id, provider_id): (i64, i64) =
+        sqlx::query_as("SELECT user_id, id FROM git_providers WHERE provider = 'github'")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+    let repository = Repository::create(
+        pool,
+        &NewRepository {
+            user_id,
+            organization_id: None,
+            provider_id,
+            provider_repo_id: None,
+            name: "alice/shop".to_string(),
+            description: None,
+            url: "https://github.com/alice/shop".to_string(),
+            clone_url: clone_url.to_string(),
+            default_branch: Some("main".to_string()),
+            visibility: Visibility::Private,
+        },
+    )
+    .await
+    .unwrap();
+    Application::create(
+        pool,
+        &NewApplication {
+            slug: "shop".to_string(),
+            name: "Shop".to_string(),
+            user_id,
+            organization_id: None,
+            settings: AppSettings {
+                repository_id: Some(repository.id),
+                root_dir: "web".to_string(),
+                ..AppSettings::default()
+            },
+        },
+    )
+    .await
+    .unwrap()
+}
+
+fn worker(pool: &SqlitePool, sources: &TempDir, builder: Arc<dyn Builder>) -> Worker {
+    let sources = SourceCache::new(sources.path()).protocols(&["file"]);
+    let registry = job_registry(pool, sources, builder);
+    Worker::new(pool.clone(), Arc::new(registry), "test", 60)
+}
+
+fn log_lines(logs: &Value) -> Vec<&str> {
+    logs.as_array()
+        .unwrap()
+        .iter()
+        .map(|line| line["line"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_deployments_are_built_by_a_job() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let dir = TempDir::new().unwrap();
+    let (clone_url, sha) = remote(&dir);
+    create_app(&pool, &clone_url).await;
+    let sources = TempDir::new().unwrap();
+    let builder = Arc::new(FakeBuilder::new());
+    let worker = worker(&pool, &sources, builder.clone());
+
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let deployment: Value = read_body_json(resp).await;
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.kind, "deployment.build");
+    assert_eq!(job.status, JobStatus::Succeeded);
+
+    let uri = format!("/api/apps/shop/deployments/{}", deployment["id"]);
+    let resp = call(&app, &alice, "GET", &uri, None).await;
+    let built: Value = read_body_json(resp).await;
+    assert_eq!(built["status"], "releasing");
+    assert_eq!(built["image"], format!("paas/shop:{}", sha));
+    assert_eq!(built["build_plan"]["language"], "node");
+    assert_eq!(built["build_plan"]["start_command"], "npm start");
+
+    let builds = builder.builds();
+    assert_eq!(builds.len(), 1);
+    assert_eq!(builds[0].commit_sha, sha);
+    assert_eq!(builds[0].plan.language, Language::Node);
+    assert_eq!(
+        builds[0].context,
+        sources.path().join("1/web").canonicalize().unwrap()
+    );
+
+    let resp = call(&app, &alice, "GET", &format!("{}/logs", uri), None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let logs: Value = read_body_json(resp).await;
+    assert_eq!(
+        log_lines(&logs),
+        [
+            format!("Fetching {} of alice/shop", sha).as_str(),
+            "Detected a node app",
+            &format!("Building paas/shop:{}", sha),
+            "$ npm install",
+            "$ npm run build",
+            &format!("Built paas/shop:{}", sha),
+        ]
+    );
+    // Following the log returns the lines written since.
+    let after = logs[3]["id"].as_i64().unwrap();
+    let resp = call(
+        &app,
+        &alice,
+        "GET",
+        &format!("{}/logs?after={}&limit=2", uri, after),
+        None,
+    )
+    .await;
+    let page: Value = read_body_json(resp).await;
+    assert_eq!(
+        log_lines(&page),
+        ["$ npm run build", &format!("Built paas/shop:{}", sha)]
+    );
+    let resp = call(&app, &alice, "GET", &format!("{}/logs?limit=0", uri), None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_failed_builds_fail_the_deployment() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let dir = TempDir::new().unwrap();
+    let (clone_url, sha) = remote(&dir);
+    let shop = create_app(&pool, &clone_url).await;
+    let sources = TempDir::new().unwrap();
+    let worker = worker(
+        &pool,
+        &sources,
+        Arc::new(FakeBuilder::failing("npm ERR! missing script: build")),
+    );
+
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let deployment: Value = read_body_json(resp).await;
+    // The job did its work, so it is not retried.
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.status, JobStatus::Succeeded);
+
+    let id = deployment["id"].as_i64().unwrap();
+    let failed = Deployment::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(failed.status, DeploymentStatus::Failed);
+    assert_eq!(
+        failed.error.as_deref(),
+        Some("External service error: Build failed: npm ERR! missing script: build")
+    );
+    assert!(failed.image.is_none());
+    let uri = format!("/api/apps/shop/deployments/{}/logs", id);
+    let resp = call(&app, &alice, "GET", &uri, None).await;
+    let logs: Value = read_body_json(resp).await;
+    assert_eq!(
+        log_lines(&logs).last().copied(),
+        Some("Error: External service error: Build failed: npm ERR! missing script: build")
+    );
+
+    // Commits the repository does not have fail the same way.
+    let missing = "0123456789abcdef0123456789abcdef01234567";
+    let body = json!({ "commit_sha": missing });
+    call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    worker.run_next().await.unwrap().unwrap();
+    let failed = Deployment::list(&pool, shop.id, 1).await.unwrap().remove(0);
+    assert_eq!(failed.status, DeploymentStatus::Failed);
+    assert!(failed.error.unwrap().contains("not found"));
+
+    // Deployments cancelled before their job runs are left alone.
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let deployment: Value = read_body_json(resp).await;
+    let uri = format!("/api/apps/shop/deployments/{}/cancel", deployment["id"]);
+    call(&app, &alice, "POST", &uri, None).await;
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.status, JobStatus::Succeeded);
+    let id = deployment["id"].as_i64().unwrap();
+    let cancelled = Deployment::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(cancelled.status, DeploymentStatus::Cancelled);
+    assert!(cancelled.building_at.is_none());
+}
+
+#[actix_web::test]
+async fn test_builds_interrupted_by_a_dead_worker_fail() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let dir = TempDir::new().unwrap();
+    let (clone_url, sha) = remote(&dir);
+    create_app(&pool, &clone_url).await;
+    let sources = TempDir::new().unwrap();
+    let builder = Arc::new(FakeBuilder::new());
+    let worker = worker(&pool, &sources, builder.clone());
+
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let deployment: Value = read_body_json(resp).await;
+    let id = deployment["id"].as_i64().unwrap();
+    // A worker claims the job and dies mid-build, its claim expiring at once.
+    Job::claim(&pool, "crashed", 0).await.unwrap().unwrap();
+    Deployment::find(&pool, id)
+        .await
+        .unwrap()
+        .unwrap()
+        .transition(&pool, DeploymentStatus::Building)
+        .await
+        .unwrap();
+
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.attempts, 2);
+    assert_eq!(job.status, JobStatus::Succeeded);
+    assert!(builder.builds().is_empty());
+    let failed = Deployment::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(failed.status, DeploymentStatus::Failed);
+    assert_eq!(
+        failed.error.as_deref(),
+        Some("Build interrupted before it finished; deploy again")
+    );
+}
+
+#[actix_web::test]
+async fn test_cancelling_a_building_deployment_stops_its_build() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let dir = TempDir::new().unwrap();
+    let (clone_url, sha) = remote(&dir);
+    create_app(&pool, &clone_url).await;
+    let sources = TempDir::new().unwrap();
+    let worker = worker(&pool, &sources, Arc::new(FakeBuilder::hanging()));
+
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let deployment: Value = read_body_json(resp).await;
+    let id = deployment["id"].as_i64().unwrap();
+    // Cancelled once the builder has started.
+    let started = "SELECT COUNT(*) FROM deployment_logs
+                   WHERE deployment_id = ? AND line LIKE 'Building %'";
+    let cancel = async {
+        let started = || {
+            sqlx::query_scalar::<_, i64>(started)
+                .bind(id)
+                .fetch_one(&pool)
+        };
+        while started().await.unwrap() == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let uri = format!("/api/apps/shop/deployments/{}/cancel", id);
+        let resp = call(&app, &alice, "POST", &uri, None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    };
+    let (job, ()) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(worker.run_next(), cancel)
+    })
+    .await
+    .expect("the build outlived its cancellation");
+    assert_eq!(job.unwrap().unwrap().status, JobStatus::Succeeded);
+
+    let cancelled = Deployment::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(cancelled.status, DeploymentStatus::Cancelled);
+    assert!(cancelled.error.is_none());
+    let uri = format!("/api/apps/shop/deployments/{}/logs", id);
+    let resp = call(&app, &alice, "GET", &uri, None).await;
+    let logs: Value = read_body_json(resp).await;
+    assert_eq!(
+        log_lines(&logs).last().copied(),
+        Some("Error: External service error: Build cancelled")
+    );
+}
+
+#[actix_web::test]
+async fn test_builds_stop_using_the_tokens_of_members_who_left() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let alice = sign_in_as(&app, &mock_server, 1, "alice").await;
+    let dir = TempDir::new().unwrap();
+    let (clone_url, sha) = remote(&dir);
+    // Acme's app is built from Alice's personal repository.
+    let shop = create_app(&pool, &clone_url).await;
+    let acme = Organization::create(&pool, "acme", "Acme", shop.user_id)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE apps SET organization_id = ? WHERE id = ?")
+        .bind(acme.id)
+        .bind(shop.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let sources = TempDir::new().unwrap();
+    let builder = Arc::new(FakeBuilder::new());
+    let worker = worker(&pool, &sources, builder.clone());
+
+    let body = json!({ "commit_sha": sha });
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body.clone()),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    worker.run_next().await.unwrap().unwrap();
+    assert_eq!(builder.builds().len(), 1);
+
+    // Once she leaves, her token is no longer used for Acme's builds.
+    let resp = call(
+        &app,
+        &alice,
+        "POST",
+        "/api/apps/shop/deployments",
+        Some(body),
+    )
+    .await;
+    let deployment: Value = read_body_json(resp).await;
+    sqlx::query("DELETE FROM memberships WHERE user_id = ?")
+        .bind(shop.user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    worker.run_next().await.unwrap().unwrap();
+    assert_eq!(builder.builds().len(), 1);
+    let id = deployment["id"].as_i64().unwrap();
+    let failed = Deployment::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(failed.status, DeploymentStatus::Failed);
+    assert_eq!(
+        failed.error.as_deref(),
+        Some(
+            "Forbidden: Repository alice/shop was connected by a user who is no longer a member \
+             of the organization"
+        )
+    );
+}
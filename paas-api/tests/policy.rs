@@ -0,0 +1,130 @@
+mod common;
+
+use common::{keyring, setup_test_db, setup_test_env};
+use paas_api::{
+    orgs::{Owner, Role},
+    policy::{self, Action, Resource},
+    AppError, Organization, User,
+};
+
+const ROLES: [Role; 4] = [Role::Viewer, Role::Developer, Role::Admin, Role::Owner];
+
+/// Whether viewers, developers, admins and owners may take each action.
+const MATRIX: &[(&str, [bool; 4])] = &[
+    ("org.read", [true, true, true, true]),
+    ("org.delete", [false, false, false, true]),
+    ("org.member.invite", [false, false, true, true]),
+    ("org.member.update", [false, false, true, true]),
+    ("org.member.remove", [false, false, true, true]),
+    ("repo.read", [true, true, true, true]),
+    ("repo.write", [false, true, true, true]),
+    ("repo.delete", [false, false, true, true]),
+    ("app.read", [true, true, true, true]),
+    ("app.create", [false, true, true, true]),
+    ("app.update", [false, true, true, true]),
+    ("app.delete", [false, false, true, true]),
+    ("app.deploy", [false, true, true, true]),
+    ("app.env.read", [false, true, true, true]),
+    ("app.env.write", [false, true, true, true]),
+    ("audit.read", [false, false, true, true]),
+];
+
+#[test]
+fn test_role_action_matrix() {
+    assert_eq!(MATRIX.len(), Action::ALL.len(), "every action is listed");
+
+    for (name, allowed) in MATRIX {
+        let action: Action = name.parse().unwrap();
+        assert_eq!(action.to_string(), *name);
+        for (role, allowed) in ROLES.into_iter().zip(allowed) {
+            assert_eq!(
+                policy::allows(role, action),
+                *allowed,
+                "{} may {}",
+                role,
+                action
+            );
+            match policy::require(role, action) {
+                Ok(()) => assert!(allowed),
+                Err(AppError::Forbidden(message)) => {
+                    assert!(!allowed);
+                    assert_eq!(
+                        message,
+                        format!("The {} role does not allow {}", role, action)
+                    );
+                }
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    assert!("app.launch".parse::<Action>().is_err());
+}
+
+async fn create_user(pool: &sqlx::SqlitePool, id: &str, username: &str) -> User {
+    User::find_or_create(pool, &keyring(), "github", id, username, None, None)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_can_resolves_the_role_over_a_resource() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let alice = create_user(&pool, "1", "alice").await;
+    let bob = create_user(&pool, "2", "bob").await;
+    let carol = create_user(&pool, "3", "carol").await;
+
+    let org = Organization::create(&pool, "acme", "Acme", alice.id)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO memberships (organization_id, user_id, role) VALUES (?, ?, 'viewer')")
+        .bind(org.id)
+        .bind(bob.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let org_app = Resource::App(Owner::Organization(org.id));
+    let personal_app = Resource::App(Owner::User(alice.id));
+    for (user, action, resource, allowed) in [
+        (&alice, Action::AppDeploy, org_app, true),
+        (
+            &alice,
+            Action::OrgDelete,
+            Resource::Organization(org.id),
+            true,
+        ),
+        (&alice, Action::AppDelete, personal_app, true),
+        (&bob, Action::AppRead, org_app, true),
+        (&bob, Action::AppDeploy, org_app, false),
+        (&bob, Action::AppRead, personal_app, false),
+        (&carol, Action::AppRead, org_app, false),
+        (
+            &carol,
+            Action::OrgRead,
+            Resource::Organization(org.id),
+            false,
+        ),
+    ] {
+        let can = policy::can(&pool, user, action, resource).await.unwrap();
+        assert_eq!(can, allowed, "{} {} {:?}", user.username, action, resource);
+    }
+
+    let role = policy::authorize(&pool, &bob, Action::AppRead, org_app)
+        .await
+        .unwrap();
+    assert_eq!(role, Role::Viewer);
+    match policy::authorize(&pool, &bob, Action::AppEnvWrite, org_app).await {
+        Err(AppError::Forbidden(message)) => {
+            assert_eq!(message, "The viewer role does not allow app.env.write")
+        }
+        other => panic!("expected Forbidden, got {:?}", other.map(|_| ())),
+    }
+    match policy::authorize(&pool, &carol, Action::AppRead, org_app).await {
+        Err(AppError::Forbidden(message)) => {
+            assert_eq!(message, "You have no access to this resource")
+        }
+        other => panic!("expected Forbidden, got {:?}", other.map(|_| ())),
+    }
+}
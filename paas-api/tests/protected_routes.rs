@@ -0,0 +1,118 @@
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use common::{
+    mock_account, setup_test_app, setup_test_db, setup_test_env, sign_in, use_mock_providers,
+};
+use serde_json::Value;
+use wiremock::MockServer;
+
+#[actix_web::test]
+async fn test_routes_outside_auth_require_a_user() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    for req in [
+        test::TestRequest::get().uri("/api/user/me"),
+        test::TestRequest::get().uri("/api/user/providers"),
+        test::TestRequest::delete().uri("/api/user/providers/github"),
+        test::TestRequest::get().uri("/api/user/tokens"),
+        test::TestRequest::post().uri("/api/user/tokens"),
+        test::TestRequest::delete().uri("/api/user/tokens/1"),
+        // Unknown paths do not reveal whether they exist.
+        test::TestRequest::get().uri("/api/does-not-exist"),
+    ] {
+        let req = req.to_request();
+        let path = req.path().to_string();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "Not authenticated", "{}", path);
+    }
+
+    // The sign-in routes stay open.
+    let req = test::TestRequest::get()
+        .uri("/api/auth/providers")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::get()
+        .uri("/api/auth/github")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_user_is_loaded_from_the_database() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in(&app, "github").await;
+
+    // The session keeps the username from sign-in; the database wins.
+    sqlx::query("UPDATE users SET username = 'renamed', email = 'new@example.com'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie.clone())
+        .to_request();
+    let me: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(me["username"], "renamed");
+    assert_eq!(me["email"], "new@example.com");
+
+    // A session outliving its account is turned away.
+    sqlx::query("DELETE FROM users")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"], "Not authenticated");
+}
+
+#[actix_web::test]
+async fn test_bearer_tokens_pass_the_guard() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    mock_account(&mock_server, "github", 1, "octocat").await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in(&app, "github").await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/token")
+        .cookie(cookie)
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let access_token = body["access_token"].as_str().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/providers")
+        .insert_header(("Authorization", format!("Bearer {}", access_token)))
+        .to_request();
+    let providers: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(providers.len(), 1);
+
+    // Starting a connect flow needs the browser session it will return to.
+    let req = test::TestRequest::get()
+        .uri("/api/user/providers/gitlab/connect")
+        .insert_header(("Authorization", format!("Bearer {}", access_token)))
+        .to_request();
+    assert_eq!(
+        test::call_service(&app, req).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
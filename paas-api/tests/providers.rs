@@ -0,0 +1,192 @@
+use paas_api::{config::OAuthProvider, error::AppError, providers::EndpointSource};
+use serde_json::json;
+
+#[test]
+fn test_github_profile_mapping() {
+    let user = OAuthProvider::GitHub
+        .provider()
+        .map_user(&json!({
+            "id": 12345,
+            "login": "octocat",
+            "email": "octocat@example.com",
+            "avatar_url": "https://example.com/octocat.png"
+        }))
+        .unwrap();
+
+    assert_eq!(user.id, "12345");
+    assert_eq!(user.username, "octocat");
+    assert_eq!(user.email.as_deref(), Some("octocat@example.com"));
+    assert_eq!(
+        user.avatar_url.as_deref(),
+        Some("https://example.com/octocat.png")
+    );
+}
+
+#[test]
+fn test_gitlab_profile_mapping() {
+    let user = OAuthProvider::GitLab
+        .provider()
+        .map_user(&json!({
+            "id": 42,
+            "username": "tanuki",
+            "email": null,
+            "avatar_url": "https://example.com/tanuki.png"
+        }))
+        .unwrap();
+
+    assert_eq!(user.id, "42");
+    assert_eq!(user.username, "tanuki");
+    assert_eq!(user.email, None);
+}
+
+#[test]
+fn test_bitbucket_profile_mapping() {
+    let user = OAuthProvider::Bitbucket
+        .provider()
+        .map_user(&json!({
+            "uuid": "{1234-5678}",
+            "username": "bucket",
+            "links": { "avatar": { "href": "https://example.com/bucket.png" } }
+        }))
+        .unwrap();
+
+    assert_eq!(user.id, "\"{1234-5678}\"");
+    assert_eq!(user.username, "bucket");
+    assert_eq!(
+        user.avatar_url.as_deref(),
+        Some("https://example.com/bucket.png")
+    );
+}
+
+#[test]
+fn test_email_verification() {
+    let github = OAuthProvider::GitHub.provider();
+    let emails = json!([
+        { "email": "octocat@example.com", "verified": true, "primary": true },
+        { "email": "unconfirmed@example.com", "verified": false, "primary": false }
+    ]);
+    assert!(github.email_verified(&emails, "Octocat@Example.com"));
+    assert!(!github.email_verified(&emails, "unconfirmed@example.com"));
+    assert!(!github.email_verified(&json!({ "message": "Not Found" }), "octocat@example.com"));
+
+    // OpenID Connect says so in the claims, sometimes as a string.
+    let oidc = OAuthProvider::Oidc.provider();
+    for (claim, verified) in [
+        (json!(true), true),
+        (json!("true"), true),
+        (json!(false), false),
+    ] {
+        let user = oidc
+            .map_user(&json!({ "sub": "1", "email": "sso@example.com", "email_verified": claim }))
+            .unwrap();
+        assert_eq!(user.email_verified, verified);
+    }
+    let user = oidc
+        .map_user(&json!({ "sub": "1", "email": "sso@example.com" }))
+        .unwrap();
+    assert!(!user.email_verified);
+
+    // Self-hosted providers do not promise verified emails.
+    let user = OAuthProvider::BitbucketServer
+        .provider()
+        .map_user(&json!({ "id": 101, "name": "jdoe", "emailAddress": "jdoe@example.com" }))
+        .unwrap();
+    assert!(!user.email_verified);
+}
+
+#[test]
+fn test_profile_without_id_is_rejected() {
+    let result = OAuthProvider::GitHub
+        .provider()
+        .map_user(&json!({ "login": "ghost" }));
+
+    assert!(matches!(result, Err(AppError::AuthError(_))));
+}
+
+#[test]
+fn test_provider_from_path_segment() {
+    let provider: OAuthProvider = serde_json::from_value(json!("gitlab")).unwrap();
+    assert_eq!(provider, OAuthProvider::GitLab);
+    assert_eq!(provider.to_string(), "gitlab");
+    assert!(serde_json::from_value::<OAuthProvider>(json!("sourceforge")).is_err());
+}
+
+#[test]
+fn test_provider_type_round_trips() {
+    for provider in [
+        OAuthProvider::GitHub,
+        OAuthProvider::GitLab,
+        OAuthProvider::Bitbucket,
+        OAuthProvider::BitbucketServer,
+        OAuthProvider::Oidc,
+    ] {
+        assert_eq!(provider.to_string().parse::<OAuthProvider>(), Ok(provider));
+        assert_eq!(
+            serde_json::to_value(provider).unwrap(),
+            json!(provider.to_string())
+        );
+    }
+    assert!("sourceforge".parse::<OAuthProvider>().is_err());
+}
+
+fn static_endpoints(provider: OAuthProvider, base_url: &str) -> (String, String, String) {
+    match provider.provider().endpoint_source(base_url) {
+        EndpointSource::Static {
+            auth_url,
+            token_url,
+            user_api_url,
+        } => (auth_url, token_url, user_api_url),
+        EndpointSource::Discovery { .. } => panic!("expected static endpoints"),
+    }
+}
+
+#[test]
+fn test_github_enterprise_endpoints() {
+    let (auth_url, _, user_api_url) = static_endpoints(OAuthProvider::GitHub, "https://github.com");
+    assert_eq!(auth_url, "https://github.com/login/oauth/authorize");
+    assert_eq!(user_api_url, "https://api.github.com/user");
+
+    let (auth_url, token_url, user_api_url) =
+        static_endpoints(OAuthProvider::GitHub, "https://github.example.com/");
+    assert_eq!(auth_url, "https://github.example.com/login/oauth/authorize");
+    assert_eq!(
+        token_url,
+        "https://github.example.com/login/oauth/access_token"
+    );
+    assert_eq!(user_api_url, "https://github.example.com/api/v3/user");
+}
+
+#[test]
+fn test_self_managed_gitlab_endpoints() {
+    let (auth_url, token_url, user_api_url) =
+        static_endpoints(OAuthProvider::GitLab, "https://git.example.com/gitlab");
+    assert_eq!(auth_url, "https://git.example.com/gitlab/oauth/authorize");
+    assert_eq!(token_url, "https://git.example.com/gitlab/oauth/token");
+    assert_eq!(user_api_url, "https://git.example.com/gitlab/api/v4/user");
+}
+
+#[test]
+fn test_bitbucket_server_profile_lookup() {
+    let provider = OAuthProvider::BitbucketServer.provider();
+    assert_eq!(
+        provider
+            .profile_url("https://bitbucket.example.com", "jane doe\n")
+            .as_deref(),
+        Some("https://bitbucket.example.com/rest/api/latest/users/jane+doe?avatarSize=64")
+    );
+    assert_eq!(
+        provider.profile_url("https://bitbucket.example.com", ""),
+        None
+    );
+
+    let user = provider
+        .map_user(&json!({
+            "id": 101,
+            "name": "jdoe",
+            "emailAddress": "jdoe@example.com"
+        }))
+        .unwrap();
+    assert_eq!(user.id, "101");
+    assert_eq!(user.username, "jdoe");
+    assert_eq!(user.email.as_deref(), Some("jdoe@example.com"));
+}
@@ -0,0 +1,489 @@
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use common::{
+    call, get_auth_url, job_registry, setup_test_app, setup_test_db, setup_test_env, sign_in_as,
+    use_mock_providers,
+};
+use paas_api::{
+    builders::FakeBuilder,
+    git::SourceCache,
+    jobs::{self, Worker},
+    providers::{self, Bitbucket, Endpoints, GitLab, RemoteRepository},
+    AppError, ProviderInstance, Visibility,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use wiremock::{
+    matchers::{header, method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn github_repo(id: i64, full_name: &str, private: bool) -> Value {
+    json!({
+        "id": id,
+        "full_name": full_name,
+        "description": format!("{} description", full_name),
+        "html_url": format!("https://github.com/{}", full_name),
+        "clone_url": format!("https://github.com/{}.git", full_name),
+        "default_branch": "main",
+        "private": private,
+    })
+}
+
+/// Serves `pages` of the GitHub repository list, linked by `Link` headers.
+async fn mock_github_repos(mock_server: &MockServer, pages: Vec<Vec<Value>>) {
+    let count = pages.len();
+    // Later pages are mounted first, as the first page matches any request.
+    for (i, page) in pages.into_iter().enumerate().rev() {
+        let mut response = ResponseTemplate::new(200).set_body_json(page);
+        if i + 1 < count {
+            let next = format!(
+                "{}/user/repos?per_page=100&page={}",
+                mock_server.uri(),
+                i + 2
+            );
+            response = response.insert_header(
+                "Link",
+                format!("<{}>; rel=\"next\", <{}>; rel=\"last\"", next, next).as_str(),
+            );
+        }
+        let mock = Mock::given(method("GET"))
+            .and(path("/user/repos"))
+            .and(header("Authorization", "Bearer github_token_1"));
+        let mock = match i {
+            0 => mock,
+            _ => mock.and(query_param("page", (i + 1).to_string().as_str())),
+        };
+        mock.respond_with(response).mount(mock_server).await;
+    }
+}
+
+fn names(page: &Value) -> Vec<&str> {
+    page["repositories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|repository| repository["name"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_sync_imports_github_repositories() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "octocat").await;
+
+    mock_github_repos(
+        &mock_server,
+        vec![
+            vec![
+                github_repo(10, "octocat/hello-world", false),
+                github_repo(11, "octocat/Spoon-Knife", false),
+            ],
+            vec![github_repo(12, "acme/private-api", true)],
+        ],
+    )
+    .await;
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let results: Value = test::read_body_json(resp).await;
+    assert_eq!(
+        results,
+        json!([{ "provider": "github", "synced": 3, "removed": 0 }])
+    );
+
+    let resp = call(&app, &cookie, "GET", "/api/repositories", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(
+        names(&page),
+        [
+            "acme/private-api",
+            "octocat/hello-world",
+            "octocat/Spoon-Knife"
+        ]
+    );
+    assert_eq!(page["total"], 3);
+    let repository = &page["repositories"][0];
+    assert_eq!(repository["provider"], "github");
+    assert_eq!(repository["provider_repo_id"], "12");
+    assert_eq!(repository["visibility"], "private");
+    assert_eq!(repository["url"], "https://github.com/acme/private-api");
+    assert_eq!(
+        repository["clone_url"],
+        "https://github.com/acme/private-api.git"
+    );
+    assert_eq!(repository["default_branch"], "main");
+    assert!(repository["last_synced"].is_string());
+
+    // A renamed repository keeps its row; a deleted one is removed.
+    let id = repository["id"].clone();
+    mock_server.reset().await;
+    mock_github_repos(
+        &mock_server,
+        vec![vec![
+            github_repo(10, "octocat/hello-world", false),
+            github_repo(12, "acme/public-api", false),
+        ]],
+    )
+    .await;
+    let body = json!({ "provider": "github" });
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", Some(body)).await;
+    let results: Value = test::read_body_json(resp).await;
+    assert_eq!(
+        results,
+        json!([{ "provider": "github", "synced": 2, "removed": 1 }])
+    );
+    let resp = call(&app, &cookie, "GET", "/api/repositories?q=api", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(names(&page), ["acme/public-api"]);
+    assert_eq!(page["repositories"][0]["id"], id);
+    assert_eq!(page["repositories"][0]["visibility"], "public");
+
+    // Provider failures are reported per connection.
+    mock_server.reset().await;
+    Mock::given(method("GET"))
+        .and(path("/user/repos"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&mock_server)
+        .await;
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let results: Value = test::read_body_json(resp).await;
+    assert_eq!(results[0]["provider"], "github");
+    assert!(results[0]["error"].as_str().unwrap().contains("401"));
+    let resp = call(&app, &cookie, "GET", "/api/repositories", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(page["total"], 2);
+
+    let body = json!({ "provider": "gitlab" });
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_sync_in_the_background() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "octocat").await;
+    mock_github_repos(
+        &mock_server,
+        vec![vec![github_repo(10, "octocat/hello-world", false)]],
+    )
+    .await;
+
+    let body = json!({ "provider": "github", "background": true });
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", Some(body)).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let job: Value = test::read_body_json(resp).await;
+    assert_eq!(job["kind"], "repository.sync");
+    assert_eq!(job["status"], "pending");
+    assert_eq!(job["payload"]["provider"], "github");
+    let resp = call(&app, &cookie, "GET", "/api/repositories", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(page["total"], 0);
+
+    let sources = SourceCache::new(std::env::temp_dir());
+    let registry = job_registry(&pool, sources, Arc::new(FakeBuilder::new()));
+    let worker = Worker::new(pool.clone(), Arc::new(registry), "test", 60);
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.status, jobs::JobStatus::Succeeded);
+    let resp = call(&app, &cookie, "GET", "/api/repositories", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(names(&page), ["octocat/hello-world"]);
+
+    // A failing provider fails the attempt, to be retried later.
+    mock_server.reset().await;
+    let body = json!({ "background": true });
+    call(&app, &cookie, "POST", "/api/repositories/sync", Some(body)).await;
+    let job = worker.run_next().await.unwrap().unwrap();
+    assert_eq!(job.status, jobs::JobStatus::Pending);
+    assert!(job.last_error.unwrap().contains("github"));
+}
+
+#[actix_web::test]
+async fn test_list_repositories_search_and_pages() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    use_mock_providers(&mock_server);
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+    let cookie = sign_in_as(&app, &mock_server, 1, "octocat").await;
+
+    let repos = (1..=5)
+        .map(|i| github_repo(i, &format!("octocat/repo_{}", i), false))
+        .chain([github_repo(6, "octocat/repo%x", false)])
+        .collect();
+    mock_github_repos(&mock_server, vec![repos]).await;
+    let resp = call(&app, &cookie, "POST", "/api/repositories/sync", None).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = call(
+        &app,
+        &cookie,
+        "GET",
+        "/api/repositories?per_page=2&page=2",
+        None,
+    )
+    .await;
+    let page: Value = test::read_body_json(resp).await;
+    // `%` sorts before `_`.
+    assert_eq!(names(&page), ["octocat/repo_2", "octocat/repo_3"]);
+    assert_eq!(page["total"], 6);
+    assert_eq!(page["page"], 2);
+
+    // LIKE wildcards in the search are taken literally.
+    let resp = call(&app, &cookie, "GET", "/api/repositories?q=repo%25", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(names(&page), ["octocat/repo%x"]);
+    let resp = call(&app, &cookie, "GET", "/api/repositories?q=o_1", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(names(&page), ["octocat/repo_1"]);
+
+    let resp = call(
+        &app,
+        &cookie,
+        "GET",
+        "/api/repositories?provider=gitlab",
+        None,
+    )
+    .await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(page["total"], 0);
+
+    for query in ["page=0", "per_page=0", "per_page=101"] {
+        let uri = format!("/api/repositories?{}", query);
+        let resp = call(&app, &cookie, "GET", &uri, None).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+
+    // Other users see only their own.
+    let other = sign_in_as(&app, &mock_server, 2, "hubot").await;
+    let resp = call(&app, &other, "GET", "/api/repositories", None).await;
+    let page: Value = test::read_body_json(resp).await;
+    assert_eq!(page["total"], 0);
+}
+
+fn endpoints(user_api_url: String) -> Endpoints {
+    Endpoints {
+        base_url: String::new(),
+        auth_url: String::new(),
+        token_url: String::new(),
+        user_api_url: Some(user_api_url),
+        oidc: None,
+    }
+}
+
+#[actix_web::test]
+async fn test_fetch_gitlab_repositories() {
+    let mock_server = MockServer::start().await;
+    let project = |id: i64, name: &str, visibility: &str| {
+        json!({
+            "id": id,
+            "path_with_namespace": name,
+            "description": null,
+            "web_url": format!("https://gitlab.com/{}", name),
+            "http_url_to_repo": format!("https://gitlab.com/{}.git", name),
+            "default_branch": if id == 3 { Value::Null } else { json!("main") },
+            "visibility": visibility,
+        })
+    };
+    Mock::given(method("GET"))
+        .and(path("/api/v4/projects"))
+        .and(query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([project(
+            3,
+            "group/public",
+            "public"
+        )])))
+        .mount(&mock_server)
+        .await;
+    let next = format!(
+        "{}/api/v4/projects?membership=true&page=2",
+        mock_server.uri()
+    );
+    Mock::given(method("GET"))
+        .and(path("/api/v4/projects"))
+        .and(query_param("membership", "true"))
+        .and(header("Authorization", "Bearer gitlab-token"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Link", format!("<{}>; rel=\"next\"", next).as_str())
+                .set_body_json(json!([
+                    project(1, "group/private", "private"),
+                    project(2, "group/internal", "internal"),
+                ])),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let endpoints = endpoints(format!("{}/api/v4/user", mock_server.uri()));
+    let repositories = providers::fetch_repositories(&GitLab, &endpoints, "gitlab-token")
+        .await
+        .unwrap();
+    let visibility: Vec<_> = repositories
+        .iter()
+        .map(|repository| (repository.id.as_str(), repository.visibility))
+        .collect();
+    assert_eq!(
+        visibility,
+        [
+            ("1", Visibility::Private),
+            ("2", Visibility::Internal),
+            ("3", Visibility::Public)
+        ]
+    );
+    assert_eq!(
+        repositories[2],
+        RemoteRepository {
+            id: "3".to_string(),
+            full_name: "group/public".to_string(),
+            description: None,
+            url: "https://gitlab.com/group/public".to_string(),
+            clone_url: "https://gitlab.com/group/public.git".to_string(),
+            default_branch: None,
+            visibility: Visibility::Public,
+        }
+    );
+}
+
+#[actix_web::test]
+async fn test_fetch_bitbucket_repositories() {
+    let mock_server = MockServer::start().await;
+    let repository = |uuid: &str, name: &str| {
+        json!({
+            "uuid": uuid,
+            "full_name": name,
+            "description": "",
+            "is_private": true,
+            "mainbranch": { "name": "master" },
+            "links": {
+                "html": { "href": format!("https://bitbucket.org/{}", name) },
+                "clone": [
+                    { "name": "ssh", "href": format!("git@bitbucket.org:{}.git", name) },
+                    { "name": "https", "href": format!("https://bitbucket.org/{}.git", name) },
+                ],
+            },
+        })
+    };
+    Mock::given(method("GET"))
+        .and(path("/2.0/repositories"))
+        .and(query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [repository("{b}", "team/two")],
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/2.0/repositories"))
+        .and(query_param("role", "member"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [repository("{a}", "team/one")],
+            "next": format!("{}/2.0/repositories?page=2", mock_server.uri()),
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let endpoints = endpoints(format!("{}/2.0/user", mock_server.uri()));
+    let repositories = providers::fetch_repositories(&Bitbucket, &endpoints, "bitbucket-token")
+        .await
+        .unwrap();
+    let ids: Vec<_> = repositories.iter().map(|r| r.id.as_str()).collect();
+    assert_eq!(ids, ["{a}", "{b}"]);
+    assert_eq!(repositories[0].description, None);
+    assert_eq!(repositories[1].url, "https://bitbucket.org/team/two");
+    assert_eq!(
+        repositories[1].clone_url,
+        "https://bitbucket.org/team/two.git"
+    );
+    assert_eq!(repositories[1].default_branch.as_deref(), Some("master"));
+}
+
+#[actix_web::test]
+async fn test_next_pages_must_stay_on_the_api() {
+    let mock_server = MockServer::start().await;
+    let elsewhere = MockServer::start().await;
+    // Nothing may reach the other server, let alone the access token.
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(0)
+        .mount(&elsewhere)
+        .await;
+
+    let next = format!("{}/api/v4/projects?page=2", elsewhere.uri());
+    Mock::given(method("GET"))
+        .and(path("/api/v4/projects"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Link", format!("<{}>; rel=\"next\"", next).as_str())
+                .set_body_json(json!([])),
+        )
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/2.0/repositories"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [],
+            "next": format!("{}/2.0/repositories?page=2", elsewhere.uri()),
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let gitlab = endpoints(format!("{}/api/v4/user", mock_server.uri()));
+    let error = providers::fetch_repositories(&GitLab, &gitlab, "gitlab-token")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "External service error: GitLab linked to a page of repositories outside its API: {}",
+            next
+        )
+    );
+    let bitbucket = endpoints(format!("{}/2.0/user", mock_server.uri()));
+    let error = providers::fetch_repositories(&Bitbucket, &bitbucket, "bitbucket-token")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, AppError::ExternalServiceError(_)));
+}
+
+/// The scope each provider requires of the endpoint listing repositories.
+const REPOSITORY_LIST_SCOPES: [(&str, &str, &str); 3] = [
+    ("github", "/user/repos", "repo"),
+    ("gitlab", "/api/v4/projects", "read_api"),
+    ("bitbucket", "/2.0/repositories", "repository"),
+];
+
+#[actix_web::test]
+async fn test_sign_in_asks_for_the_scopes_the_sync_needs() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    for (slug, list_path, scope) in REPOSITORY_LIST_SCOPES {
+        let instance = ProviderInstance::find_by_slug(&pool, slug)
+            .await
+            .unwrap()
+            .unwrap();
+        let endpoints = providers::resolve_endpoints(&instance).await.unwrap();
+        let list_url = instance.spec().unwrap().repositories_url(&endpoints);
+        let list_url = url::Url::parse(&list_url.unwrap()).unwrap();
+        assert_eq!(list_url.path(), list_path, "{}", slug);
+
+        let auth_url = get_auth_url(&app, slug).await;
+        let scopes = common::query_param(&auth_url, "scope").unwrap();
+        assert!(
+            scopes.split(' ').any(|requested| requested == scope),
+            "{} asks for {:?}, listing repositories takes {}",
+            slug,
+            scopes,
+            scope
+        );
+    }
+}
@@ -0,0 +1,295 @@
+#![cfg(target_os = "linux")]
+
+use paas_api::{
+    config::RuntimeConfig,
+    runtimes::{self, InstanceState, ProcessRuntime, RunSpec, Runtime, RuntimeError},
+    AppError,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+use tempfile::TempDir;
+
+/// Answers `/` with its environment and working directory, and `/health`
+/// with 500 while a `sick` file exists. `/crash` exits. SIGTERM writes
+/// `stopped` before exiting, unless `IGNORE_TERM` is set.
+const APP: &str = r#"
+import http.server, json, os, signal, sys
+
+if os.environ.get("IGNORE_TERM"):
+    signal.signal(signal.SIGTERM, signal.SIG_IGN)
+else:
+    def stop(*_):
+        open("stopped", "w").close()
+        sys.exit(0)
+    signal.signal(signal.SIGTERM, stop)
+
+class Handler(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        if self.path == "/crash":
+            os._exit(3)
+        status = 500 if self.path == "/health" and os.path.exists("sick") else 200
+        body = json.dumps({"env": dict(os.environ), "cwd": os.getcwd(), "pid": os.getpid()})
+        self.send_response(status)
+        self.end_headers()
+        self.wfile.write(body.encode())
+
+    def log_message(self, *_):
+        pass
+
+http.server.HTTPServer(("127.0.0.1", int(os.environ["PORT"])), Handler).serve_forever()
+"#;
+
+/// The interpreter itself rather than a shim, as apps do not inherit the
+/// test's environment.
+fn python() -> String {
+    let output = Command::new("python3")
+        .args(["-c", "import sys; print(sys.executable)"])
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+fn app_dir() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("app.py"), APP).unwrap();
+    dir
+}
+
+fn spec(name: &str, dir: &Path) -> RunSpec {
+    RunSpec::new(name, &format!("exec {} app.py", python()), dir).health_path("/health")
+}
+
+fn runtime(ports: std::ops::RangeInclusive<u16>) -> ProcessRuntime {
+    ProcessRuntime::new()
+        .ports(ports)
+        .start_timeout(Duration::from_secs(10))
+        .stop_timeout(Duration::from_secs(5))
+        .probe_interval(Duration::from_millis(100))
+        .probe_failures(2)
+        .backoff(Duration::from_millis(50), Duration::from_millis(200))
+}
+
+async fn get(port: u16, path: &str) -> serde_json::Value {
+    reqwest::get(format!("http://127.0.0.1:{}{}", port, path))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Polls the instance until `done` holds, for up to 10 seconds.
+async fn wait_until(
+    runtime: &ProcessRuntime,
+    name: &str,
+    done: impl Fn(&paas_api::runtimes::Instance) -> bool,
+) -> paas_api::runtimes::Instance {
+    for _ in 0..200 {
+        let instance = runtime.status(name).await.unwrap();
+        if done(&instance) {
+            return instance;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{:?}", runtime.status(name).await);
+}
+
+fn is_alive(pid: u32) -> bool {
+    // Reaped processes are gone from /proc; zombies are not running either.
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map(|stat| !stat.contains(") Z "))
+        .unwrap_or(false)
+}
+
+#[actix_web::test]
+async fn test_runs_and_restarts_an_app() {
+    let dir = app_dir();
+    let runtime = runtime(21000..=21099);
+    std::env::set_var("PAAS_RUNTIME_TEST_SECRET", "s3cret");
+
+    let instance = runtime
+        .start(&spec("shop-1", dir.path()).env("GREETING", "hello"))
+        .await
+        .unwrap();
+    assert_eq!(instance.state, InstanceState::Running);
+    assert!((21000..=21099).contains(&instance.port));
+    let page = get(instance.port, "/").await;
+    assert_eq!(page["env"]["PORT"], instance.port.to_string());
+    assert_eq!(page["env"]["GREETING"], "hello");
+    assert!(page["env"]["PAAS_RUNTIME_TEST_SECRET"].is_null());
+    assert_eq!(
+        PathBuf::from(page["cwd"].as_str().unwrap()),
+        dir.path().canonicalize().unwrap()
+    );
+
+    // Names are unique, and each instance gets its own port.
+    let error = runtime
+        .start(&spec("shop-1", dir.path()))
+        .await
+        .unwrap_err();
+    assert_eq!(error, RuntimeError::AlreadyRunning("shop-1".to_string()));
+    let other = runtime.start(&spec("blog-1", dir.path())).await.unwrap();
+    assert_ne!(other.port, instance.port);
+    runtime.stop("blog-1").await.unwrap();
+
+    // A crash restarts the app on the same port.
+    let first_pid = page["pid"].as_u64().unwrap() as u32;
+    let _ = reqwest::get(format!("http://127.0.0.1:{}/crash", instance.port)).await;
+    let restarted = wait_until(&runtime, "shop-1", |instance| {
+        instance.restarts == 1 && instance.state == InstanceState::Running
+    })
+    .await;
+    assert_eq!(restarted.port, instance.port);
+    assert_eq!(
+        restarted.last_error.as_deref(),
+        Some("exited with exit status: 3")
+    );
+    let page = get(instance.port, "/").await;
+    assert_ne!(page["pid"].as_u64().unwrap() as u32, first_pid);
+
+    // So does failing the health probe.
+    fs::write(dir.path().join("sick"), "").unwrap();
+    let unhealthy = wait_until(&runtime, "shop-1", |instance| instance.restarts == 2).await;
+    assert_eq!(
+        unhealthy.last_error.as_deref(),
+        Some("failed 2 health probes in a row")
+    );
+    fs::remove_file(dir.path().join("sick")).unwrap();
+    wait_until(&runtime, "shop-1", |instance| {
+        instance.state == InstanceState::Running
+    })
+    .await;
+    // The unhealthy process was asked to stop.
+    assert!(dir.path().join("stopped").exists());
+    fs::remove_file(dir.path().join("stopped")).unwrap();
+
+    let pid = runtime.status("shop-1").await.unwrap().pid.unwrap();
+    runtime.stop("shop-1").await.unwrap();
+    assert!(dir.path().join("stopped").exists());
+    assert!(!is_alive(pid));
+    assert!(runtime.status("shop-1").await.is_none());
+    assert_eq!(
+        runtime.stop("shop-1").await.unwrap_err(),
+        RuntimeError::NotFound("shop-1".to_string())
+    );
+}
+
+#[actix_web::test]
+async fn test_apps_ignoring_sigterm_are_killed() {
+    let dir = app_dir();
+    let runtime = runtime(21100..=21199).stop_timeout(Duration::from_millis(300));
+
+    let instance = runtime
+        .start(&spec("shop-1", dir.path()).env("IGNORE_TERM", "1"))
+        .await
+        .unwrap();
+    let pid = instance.pid.unwrap();
+    runtime.stop("shop-1").await.unwrap();
+    assert!(!is_alive(pid));
+    assert!(!dir.path().join("stopped").exists());
+}
+
+#[actix_web::test]
+async fn test_apps_that_do_not_start() {
+    let dir = app_dir();
+    let runtime = runtime(21200..=21299).start_timeout(Duration::from_millis(500));
+
+    let error = runtime
+        .start(&RunSpec::new("shop-1", "echo starting; exit 3", dir.path()))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error,
+        RuntimeError::StartFailed {
+            name: "shop-1".to_string(),
+            reason: "exited with exit status: 3".to_string(),
+        }
+    );
+    assert!(runtime.status("shop-1").await.is_none());
+
+    // Without a health path, the app must accept connections on its port.
+    let error = runtime
+        .start(&RunSpec::new("shop-1", "sleep 30", dir.path()))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Instance shop-1 failed to start: did not pass its health probe within 500ms"
+    );
+
+    let error = runtime
+        .start(&spec("shop-1", &dir.path().join("missing")))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, RuntimeError::StartFailed { .. }));
+    let error: AppError = error.into();
+    assert!(matches!(error, AppError::ExternalServiceError(_)));
+
+    // Ports in use are skipped, and running out of them is an error.
+    let _taken = std::net::TcpListener::bind(("127.0.0.1", 21200)).unwrap();
+    let full = runtime.ports(21200..=21200);
+    let error = full.start(&spec("shop-1", dir.path())).await.unwrap_err();
+    assert_eq!(error, RuntimeError::NoFreePort("shop-1".to_string()));
+}
+
+#[actix_web::test]
+async fn test_dropping_the_runtime_kills_its_apps() {
+    let dir = app_dir();
+    let runtime = runtime(21300..=21399);
+
+    // The shell forks the app rather than exec'ing it, so the app is not
+    // the process the runtime spawned.
+    let command = format!("{} app.py & wait", python());
+    let spec = RunSpec::new("shop-1", &command, dir.path()).health_path("/health");
+    let instance = runtime.start(&spec).await.unwrap();
+    let pid = get(instance.port, "/").await["pid"].as_u64().unwrap() as u32;
+    assert_ne!(instance.pid, Some(pid));
+
+    drop(runtime);
+    for _ in 0..100 {
+        if !is_alive(pid) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(!is_alive(pid));
+}
+
+#[actix_web::test]
+async fn test_runtime_config_from_env() {
+    let config = RuntimeConfig::from_env().unwrap();
+    assert_eq!(config.ports, runtimes::DEFAULT_PORTS);
+    assert_eq!(
+        config.start_timeout.whole_seconds(),
+        runtimes::DEFAULT_START_TIMEOUT_SECS as i64
+    );
+
+    for ports in ["21400", "21499-21400", "0-100", "21400-70000"] {
+        std::env::set_var("RUNTIME_PORTS", ports);
+        assert!(
+            matches!(RuntimeConfig::from_env(), Err(AppError::ConfigError(_))),
+            "{}",
+            ports
+        );
+    }
+    std::env::set_var("RUNTIME_PORTS", "21400-21499");
+    std::env::set_var("RUNTIME_STOP_TIMEOUT_SECS", "0");
+    assert!(matches!(
+        RuntimeConfig::from_env(),
+        Err(AppError::ConfigError(_))
+    ));
+    std::env::set_var("RUNTIME_STOP_TIMEOUT_SECS", "5");
+    let config = RuntimeConfig::from_env();
+    std::env::remove_var("RUNTIME_PORTS");
+    std::env::remove_var("RUNTIME_STOP_TIMEOUT_SECS");
+
+    let dir = app_dir();
+    let runtime = ProcessRuntime::from_config(&config.unwrap());
+    let instance = runtime.start(&spec("shop-1", dir.path())).await.unwrap();
+    assert!((21400..=21499).contains(&instance.port));
+    runtime.stop("shop-1").await.unwrap();
+}
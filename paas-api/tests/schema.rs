@@ -0,0 +1,254 @@
+mod common;
+
+use common::{keyring, setup_test_db, setup_test_env};
+use paas_api::{
+    api_tokens::Scope,
+    audit::{Event, RequestContext},
+    jobs::{JobStatus, NewJob},
+    orgs::{Invitee, Role},
+    ApiToken, AppSettings, Application, AuditEvent, ConnectedProvider, Deployment,
+    DeploymentLogLine, GitProvider, Invitation, Job, Member, NewApplication, NewRepository,
+    OAuthState, Organization, OrganizationMembership, PendingInvitation, ProviderInstance,
+    RefreshToken, Repository, SessionRecord, User, Visibility,
+};
+use serde_json::json;
+use sqlx::{sqlite::SqliteRow, FromRow, SqlitePool};
+
+/// Reads every row of `table` as `T`, failing if the model names a column
+/// the table does not have or decodes one with the wrong type.
+async fn select_all<T>(pool: &SqlitePool, table: &str) -> Vec<T>
+where
+    T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin,
+{
+    sqlx::query_as::<_, T>(&format!("SELECT * FROM {}", table))
+        .fetch_all(pool)
+        .await
+        .unwrap_or_else(|e| panic!("{} does not match its model: {}", table, e))
+}
+
+#[actix_web::test]
+async fn test_models_round_trip() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+
+    let user = User::find_or_create(&pool, &keyring(), "github", "1", "alice", None, None)
+        .await
+        .unwrap();
+    let bob = User::find_or_create(&pool, &keyring(), "github", "2", "bob", None, None)
+        .await
+        .unwrap();
+    let users: Vec<User> = select_all(&pool, "users").await;
+    assert_eq!(users.len(), 2);
+
+    sqlx::query(
+        "INSERT INTO provider_instances (slug, provider_type, display_name, base_url)
+         VALUES ('gitlab-acme', 'gitlab', 'Acme GitLab', 'https://gitlab.acme.test')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let instances: Vec<ProviderInstance> = select_all(&pool, "provider_instances").await;
+    assert!(instances
+        .iter()
+        .any(|instance| instance.slug == "gitlab-acme"));
+
+    let connection = GitProvider::upsert(
+        &pool,
+        &keyring(),
+        user.id,
+        "github",
+        "1",
+        "gho_a",
+        None,
+        Some(60),
+    )
+    .await
+    .unwrap();
+    assert_eq!(connection.access_token, "gho_a");
+    let connections: Vec<GitProvider> = select_all(&pool, "git_providers").await;
+    assert_eq!(connections[0].id, connection.id);
+    assert_eq!(connections[0].created_at, connection.created_at);
+    let connected = ConnectedProvider::list(&pool, user.id).await.unwrap();
+    assert_eq!(connected[0].provider, "github");
+
+    let api_token = ApiToken::create(
+        &pool,
+        user.id,
+        "ci",
+        "hash",
+        "paas_ab",
+        &[Scope::Read],
+        3600,
+    )
+    .await
+    .unwrap();
+    let api_tokens: Vec<ApiToken> = select_all(&pool, "api_tokens").await;
+    assert_eq!(api_tokens[0].id, api_token.id);
+    RefreshToken::create(
+        &pool,
+        user.id,
+        Some(api_token.id),
+        "family",
+        "refresh-hash",
+        &[Scope::Read],
+        3600,
+    )
+    .await
+    .unwrap();
+    let refresh_tokens: Vec<RefreshToken> = select_all(&pool, "refresh_tokens").await;
+    assert_eq!(refresh_tokens[0].api_token_id, Some(api_token.id));
+
+    let org = Organization::create(&pool, "acme", "Acme", user.id)
+        .await
+        .unwrap();
+    let organizations: Vec<Organization> = select_all(&pool, "organizations").await;
+    assert_eq!(organizations[0].slug, "acme");
+    let memberships = Organization::list_for_member(&pool, user.id).await.unwrap();
+    let membership: &OrganizationMembership = &memberships[0];
+    assert_eq!(membership.role, Role::Owner);
+    let members = Member::list(&pool, org.id).await.unwrap();
+    assert_eq!(members[0].username, "alice");
+
+    Application::create(
+        &pool,
+        &NewApplication {
+            slug: "shop".to_string(),
+            name: "Shop".to_string(),
+            user_id: user.id,
+            organization_id: Some(org.id),
+            settings: AppSettings {
+                root_dir: "web".to_string(),
+                ..AppSettings::default()
+            },
+        },
+    )
+    .await
+    .unwrap();
+    let apps: Vec<Application> = select_all(&pool, "apps").await;
+    assert_eq!(apps[0].settings.root_dir, "web");
+    Deployment::create(&pool, apps[0].id, &"a".repeat(40), user.id)
+        .await
+        .unwrap();
+    let deployments: Vec<Deployment> = select_all(&pool, "deployments").await;
+    assert_eq!(deployments[0].triggered_by, Some(user.id));
+    DeploymentLogLine::append(&pool, deployments[0].id, "Building")
+        .await
+        .unwrap();
+    let lines: Vec<DeploymentLogLine> = select_all(&pool, "deployment_logs").await;
+    assert_eq!(lines[0].line, "Building");
+
+    let invitee = Invitee::Username("bob".to_string());
+    Invitation::create(&pool, org.id, &invitee, Role::Viewer, user.id)
+        .await
+        .unwrap();
+    let invitations: Vec<Invitation> = select_all(&pool, "invitations").await;
+    assert_eq!(invitations[0].role, Role::Viewer);
+    let pending: Vec<PendingInvitation> = Invitation::list_for_user(&pool, &bob).await.unwrap();
+    assert_eq!(pending[0].organization, "acme");
+
+    Event::new("test.event")
+        .actor(&user)
+        .record(&pool, &RequestContext::default())
+        .await
+        .unwrap();
+    let events: Vec<AuditEvent> = select_all(&pool, "audit_events").await;
+    assert_eq!(events[0].action, "test.event");
+
+    NewJob::new("test.job", json!({ "user_id": user.id }))
+        .enqueue(&pool)
+        .await
+        .unwrap();
+    let jobs: Vec<Job> = select_all(&pool, "jobs").await;
+    assert_eq!(jobs[0].status, JobStatus::Pending);
+    User::set_admin(&pool, user.id, true).await.unwrap();
+    let admins = User::list_admins(&pool, &keyring()).await.unwrap();
+    assert_eq!(admins[0].id, user.id);
+
+    OAuthState::create(&pool, "github", "state", Some("verifier"), None, None)
+        .await
+        .unwrap();
+    let states: Vec<OAuthState> = select_all(&pool, "oauth_states").await;
+    assert_eq!(states[0].pkce_verifier.as_deref(), Some("verifier"));
+
+    SessionRecord::create(&pool, "session", Some(user.id), "{}", 60, 3600)
+        .await
+        .unwrap();
+    let sessions: Vec<SessionRecord> = select_all(&pool, "sessions").await;
+    assert_eq!(sessions[0].user_id, Some(user.id));
+}
+
+#[actix_web::test]
+async fn test_repository_crud() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = User::find_or_create(&pool, &keyring(), "github", "1", "alice", None, None)
+        .await
+        .unwrap();
+    let connection = GitProvider::upsert(
+        &pool,
+        &keyring(),
+        user.id,
+        "github",
+        "1",
+        "gho_a",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let new = NewRepository {
+        user_id: user.id,
+        organization_id: None,
+        provider_id: connection.id,
+        provider_repo_id: Some("42".to_string()),
+        name: "alice/site".to_string(),
+        description: Some("My site".to_string()),
+        url: "https://github.com/alice/site".to_string(),
+        clone_url: "https://github.com/alice/site.git".to_string(),
+        default_branch: Some("main".to_string()),
+        visibility: Visibility::Internal,
+    };
+    let created = Repository::create(&pool, &new).await.unwrap();
+    let found = Repository::find(&pool, created.id).await.unwrap().unwrap();
+    assert_eq!(found.provider_repo_id.as_deref(), Some("42"));
+    assert_eq!(found.name, new.name);
+    assert_eq!(found.description, new.description);
+    assert_eq!(found.url, new.url);
+    assert_eq!(found.clone_url, new.clone_url);
+    assert_eq!(found.default_branch, new.default_branch);
+    assert_eq!(found.visibility, Visibility::Internal);
+    assert_eq!(found.created_at, created.created_at);
+    assert_eq!(found.updated_at, None);
+
+    // The provider's id is unique per user and connection.
+    assert!(Repository::create(&pool, &new).await.is_err());
+    let invalid = sqlx::query("UPDATE repositories SET visibility = 'secret'")
+        .execute(&pool)
+        .await;
+    assert!(invalid.is_err());
+
+    let org = Organization::create(&pool, "acme", "Acme", user.id)
+        .await
+        .unwrap();
+    let updated = Repository {
+        organization_id: Some(org.id),
+        default_branch: Some("trunk".to_string()),
+        visibility: Visibility::Public,
+        ..found
+    }
+    .update(&pool)
+    .await
+    .unwrap();
+    assert_eq!(updated.default_branch.as_deref(), Some("trunk"));
+    assert_eq!(updated.visibility, Visibility::Public);
+    assert!(updated.updated_at.is_some());
+
+    // Deleting the organization deletes its repositories.
+    Organization::delete(&pool, org.id).await.unwrap();
+    assert!(Repository::find(&pool, updated.id).await.unwrap().is_none());
+
+    let created = Repository::create(&pool, &new).await.unwrap();
+    assert!(Repository::delete(&pool, created.id).await.unwrap());
+    assert!(!Repository::delete(&pool, created.id).await.unwrap());
+}
@@ -0,0 +1,407 @@
+mod common;
+
+use actix_session::storage::{SessionKey, SessionStore};
+use actix_web::{
+    cookie::{time::Duration, Cookie, CookieJar, Key, SameSite},
+    dev::ServiceResponse,
+    test,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{
+    keyring, setup_test_app, setup_test_app_with_session, setup_test_db, setup_test_env,
+    start_auth, test_session_config,
+};
+use paas_api::{
+    config::SessionConfig,
+    error::AppError,
+    models::User,
+    session::{SqliteSessionStore, SESSION_COOKIE_NAME},
+};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::{collections::HashMap, env};
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn state(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+async fn session_count(pool: &SqlitePool) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM sessions")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_sign_in_keeps_tokens_server_side() {
+    let _env = setup_test_env().await;
+    let mock_server = MockServer::start().await;
+    env::set_var(
+        "GITLAB_TOKEN_URL",
+        format!("{}/oauth/token", mock_server.uri()),
+    );
+    env::set_var(
+        "GITLAB_API_URL",
+        format!("{}/api/v4/user", mock_server.uri()),
+    );
+
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "gitlab_access_token",
+            "refresh_token": "gitlab_refresh_token",
+            "token_type": "bearer"
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v4/user"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": 7,
+            "username": "tanuki"
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let app = setup_test_app(pool.clone()).await;
+
+    let (state, login_cookie) = start_auth(&app, "gitlab").await;
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/api/auth/gitlab/callback?code=test_code&state={}",
+            state
+        ))
+        .cookie(login_cookie.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_redirection());
+    let cookie = resp
+        .response()
+        .cookies()
+        .find(|cookie| !cookie.value().is_empty())
+        .expect("renewed session cookie")
+        .into_owned();
+    assert_ne!(cookie.value(), login_cookie.value());
+
+    // The pre-login session was replaced by the signed-in one.
+    let user = User::find_or_create(&pool, &keyring(), "gitlab", "7", "tanuki", None, None)
+        .await
+        .unwrap();
+    let (user_id, stored_state): (Option<i64>, String) =
+        sqlx::query_as("SELECT user_id, state FROM sessions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(user_id, Some(user.id));
+    assert!(!stored_state.contains("gitlab_access_token"));
+    assert!(!stored_state.contains("gitlab_refresh_token"));
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["username"], "tanuki");
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(login_cookie)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/logout")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    assert_eq!(session_count(&pool).await, 0);
+
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn test_session_store_round_trip() {
+    let pool = setup_test_db().await;
+    let store = SqliteSessionStore::new(pool.clone(), Duration::days(30));
+    let ttl = Duration::hours(1);
+
+    let key = store
+        .save(state(&[("username", "\"octocat\"")]), &ttl)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load(&key).await.unwrap(),
+        Some(state(&[("username", "\"octocat\"")]))
+    );
+
+    // Only a hash of the key is stored.
+    let stored: Option<String> = sqlx::query_scalar("SELECT id FROM sessions WHERE id = ?")
+        .bind(key.as_ref())
+        .fetch_optional(&pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, None);
+
+    let key = store
+        .update(key, state(&[("username", "\"hubot\"")]), &ttl)
+        .await
+        .unwrap();
+    assert_eq!(
+        store.load(&key).await.unwrap(),
+        Some(state(&[("username", "\"hubot\"")]))
+    );
+
+    store.delete(&key).await.unwrap();
+    assert_eq!(store.load(&key).await.unwrap(), None);
+
+    let unknown: SessionKey = "unknown".to_string().try_into().unwrap();
+    assert_eq!(store.load(&unknown).await.unwrap(), None);
+}
+
+#[actix_web::test]
+async fn test_idle_sessions_expire() {
+    let pool = setup_test_db().await;
+    let store = SqliteSessionStore::new(pool.clone(), Duration::days(30));
+    let ttl = Duration::hours(1);
+
+    let key = store.save(state(&[]), &ttl).await.unwrap();
+    sqlx::query("UPDATE sessions SET expires_at = datetime('now', '-1 second')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(store.load(&key).await.unwrap(), None);
+
+    // Activity cannot revive an expired session.
+    store.update_ttl(&key, &ttl).await.unwrap();
+    assert_eq!(store.load(&key).await.unwrap(), None);
+
+    // Writing to it starts a new, empty session replacing the expired one,
+    // rather than bringing its state back.
+    let old_key = key.as_ref().to_string();
+    let key = store
+        .update(
+            key,
+            state(&[("user_id", "1"), ("username", "\"octocat\"")]),
+            &ttl,
+        )
+        .await
+        .unwrap();
+    assert_ne!(key.as_ref(), old_key);
+    assert_eq!(store.load(&key).await.unwrap(), Some(state(&[])));
+    assert_eq!(session_count(&pool).await, 1);
+    let user_id: Option<i64> = sqlx::query_scalar("SELECT user_id FROM sessions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(user_id, None);
+}
+
+#[actix_web::test]
+async fn test_sessions_expire_after_absolute_ttl() {
+    let pool = setup_test_db().await;
+    let store = SqliteSessionStore::new(pool.clone(), Duration::hours(1));
+
+    // The idle expiry is capped by the absolute one.
+    let key = store.save(state(&[]), &Duration::days(1)).await.unwrap();
+    let (expires_at, absolute_expires_at): (String, String) =
+        sqlx::query_as("SELECT expires_at, absolute_expires_at FROM sessions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(expires_at, absolute_expires_at);
+
+    sqlx::query("UPDATE sessions SET absolute_expires_at = datetime('now', '-1 second')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(store.load(&key).await.unwrap().is_some());
+
+    // The next request cannot extend the session past its absolute expiry.
+    store.update_ttl(&key, &Duration::days(1)).await.unwrap();
+    assert_eq!(store.load(&key).await.unwrap(), None);
+}
+
+/// Stores a signed-in session and returns its cookie, encrypted with `key`.
+async fn signed_in_cookie(pool: &SqlitePool, key: &Key) -> Cookie<'static> {
+    let user = User::find_or_create(pool, &keyring(), "github", "1", "octocat", None, None)
+        .await
+        .unwrap();
+    let store = SqliteSessionStore::new(pool.clone(), Duration::days(30));
+    let session_key = store
+        .save(
+            state(&[
+                ("user_id", &user.id.to_string()),
+                ("username", "\"octocat\""),
+            ]),
+            &Duration::hours(1),
+        )
+        .await
+        .unwrap();
+
+    let mut jar = CookieJar::new();
+    jar.private_mut(key).add(Cookie::new(
+        SESSION_COOKIE_NAME,
+        session_key.as_ref().to_string(),
+    ));
+    jar.get(SESSION_COOKIE_NAME).unwrap().clone()
+}
+
+fn response_cookie(resp: &ServiceResponse) -> Cookie<'static> {
+    resp.response()
+        .cookies()
+        .find(|cookie| cookie.name() == SESSION_COOKIE_NAME)
+        .expect("session cookie")
+        .into_owned()
+}
+
+async fn current_user<S>(app: &S, cookie: Cookie<'static>) -> ServiceResponse
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let req = test::TestRequest::get()
+        .uri("/api/user/me")
+        .cookie(cookie)
+        .to_request();
+    test::call_service(app, req).await
+}
+
+#[actix_web::test]
+async fn test_cookie_key_rotation() {
+    let pool = setup_test_db().await;
+    let old_key = Key::generate();
+    let new_key = Key::generate();
+    let cookie = signed_in_cookie(&pool, &old_key).await;
+
+    // Cookies from before the rotation are accepted and re-encrypted.
+    let app = setup_test_app_with_session(
+        pool.clone(),
+        test_session_config(vec![new_key.clone(), old_key.clone()]),
+    )
+    .await;
+    let resp = current_user(&app, cookie.clone()).await;
+    assert!(resp.status().is_success());
+    let resealed = response_cookie(&resp);
+    let mut jar = CookieJar::new();
+    jar.add_original(resealed.clone());
+    assert!(jar.private(&new_key).get(SESSION_COOKIE_NAME).is_some());
+
+    // Once the old key is dropped only re-encrypted cookies still work.
+    let app = setup_test_app_with_session(pool.clone(), test_session_config(vec![new_key])).await;
+    assert!(current_user(&app, resealed).await.status().is_success());
+    assert_eq!(current_user(&app, cookie).await.status(), 401);
+}
+
+#[actix_web::test]
+async fn test_cookie_attributes_follow_config() {
+    let pool = setup_test_db().await;
+    let key = Key::generate();
+    let cookie = signed_in_cookie(&pool, &key).await;
+
+    let app = setup_test_app_with_session(
+        pool.clone(),
+        SessionConfig {
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: Some("paas.example.com".to_string()),
+            idle_ttl: Duration::hours(2),
+            ..test_session_config(vec![key])
+        },
+    )
+    .await;
+    let resp = current_user(&app, cookie).await;
+    assert!(resp.status().is_success());
+
+    let cookie = response_cookie(&resp);
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    assert_eq!(cookie.domain(), Some("paas.example.com"));
+    assert_eq!(cookie.max_age(), Some(Duration::hours(2)));
+}
+
+#[actix_web::test]
+async fn test_session_config_from_env() {
+    let _env = setup_test_env().await;
+    let key = STANDARD.encode([7u8; 64]);
+
+    // Plain http development setup: a throwaway key and no Secure flag.
+    let config = SessionConfig::from_env().unwrap();
+    assert_eq!(config.keys.len(), 1);
+    assert!(!config.secure);
+    assert_eq!(config.same_site, SameSite::Lax);
+    assert_eq!(config.domain, None);
+
+    env::set_var(
+        "SESSION_COOKIE_KEYS",
+        format!("{},{}", key, STANDARD.encode([8u8; 64])),
+    );
+    env::set_var("SESSION_COOKIE_DOMAIN", "paas.example.com");
+    env::set_var("SESSION_IDLE_TTL_SECS", "600");
+    env::set_var("SESSION_ABSOLUTE_TTL_SECS", "3600");
+    let config = SessionConfig::from_env().unwrap();
+    assert_eq!(config.keys.len(), 2);
+    assert_eq!(config.domain.as_deref(), Some("paas.example.com"));
+    assert_eq!(config.idle_ttl, Duration::minutes(10));
+    assert_eq!(config.absolute_ttl, Duration::hours(1));
+
+    // https deployments default to Secure and refuse to turn it off.
+    env::set_var("BASE_URL", "https://paas.example.com");
+    assert!(SessionConfig::from_env().unwrap().secure);
+    env::set_var("SESSION_COOKIE_SECURE", "false");
+    assert!(matches!(
+        SessionConfig::from_env(),
+        Err(AppError::ConfigError(_))
+    ));
+    env::remove_var("SESSION_COOKIE_SECURE");
+    env::remove_var("SESSION_COOKIE_KEYS");
+    assert!(matches!(
+        SessionConfig::from_env(),
+        Err(AppError::ConfigError(_))
+    ));
+
+    env::set_var("BASE_URL", "http://127.0.0.1:3000");
+    for (name, value) in [
+        ("SESSION_COOKIE_KEYS", "not base64"),
+        ("SESSION_COOKIE_KEYS", "c2hvcnQ="),
+        ("SESSION_COOKIE_SECURE", "maybe"),
+        ("SESSION_COOKIE_SAME_SITE", "sometimes"),
+        // Browsers drop SameSite=None cookies without Secure.
+        ("SESSION_COOKIE_SAME_SITE", "none"),
+        ("SESSION_IDLE_TTL_SECS", "0"),
+        ("SESSION_ABSOLUTE_TTL_SECS", "forever"),
+    ] {
+        env::set_var(name, value);
+        match SessionConfig::from_env() {
+            Err(AppError::ConfigError(msg)) => {
+                assert!(!msg.contains(&key), "key material in {:?}", msg)
+            }
+            other => panic!("{}={:?} was accepted: {:?}", name, value, other.is_ok()),
+        }
+        env::remove_var(name);
+    }
+
+    env::set_var("SESSION_COOKIE_SAME_SITE", "none");
+    env::set_var("SESSION_COOKIE_SECURE", "true");
+    assert_eq!(SessionConfig::from_env().unwrap().same_site, SameSite::None);
+}
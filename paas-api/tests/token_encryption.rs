@@ -0,0 +1,244 @@
+mod common;
+
+use common::{keyring, setup_test_db, setup_test_env, TEST_ENCRYPTION_KEYS};
+use paas_api::{
+    crypto::Keyring,
+    db,
+    error::AppError,
+    models::{self, GitProvider, User},
+};
+use sqlx::SqlitePool;
+use std::env;
+
+const NEW_KEY: &str = "new:ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+type StoredTokens = (Option<String>, Option<String>, Option<String>);
+
+async fn stored_tokens(pool: &SqlitePool, table: &str) -> StoredTokens {
+    sqlx::query_as(&format!(
+        "SELECT access_token, refresh_token, token_key_id FROM {}",
+        table
+    ))
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn setup_connection(pool: &SqlitePool) -> User {
+    let user = User::find_or_create(pool, &keyring(), "github", "1", "octocat", None, None)
+        .await
+        .unwrap();
+    GitProvider::upsert(
+        pool,
+        &keyring(),
+        user.id,
+        "github",
+        "1",
+        "gho_access",
+        Some("ghr_refresh"),
+        Some(3600),
+    )
+    .await
+    .unwrap();
+    user
+}
+
+#[actix_web::test]
+async fn test_tokens_are_encrypted_at_rest() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = setup_connection(&pool).await;
+
+    let (access_token, refresh_token, key_id) = stored_tokens(&pool, "git_providers").await;
+    let access_token = access_token.unwrap();
+    let refresh_token = refresh_token.unwrap();
+    assert!(!access_token.contains("gho_access"));
+    assert!(!refresh_token.contains("ghr_refresh"));
+    assert_eq!(key_id.as_deref(), Some("test"));
+
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "github")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(connection.access_token, "gho_access");
+    assert_eq!(connection.refresh_token.as_deref(), Some("ghr_refresh"));
+
+    // Storing the same token again uses a fresh nonce.
+    GitProvider::upsert(
+        &pool,
+        &keyring(),
+        user.id,
+        "github",
+        "1",
+        "gho_access",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let (reencrypted, refresh_token, _) = stored_tokens(&pool, "git_providers").await;
+    assert_ne!(reencrypted.unwrap(), access_token);
+    assert_eq!(refresh_token, None);
+}
+
+#[actix_web::test]
+async fn test_refreshed_tokens_are_encrypted() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = setup_connection(&pool).await;
+
+    // Written under the old key, refreshed under the new one: the refresh
+    // token that is kept must be re-encrypted along with the access token.
+    env::set_var(
+        "TOKEN_ENCRYPTION_KEYS",
+        format!("{},{}", NEW_KEY, TEST_ENCRYPTION_KEYS),
+    );
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "github")
+        .await
+        .unwrap()
+        .unwrap();
+    connection
+        .update_tokens(&pool, &keyring(), "gho_refreshed", None, Some(3600))
+        .await
+        .unwrap();
+
+    let (access_token, _, key_id) = stored_tokens(&pool, "git_providers").await;
+    assert!(!access_token.unwrap().contains("gho_refreshed"));
+    assert_eq!(key_id.as_deref(), Some("new"));
+
+    env::set_var("TOKEN_ENCRYPTION_KEYS", NEW_KEY);
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "github")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(connection.access_token, "gho_refreshed");
+    assert_eq!(connection.refresh_token.as_deref(), Some("ghr_refresh"));
+}
+
+#[actix_web::test]
+async fn test_plaintext_tokens_are_encrypted_on_startup() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+
+    sqlx::query(
+        "INSERT INTO users (provider, provider_user_id, username, access_token, refresh_token)
+         VALUES ('github', '1', 'octocat', 'legacy_access', 'legacy_refresh')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO git_providers (user_id, provider, access_token, refresh_token)
+         VALUES (1, 'github', 'legacy_access', NULL)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    db::init_db(&pool).await.unwrap();
+
+    for table in ["users", "git_providers"] {
+        let (access_token, _, key_id) = stored_tokens(&pool, table).await;
+        assert!(
+            !access_token.unwrap().contains("legacy_access"),
+            "{}",
+            table
+        );
+        assert_eq!(key_id.as_deref(), Some("test"), "{}", table);
+    }
+    let (_, refresh_token, _) = stored_tokens(&pool, "git_providers").await;
+    assert_eq!(refresh_token, None);
+
+    let user = User::find_or_create(&pool, &keyring(), "github", "1", "octocat", None, None)
+        .await
+        .unwrap();
+    assert_eq!(user.access_token.as_deref(), Some("legacy_access"));
+    assert_eq!(user.refresh_token.as_deref(), Some("legacy_refresh"));
+
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "github")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(connection.access_token, "legacy_access");
+
+    // Already encrypted rows are left alone.
+    assert_eq!(models::encrypt_plaintext_tokens(&pool).await.unwrap(), 0);
+}
+
+#[actix_web::test]
+async fn test_key_rotation() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = setup_connection(&pool).await;
+
+    // Without the old key the tokens cannot be read.
+    env::set_var("TOKEN_ENCRYPTION_KEYS", NEW_KEY);
+    assert!(matches!(
+        GitProvider::find(&pool, &keyring(), user.id, "github").await,
+        Err(AppError::ConfigError(_))
+    ));
+
+    env::set_var(
+        "TOKEN_ENCRYPTION_KEYS",
+        format!("{},{}", NEW_KEY, TEST_ENCRYPTION_KEYS),
+    );
+    assert_eq!(models::rotate_token_key(&pool).await.unwrap(), 1);
+    assert_eq!(models::rotate_token_key(&pool).await.unwrap(), 0);
+
+    let (_, _, key_id) = stored_tokens(&pool, "git_providers").await;
+    assert_eq!(key_id.as_deref(), Some("new"));
+
+    env::set_var("TOKEN_ENCRYPTION_KEYS", NEW_KEY);
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "github")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(connection.access_token, "gho_access");
+    assert_eq!(connection.refresh_token.as_deref(), Some("ghr_refresh"));
+}
+
+#[actix_web::test]
+async fn test_tampered_tokens_are_rejected() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = setup_connection(&pool).await;
+
+    // Ciphertexts are bound to their column.
+    sqlx::query(
+        "UPDATE git_providers SET access_token = refresh_token, refresh_token = access_token",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        GitProvider::find(&pool, &keyring(), user.id, "github").await,
+        Err(AppError::DatabaseError(_))
+    ));
+}
+
+#[actix_web::test]
+async fn test_invalid_keys_are_rejected() {
+    let _env = setup_test_env().await;
+
+    env::remove_var("TOKEN_ENCRYPTION_KEYS");
+    assert!(matches!(Keyring::from_env(), Err(AppError::ConfigError(_))));
+
+    for keys in [
+        "",
+        "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=",
+        ":MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=",
+        "test:not base64",
+        "test:c2hvcnQ=",
+        "test:MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=,test:ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=",
+    ] {
+        env::set_var("TOKEN_ENCRYPTION_KEYS", keys);
+        match Keyring::from_env() {
+            Err(AppError::ConfigError(msg)) => {
+                assert!(!msg.contains("MDEyMzQ1"), "key material in {:?}", msg)
+            }
+            Err(other) => panic!("{:?}: expected a configuration error, got {:?}", keys, other),
+            Ok(_) => panic!("{:?}: expected a configuration error", keys),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+mod common;
+
+use common::{keyring, setup_test_db, setup_test_env};
+use paas_api::{
+    error::AppError,
+    models::{GitProvider, User},
+    tokens::TokenService,
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::{env, sync::Arc};
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+/// Creates a GitLab user whose stored connection expires in `expires_in_secs`.
+async fn setup_connection(pool: &SqlitePool, expires_in_secs: i64) -> (User, GitProvider) {
+    let user = User::find_or_create(pool, &keyring(), "gitlab", "42", "tanuki", None, None)
+        .await
+        .unwrap();
+    let connection = GitProvider::upsert(
+        pool,
+        &keyring(),
+        user.id,
+        "gitlab",
+        "42",
+        "old_access_token",
+        Some("old_refresh_token"),
+        Some(expires_in_secs),
+    )
+    .await
+    .unwrap();
+
+    (user, connection)
+}
+
+async fn setup_token_endpoint() -> MockServer {
+    let mock_server = MockServer::start().await;
+    env::set_var(
+        "GITLAB_TOKEN_URL",
+        format!("{}/oauth/token", mock_server.uri()),
+    );
+    mock_server
+}
+
+#[actix_web::test]
+async fn test_valid_token_is_not_refreshed() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let (user, _) = setup_connection(&pool, 3600).await;
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    assert_eq!(
+        tokens.access_token(user.id, "gitlab").await.unwrap(),
+        "old_access_token"
+    );
+}
+
+#[actix_web::test]
+async fn test_expired_token_is_refreshed() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=old_refresh_token"))
+        .and(body_string_contains("redirect_uri="))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "new_access_token",
+            "token_type": "bearer",
+            "refresh_token": "new_refresh_token",
+            "expires_in": 7200
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let (user, _) = setup_connection(&pool, -10).await;
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    assert_eq!(
+        tokens.access_token(user.id, "gitlab").await.unwrap(),
+        "new_access_token"
+    );
+
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "gitlab")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(connection.access_token, "new_access_token");
+    assert_eq!(
+        connection.refresh_token.as_deref(),
+        Some("new_refresh_token")
+    );
+    assert!(!connection.expires_within(3600));
+}
+
+#[actix_web::test]
+async fn test_token_expiring_soon_is_refreshed() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "new_access_token",
+            "token_type": "bearer",
+            "expires_in": 7200
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let (user, _) = setup_connection(&pool, 30).await;
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    assert_eq!(
+        tokens.access_token(user.id, "gitlab").await.unwrap(),
+        "new_access_token"
+    );
+
+    // Refresh tokens are only replaced when the provider rotates them.
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "gitlab")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        connection.refresh_token.as_deref(),
+        Some("old_refresh_token")
+    );
+}
+
+#[actix_web::test]
+async fn test_concurrent_requests_refresh_once() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "access_token": "new_access_token",
+                    "token_type": "bearer",
+                    "refresh_token": "new_refresh_token",
+                    "expires_in": 7200
+                }))
+                .set_delay(std::time::Duration::from_millis(100)),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let (user, _) = setup_connection(&pool, -10).await;
+
+    let tokens = Arc::new(TokenService::new(pool.clone(), keyring()));
+    let (first, second) = tokio::join!(
+        tokens.access_token(user.id, "gitlab"),
+        tokens.access_token(user.id, "gitlab")
+    );
+    assert_eq!(first.unwrap(), "new_access_token");
+    assert_eq!(second.unwrap(), "new_access_token");
+}
+
+#[actix_web::test]
+async fn test_rejected_refresh_requires_reauth() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "invalid_grant",
+            "error_description": "The refresh token is invalid"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let pool = setup_test_db().await;
+    let (user, _) = setup_connection(&pool, -10).await;
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    let result = tokens.access_token(user.id, "gitlab").await;
+    assert!(matches!(result, Err(AppError::AuthError(_))));
+
+    let connection = GitProvider::find(&pool, &keyring(), user.id, "gitlab")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(connection.needs_reauth);
+
+    // No further refresh attempts until the user signs in again.
+    let result = tokens.access_token(user.id, "gitlab").await;
+    assert!(matches!(result, Err(AppError::AuthError(_))));
+
+    GitProvider::upsert(
+        &pool,
+        &keyring(),
+        user.id,
+        "gitlab",
+        "42",
+        "fresh_token",
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        tokens.access_token(user.id, "gitlab").await.unwrap(),
+        "fresh_token"
+    );
+}
+
+#[actix_web::test]
+async fn test_unreachable_provider_does_not_require_reauth() {
+    let _env = setup_test_env().await;
+    let mock_server = setup_token_endpoint().await;
+    drop(mock_server);
+
+    let pool = setup_test_db().await;
+    let (user, connection) = setup_connection(&pool, -10).await;
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    let result = tokens.access_token(user.id, "gitlab").await;
+    assert!(matches!(result, Err(AppError::ExternalServiceError(_))));
+
+    let connection = GitProvider::find(&pool, &keyring(), user.id, &connection.provider)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!connection.needs_reauth);
+}
+
+#[actix_web::test]
+async fn test_expired_token_without_refresh_token_requires_reauth() {
+    let _env = setup_test_env().await;
+    let pool = setup_test_db().await;
+    let user = User::find_or_create(&pool, &keyring(), "gitlab", "42", "tanuki", None, None)
+        .await
+        .unwrap();
+    GitProvider::upsert(
+        &pool,
+        &keyring(),
+        user.id,
+        "gitlab",
+        "42",
+        "old_access_token",
+        None,
+        Some(-10),
+    )
+    .await
+    .unwrap();
+
+    let tokens = TokenService::new(pool.clone(), keyring());
+    let result = tokens.access_token(user.id, "gitlab").await;
+    assert!(matches!(result, Err(AppError::AuthError(_))));
+    assert!(
+        GitProvider::find(&pool, &keyring(), user.id, "gitlab")
+            .await
+            .unwrap()
+            .unwrap()
+            .needs_reauth
+    );
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::api::{json, request};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct App {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    pub organization_id: Option<i64>,
+    pub repository_id: Option<i64>,
+    /// Name of the repository, e.g. "octocat/hello-world".
+    pub repository: Option<String>,
+    pub branch: Option<String>,
+    pub root_dir: String,
+    pub build_command: Option<String>,
+    pub start_command: Option<String>,
+    pub runtime_version: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// The fields of a new app. Blank optional fields are left to the API's
+/// defaults.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct NewApp {
+    pub name: String,
+    pub slug: Option<String>,
+    /// The organization to create the app in; the personal account otherwise.
+    pub org: Option<String>,
+    pub repository_id: Option<i64>,
+    pub branch: Option<String>,
+    pub root_dir: String,
+    pub build_command: Option<String>,
+    pub start_command: Option<String>,
+    pub runtime_version: Option<String>,
+}
+
+pub struct AppApi;
+
+impl AppApi {
+    /// The apps of `org`, or of the personal account.
+    pub async fn list(org: Option<&str>) -> Result<Vec<App>, JsValue> {
+        let path = match org {
+            Some(org) => format!("/api/apps?org={}", js_sys::encode_uri_component(org)),
+            None => "/api/apps".to_string(),
+        };
+        json(request("GET", &path, None).await?).await
+    }
+
+    pub async fn create(app: &NewApp) -> Result<App, JsValue> {
+        let body = serde_json::to_value(app).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        json(request("POST", "/api/apps", Some(&body)).await?).await
+    }
+
+    pub async fn get(slug: &str) -> Result<App, JsValue> {
+        json(request("GET", &format!("/api/apps/{}", slug), None).await?).await
+    }
+
+    pub async fn delete(slug: &str) -> Result<(), JsValue> {
+        request("DELETE", &format!("/api/apps/{}", slug), None).await?;
+        Ok(())
+    }
+}
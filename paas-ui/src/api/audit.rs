@@ -0,0 +1,77 @@
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::api::{json, request};
+use crate::config::Config;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor: Option<String>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub metadata: serde_json::Value,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub next_cursor: Option<i64>,
+}
+
+/// Filters of the audit log. Empty fields are left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditFilter {
+    /// The organization whose log to show; the user's own otherwise.
+    pub org: Option<String>,
+    pub action: String,
+    pub actor: String,
+    pub since: String,
+    pub until: String,
+}
+
+impl AuditFilter {
+    fn query_string(&self) -> String {
+        let org = self.org.as_deref().unwrap_or_default();
+        [
+            ("org", org),
+            ("action", self.action.as_str()),
+            ("actor", self.actor.as_str()),
+            ("since", self.since.as_str()),
+            ("until", self.until.as_str()),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(key, value)| format!("{}={}", key, js_sys::encode_uri_component(value.trim())))
+        .collect::<Vec<_>>()
+        .join("&")
+    }
+}
+
+pub struct AuditApi;
+
+impl AuditApi {
+    /// Fetches a page of events, continuing after `cursor` if given.
+    pub async fn list(filter: &AuditFilter, cursor: Option<i64>) -> Result<AuditPage, JsValue> {
+        let mut path = format!("/api/audit?{}", filter.query_string());
+        if let Some(cursor) = cursor {
+            path.push_str(&format!("&cursor={}", cursor));
+        }
+        json(request("GET", &path, None).await?).await
+    }
+
+    /// The URL downloading every matching event as NDJSON.
+    pub fn export_url(filter: &AuditFilter) -> String {
+        let config = use_context::<Config>().expect("Config not found in context");
+        format!(
+            "{}/api/audit/export?{}",
+            config.api_host,
+            filter.query_string()
+        )
+    }
+}
@@ -18,6 +18,14 @@ pub struct AuthResponse {
     pub auth_url: String,
 }
 
+/// A provider instance offered on the login page.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LoginProvider {
+    pub slug: String,
+    pub provider_type: String,
+    pub display_name: String,
+}
+
 impl AuthError {
     pub fn as_string(&self) -> String {
         self.error_description
@@ -37,7 +45,7 @@ impl AuthApi {
         let config = Self::get_config();
         let window = web_sys::window().unwrap();
 
-        let mut opts = RequestInit::new();
+        let opts = RequestInit::new();
         opts.set_method("GET");
         opts.set_mode(RequestMode::Cors);
         opts.set_credentials(web_sys::RequestCredentials::Include);
@@ -62,24 +70,35 @@ impl AuthApi {
         }
     }
 
-    pub async fn github_auth() -> Result<(), JsValue> {
-        let auth_url = Self::get_oauth_url("github").await?;
+    pub async fn list_providers() -> Result<Vec<LoginProvider>, JsValue> {
+        let config = Self::get_config();
         let window = web_sys::window().unwrap();
-        let location = window.location();
-        location.set_href(&auth_url)?;
-        Ok(())
-    }
 
-    pub async fn gitlab_auth() -> Result<(), JsValue> {
-        let auth_url = Self::get_oauth_url("gitlab").await?;
-        let window = web_sys::window().unwrap();
-        let location = window.location();
-        location.set_href(&auth_url)?;
-        Ok(())
+        let opts = RequestInit::new();
+        opts.set_method("GET");
+        opts.set_mode(RequestMode::Cors);
+
+        let request = Request::new_with_str_and_init(
+            &format!("{}/api/auth/providers", config.api_host),
+            &opts,
+        )?;
+        request.headers().set("Accept", "application/json")?;
+
+        let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+        let resp: Response = resp_value.dyn_into()?;
+
+        let json = JsFuture::from(resp.json()?).await?;
+        if resp.ok() {
+            Ok(serde_wasm_bindgen::from_value(json)?)
+        } else {
+            let error: AuthError = serde_wasm_bindgen::from_value(json)?;
+            Err(JsValue::from_str(&error.as_string()))
+        }
     }
 
-    pub async fn bitbucket_auth() -> Result<(), JsValue> {
-        let auth_url = Self::get_oauth_url("bitbucket").await?;
+    /// Redirects the browser to the provider instance's authorization page.
+    pub async fn login_with(provider: &str) -> Result<(), JsValue> {
+        let auth_url = Self::get_oauth_url(provider).await?;
         let window = web_sys::window().unwrap();
         let location = window.location();
         location.set_href(&auth_url)?;
@@ -93,7 +112,7 @@ impl AuthApi {
         let search = location.search()?;
         let params = web_sys::UrlSearchParams::new_with_str(&search)?;
 
-        if let Some(error) = params.get("error") {
+        if params.get("error").is_some() {
             let error_description = params
                 .get("error_description")
                 .unwrap_or_else(|| "Authentication failed".to_string());
@@ -113,7 +132,7 @@ impl AuthApi {
             .get("state")
             .ok_or_else(|| JsValue::from_str("No state parameter found"))?;
 
-        let mut opts = RequestInit::new();
+        let opts = RequestInit::new();
         opts.set_method("GET");
         opts.set_mode(RequestMode::Cors);
         opts.set_credentials(web_sys::RequestCredentials::Include);
@@ -157,7 +176,7 @@ impl AuthApi {
         let config = Self::get_config();
         let window = web_sys::window().unwrap();
 
-        let mut opts = RequestInit::new();
+        let opts = RequestInit::new();
         opts.set_method("POST");
         opts.set_mode(RequestMode::Cors);
         opts.set_credentials(web_sys::RequestCredentials::Include);
@@ -175,7 +194,7 @@ impl AuthApi {
             let json = JsFuture::from(resp.json()?).await?;
             let error: serde_json::Value = serde_wasm_bindgen::from_value(json)?;
             Err(JsValue::from_str(
-                &error["error"].as_str().unwrap_or("Unknown error"),
+                error["error"].as_str().unwrap_or("Unknown error"),
             ))
         }
     }
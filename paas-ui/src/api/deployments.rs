@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wasm_bindgen::JsValue;
+
+use crate::api::{json, request};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Deployment {
+    pub id: i64,
+    pub app_id: i64,
+    pub commit_sha: String,
+    /// One of queued, building, releasing, running, failed, cancelled and
+    /// superseded.
+    pub status: String,
+    pub triggered_by: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub building_at: Option<String>,
+    pub releasing_at: Option<String>,
+    pub running_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// Set once the build has looked at the source.
+    #[serde(default)]
+    pub build_plan: Option<BuildPlan>,
+}
+
+/// How a deployment is built and started.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BuildPlan {
+    /// One of docker, rust, go, ruby, python and node.
+    pub language: String,
+    pub runtime_version: Option<String>,
+    pub build_commands: Vec<String>,
+    pub start_command: Option<String>,
+}
+
+impl BuildPlan {
+    /// The language and its version, e.g. "node 20".
+    pub fn runtime(&self) -> String {
+        match &self.runtime_version {
+            Some(version) => format!("{} {}", self.language, version),
+            None => self.language.clone(),
+        }
+    }
+}
+
+impl Deployment {
+    /// Only deployments still waiting or building can be cancelled.
+    pub fn cancellable(&self) -> bool {
+        matches!(self.status.as_str(), "queued" | "building")
+    }
+}
+
+pub struct DeploymentApi;
+
+impl DeploymentApi {
+    /// The latest deployments of app `slug`, newest first.
+    pub async fn list(slug: &str) -> Result<Vec<Deployment>, JsValue> {
+        json(request("GET", &format!("/api/apps/{}/deployments", slug), None).await?).await
+    }
+
+    pub async fn create(slug: &str, commit_sha: &str) -> Result<Deployment, JsValue> {
+        let body = json!({ "commit_sha": commit_sha });
+        let path = format!("/api/apps/{}/deployments", slug);
+        json(request("POST", &path, Some(&body)).await?).await
+    }
+
+    pub async fn cancel(slug: &str, id: i64) -> Result<Deployment, JsValue> {
+        let path = format!("/api/apps/{}/deployments/{}/cancel", slug, id);
+        json(request("POST", &path, None).await?).await
+    }
+}
@@ -1,5 +1,66 @@
+use leptos::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, RequestMode, Response};
+
+use crate::config::Config;
+
+pub mod apps;
+pub mod audit;
 pub mod auth;
+pub mod deployments;
+pub mod orgs;
+pub mod repositories;
 pub mod user;
 
+pub use apps::AppApi;
+pub use audit::AuditApi;
 pub use auth::AuthApi;
+pub use deployments::DeploymentApi;
+pub use orgs::OrgApi;
+pub use repositories::RepositoryApi;
 pub use user::UserApi;
+
+/// Sends an authenticated request to the API, with `body` as JSON if given.
+/// Error responses are turned into their `error` message.
+pub(crate) async fn request(
+    method: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<Response, JsValue> {
+    let config = use_context::<Config>().expect("Config not found in context");
+    let window = web_sys::window().unwrap();
+
+    let opts = RequestInit::new();
+    opts.set_method(method);
+    opts.set_mode(RequestMode::Cors);
+    opts.set_credentials(web_sys::RequestCredentials::Include);
+    if let Some(body) = body {
+        opts.set_body(&JsValue::from_str(&body.to_string()));
+    }
+
+    let request = Request::new_with_str_and_init(&format!("{}{}", config.api_host, path), &opts)?;
+    request.headers().set("Accept", "application/json")?;
+    if body.is_some() {
+        request.headers().set("Content-Type", "application/json")?;
+    }
+
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if resp.ok() {
+        return Ok(resp);
+    }
+
+    let json = JsFuture::from(resp.json()?).await?;
+    let error: serde_json::Value = serde_wasm_bindgen::from_value(json)?;
+    Err(JsValue::from_str(
+        error["error"].as_str().unwrap_or("Unknown error"),
+    ))
+}
+
+/// Parses the JSON body of a successful response.
+pub(crate) async fn json<T: serde::de::DeserializeOwned>(resp: Response) -> Result<T, JsValue> {
+    let json = JsFuture::from(resp.json()?).await?;
+    Ok(serde_wasm_bindgen::from_value(json)?)
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wasm_bindgen::JsValue;
+
+use crate::api::{json, request};
+
+/// Roles in the order they are offered, most privileged first.
+pub const ROLES: &[&str] = &["owner", "admin", "developer", "viewer"];
+
+/// An organization the signed-in user belongs to, with their role in it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Organization {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    pub role: String,
+    pub created_at: String,
+}
+
+impl Organization {
+    /// Whether the user may invite and manage members.
+    pub fn can_manage(&self) -> bool {
+        matches!(self.role.as_str(), "owner" | "admin")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Member {
+    pub user_id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub avatar_url: Option<String>,
+    pub role: String,
+    pub joined_at: String,
+}
+
+/// An open invitation of an organization.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Invitation {
+    pub id: i64,
+    pub invitee: String,
+    pub role: String,
+    pub expires_at: String,
+}
+
+/// An invitation addressed to the signed-in user.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PendingInvitation {
+    pub id: i64,
+    pub organization: String,
+    pub organization_name: String,
+    pub role: String,
+    pub invited_by: Option<String>,
+    pub expires_at: String,
+}
+
+pub struct OrgApi;
+
+impl OrgApi {
+    pub async fn list() -> Result<Vec<Organization>, JsValue> {
+        json(request("GET", "/api/orgs", None).await?).await
+    }
+
+    pub async fn create(name: &str) -> Result<Organization, JsValue> {
+        let body = json!({ "name": name });
+        json(request("POST", "/api/orgs", Some(&body)).await?).await
+    }
+
+    pub async fn get(slug: &str) -> Result<Organization, JsValue> {
+        json(request("GET", &format!("/api/orgs/{}", slug), None).await?).await
+    }
+
+    pub async fn delete(slug: &str) -> Result<(), JsValue> {
+        request("DELETE", &format!("/api/orgs/{}", slug), None).await?;
+        Ok(())
+    }
+
+    pub async fn members(slug: &str) -> Result<Vec<Member>, JsValue> {
+        json(request("GET", &format!("/api/orgs/{}/members", slug), None).await?).await
+    }
+
+    pub async fn set_role(slug: &str, user_id: i64, role: &str) -> Result<Member, JsValue> {
+        let body = json!({ "role": role });
+        let path = format!("/api/orgs/{}/members/{}", slug, user_id);
+        json(request("PUT", &path, Some(&body)).await?).await
+    }
+
+    pub async fn remove_member(slug: &str, user_id: i64) -> Result<(), JsValue> {
+        let path = format!("/api/orgs/{}/members/{}", slug, user_id);
+        request("DELETE", &path, None).await?;
+        Ok(())
+    }
+
+    pub async fn invitations(slug: &str) -> Result<Vec<Invitation>, JsValue> {
+        json(request("GET", &format!("/api/orgs/{}/invitations", slug), None).await?).await
+    }
+
+    /// Invites a user by username or email address.
+    pub async fn invite(slug: &str, invitee: &str, role: &str) -> Result<Invitation, JsValue> {
+        let body = json!({ "invitee": invitee, "role": role });
+        let path = format!("/api/orgs/{}/invitations", slug);
+        json(request("POST", &path, Some(&body)).await?).await
+    }
+
+    pub async fn withdraw_invitation(slug: &str, id: i64) -> Result<(), JsValue> {
+        let path = format!("/api/orgs/{}/invitations/{}", slug, id);
+        request("DELETE", &path, None).await?;
+        Ok(())
+    }
+
+    pub async fn my_invitations() -> Result<Vec<PendingInvitation>, JsValue> {
+        json(request("GET", "/api/orgs/invitations", None).await?).await
+    }
+
+    pub async fn accept_invitation(id: i64) -> Result<Organization, JsValue> {
+        let path = format!("/api/orgs/invitations/{}/accept", id);
+        json(request("POST", &path, None).await?).await
+    }
+
+    pub async fn decline_invitation(id: i64) -> Result<(), JsValue> {
+        request("DELETE", &format!("/api/orgs/invitations/{}", id), None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::api::{json, request};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Repository {
+    pub id: i64,
+    pub provider: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub url: String,
+    pub clone_url: String,
+    pub default_branch: Option<String>,
+    /// "public", "internal" or "private".
+    pub visibility: String,
+    pub last_synced: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RepositoryPage {
+    pub repositories: Vec<Repository>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl RepositoryPage {
+    pub fn page_count(&self) -> i64 {
+        ((self.total + self.per_page - 1) / self.per_page).max(1)
+    }
+}
+
+/// The outcome of syncing one provider connection.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SyncResult {
+    pub provider: String,
+    #[serde(default)]
+    pub synced: u64,
+    #[serde(default)]
+    pub removed: u64,
+    pub error: Option<String>,
+}
+
+pub struct RepositoryApi;
+
+impl RepositoryApi {
+    /// Fetches a page of the user's repositories whose name contains `search`.
+    pub async fn list(search: &str, page: i64) -> Result<RepositoryPage, JsValue> {
+        let mut path = format!("/api/repositories?page={}", page);
+        if !search.trim().is_empty() {
+            path.push_str(&format!(
+                "&q={}",
+                js_sys::encode_uri_component(search.trim())
+            ));
+        }
+        json(request("GET", &path, None).await?).await
+    }
+
+    /// Re-imports the repositories of every connected provider.
+    pub async fn sync() -> Result<Vec<SyncResult>, JsValue> {
+        json(request("POST", "/api/repositories/sync", None).await?).await
+    }
+}
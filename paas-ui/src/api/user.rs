@@ -5,6 +5,7 @@ use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{Request, RequestInit, RequestMode, Response};
 
+use crate::api::request;
 use crate::config::Config;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -14,6 +15,23 @@ pub struct User {
     pub email: Option<String>,
 }
 
+/// A provider connected to the signed-in user's account.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConnectedProvider {
+    pub provider: String,
+    pub provider_type: String,
+    pub display_name: String,
+    pub needs_reauth: bool,
+    /// Whether this is the provider the account was created with.
+    pub primary: bool,
+    pub connected_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConnectResponse {
+    auth_url: String,
+}
+
 pub struct UserApi;
 
 impl UserApi {
@@ -25,7 +43,7 @@ impl UserApi {
         let config = Self::get_config();
         let window = web_sys::window().unwrap();
 
-        let mut opts = RequestInit::new();
+        let opts = RequestInit::new();
         opts.set_method("GET");
         opts.set_mode(RequestMode::Cors);
         opts.set_credentials(web_sys::RequestCredentials::Include);
@@ -44,8 +62,37 @@ impl UserApi {
             let json = JsFuture::from(resp.json()?).await?;
             let error: serde_json::Value = serde_wasm_bindgen::from_value(json)?;
             Err(JsValue::from_str(
-                &error["error"].as_str().unwrap_or("Unknown error"),
+                error["error"].as_str().unwrap_or("Unknown error"),
             ))
         }
     }
+
+    pub async fn list_providers() -> Result<Vec<ConnectedProvider>, JsValue> {
+        let resp = request("GET", "/api/user/providers", None).await?;
+        let json = JsFuture::from(resp.json()?).await?;
+        Ok(serde_wasm_bindgen::from_value(json)?)
+    }
+
+    /// Redirects the browser to the provider instance's authorization page;
+    /// the API sends it back to the settings page once connected.
+    pub async fn connect_provider(provider: &str) -> Result<(), JsValue> {
+        let resp = request(
+            "GET",
+            &format!("/api/user/providers/{}/connect", provider),
+            None,
+        )
+        .await?;
+        let json = JsFuture::from(resp.json()?).await?;
+        let response: ConnectResponse = serde_wasm_bindgen::from_value(json)?;
+        web_sys::window()
+            .unwrap()
+            .location()
+            .set_href(&response.auth_url)?;
+        Ok(())
+    }
+
+    pub async fn disconnect_provider(provider: &str) -> Result<(), JsValue> {
+        request("DELETE", &format!("/api/user/providers/{}", provider), None).await?;
+        Ok(())
+    }
 }
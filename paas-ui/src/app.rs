@@ -2,13 +2,17 @@ use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 
-use crate::components::nav::NavBar;
+use crate::components::nav::{provide_current_org, NavBar};
 use crate::config::ConfigProvider;
-use crate::pages::{Dashboard, Home, Login, OAuthCallback};
+use crate::pages::{
+    AppDetail, Apps, AuditLog, CreateApp, Dashboard, Home, Login, OAuthCallback,
+    OrganizationDetail, Organizations, Repositories, Settings,
+};
 
 #[component]
 pub fn App() -> impl IntoView {
     provide_meta_context();
+    provide_current_org();
 
     view! {
         <Stylesheet id="leptos" href="/pkg/tailwind.css"/>
@@ -23,9 +27,15 @@ pub fn App() -> impl IntoView {
                         <Route path="" view=Home/>
                         <Route path="/login" view=Login/>
                         <Route path="/dashboard" view=Dashboard/>
-                        <Route path="/auth/github/callback" view=OAuthCallback/>
-                        <Route path="/auth/gitlab/callback" view=OAuthCallback/>
-                        <Route path="/auth/bitbucket/callback" view=OAuthCallback/>
+                        <Route path="/settings" view=Settings/>
+                        <Route path="/orgs" view=Organizations/>
+                        <Route path="/orgs/:slug" view=OrganizationDetail/>
+                        <Route path="/apps" view=Apps/>
+                        <Route path="/apps/new" view=CreateApp/>
+                        <Route path="/apps/:slug" view=AppDetail/>
+                        <Route path="/repositories" view=Repositories/>
+                        <Route path="/audit" view=AuditLog/>
+                        <Route path="/auth/:provider/callback" view=OAuthCallback/>
                     </Routes>
                 </main>
             </Router>
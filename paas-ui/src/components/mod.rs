@@ -1,2 +1,3 @@
 pub mod loading;
 pub mod nav;
+pub mod repository_picker;
@@ -1,7 +1,78 @@
-use crate::api::{AuthApi, UserApi};
+use crate::api::{AuthApi, OrgApi, UserApi};
+use gloo::storage::{LocalStorage, Storage};
 use leptos::*;
 use leptos_router::*;
 
+const CURRENT_ORG_KEY: &str = "current_org";
+
+/// The organization the UI works in, by slug; None for the user's personal
+/// account. Kept in local storage so it survives reloads.
+#[derive(Clone, Copy)]
+pub struct CurrentOrg(pub RwSignal<Option<String>>);
+
+pub fn provide_current_org() {
+    let current = create_rw_signal(LocalStorage::get::<String>(CURRENT_ORG_KEY).ok());
+    create_effect(move |_| match current.get() {
+        Some(slug) => {
+            if let Err(err) = LocalStorage::set(CURRENT_ORG_KEY, slug) {
+                log::warn!("Failed to remember organization: {}", err);
+            }
+        }
+        None => LocalStorage::delete(CURRENT_ORG_KEY),
+    });
+    provide_context(CurrentOrg(current));
+}
+
+pub fn use_current_org() -> RwSignal<Option<String>> {
+    use_context::<CurrentOrg>()
+        .expect("CurrentOrg not found in context")
+        .0
+}
+
+/// Switches between the personal account and the user's organizations.
+#[component]
+pub fn OrgSwitcher() -> impl IntoView {
+    let current = use_current_org();
+    let orgs = create_resource(|| (), |_| async move { OrgApi::list().await.ok() });
+
+    // Forget an organization the user no longer belongs to.
+    create_effect(move |_| {
+        if let Some(Some(orgs)) = orgs.get() {
+            let known = current.with_untracked(|slug| {
+                slug.as_ref()
+                    .is_none_or(|slug| orgs.iter().any(|org| &org.slug == slug))
+            });
+            if !known {
+                current.set(None);
+            }
+        }
+    });
+
+    view! {
+        <select
+            class="block w-44 pl-3 pr-8 py-1.5 text-sm border-gray-300 rounded-md focus:outline-none focus:ring-blue-500 focus:border-blue-500"
+            aria-label="Organization"
+            on:change=move |ev| {
+                let slug = event_target_value(&ev);
+                current.set((!slug.is_empty()).then_some(slug));
+            }
+        >
+            <option value="" selected=move || current.get().is_none()>"Personal"</option>
+            {move || orgs.get().flatten().unwrap_or_default().into_iter().map(|org| {
+                let slug = org.slug.clone();
+                view! {
+                    <option
+                        value=org.slug.clone()
+                        selected=move || current.get().as_deref() == Some(slug.as_str())
+                    >
+                        {org.name}
+                    </option>
+                }
+            }).collect_view()}
+        </select>
+    }
+}
+
 #[component]
 pub fn NavBar() -> impl IntoView {
     let user_resource = create_resource(
@@ -16,22 +87,16 @@ pub fn NavBar() -> impl IntoView {
         },
     );
 
-    let user_resource_clone = user_resource.clone();
-
-    let logout = create_action(move |_: &()| {
-        let user_resource = user_resource_clone.clone();
-
-        async move {
-            match AuthApi::logout().await {
-                Ok(_) => {
-                    user_resource.refetch();
-                    window().location().set_href("/login").unwrap();
-                    Ok(())
-                }
-                Err(err) => Err(err
-                    .as_string()
-                    .unwrap_or_else(|| "Unknown error".to_string())),
+    let logout = create_action(move |_: &()| async move {
+        match AuthApi::logout().await {
+            Ok(_) => {
+                user_resource.refetch();
+                window().location().set_href("/login").unwrap();
+                Ok(())
             }
+            Err(err) => Err(err
+                .as_string()
+                .unwrap_or_else(|| "Unknown error".to_string())),
         }
     });
 
@@ -49,10 +114,41 @@ pub fn NavBar() -> impl IntoView {
                         {move || user_resource.get().map(|result| match result {
                             Ok(user) => view! {
                                 <div class="flex items-center space-x-4">
+                                    <OrgSwitcher />
                                     <div class="flex items-center space-x-2">
                                         <span class="text-sm text-gray-700">
                                             {user.username}
                                         </span>
+                                        <A
+                                            href="/orgs"
+                                            class="text-sm font-medium text-gray-600 hover:text-gray-900"
+                                        >
+                                            "Organizations"
+                                        </A>
+                                        <A
+                                            href="/apps"
+                                            class="text-sm font-medium text-gray-600 hover:text-gray-900"
+                                        >
+                                            "Apps"
+                                        </A>
+                                        <A
+                                            href="/repositories"
+                                            class="text-sm font-medium text-gray-600 hover:text-gray-900"
+                                        >
+                                            "Repositories"
+                                        </A>
+                                        <A
+                                            href="/audit"
+                                            class="text-sm font-medium text-gray-600 hover:text-gray-900"
+                                        >
+                                            "Audit log"
+                                        </A>
+                                        <A
+                                            href="/settings"
+                                            class="text-sm font-medium text-gray-600 hover:text-gray-900"
+                                        >
+                                            "Settings"
+                                        </A>
                                         <button
                                             class="inline-flex items-center px-4 py-2 border border-transparent text-sm font-medium rounded-md text-white bg-red-600 hover:bg-red-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-red-500"
                                             on:click=move |_| logout.dispatch(())
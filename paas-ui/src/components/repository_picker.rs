@@ -0,0 +1,109 @@
+use leptos::*;
+
+use crate::api::repositories::{Repository, RepositoryPage};
+use crate::api::RepositoryApi;
+use crate::pages::orgs::{error_banner, error_string, INPUT_CLASS};
+
+/// A searchable, paged list of the user's imported repositories. Clicking one
+/// passes it to `on_select`; the list is fetched again whenever `reload`
+/// changes.
+#[component]
+pub fn RepositoryPicker(
+    #[prop(into)] on_select: Callback<Repository>,
+    #[prop(optional, into)] selected: MaybeSignal<Option<i64>>,
+    #[prop(optional, into)] reload: MaybeSignal<usize>,
+) -> impl IntoView {
+    let (search, set_search) = create_signal(String::new());
+    let (page, set_page) = create_signal(1_i64);
+    let repositories = create_resource(
+        move || (search.get(), page.get(), reload.get()),
+        |(search, page, _)| async move {
+            RepositoryApi::list(&search, page)
+                .await
+                .map_err(error_string)
+        },
+    );
+
+    let repository_row = move |repository: Repository| {
+        let id = repository.id;
+        let class = move || {
+            if selected.get() == Some(id) {
+                "w-full text-left px-4 py-3 bg-blue-50"
+            } else {
+                "w-full text-left px-4 py-3 hover:bg-gray-50"
+            }
+        };
+        let (name, provider) = (repository.name.clone(), repository.provider.clone());
+        let visibility = repository.visibility.clone();
+        let description = repository.description.clone().unwrap_or_default();
+        view! {
+            <li>
+                <button type="button" class=class on:click=move |_| on_select.call(repository.clone())>
+                    <div class="flex items-center justify-between">
+                        <span class="text-sm font-medium text-gray-900">{name}</span>
+                        <span class="text-xs text-gray-500">{provider} " · " {visibility}</span>
+                    </div>
+                    <p class="text-xs text-gray-500 truncate">{description}</p>
+                </button>
+            </li>
+        }
+    };
+
+    let pager = move |result: &RepositoryPage| {
+        let (current, count) = (result.page, result.page_count());
+        view! {
+            <div class="flex items-center justify-between px-4 py-2 text-sm text-gray-600">
+                <button
+                    type="button"
+                    class="hover:text-gray-900 disabled:opacity-50"
+                    disabled=current <= 1
+                    on:click=move |_| set_page.update(|page| *page -= 1)
+                >
+                    "Previous"
+                </button>
+                <span>{format!("Page {} of {}", current, count)}</span>
+                <button
+                    type="button"
+                    class="hover:text-gray-900 disabled:opacity-50"
+                    disabled=current >= count
+                    on:click=move |_| set_page.update(|page| *page += 1)
+                >
+                    "Next"
+                </button>
+            </div>
+        }
+    };
+
+    view! {
+        <div class="bg-white shadow rounded-lg">
+            <div class="p-4 border-b border-gray-200">
+                <input
+                    type="search"
+                    class=INPUT_CLASS
+                    placeholder="Search repositories"
+                    prop:value=search
+                    on:input=move |ev| {
+                        set_search.set(event_target_value(&ev));
+                        set_page.set(1);
+                    }
+                />
+            </div>
+            <Transition fallback=|| ()>
+                {move || repositories.get().map(|result| match result {
+                    Ok(result) if result.repositories.is_empty() => view! {
+                        <p class="p-4 text-sm text-gray-500">"No repositories."</p>
+                    }
+                    .into_view(),
+                    Ok(result) => view! {
+                        <ul class="divide-y divide-gray-200">
+                            {result.repositories.clone().into_iter().map(repository_row).collect_view()}
+                        </ul>
+                        {pager(&result)}
+                    }
+                    .into_view(),
+                    Err(err) => error_banner(err).into_view(),
+                })}
+            </Transition>
+        </div>
+    }
+}
@@ -0,0 +1,381 @@
+use leptos::*;
+use leptos_router::*;
+
+use super::orgs::{
+    error_banner, error_string, DANGER_BUTTON_CLASS, INPUT_CLASS, PRIMARY_BUTTON_CLASS,
+};
+use crate::api::apps::{App, NewApp};
+use crate::api::deployments::Deployment;
+use crate::api::repositories::Repository;
+use crate::api::{AppApi, DeploymentApi};
+use crate::components::nav::use_current_org;
+use crate::components::repository_picker::RepositoryPicker;
+
+/// The apps of the current organization, or of the personal account.
+#[component]
+pub fn Apps() -> impl IntoView {
+    let current = use_current_org();
+    let apps = create_resource(
+        move || current.get(),
+        |org| async move { AppApi::list(org.as_deref()).await.map_err(error_string) },
+    );
+    let scope = move || match current.get() {
+        Some(slug) => format!("Apps of {}.", slug),
+        None => "Your personal apps.".to_string(),
+    };
+
+    let app_row = |app: App| {
+        let href = format!("/apps/{}", app.slug);
+        view! {
+            <li>
+                <A href=href class="block px-4 py-4 hover:bg-gray-50">
+                    <div class="flex items-center justify-between">
+                        <span class="text-sm font-medium text-gray-900">{app.name}</span>
+                        <span class="text-xs text-gray-500">{app.slug}</span>
+                    </div>
+                    <p class="text-xs text-gray-500">
+                        {app.repository.unwrap_or_else(|| "No repository".to_string())}
+                    </p>
+                </A>
+            </li>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-4xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <div class="flex items-end justify-between">
+                    <div>
+                        <h1 class="text-3xl font-bold text-gray-900">"Apps"</h1>
+                        <p class="mt-2 text-sm text-gray-600">{scope}</p>
+                    </div>
+                    <A href="/apps/new" class=PRIMARY_BUTTON_CLASS>"New app"</A>
+                </div>
+                <Transition fallback=|| ()>
+                    {move || apps.get().map(|result| match result {
+                        Ok(apps) if apps.is_empty() => view! {
+                            <p class="text-sm text-gray-500">"No apps yet."</p>
+                        }
+                        .into_view(),
+                        Ok(apps) => view! {
+                            <ul class="bg-white shadow rounded-lg divide-y divide-gray-200">
+                                {apps.into_iter().map(app_row).collect_view()}
+                            </ul>
+                        }
+                        .into_view(),
+                        Err(err) => error_banner(err).into_view(),
+                    })}
+                </Transition>
+            </main>
+        </div>
+    }
+}
+
+fn optional(value: String) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+/// Creates an app in the current organization, or in the personal account.
+#[component]
+pub fn CreateApp() -> impl IntoView {
+    let current = use_current_org();
+    let (name, set_name) = create_signal(String::new());
+    let (slug, set_slug) = create_signal(String::new());
+    let repository = create_rw_signal(None::<Repository>);
+    let (branch, set_branch) = create_signal(String::new());
+    let (root_dir, set_root_dir) = create_signal(String::new());
+    let (build_command, set_build_command) = create_signal(String::new());
+    let (start_command, set_start_command) = create_signal(String::new());
+    let (runtime_version, set_runtime_version) = create_signal(String::new());
+
+    let create = create_action(move |app: &NewApp| {
+        let app = app.clone();
+        async move {
+            let app = AppApi::create(&app).await.map_err(error_string)?;
+            window()
+                .location()
+                .set_href(&format!("/apps/{}", app.slug))
+                .unwrap();
+            Ok::<_, String>(())
+        }
+    });
+
+    let submit = move |ev: ev::SubmitEvent| {
+        ev.prevent_default();
+        create.dispatch(NewApp {
+            name: name.get(),
+            slug: optional(slug.get()),
+            org: current.get(),
+            repository_id: repository.with(|repository| repository.as_ref().map(|r| r.id)),
+            branch: optional(branch.get()),
+            root_dir: root_dir.get(),
+            build_command: optional(build_command.get()),
+            start_command: optional(start_command.get()),
+            runtime_version: optional(runtime_version.get()),
+        });
+    };
+    let default_branch = move || {
+        repository
+            .get()
+            .and_then(|repository| repository.default_branch)
+            .unwrap_or_else(|| "default branch".to_string())
+    };
+    let text_field = |label: &'static str,
+                      placeholder: &'static str,
+                      value: ReadSignal<String>,
+                      set_value: WriteSignal<String>| {
+        view! {
+            <label class="block text-sm font-medium text-gray-700">
+                {label}
+                <input
+                    type="text"
+                    class=INPUT_CLASS
+                    placeholder=placeholder
+                    prop:value=value
+                    on:input=move |ev| set_value.set(event_target_value(&ev))
+                />
+            </label>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-3xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <h1 class="text-3xl font-bold text-gray-900">"New app"</h1>
+                {move || create.value().get().and_then(|result| result.err()).map(error_banner)}
+                <form class="bg-white shadow rounded-lg p-6 space-y-4" on:submit=submit>
+                    {text_field("Name", "My app", name, set_name)}
+                    {text_field("Slug", "Derived from the name", slug, set_slug)}
+
+                    <div class="space-y-2">
+                        <p class="text-sm font-medium text-gray-700">"Repository"</p>
+                        <p class="text-sm text-gray-600">
+                            {move || repository
+                                .get()
+                                .map(|repository| repository.name)
+                                .unwrap_or_else(|| "None selected".to_string())}
+                        </p>
+                        <RepositoryPicker
+                            on_select=move |selected| repository.set(Some(selected))
+                            selected=Signal::derive(move || repository.with(|r| r.as_ref().map(|r| r.id)))
+                        />
+                    </div>
+
+                    <label class="block text-sm font-medium text-gray-700">
+                        "Branch"
+                        <input
+                            type="text"
+                            class=INPUT_CLASS
+                            placeholder=default_branch
+                            prop:value=branch
+                            on:input=move |ev| set_branch.set(event_target_value(&ev))
+                        />
+                    </label>
+                    {text_field("Root directory", "Repository root", root_dir, set_root_dir)}
+                    {text_field("Build command", "Detected from the source", build_command, set_build_command)}
+                    {text_field("Start command", "Detected from the source", start_command, set_start_command)}
+                    {text_field("Runtime version", "Detected from the source", runtime_version, set_runtime_version)}
+
+                    <button type="submit" class=PRIMARY_BUTTON_CLASS disabled=move || create.pending().get()>
+                        "Create app"
+                    </button>
+                </form>
+            </main>
+        </div>
+    }
+}
+
+/// One app's settings.
+#[component]
+pub fn AppDetail() -> impl IntoView {
+    let params = use_params_map();
+    let slug = move || params.with(|p| p.get("slug").cloned().unwrap_or_default());
+    let app = create_resource(slug, |slug| async move {
+        AppApi::get(&slug).await.map_err(error_string)
+    });
+
+    let delete = create_action(move |_: &()| async move {
+        let result = AppApi::delete(&slug()).await.map_err(error_string);
+        if result.is_ok() {
+            window().location().set_href("/apps").unwrap();
+        }
+        result
+    });
+
+    let setting = |label: &'static str, value: String| {
+        view! {
+            <div class="py-3 grid grid-cols-3 gap-4">
+                <dt class="text-sm font-medium text-gray-500">{label}</dt>
+                <dd class="text-sm text-gray-900 col-span-2 font-mono break-all">{value}</dd>
+            </div>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-3xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                {move || delete.value().get().and_then(|result| result.err()).map(error_banner)}
+                <Suspense fallback=|| ()>
+                    {move || app.get().map(|result| match result {
+                        Ok(app) => {
+                            let detected = || "detected".to_string();
+                            view! {
+                                <div class="flex items-start justify-between">
+                                    <div>
+                                        <h1 class="text-3xl font-bold text-gray-900">{app.name.clone()}</h1>
+                                        <p class="mt-2 text-sm text-gray-600">{app.slug.clone()}</p>
+                                    </div>
+                                    <button
+                                        class=DANGER_BUTTON_CLASS
+                                        disabled=move || delete.pending().get()
+                                        on:click=move |_| delete.dispatch(())
+                                    >
+                                        "Delete app"
+                                    </button>
+                                </div>
+                                <dl class="bg-white shadow rounded-lg px-6 divide-y divide-gray-200">
+                                    {setting("Repository", app.repository.clone().unwrap_or_else(|| "none".to_string()))}
+                                    {setting("Branch", app.branch.clone().unwrap_or_else(|| "default".to_string()))}
+                                    {setting("Root directory", format!("/{}", app.root_dir))}
+                                    {setting("Build command", app.build_command.clone().unwrap_or_else(detected))}
+                                    {setting("Start command", app.start_command.clone().unwrap_or_else(detected))}
+                                    {setting("Runtime version", app.runtime_version.clone().unwrap_or_else(detected))}
+                                    {setting("Created", format!("{} UTC", app.created_at))}
+                                    {setting("Updated", format!("{} UTC", app.updated_at))}
+                                </dl>
+                                <Deployments slug=app.slug.clone()/>
+                            }
+                            .into_view()
+                        }
+                        Err(err) => error_banner(err).into_view(),
+                    })}
+                </Suspense>
+            </main>
+        </div>
+    }
+}
+
+fn status_class(status: &str) -> &'static str {
+    match status {
+        "running" => "text-green-700 bg-green-50",
+        "failed" => "text-red-700 bg-red-50",
+        "queued" | "building" | "releasing" => "text-blue-700 bg-blue-50",
+        _ => "text-gray-600 bg-gray-100",
+    }
+}
+
+/// An app's latest deployments, with a form to deploy a commit.
+#[component]
+fn Deployments(slug: String) -> impl IntoView {
+    let slug = store_value(slug);
+    let (commit_sha, set_commit_sha) = create_signal(String::new());
+
+    let deploy = create_action(move |commit_sha: &String| {
+        let commit_sha = commit_sha.clone();
+        async move {
+            DeploymentApi::create(&slug.get_value(), &commit_sha)
+                .await
+                .map_err(error_string)
+        }
+    });
+    let cancel = create_action(move |id: &i64| {
+        let id = *id;
+        async move {
+            DeploymentApi::cancel(&slug.get_value(), id)
+                .await
+                .map_err(error_string)
+        }
+    });
+    let deployments = create_resource(
+        move || (deploy.version().get(), cancel.version().get()),
+        move |_| async move {
+            DeploymentApi::list(&slug.get_value())
+                .await
+                .map_err(error_string)
+        },
+    );
+
+    let submit = move |ev: ev::SubmitEvent| {
+        ev.prevent_default();
+        deploy.dispatch(commit_sha.get());
+    };
+    let error = move || {
+        deploy
+            .value()
+            .get()
+            .and_then(|result| result.err())
+            .or_else(|| cancel.value().get().and_then(|result| result.err()))
+            .map(error_banner)
+    };
+
+    let deployment_row = move |deployment: Deployment| {
+        let id = deployment.id;
+        let status = format!(
+            "px-2 py-0.5 text-xs font-medium rounded-full {}",
+            status_class(&deployment.status)
+        );
+        view! {
+            <li class="px-4 py-3 flex items-center justify-between">
+                <div>
+                    <p class="text-sm font-mono text-gray-900">{deployment.commit_sha[..12].to_string()}</p>
+                    <p class="text-xs text-gray-500">{format!("{} UTC", deployment.created_at)}</p>
+                    {deployment.build_plan.as_ref().map(|plan| view! {
+                        <p class="text-xs text-gray-500">{plan.runtime()}</p>
+                    })}
+                    {deployment.error.clone().map(|error| view! {
+                        <p class="text-xs text-red-700">{error}</p>
+                    })}
+                </div>
+                <div class="flex items-center space-x-3">
+                    <span class=status>{deployment.status.clone()}</span>
+                    <Show when=move || deployment.cancellable() fallback=|| ()>
+                        <button
+                            class=DANGER_BUTTON_CLASS
+                            disabled=move || cancel.pending().get()
+                            on:click=move |_| cancel.dispatch(id)
+                        >
+                            "Cancel"
+                        </button>
+                    </Show>
+                </div>
+            </li>
+        }
+    };
+
+    view! {
+        <section class="space-y-4">
+            <h2 class="text-xl font-semibold text-gray-900">"Deployments"</h2>
+            {error}
+            <form class="flex items-end space-x-3" on:submit=submit>
+                <label class="flex-1 block text-sm font-medium text-gray-700">
+                    "Commit SHA"
+                    <input
+                        type="text"
+                        class=INPUT_CLASS
+                        placeholder="Full 40-character commit id"
+                        prop:value=commit_sha
+                        on:input=move |ev| set_commit_sha.set(event_target_value(&ev))
+                    />
+                </label>
+                <button type="submit" class=PRIMARY_BUTTON_CLASS disabled=move || deploy.pending().get()>
+                    "Deploy"
+                </button>
+            </form>
+            <Transition fallback=|| ()>
+                {move || deployments.get().map(|result| match result {
+                    Ok(deployments) if deployments.is_empty() => view! {
+                        <p class="text-sm text-gray-500">"No deployments yet."</p>
+                    }
+                    .into_view(),
+                    Ok(deployments) => view! {
+                        <ul class="bg-white shadow rounded-lg divide-y divide-gray-200">
+                            {deployments.into_iter().map(deployment_row).collect_view()}
+                        </ul>
+                    }
+                    .into_view(),
+                    Err(err) => error_banner(err).into_view(),
+                })}
+            </Transition>
+        </section>
+    }
+}
@@ -0,0 +1,194 @@
+use leptos::*;
+
+use super::orgs::{error_banner, error_string, INPUT_CLASS, PRIMARY_BUTTON_CLASS};
+use crate::api::audit::{AuditEvent, AuditFilter, AuditPage};
+use crate::api::AuditApi;
+use crate::components::nav::use_current_org;
+
+/// The audit log of the current organization, or of the user's own actions
+/// when working in the personal account.
+#[component]
+pub fn AuditLog() -> impl IntoView {
+    let current = use_current_org();
+
+    let (action, set_action) = create_signal(String::new());
+    let (actor, set_actor) = create_signal(String::new());
+    let (since, set_since) = create_signal(String::new());
+    let (until, set_until) = create_signal(String::new());
+    // The organization is taken from the switcher when loading.
+    let form_filter = move || AuditFilter {
+        org: None,
+        action: action.get(),
+        actor: actor.get(),
+        since: since.get(),
+        until: until.get(),
+    };
+    let applied = create_rw_signal(AuditFilter::default());
+
+    let events = create_rw_signal(Vec::<AuditEvent>::new());
+    let next_cursor = create_rw_signal(None::<i64>);
+    let load = create_action(move |(filter, cursor): &(AuditFilter, Option<i64>)| {
+        let (filter, cursor) = (filter.clone(), *cursor);
+        async move {
+            let page: AuditPage = AuditApi::list(&filter, cursor)
+                .await
+                .map_err(error_string)?;
+            if cursor.is_none() {
+                events.set(Vec::new());
+            }
+            events.update(|events| events.extend(page.events));
+            next_cursor.set(page.next_cursor);
+            Ok::<_, String>(())
+        }
+    });
+
+    // Start over whenever the organization or the applied filters change.
+    create_effect(move |_| {
+        let filter = AuditFilter {
+            org: current.get(),
+            ..applied.get()
+        };
+        load.dispatch((filter, None));
+    });
+
+    let apply = move |ev: ev::SubmitEvent| {
+        ev.prevent_default();
+        applied.set(form_filter());
+    };
+    let export_url = move || {
+        AuditApi::export_url(&AuditFilter {
+            org: current.get(),
+            ..applied.get()
+        })
+    };
+    let scope = move || match current.get() {
+        Some(slug) => format!("Everything done in {}.", slug),
+        None => "Everything you have done.".to_string(),
+    };
+
+    let event_row = |event: AuditEvent| {
+        let target = match (&event.target_type, &event.target_id) {
+            (Some(kind), Some(id)) => format!("{} {}", kind, id),
+            _ => String::new(),
+        };
+        let metadata = match &event.metadata {
+            serde_json::Value::Object(map) if map.is_empty() => String::new(),
+            metadata => metadata.to_string(),
+        };
+        view! {
+            <tr>
+                <td class="px-4 py-2 text-xs text-gray-500 whitespace-nowrap">{event.created_at}</td>
+                <td class="px-4 py-2 text-sm text-gray-900">{event.actor.unwrap_or_default()}</td>
+                <td class="px-4 py-2 text-sm font-mono text-gray-900">{event.action}</td>
+                <td class="px-4 py-2 text-sm text-gray-700">{target}</td>
+                <td class="px-4 py-2 text-xs text-gray-500" title=event.user_agent.unwrap_or_default()>
+                    {event.ip.unwrap_or_default()}
+                </td>
+                <td class="px-4 py-2 text-xs font-mono text-gray-500 break-all">{metadata}</td>
+            </tr>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-7xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <div class="flex items-end justify-between">
+                    <div>
+                        <h1 class="text-3xl font-bold text-gray-900">"Audit log"</h1>
+                        <p class="mt-2 text-sm text-gray-600">{scope}</p>
+                    </div>
+                    <a
+                        href=export_url
+                        class="text-sm font-medium text-blue-600 hover:text-blue-800"
+                    >
+                        "Export NDJSON"
+                    </a>
+                </div>
+
+                <form class="bg-white shadow rounded-lg p-4 grid grid-cols-1 gap-4 sm:grid-cols-5 items-end" on:submit=apply>
+                    <label class="block text-xs font-medium text-gray-700">
+                        "Action"
+                        <input
+                            type="text"
+                            class=INPUT_CLASS
+                            placeholder="org.member"
+                            prop:value=action
+                            on:input=move |ev| set_action.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="block text-xs font-medium text-gray-700">
+                        "Actor"
+                        <input
+                            type="text"
+                            class=INPUT_CLASS
+                            placeholder="username"
+                            prop:value=actor
+                            on:input=move |ev| set_actor.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="block text-xs font-medium text-gray-700">
+                        "Since"
+                        <input
+                            type="date"
+                            class=INPUT_CLASS
+                            prop:value=since
+                            on:input=move |ev| set_since.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <label class="block text-xs font-medium text-gray-700">
+                        "Until"
+                        <input
+                            type="date"
+                            class=INPUT_CLASS
+                            prop:value=until
+                            on:input=move |ev| set_until.set(event_target_value(&ev))
+                        />
+                    </label>
+                    <button type="submit" class=PRIMARY_BUTTON_CLASS>"Filter"</button>
+                </form>
+
+                {move || load.value().get().and_then(|result| result.err()).map(error_banner)}
+
+                <div class="bg-white shadow rounded-lg overflow-x-auto">
+                    <table class="min-w-full divide-y divide-gray-200">
+                        <thead class="bg-gray-50">
+                            <tr class="text-left text-xs font-medium uppercase text-gray-500">
+                                <th class="px-4 py-2">"Time (UTC)"</th>
+                                <th class="px-4 py-2">"Actor"</th>
+                                <th class="px-4 py-2">"Action"</th>
+                                <th class="px-4 py-2">"Target"</th>
+                                <th class="px-4 py-2">"IP"</th>
+                                <th class="px-4 py-2">"Details"</th>
+                            </tr>
+                        </thead>
+                        <tbody class="divide-y divide-gray-200">
+                            {move || events.get().into_iter().map(event_row).collect_view()}
+                        </tbody>
+                    </table>
+                    <Show
+                        when=move || events.with(Vec::is_empty) && !load.pending().get()
+                        fallback=|| ()
+                    >
+                        <p class="p-4 text-sm text-gray-500">"No events."</p>
+                    </Show>
+                </div>
+
+                <Show when=move || next_cursor.get().is_some() fallback=|| ()>
+                    <button
+                        class=PRIMARY_BUTTON_CLASS
+                        disabled=move || load.pending().get()
+                        on:click=move |_| {
+                            let filter = AuditFilter {
+                                org: current.get_untracked(),
+                                ..applied.get_untracked()
+                            };
+                            load.dispatch((filter, next_cursor.get_untracked()));
+                        }
+                    >
+                        "Load more"
+                    </button>
+                </Show>
+            </main>
+        </div>
+    }
+}
@@ -1,6 +1,5 @@
 use crate::api::auth::AuthApi;
 use leptos::*;
-use leptos_router::*;
 
 #[component]
 pub fn OAuthCallback() -> impl IntoView {
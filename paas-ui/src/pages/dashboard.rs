@@ -1,6 +1,5 @@
 use crate::api::UserApi;
 use leptos::*;
-use wasm_bindgen::JsValue;
 
 #[component]
 pub fn Dashboard() -> impl IntoView {
@@ -51,9 +50,12 @@ pub fn Dashboard() -> impl IntoView {
                                         </div>
                                     </div>
                                     <div class="px-6 py-4 bg-gray-50">
-                                        <button class="w-full flex justify-center py-2 px-4 border border-transparent rounded-md shadow-sm text-sm font-medium text-white bg-blue-600 hover:bg-blue-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-blue-500">
+                                        <a
+                                            href="/apps/new"
+                                            class="w-full flex justify-center py-2 px-4 border border-transparent rounded-md shadow-sm text-sm font-medium text-white bg-blue-600 hover:bg-blue-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-blue-500"
+                                        >
                                             "Create New App"
-                                        </button>
+                                        </a>
                                     </div>
                                 </div>
 
@@ -76,9 +78,12 @@ pub fn Dashboard() -> impl IntoView {
                                         </div>
                                     </div>
                                     <div class="px-6 py-4 bg-gray-50">
-                                        <button class="w-full flex justify-center py-2 px-4 border border-transparent rounded-md shadow-sm text-sm font-medium text-white bg-green-600 hover:bg-green-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-green-500">
+                                        <a
+                                            href="/apps"
+                                            class="w-full flex justify-center py-2 px-4 border border-transparent rounded-md shadow-sm text-sm font-medium text-white bg-green-600 hover:bg-green-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-green-500"
+                                        >
                                             "View Deployments"
-                                        </button>
+                                        </a>
                                     </div>
                                 </div>
 
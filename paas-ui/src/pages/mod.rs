@@ -1,9 +1,19 @@
+pub mod apps;
+pub mod audit;
 pub mod callback;
 pub mod dashboard;
 pub mod home;
 pub mod login;
+pub mod orgs;
+pub mod repositories;
+pub mod settings;
 
+pub use apps::{AppDetail, Apps, CreateApp};
+pub use audit::AuditLog;
 pub use callback::OAuthCallback;
 pub use dashboard::Dashboard;
 pub use home::Home;
 pub use login::Login;
+pub use orgs::{OrganizationDetail, Organizations};
+pub use repositories::Repositories;
+pub use settings::Settings;
@@ -0,0 +1,458 @@
+use leptos::*;
+use leptos_router::*;
+
+use crate::api::orgs::{Invitation, Member, Organization, PendingInvitation, ROLES};
+use crate::api::OrgApi;
+use crate::components::nav::use_current_org;
+
+pub(crate) const INPUT_CLASS: &str = "block w-full px-3 py-2 text-sm border border-gray-300 rounded-md shadow-sm focus:outline-none focus:ring-blue-500 focus:border-blue-500";
+pub(crate) const PRIMARY_BUTTON_CLASS: &str = "px-4 py-2 text-sm font-medium rounded-md text-white bg-blue-600 hover:bg-blue-700 disabled:opacity-50 disabled:cursor-not-allowed";
+pub(crate) const DANGER_BUTTON_CLASS: &str = "px-3 py-1.5 text-sm font-medium rounded-md text-red-700 bg-red-50 hover:bg-red-100 disabled:opacity-50";
+
+pub(crate) fn error_string(err: wasm_bindgen::JsValue) -> String {
+    err.as_string()
+        .unwrap_or_else(|| "Unknown error".to_string())
+}
+
+pub(crate) fn error_banner(err: String) -> impl IntoView {
+    view! {
+        <div class="bg-red-50 border-l-4 border-red-400 p-4" role="alert">
+            <p class="text-sm text-red-700">{err}</p>
+        </div>
+    }
+}
+
+/// Lists the user's organizations and the invitations addressed to them.
+#[component]
+pub fn Organizations() -> impl IntoView {
+    let current = use_current_org();
+    let orgs = create_resource(
+        || (),
+        |_| async move { OrgApi::list().await.map_err(error_string) },
+    );
+    let invitations = create_resource(
+        || (),
+        |_| async move { OrgApi::my_invitations().await.map_err(error_string) },
+    );
+
+    let (name, set_name) = create_signal(String::new());
+    let create = create_action(move |name: &String| {
+        let name = name.clone();
+        async move {
+            let result = OrgApi::create(&name).await.map_err(error_string);
+            if let Ok(org) = &result {
+                set_name.set(String::new());
+                current.set(Some(org.slug.clone()));
+                orgs.refetch();
+            }
+            result
+        }
+    });
+
+    let respond = create_action(move |(id, accept): &(i64, bool)| {
+        let (id, accept) = (*id, *accept);
+        async move {
+            let result = if accept {
+                OrgApi::accept_invitation(id).await.map(|_| ())
+            } else {
+                OrgApi::decline_invitation(id).await
+            }
+            .map_err(error_string);
+            invitations.refetch();
+            orgs.refetch();
+            result
+        }
+    });
+
+    let action_error = move || {
+        create
+            .value()
+            .get()
+            .and_then(|result| result.err())
+            .or_else(|| respond.value().get().and_then(|result| result.err()))
+    };
+
+    let org_row = move |org: Organization| {
+        view! {
+            <li class="flex items-center justify-between py-4">
+                <div>
+                    <A href=format!("/orgs/{}", org.slug) class="text-sm font-medium text-blue-600 hover:text-blue-800">
+                        {org.name.clone()}
+                    </A>
+                    <p class="text-xs text-gray-500">{org.slug.clone()}</p>
+                </div>
+                <span class="text-xs font-medium uppercase text-gray-500">{org.role.clone()}</span>
+            </li>
+        }
+    };
+
+    let invitation_row = move |invitation: PendingInvitation| {
+        let id = invitation.id;
+        view! {
+            <li class="flex items-center justify-between py-4">
+                <div>
+                    <p class="text-sm font-medium text-gray-900">
+                        {invitation.organization_name.clone()} " as " {invitation.role.clone()}
+                    </p>
+                    <p class="text-xs text-gray-500">
+                        {invitation.invited_by.clone().map(|by| format!("Invited by {} · ", by))}
+                        "Expires " {invitation.expires_at.clone()}
+                    </p>
+                </div>
+                <div class="flex space-x-2">
+                    <button
+                        class=PRIMARY_BUTTON_CLASS
+                        disabled=move || respond.pending().get()
+                        on:click=move |_| respond.dispatch((id, true))
+                    >
+                        "Accept"
+                    </button>
+                    <button
+                        class=DANGER_BUTTON_CLASS
+                        disabled=move || respond.pending().get()
+                        on:click=move |_| respond.dispatch((id, false))
+                    >
+                        "Decline"
+                    </button>
+                </div>
+            </li>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-3xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <div>
+                    <h1 class="text-3xl font-bold text-gray-900">"Organizations"</h1>
+                    <p class="mt-2 text-sm text-gray-600">
+                        "Share repositories and apps with your team."
+                    </p>
+                </div>
+
+                {move || action_error().map(error_banner)}
+
+                <Suspense fallback=|| ()>
+                    {move || invitations.get().and_then(Result::ok).filter(|list| !list.is_empty()).map(|list| view! {
+                        <div class="bg-white shadow rounded-lg p-6">
+                            <h2 class="text-lg font-medium text-gray-900">"Invitations"</h2>
+                            <ul class="mt-2 divide-y divide-gray-200">
+                                {list.into_iter().map(invitation_row).collect_view()}
+                            </ul>
+                        </div>
+                    })}
+                </Suspense>
+
+                <div class="bg-white shadow rounded-lg p-6">
+                    <h2 class="text-lg font-medium text-gray-900">"Your organizations"</h2>
+                    <Suspense fallback=move || view! {
+                        <p class="mt-4 text-sm text-gray-500">"Loading organizations..."</p>
+                    }>
+                        {move || orgs.get().map(|result| match result {
+                            Ok(list) if list.is_empty() => view! {
+                                <p class="mt-4 text-sm text-gray-500">"You are not a member of any organization yet."</p>
+                            }
+                            .into_view(),
+                            Ok(list) => view! {
+                                <ul class="mt-2 divide-y divide-gray-200">
+                                    {list.into_iter().map(org_row).collect_view()}
+                                </ul>
+                            }
+                            .into_view(),
+                            Err(err) => view! {
+                                <p class="mt-4 text-sm text-red-700">{err}</p>
+                            }
+                            .into_view(),
+                        })}
+                    </Suspense>
+                </div>
+
+                <div class="bg-white shadow rounded-lg p-6">
+                    <h2 class="text-lg font-medium text-gray-900">"Create an organization"</h2>
+                    <form
+                        class="mt-4 flex space-x-3"
+                        on:submit=move |ev| {
+                            ev.prevent_default();
+                            create.dispatch(name.get());
+                        }
+                    >
+                        <input
+                            class=INPUT_CLASS
+                            placeholder="Organization name"
+                            prop:value=name
+                            on:input=move |ev| set_name.set(event_target_value(&ev))
+                        />
+                        <button
+                            type="submit"
+                            class=PRIMARY_BUTTON_CLASS
+                            disabled=move || create.pending().get() || name.with(|name| name.trim().is_empty())
+                        >
+                            "Create"
+                        </button>
+                    </form>
+                </div>
+            </main>
+        </div>
+    }
+}
+
+/// Members and invitations of one organization.
+#[component]
+pub fn OrganizationDetail() -> impl IntoView {
+    let params = use_params_map();
+    let slug = move || params.with(|p| p.get("slug").cloned().unwrap_or_default());
+    let current = use_current_org();
+
+    let org = create_resource(slug, |slug| async move {
+        OrgApi::get(&slug).await.map_err(error_string)
+    });
+    let members = create_resource(slug, |slug| async move {
+        OrgApi::members(&slug).await.map_err(error_string)
+    });
+    let invitations = create_resource(
+        move || {
+            org.get()
+                .and_then(Result::ok)
+                .filter(Organization::can_manage)
+                .map(|org| org.slug)
+        },
+        |slug| async move {
+            match slug {
+                Some(slug) => OrgApi::invitations(&slug).await.map_err(error_string),
+                None => Ok(Vec::new()),
+            }
+        },
+    );
+    let can_manage = move || {
+        org.get()
+            .and_then(Result::ok)
+            .is_some_and(|org| org.can_manage())
+    };
+
+    let (invitee, set_invitee) = create_signal(String::new());
+    let (invite_role, set_invite_role) = create_signal("developer".to_string());
+    let invite = create_action(move |(invitee, role): &(String, String)| {
+        let (invitee, role) = (invitee.clone(), role.clone());
+        async move {
+            let result = OrgApi::invite(&slug(), &invitee, &role)
+                .await
+                .map(|_| ())
+                .map_err(error_string);
+            if result.is_ok() {
+                set_invitee.set(String::new());
+                invitations.refetch();
+            }
+            result
+        }
+    });
+
+    let update = create_action(move |(user_id, role): &(i64, Option<String>)| {
+        let (user_id, role) = (*user_id, role.clone());
+        async move {
+            let result = match role {
+                Some(role) => OrgApi::set_role(&slug(), user_id, &role).await.map(|_| ()),
+                None => OrgApi::remove_member(&slug(), user_id).await,
+            }
+            .map_err(error_string);
+            members.refetch();
+            org.refetch();
+            result
+        }
+    });
+
+    let withdraw = create_action(move |id: &i64| {
+        let id = *id;
+        async move {
+            let result = OrgApi::withdraw_invitation(&slug(), id)
+                .await
+                .map_err(error_string);
+            invitations.refetch();
+            result
+        }
+    });
+
+    let delete = create_action(move |_: &()| async move {
+        let result = OrgApi::delete(&slug()).await.map_err(error_string);
+        if result.is_ok() {
+            if current.get_untracked() == Some(slug()) {
+                current.set(None);
+            }
+            window().location().set_href("/orgs").unwrap();
+        }
+        result
+    });
+
+    let action_error = move || {
+        invite
+            .value()
+            .get()
+            .and_then(|result| result.err())
+            .or_else(|| update.value().get().and_then(|result| result.err()))
+            .or_else(|| withdraw.value().get().and_then(|result| result.err()))
+            .or_else(|| delete.value().get().and_then(|result| result.err()))
+    };
+
+    let member_row = move |member: Member| {
+        let user_id = member.user_id;
+        let role = member.role.clone();
+        view! {
+            <li class="flex items-center justify-between py-4">
+                <div>
+                    <p class="text-sm font-medium text-gray-900">{member.username.clone()}</p>
+                    <p class="text-xs text-gray-500">{member.email.clone()}</p>
+                </div>
+                <div class="flex items-center space-x-2">
+                    {move || if can_manage() {
+                        let role = role.clone();
+                        view! {
+                            <select
+                                class="text-sm border-gray-300 rounded-md"
+                                aria-label="Role"
+                                disabled=move || update.pending().get()
+                                on:change=move |ev| update.dispatch((user_id, Some(event_target_value(&ev))))
+                            >
+                                {ROLES.iter().map(|option| view! {
+                                    <option value=*option selected=*option == role>{*option}</option>
+                                }).collect_view()}
+                            </select>
+                            <button
+                                class=DANGER_BUTTON_CLASS
+                                disabled=move || update.pending().get()
+                                on:click=move |_| update.dispatch((user_id, None))
+                            >
+                                "Remove"
+                            </button>
+                        }
+                        .into_view()
+                    } else {
+                        view! {
+                            <span class="text-xs font-medium uppercase text-gray-500">{role.clone()}</span>
+                        }
+                        .into_view()
+                    }}
+                </div>
+            </li>
+        }
+    };
+
+    let invitation_row = move |invitation: Invitation| {
+        let id = invitation.id;
+        view! {
+            <li class="flex items-center justify-between py-4">
+                <div>
+                    <p class="text-sm font-medium text-gray-900">{invitation.invitee.clone()}</p>
+                    <p class="text-xs text-gray-500">
+                        {invitation.role.clone()} " · expires " {invitation.expires_at.clone()}
+                    </p>
+                </div>
+                <button
+                    class=DANGER_BUTTON_CLASS
+                    disabled=move || withdraw.pending().get()
+                    on:click=move |_| withdraw.dispatch(id)
+                >
+                    "Withdraw"
+                </button>
+            </li>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-3xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <Suspense fallback=|| ()>
+                    {move || org.get().map(|result| match result {
+                        Ok(org) => view! {
+                            <div class="flex items-start justify-between">
+                                <div>
+                                    <h1 class="text-3xl font-bold text-gray-900">{org.name.clone()}</h1>
+                                    <p class="mt-2 text-sm text-gray-600">
+                                        {org.slug.clone()} " · you are " {org.role.clone()}
+                                    </p>
+                                </div>
+                                {(org.role == "owner").then(|| view! {
+                                    <button
+                                        class=DANGER_BUTTON_CLASS
+                                        disabled=move || delete.pending().get()
+                                        on:click=move |_| delete.dispatch(())
+                                    >
+                                        "Delete organization"
+                                    </button>
+                                })}
+                            </div>
+                        }
+                        .into_view(),
+                        Err(err) => error_banner(err).into_view(),
+                    })}
+                </Suspense>
+
+                {move || action_error().map(error_banner)}
+
+                <div class="bg-white shadow rounded-lg p-6">
+                    <h2 class="text-lg font-medium text-gray-900">"Members"</h2>
+                    <Suspense fallback=move || view! {
+                        <p class="mt-4 text-sm text-gray-500">"Loading members..."</p>
+                    }>
+                        {move || members.get().map(|result| match result {
+                            Ok(list) => view! {
+                                <ul class="mt-2 divide-y divide-gray-200">
+                                    {list.into_iter().map(member_row).collect_view()}
+                                </ul>
+                            }
+                            .into_view(),
+                            Err(err) => view! {
+                                <p class="mt-4 text-sm text-red-700">{err}</p>
+                            }
+                            .into_view(),
+                        })}
+                    </Suspense>
+                </div>
+
+                <Show when=can_manage fallback=|| ()>
+                    <div class="bg-white shadow rounded-lg p-6">
+                        <h2 class="text-lg font-medium text-gray-900">"Invite someone"</h2>
+                        <form
+                            class="mt-4 flex space-x-3"
+                            on:submit=move |ev| {
+                                ev.prevent_default();
+                                invite.dispatch((invitee.get(), invite_role.get()));
+                            }
+                        >
+                            <input
+                                class=INPUT_CLASS
+                                placeholder="Username or email address"
+                                prop:value=invitee
+                                on:input=move |ev| set_invitee.set(event_target_value(&ev))
+                            />
+                            <select
+                                class="text-sm border-gray-300 rounded-md"
+                                aria-label="Role"
+                                on:change=move |ev| set_invite_role.set(event_target_value(&ev))
+                            >
+                                {ROLES.iter().map(|option| view! {
+                                    <option value=*option selected=move || invite_role.get() == *option>
+                                        {*option}
+                                    </option>
+                                }).collect_view()}
+                            </select>
+                            <button
+                                type="submit"
+                                class=PRIMARY_BUTTON_CLASS
+                                disabled=move || invite.pending().get() || invitee.with(|invitee| invitee.trim().is_empty())
+                            >
+                                "Invite"
+                            </button>
+                        </form>
+
+                        <Suspense fallback=|| ()>
+                            {move || invitations.get().and_then(Result::ok).filter(|list| !list.is_empty()).map(|list| view! {
+                                <h3 class="mt-6 text-sm font-medium text-gray-700">"Pending invitations"</h3>
+                                <ul class="mt-2 divide-y divide-gray-200">
+                                    {list.into_iter().map(invitation_row).collect_view()}
+                                </ul>
+                            })}
+                        </Suspense>
+                    </div>
+                </Show>
+            </main>
+        </div>
+    }
+}
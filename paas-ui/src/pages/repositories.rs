@@ -0,0 +1,85 @@
+use leptos::*;
+
+use super::orgs::{error_banner, error_string, PRIMARY_BUTTON_CLASS};
+use crate::api::repositories::{Repository, SyncResult};
+use crate::api::RepositoryApi;
+use crate::components::repository_picker::RepositoryPicker;
+
+/// The repositories imported from the user's Git providers, with a button to
+/// import them again.
+#[component]
+pub fn Repositories() -> impl IntoView {
+    let (reload, set_reload) = create_signal(0_usize);
+    let selected = create_rw_signal(None::<Repository>);
+    let sync = create_action(move |_: &()| async move {
+        let results = RepositoryApi::sync().await.map_err(error_string)?;
+        set_reload.update(|reload| *reload += 1);
+        Ok::<_, String>(results)
+    });
+
+    let sync_row = |result: SyncResult| {
+        let outcome = match result.error {
+            Some(error) => error,
+            None => format!("{} synced, {} removed", result.synced, result.removed),
+        };
+        view! {
+            <li class="text-sm text-gray-700">
+                <span class="font-medium">{result.provider}</span> ": " {outcome}
+            </li>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-4xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <div class="flex items-end justify-between">
+                    <div>
+                        <h1 class="text-3xl font-bold text-gray-900">"Repositories"</h1>
+                        <p class="mt-2 text-sm text-gray-600">
+                            "Repositories you can access through your connected providers."
+                        </p>
+                    </div>
+                    <button
+                        class=PRIMARY_BUTTON_CLASS
+                        disabled=move || sync.pending().get()
+                        on:click=move |_| sync.dispatch(())
+                    >
+                        {move || if sync.pending().get() { "Syncing..." } else { "Sync" }}
+                    </button>
+                </div>
+
+                {move || sync.value().get().map(|result| match result {
+                    Ok(results) => view! {
+                        <ul class="bg-white shadow rounded-lg p-4 space-y-1">
+                            {results.into_iter().map(sync_row).collect_view()}
+                        </ul>
+                    }
+                    .into_view(),
+                    Err(err) => error_banner(err).into_view(),
+                })}
+
+                <RepositoryPicker
+                    on_select=move |repository| selected.set(Some(repository))
+                    selected=Signal::derive(move || selected.with(|selected| selected.as_ref().map(|r| r.id)))
+                    reload=reload
+                />
+
+                {move || selected.get().map(|repository| view! {
+                    <div class="bg-white shadow rounded-lg p-4 text-sm text-gray-700">
+                        <p class="font-medium text-gray-900">{repository.name}</p>
+                        <a href=repository.url.clone() class="text-blue-600 hover:text-blue-800" target="_blank">
+                            {repository.url.clone()}
+                        </a>
+                        <p class="text-xs text-gray-500 font-mono">{repository.clone_url}</p>
+                        <p class="text-xs text-gray-500">
+                            "Default branch: " {repository.default_branch.unwrap_or_else(|| "none".to_string())}
+                        </p>
+                        <p class="text-xs text-gray-500">
+                            "Last synced " {repository.last_synced.unwrap_or_default()} " UTC"
+                        </p>
+                    </div>
+                })}
+            </main>
+        </div>
+    }
+}
@@ -0,0 +1,186 @@
+use leptos::*;
+use leptos_router::*;
+
+use crate::api::auth::{AuthApi, LoginProvider};
+use crate::api::user::{ConnectedProvider, UserApi};
+use crate::pages::login::{button_colors, provider_icon};
+
+const BUTTON_CLASS: &str = "inline-flex items-center space-x-2 px-4 py-2 border border-transparent text-sm font-medium rounded-md text-white focus:outline-none focus:ring-2 focus:ring-offset-2 disabled:opacity-50 disabled:cursor-not-allowed";
+
+fn error_string(err: wasm_bindgen::JsValue) -> String {
+    err.as_string()
+        .unwrap_or_else(|| "Unknown error".to_string())
+}
+
+#[component]
+pub fn Settings() -> impl IntoView {
+    let params = use_query_map();
+    let error = move || params.with(|p| p.get("error").cloned());
+    let connected = move || params.with(|p| p.get("connected").cloned());
+
+    let connections = create_resource(
+        || (),
+        |_| async move { UserApi::list_providers().await.map_err(error_string) },
+    );
+    let providers = create_resource(
+        || (),
+        |_| async move { AuthApi::list_providers().await.map_err(error_string) },
+    );
+
+    let connect = create_action(|slug: &String| {
+        let slug = slug.clone();
+        async move { UserApi::connect_provider(&slug).await.map_err(error_string) }
+    });
+
+    let disconnect = create_action(move |slug: &String| {
+        let slug = slug.clone();
+        async move {
+            let result = UserApi::disconnect_provider(&slug)
+                .await
+                .map_err(error_string);
+            if result.is_ok() {
+                connections.refetch();
+            }
+            result
+        }
+    });
+
+    let action_error = move || {
+        connect
+            .value()
+            .get()
+            .and_then(|result| result.err())
+            .or_else(|| disconnect.value().get().and_then(|result| result.err()))
+    };
+
+    let connection_row = move |connection: ConnectedProvider| {
+        let slug = connection.provider.clone();
+        view! {
+            <li class="flex items-center justify-between py-4">
+                <div class="flex items-center space-x-3">
+                    <span class="text-gray-700">{provider_icon(&connection.provider_type)}</span>
+                    <div>
+                        <p class="text-sm font-medium text-gray-900">
+                            {connection.display_name.clone()}
+                            {connection.primary.then(|| view! {
+                                <span class="ml-2 text-xs text-gray-500">"(signed up with)"</span>
+                            })}
+                        </p>
+                        <p class="text-xs text-gray-500">
+                            "Connected " {connection.connected_at.clone()}
+                        </p>
+                        {connection.needs_reauth.then(|| view! {
+                            <p class="text-xs text-red-600">"Reconnect to keep using this provider"</p>
+                        })}
+                    </div>
+                </div>
+                <button
+                    class="px-3 py-1.5 text-sm font-medium rounded-md text-red-700 bg-red-50 hover:bg-red-100 disabled:opacity-50"
+                    disabled=move || disconnect.pending().get()
+                    on:click=move |_| disconnect.dispatch(slug.clone())
+                >
+                    "Disconnect"
+                </button>
+            </li>
+        }
+    };
+
+    let connect_button = move |provider: LoginProvider| {
+        let slug = provider.slug.clone();
+        view! {
+            <button
+                class=format!("{} {}", BUTTON_CLASS, button_colors(&provider.provider_type))
+                disabled=move || connect.pending().get()
+                on:click=move |_| connect.dispatch(slug.clone())
+            >
+                {provider_icon(&provider.provider_type)}
+                <span>"Connect " {provider.display_name.clone()}</span>
+            </button>
+        }
+    };
+
+    view! {
+        <div class="min-h-screen bg-gray-50">
+            <main class="max-w-3xl mx-auto py-6 px-4 sm:px-6 lg:px-8 space-y-6">
+                <div>
+                    <h1 class="text-3xl font-bold text-gray-900">"Settings"</h1>
+                    <p class="mt-2 text-sm text-gray-600">
+                        "Connect the Git providers you want to sign in with and deploy from."
+                    </p>
+                </div>
+
+                {move || error().or_else(action_error).map(|err| view! {
+                    <div class="bg-red-50 border-l-4 border-red-400 p-4" role="alert">
+                        <p class="text-sm text-red-700">{err}</p>
+                    </div>
+                })}
+                {move || connected().map(|slug| view! {
+                    <div class="bg-green-50 border-l-4 border-green-400 p-4" role="status">
+                        <p class="text-sm text-green-700">"Connected " {slug} "."</p>
+                    </div>
+                })}
+
+                <div class="bg-white shadow rounded-lg p-6">
+                    <h2 class="text-lg font-medium text-gray-900">"Connected providers"</h2>
+                    <Suspense fallback=move || view! {
+                        <p class="mt-4 text-sm text-gray-500">"Loading connections..."</p>
+                    }>
+                        {move || connections.get().map(|result| match result {
+                            Ok(connections) => view! {
+                                <ul class="mt-2 divide-y divide-gray-200">
+                                    {connections.into_iter().map(connection_row).collect_view()}
+                                </ul>
+                            }
+                            .into_view(),
+                            Err(err) => view! {
+                                <p class="mt-4 text-sm text-red-700">{err}</p>
+                            }
+                            .into_view(),
+                        })}
+                    </Suspense>
+                </div>
+
+                <div class="bg-white shadow rounded-lg p-6">
+                    <h2 class="text-lg font-medium text-gray-900">"Connect another provider"</h2>
+                    <div class="mt-4 flex flex-wrap gap-3">
+                        <Suspense fallback=move || view! {
+                            <p class="text-sm text-gray-500">"Loading providers..."</p>
+                        }>
+                            {move || {
+                                let connected: Vec<String> = connections
+                                    .get()
+                                    .and_then(Result::ok)
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .map(|connection| connection.provider)
+                                    .collect();
+                                providers.get().map(|result| match result {
+                                    Ok(providers) => {
+                                        let available: Vec<_> = providers
+                                            .into_iter()
+                                            .filter(|provider| !connected.contains(&provider.slug))
+                                            .collect();
+                                        if available.is_empty() {
+                                            view! {
+                                                <p class="text-sm text-gray-500">
+                                                    "All configured providers are connected."
+                                                </p>
+                                            }
+                                            .into_view()
+                                        } else {
+                                            available.into_iter().map(connect_button).collect_view()
+                                        }
+                                    }
+                                    Err(err) => view! {
+                                        <p class="text-sm text-red-700">{err}</p>
+                                    }
+                                    .into_view(),
+                                })
+                            }}
+                        </Suspense>
+                    </div>
+                </div>
+            </main>
+        </div>
+    }
+}